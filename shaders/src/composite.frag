@@ -0,0 +1,76 @@
+#version 450
+
+// 0 = none, 1 = Reinhard, 2 = ACES
+layout(constant_id = 0) const uint TONEMAP_MODE = 0;
+// 0 = SDR, the sRGB swapchain encodes, 1 = HDR10 PQ, 2 = scRGB, 3 = SDR encoded here for a
+// UNORM swapchain
+layout(constant_id = 1) const uint OUTPUT_TRANSFER = 0;
+
+// `exposure` is a linear scale applied to the scene color and bloom before tonemapping. With
+// `compareEncoding` set, the right half of SDR output gets the other sRGB encoding, so a
+// missing or doubled encode stands out next to the correct one.
+layout(push_constant) uniform Composite {
+    float exposure;
+    float bloomIntensity;
+    uint compareEncoding;
+} composite;
+
+layout(set = 0, binding = 0) uniform sampler2D sceneColor;
+layout(set = 0, binding = 1) uniform sampler2D bloomColor;
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 0) out vec4 outColor;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Reference white of HDR output, in nits. scRGB has 1.0 at 80 nits, PQ 1.0 at 10000.
+const float SDR_WHITE_NITS = 203.0;
+
+// Rec. 709 primaries, which sRGB and scRGB share, to Rec. 2020
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// SMPTE ST 2084 inverse EOTF, `color` is in units of 10000 nits
+vec3 pq(vec3 color) {
+    vec3 ym = pow(max(color, vec3(0.0)), vec3(0.1593017578125));
+    return pow((0.8359375 + 18.8515625 * ym) / (1.0 + 18.6875 * ym), vec3(78.84375));
+}
+
+// The sRGB OETF
+vec3 srgbEncode(vec3 color) {
+    color = clamp(color, 0.0, 1.0);
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), color));
+}
+
+void main() {
+    vec4 color = texture(sceneColor, fragUv);
+    color.rgb += texture(bloomColor, fragUv).rgb * composite.bloomIntensity;
+    color.rgb *= composite.exposure;
+    if (TONEMAP_MODE == 1) {
+        color.rgb = color.rgb / (color.rgb + vec3(1.0));
+    } else if (TONEMAP_MODE == 2) {
+        color.rgb = aces(color.rgb);
+    }
+    if (OUTPUT_TRANSFER == 1) {
+        color.rgb = pq(REC709_TO_REC2020 * color.rgb * (SDR_WHITE_NITS / 10000.0));
+    } else if (OUTPUT_TRANSFER == 2) {
+        color.rgb *= SDR_WHITE_NITS / 80.0;
+    } else {
+        bool encode = OUTPUT_TRANSFER == 3;
+        if (composite.compareEncoding != 0 && fragUv.x > 0.5) {
+            encode = !encode;
+        }
+        if (encode) {
+            color.rgb = srgbEncode(color.rgb);
+        }
+    }
+    outColor = color;
+}
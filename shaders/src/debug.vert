@@ -0,0 +1,14 @@
+#version 450
+
+layout(constant_id = 0) const float POINT_SIZE = 1.0;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 inColor;
+
+layout(location = 0) out vec4 fragColor;
+
+void main() {
+    gl_Position = vec4(inPosition, 1.0);
+    gl_PointSize = POINT_SIZE;
+    fragColor = inColor;
+}
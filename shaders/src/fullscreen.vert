@@ -0,0 +1,14 @@
+#version 450
+
+// The rotation of clip space for a pre-transformed swapchain, identity by default
+layout(constant_id = 0) const float PRE_ROTATION_COS = 1.0;
+layout(constant_id = 1) const float PRE_ROTATION_SIN = 0.0;
+
+layout(location = 0) out vec2 fragUv;
+
+void main() {
+    fragUv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    vec2 position = fragUv * 2.0 - 1.0;
+    mat2 preRotation = mat2(PRE_ROTATION_COS, PRE_ROTATION_SIN, -PRE_ROTATION_SIN, PRE_ROTATION_COS);
+    gl_Position = vec4(preRotation * position, 0.0, 1.0);
+}
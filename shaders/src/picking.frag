@@ -0,0 +1,11 @@
+#version 450
+
+layout(push_constant) uniform Picking {
+    uint objectId;
+} picking;
+
+layout(location = 0) out uint outObjectId;
+
+void main() {
+    outObjectId = picking.objectId;
+}
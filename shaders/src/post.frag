@@ -0,0 +1,21 @@
+#version 450
+
+// 0 = none, 1 = grayscale, 2 = vignette
+layout(push_constant) uniform PostProcess {
+    uint effect;
+} postProcess;
+
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput compositeColor;
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 color = subpassLoad(compositeColor);
+    if (postProcess.effect == 1) {
+        color.rgb = vec3(dot(color.rgb, vec3(0.2126, 0.7152, 0.0722)));
+    } else if (postProcess.effect == 2) {
+        color.rgb *= 1.0 - smoothstep(0.4, 0.8, length(fragUv - vec2(0.5)));
+    }
+    outColor = color;
+}
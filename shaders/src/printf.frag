@@ -0,0 +1,16 @@
+#version 450
+#extension GL_EXT_debug_printf : enable
+
+// shader.frag with a debugPrintfEXT of the fragment at the center of the default window, to smoke
+// test the printf path. It only prints when DEBUG_PRINTF is nonzero.
+layout(constant_id = 0) const uint DEBUG_PRINTF = 0u;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    if (DEBUG_PRINTF != 0u && uint(gl_FragCoord.x) == 512u && uint(gl_FragCoord.y) == 384u) {
+        debugPrintfEXT("Fragment (%f, %f) has color (%f, %f, %f)", gl_FragCoord.x, gl_FragCoord.y, fragColor.r, fragColor.g, fragColor.b);
+    }
+    outColor = vec4(fragColor, 1.0);
+}
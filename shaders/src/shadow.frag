@@ -0,0 +1,5 @@
+#version 450
+
+// The shadow pass only writes depth
+void main() {
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Light {
+    mat4 viewProjection;
+} light;
+
+layout(location = 0) in vec3 inPosition;
+
+void main() {
+    gl_Position = light.viewProjection * vec4(inPosition, 1.0);
+}
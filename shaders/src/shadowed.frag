@@ -0,0 +1,34 @@
+#version 450
+
+layout(set = 0, binding = 1) uniform sampler2DShadow shadowMap;
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec4 fragLightPosition;
+layout(location = 0) out vec4 outColor;
+
+// How dark fully shadowed surfaces get
+const float SHADOW_AMBIENT = 0.35;
+
+// Averages the depth comparisons of a 3x3 texel block, 1 is fully lit. Outside the light's depth
+// range is lit in either depth convention.
+float shadowFactor(vec3 lightPosition) {
+    vec2 uv = lightPosition.xy * 0.5 + 0.5;
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || lightPosition.z < 0.0
+            || lightPosition.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0));
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            lit += texture(shadowMap, vec3(uv + vec2(x, y) * texel, lightPosition.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+void main() {
+    float lit = shadowFactor(fragLightPosition.xyz / fragLightPosition.w);
+    outColor = vec4(fragColor.rgb * mix(SHADOW_AMBIENT, 1.0, lit), fragColor.a);
+}
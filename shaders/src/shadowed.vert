@@ -0,0 +1,17 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Light {
+    mat4 viewProjection;
+} light;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 inColor;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec4 fragLightPosition;
+
+void main() {
+    gl_Position = vec4(inPosition, 1.0);
+    fragColor = inColor;
+    fragLightPosition = light.viewProjection * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// Corners of the tessellated demo quad, in patch order
+vec2 positions[4] = vec2[](
+    vec2(0.55, -0.95),
+    vec2(0.95, -0.95),
+    vec2(0.95, -0.55),
+    vec2(0.55, -0.55)
+);
+
+void main() {
+    gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+}
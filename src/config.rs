@@ -0,0 +1,517 @@
+use std::env;
+use std::path::PathBuf;
+
+use ash::vk::{
+    ColorSpaceKHR, CompareOp, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
+    Format, ImageUsageFlags, PresentModeKHR,
+};
+
+use crate::constants::{
+    BLOOM_MIPS_ENV_VAR, DEPTH_CONVENTION_ENV_VAR, DEPTH_PREPASS_ENV_VAR, DYNAMIC_RENDERING_ENV_VAR,
+    GPU_INDEX_ENV_VAR, GPU_NAME_ENV_VAR, PICKING_ENV_VAR, PIPELINE_DERIVATIVES_ENV_VAR,
+    POST_PROCESS_SUBPASS_ENV_VAR, SHADER_BUILD_DIR, SHADER_DIR_ENV_VAR, SHADER_LANGUAGE_ENV_VAR,
+    SHADOW_MAP_SIZE_ENV_VAR, SUPPRESSED_VALIDATION_MESSAGES, SWAPCHAIN_FORMATS, VALIDATION_ENV_VAR,
+    VALIDATION_EXTRA_ENV_VAR, VALIDATION_LAYERS, VALIDATION_POLICY_ENV_VAR, VK_LOG_ENV_VAR,
+    VK_LOG_TYPES_ENV_VAR, VK_SUPPRESS_ENV_VAR, WINDOW_TRANSPARENCY_ENV_VAR,
+};
+use crate::util::debug::{
+    parse_message_types, parse_min_severity, parse_validation_features, MessageId, ValidationInfo,
+    ValidationPolicy, ValidationSettings,
+};
+use crate::vulkan::format::ColorSpaceIntent;
+use crate::vulkan::screenshot::ScreenshotReadback;
+
+/// Tonemapping operator applied when compositing the HDR scene onto the swapchain. The value is
+/// the `TONEMAP_MODE` specialization constant of the composite shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TonemapMode {
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+/// What the swapchain presents. The HDR spaces need a display and surface that support them and
+/// fall back to `SdrSrgb` otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    SdrSrgb,
+    /// HDR10, Rec. 2020 primaries with the ST 2084 transfer function
+    HdrPq,
+    /// Linear extended sRGB in a float format, 1.0 is 80 nits
+    ScRgb,
+}
+
+impl OutputColorSpace {
+    pub fn color_space(self) -> ColorSpaceKHR {
+        match self {
+            OutputColorSpace::SdrSrgb => ColorSpaceKHR::SRGB_NONLINEAR,
+            OutputColorSpace::HdrPq => ColorSpaceKHR::HDR10_ST2084_EXT,
+            OutputColorSpace::ScRgb => ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        }
+    }
+
+    /// The swapchain formats for the HDR spaces, best first. SDR formats come from
+    /// `EngineConfig::swapchain_formats`.
+    pub fn hdr_formats(self) -> &'static [Format] {
+        match self {
+            OutputColorSpace::SdrSrgb => &[],
+            OutputColorSpace::HdrPq => &[
+                Format::A2B10G10R10_UNORM_PACK32,
+                Format::A2R10G10B10_UNORM_PACK32,
+            ],
+            OutputColorSpace::ScRgb => &[Format::R16G16B16A16_SFLOAT],
+        }
+    }
+
+    /// The `OUTPUT_TRANSFER` specialization constant of the composite shader. Only an `_SRGB`
+    /// swapchain format encodes SDR output on write, the shader encodes it for any other.
+    pub fn output_transfer(self, swapchain_format: Format) -> u32 {
+        match self {
+            OutputColorSpace::SdrSrgb => {
+                match ColorSpaceIntent::of_format(swapchain_format) == Some(ColorSpaceIntent::Srgb)
+                {
+                    true => 0,
+                    false => 3,
+                }
+            }
+            OutputColorSpace::HdrPq => 1,
+            OutputColorSpace::ScRgb => 2,
+        }
+    }
+}
+
+/// Fullscreen effect drawn by the post-process subpass. The value is the push constant of the
+/// post-process shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    None = 0,
+    Grayscale = 1,
+    Vignette = 2,
+}
+
+/// Language a shader source is written in. GLSL and HLSL sources are compiled with shaderc
+/// when the `shaderc` feature is enabled, WGSL sources are translated by naga with the `wgsl`
+/// feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+    Wgsl,
+}
+
+/// How depth is laid out in depth buffers. `ReverseZ` maps the near plane to 1 and the far plane
+/// to 0, which spreads float precision evenly over distance instead of spending it near the
+/// camera, so it needs a `D32_SFLOAT` depth buffer.
+///
+/// Projection matrices, depth clear values, compare ops and depth bias follow the convention
+/// through the methods below. Shaders have to follow it themselves where they write clip-space
+/// depth without a projection matrix, like the demo scene, write `gl_FragDepth`, compare depths
+/// by hand, like the range check in `shadowed.frag`, or linearize depth read from a texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthConvention {
+    Standard,
+    ReverseZ,
+}
+
+impl DepthConvention {
+    /// The depth of the far plane.
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::ReverseZ => 0.0,
+        }
+    }
+
+    /// The compare op that does what `compare_op` does with standard depth.
+    pub fn compare_op(self, compare_op: CompareOp) -> CompareOp {
+        match (self, compare_op) {
+            (DepthConvention::Standard, _) => compare_op,
+            (DepthConvention::ReverseZ, CompareOp::LESS) => CompareOp::GREATER,
+            (DepthConvention::ReverseZ, CompareOp::LESS_OR_EQUAL) => CompareOp::GREATER_OR_EQUAL,
+            (DepthConvention::ReverseZ, CompareOp::GREATER) => CompareOp::LESS,
+            (DepthConvention::ReverseZ, CompareOp::GREATER_OR_EQUAL) => CompareOp::LESS_OR_EQUAL,
+            (DepthConvention::ReverseZ, _) => compare_op,
+        }
+    }
+
+    /// Constant and slope factors that push depth away from the light or camera.
+    pub fn depth_bias(self, depth_bias: (f32, f32)) -> (f32, f32) {
+        match self {
+            DepthConvention::Standard => depth_bias,
+            DepthConvention::ReverseZ => (-depth_bias.0, -depth_bias.1),
+        }
+    }
+
+    /// Adjusts a column-major projection with depth from 0 at the near plane to 1 at the far
+    /// plane, perspective or orthographic, to the convention. Reverse-Z depth is `w - z`.
+    pub fn projection(self, projection: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+        match self {
+            DepthConvention::Standard => projection,
+            DepthConvention::ReverseZ => {
+                projection.map(|column| [column[0], column[1], column[3] - column[2], column[3]])
+            }
+        }
+    }
+}
+
+/// How the swapchain presents. `VsyncOff` and `LowLatency` both avoid waiting on vertical blank,
+/// `VsyncOff` prefers tearing and `LowLatency` prefers replacing queued images. `AdaptiveVsync`
+/// tears only when a frame misses its vertical blank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentPreference {
+    VsyncOn,
+    VsyncOff,
+    LowLatency,
+    AdaptiveVsync,
+}
+
+impl PresentPreference {
+    /// The present modes to try, best first. Every device supports FIFO, so each ends with it.
+    pub fn present_modes(self) -> &'static [PresentModeKHR] {
+        match self {
+            PresentPreference::VsyncOn => &[PresentModeKHR::FIFO],
+            PresentPreference::VsyncOff => &[
+                PresentModeKHR::IMMEDIATE,
+                PresentModeKHR::MAILBOX,
+                PresentModeKHR::FIFO,
+            ],
+            PresentPreference::LowLatency => &[
+                PresentModeKHR::MAILBOX,
+                PresentModeKHR::IMMEDIATE,
+                PresentModeKHR::FIFO,
+            ],
+            PresentPreference::AdaptiveVsync => {
+                &[PresentModeKHR::FIFO_RELAXED, PresentModeKHR::FIFO]
+            }
+        }
+    }
+}
+
+/// Which device `select_physical_device` takes. `Auto` takes the best scoring suitable device,
+/// the others force one, by its enumeration index or by a case-insensitive part of its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelection {
+    Auto,
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    /// `Srgb` lets the swapchain encode gamma on write, `Linear` picks a UNORM swapchain format
+    /// and leaves the gamma encoding to the fragment shader.
+    pub swapchain_color_space: ColorSpaceIntent,
+    pub output_color_space: OutputColorSpace,
+    /// The swapchain formats to try, best first. Those matching `swapchain_color_space` are
+    /// tried before the others, all of them in the sRGB nonlinear color space.
+    pub swapchain_formats: Vec<Format>,
+    pub present_preference: PresentPreference,
+    /// Swapchain images to ask for, within what the surface allows. `None` asks for one more
+    /// than the surface minimum. Drivers may create more than asked for.
+    pub desired_image_count: Option<u32>,
+    /// Usage of the swapchain images besides color attachment, the bits the surface doesn't
+    /// support are left out. Screenshots need `ScreenshotReadback::SWAPCHAIN_USAGE`.
+    pub extra_swapchain_usage: ImageUsageFlags,
+    /// Where the pipeline cache is loaded from at startup and written to on shutdown, `None`
+    /// keeps the cache in memory only.
+    pub pipeline_cache_path: Option<PathBuf>,
+    /// Directory holding the compiled SPIR-V shaders, taken from `PISTON_SHADER_DIR` when set.
+    /// Engine shaders missing from it are loaded from the copies embedded in the binary.
+    pub shader_dir: PathBuf,
+    pub tonemap_mode: TonemapMode,
+    /// In stops, the scene color is scaled by `2^exposure` before tonemapping.
+    pub exposure: f32,
+    /// How much of the blurred bright parts is added to the scene, 0 turns bloom off without
+    /// removing its passes.
+    pub bloom_intensity: f32,
+    /// Scene colors brighter than this bloom.
+    pub bloom_threshold: f32,
+    /// Number of half-resolution steps of the bloom blur, 2 to 6. Fewer are cheaper on low-end
+    /// GPUs, set `PISTON_BLOOM_MIPS` to change it.
+    pub bloom_mip_count: u32,
+    /// Width and height of the shadow map, set `PISTON_SHADOW_MAP_SIZE` to change it.
+    pub shadow_map_size: u32,
+    /// The direction the directional light shines in, it doesn't need to be normalized.
+    pub light_direction: [f32; 3],
+    /// Depth bias of the shadow pass, larger values trade shadow acne for shadows detaching
+    /// from their casters.
+    pub shadow_bias_constant: f32,
+    pub shadow_bias_slope: f32,
+    /// Background of the scene in linear RGBA. The scene is rendered to a float target and
+    /// gamma is only encoded when presenting, so colors picked in sRGB go through
+    /// `srgb_to_linear` first. With `window_transparency` the color is premultiplied by its alpha,
+    /// the default is transparent black then.
+    pub clear_color: [f32; 4],
+    /// The far plane of `depth_convention` unless changed.
+    pub clear_depth: f32,
+    /// Set `PISTON_GPU_INDEX=<n>` or `PISTON_GPU_NAME=<part of the name>` to force a device, the
+    /// devices and their indices are logged at startup.
+    pub gpu_selection: GpuSelection,
+    /// On in debug builds and off in release builds, set `PISTON_VALIDATION=0` or `1` to
+    /// override it. Turned off with a warning when the layers aren't installed. Messages from
+    /// warnings up are reported, set `PISTON_VK_LOG=verbose`, `info`, `warn` or `error` to change
+    /// that and `PISTON_VK_LOG_TYPES=general,validation,performance` to pick the message types.
+    /// `PISTON_VK_SUPPRESS` takes a comma separated list of message ID names or numbers to drop
+    /// on top of the known noise. `PISTON_VALIDATION_POLICY=count` fails the run when errors were
+    /// reported and `panic` stops it at the first error.
+    /// `PISTON_VALIDATION_EXTRA=gpu,bestpractices,sync` turns on GPU-assisted, best practices and
+    /// synchronization validation, and `printf` logs shader `debugPrintfEXT` output at debug
+    /// level. `printf` replaces `gpu`, the layer can't run both. Layer settings such as message
+    /// limits are set in code, through `settings`.
+    pub validation: ValidationInfo,
+    /// Set `PISTON_DEPTH_CONVENTION=reverse-z` for reverse-Z depth.
+    pub depth_convention: DepthConvention,
+    /// Creates the variants of a `PipelineFamily` as derivatives of its base. Set
+    /// `PISTON_PIPELINE_DERIVATIVES=0` to compare creation times without them.
+    pub pipeline_derivatives: bool,
+    /// Checks loaded SPIR-V with naga before creating shader modules. On in debug builds.
+    pub validate_shaders: bool,
+    /// Draws the scene with the shaders written in this language. Set
+    /// `PISTON_SHADER_LANGUAGE=hlsl` to use the HLSL pair in `shaders/src/hlsl`, or `wgsl` for
+    /// `shaders/src/wgsl/triangle.wgsl`, which is loaded from its prebuilt translation without
+    /// the `wgsl` feature.
+    pub scene_shader_language: ShaderLanguage,
+    /// Renders without render pass and framebuffer objects when the device supports
+    /// `VK_KHR_dynamic_rendering`. Set `PISTON_DYNAMIC_RENDERING=0` to use render passes anyway.
+    pub dynamic_rendering: bool,
+    /// Splits the swapchain pass in two subpasses, the second reads the composited image as an
+    /// input attachment and draws `post_effect` over it. Input attachments need a render pass,
+    /// so the swapchain pass keeps one even with dynamic rendering. Set
+    /// `PISTON_POST_PROCESS_SUBPASS=1` to enable it.
+    pub post_process_subpass: bool,
+    pub post_effect: PostEffect,
+    /// Renders object IDs on the frames after a click to find the object under the cursor. Set
+    /// `PISTON_PICKING=0` to disable it.
+    pub picking: bool,
+    /// Writes the depth of the opaque scene geometry in a depth-only pre-pass, so the scene pass
+    /// shades each of its pixels once. Transparent geometry is only drawn in the scene pass. Set
+    /// `PISTON_DEPTH_PREPASS=1` to enable it.
+    pub depth_prepass: bool,
+    /// Creates a transparent window that blends with what's behind it where the scene's alpha is
+    /// below 1, when the surface supports a composite alpha other than opaque. Set
+    /// `PISTON_WINDOW_TRANSPARENCY=1` to enable it.
+    pub window_transparency: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
+        let depth_convention = match env::var(DEPTH_CONVENTION_ENV_VAR) {
+            Ok(convention) if convention.eq_ignore_ascii_case("reverse-z") => {
+                DepthConvention::ReverseZ
+            }
+            _ => DepthConvention::Standard,
+        };
+
+        let window_transparency =
+            env::var_os(WINDOW_TRANSPARENCY_ENV_VAR).is_some_and(|value| value == "1");
+
+        EngineConfig {
+            swapchain_color_space: ColorSpaceIntent::Srgb,
+            output_color_space: OutputColorSpace::SdrSrgb,
+            swapchain_formats: SWAPCHAIN_FORMATS.to_vec(),
+            present_preference: PresentPreference::LowLatency,
+            desired_image_count: None,
+            extra_swapchain_usage: ScreenshotReadback::SWAPCHAIN_USAGE,
+            pipeline_cache_path: default_pipeline_cache_path(),
+            shader_dir: env::var_os(SHADER_DIR_ENV_VAR)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(SHADER_BUILD_DIR)),
+            tonemap_mode: TonemapMode::None,
+            exposure: 0.0,
+            bloom_intensity: 0.05,
+            bloom_threshold: 1.0,
+            bloom_mip_count: env::var(BLOOM_MIPS_ENV_VAR)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(5),
+            shadow_map_size: env::var(SHADOW_MAP_SIZE_ENV_VAR)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(2048),
+            light_direction: [0.5, 0.5, 1.0],
+            shadow_bias_constant: 1.25,
+            shadow_bias_slope: 1.75,
+            clear_color: match window_transparency {
+                true => [0.0, 0.0, 0.0, 0.0],
+                false => [0.0, 0.0, 0.0, 1.0],
+            },
+            clear_depth: depth_convention.clear_depth(),
+            depth_convention,
+            gpu_selection: match (
+                env::var(GPU_INDEX_ENV_VAR)
+                    .ok()
+                    .and_then(|index| index.parse().ok()),
+                env::var(GPU_NAME_ENV_VAR),
+            ) {
+                (Some(index), _) => GpuSelection::Index(index),
+                (None, Ok(name)) if !name.is_empty() => GpuSelection::Name(name),
+                _ => GpuSelection::Auto,
+            },
+            validation: ValidationInfo {
+                is_enabled: match env::var(VALIDATION_ENV_VAR).as_deref() {
+                    Ok("1") => true,
+                    Ok("0") => false,
+                    _ => cfg!(debug_assertions),
+                },
+                required_validation_layers: VALIDATION_LAYERS,
+                min_severity: env::var(VK_LOG_ENV_VAR)
+                    .ok()
+                    .and_then(|level| parse_min_severity(&level))
+                    .unwrap_or(DebugUtilsMessageSeverityFlagsEXT::WARNING),
+                message_types: env::var(VK_LOG_TYPES_ENV_VAR)
+                    .ok()
+                    .and_then(|names| parse_message_types(&names))
+                    .unwrap_or(
+                        DebugUtilsMessageTypeFlagsEXT::GENERAL
+                            | DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                            | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+                    ),
+                suppressed_messages: SUPPRESSED_VALIDATION_MESSAGES
+                    .iter()
+                    .map(|id| MessageId::Name(id.to_string()))
+                    .chain(
+                        env::var(VK_SUPPRESS_ENV_VAR)
+                            .unwrap_or_default()
+                            .split(',')
+                            .filter(|id| !id.trim().is_empty())
+                            .map(MessageId::parse),
+                    )
+                    .collect(),
+                policy: env::var(VALIDATION_POLICY_ENV_VAR)
+                    .ok()
+                    .and_then(|policy| ValidationPolicy::parse(&policy))
+                    .unwrap_or_default(),
+                extra_features: env::var(VALIDATION_EXTRA_ENV_VAR)
+                    .ok()
+                    .and_then(|names| parse_validation_features(&names))
+                    .unwrap_or_default(),
+                settings: ValidationSettings::default(),
+            },
+            pipeline_derivatives: env::var_os(PIPELINE_DERIVATIVES_ENV_VAR)
+                .is_none_or(|value| value != "0"),
+            validate_shaders: cfg!(debug_assertions),
+            scene_shader_language: match env::var(SHADER_LANGUAGE_ENV_VAR) {
+                Ok(language) if language.eq_ignore_ascii_case("hlsl") => ShaderLanguage::Hlsl,
+                Ok(language) if language.eq_ignore_ascii_case("wgsl") => ShaderLanguage::Wgsl,
+                _ => ShaderLanguage::Glsl,
+            },
+            dynamic_rendering: env::var_os(DYNAMIC_RENDERING_ENV_VAR)
+                .is_none_or(|value| value != "0"),
+            post_process_subpass: env::var_os(POST_PROCESS_SUBPASS_ENV_VAR)
+                .is_some_and(|value| value == "1"),
+            post_effect: PostEffect::None,
+            picking: env::var_os(PICKING_ENV_VAR).is_none_or(|value| value != "0"),
+            depth_prepass: env::var_os(DEPTH_PREPASS_ENV_VAR).is_some_and(|value| value == "1"),
+            window_transparency,
+        }
+    }
+}
+
+fn default_pipeline_cache_path() -> Option<PathBuf> {
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(cache_home) => PathBuf::from(cache_home),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+
+    Some(cache_dir.join("piston").join("pipeline_cache.bin"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    /// Right-handed, looking down -Z, with depth from 0 at the near plane to 1 at the far plane.
+    fn perspective() -> [[f32; 4]; 4] {
+        let depth_range = NEAR - FAR;
+        [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, FAR / depth_range, -1.0],
+            [0.0, 0.0, NEAR * FAR / depth_range, 0.0],
+        ]
+    }
+
+    fn orthographic() -> [[f32; 4]; 4] {
+        let depth_range = NEAR - FAR;
+        [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0 / depth_range, 0.0],
+            [0.0, 0.0, NEAR / depth_range, 1.0],
+        ]
+    }
+
+    /// The depth after the perspective divide of a point at `distance` in front of the camera.
+    fn depth_at(projection: [[f32; 4]; 4], distance: f32) -> f32 {
+        let point = [0.0, 0.0, -distance, 1.0];
+        let clip = [2, 3].map(|row| {
+            (0..4)
+                .map(|column| projection[column][row] * point[column])
+                .sum::<f32>()
+        });
+        clip[0] / clip[1]
+    }
+
+    fn assert_depth(projection: [[f32; 4]; 4], distance: f32, expected: f32) {
+        let depth = depth_at(projection, distance);
+        assert!(
+            (depth - expected).abs() < 1e-5,
+            "depth at {} is {}, expected {}",
+            distance,
+            depth,
+            expected
+        );
+    }
+
+    #[test]
+    fn standard_projection_is_unchanged() {
+        let projection = DepthConvention::Standard.projection(perspective());
+        assert_eq!(projection, perspective());
+        assert_depth(projection, NEAR, 0.0);
+        assert_depth(projection, FAR, 1.0);
+    }
+
+    #[test]
+    fn reverse_z_perspective_maps_near_to_one_and_far_to_zero() {
+        let projection = DepthConvention::ReverseZ.projection(perspective());
+        assert_depth(projection, NEAR, 1.0);
+        assert_depth(projection, FAR, 0.0);
+    }
+
+    #[test]
+    fn reverse_z_orthographic_maps_near_to_one_and_far_to_zero() {
+        let projection = DepthConvention::ReverseZ.projection(orthographic());
+        assert_depth(projection, NEAR, 1.0);
+        assert_depth(projection, FAR, 0.0);
+    }
+
+    #[test]
+    fn reverse_z_matches_clear_depth_and_compare_op() {
+        let convention = DepthConvention::ReverseZ;
+        assert_depth(
+            convention.projection(perspective()),
+            FAR,
+            convention.clear_depth(),
+        );
+        assert_eq!(convention.compare_op(CompareOp::LESS), CompareOp::GREATER);
+    }
+
+    #[test]
+    fn sdr_output_is_encoded_by_the_shader_unless_the_format_does() {
+        let sdr = OutputColorSpace::SdrSrgb;
+        assert_eq!(sdr.output_transfer(Format::B8G8R8A8_SRGB), 0);
+        assert_eq!(sdr.output_transfer(Format::B8G8R8A8_UNORM), 3);
+        assert_eq!(sdr.output_transfer(Format::A2B10G10R10_UNORM_PACK32), 3);
+        assert_eq!(
+            OutputColorSpace::HdrPq.output_transfer(Format::A2B10G10R10_UNORM_PACK32),
+            1
+        );
+        assert_eq!(
+            OutputColorSpace::ScRgb.output_transfer(Format::R16G16B16A16_SFLOAT),
+            2
+        );
+    }
+}
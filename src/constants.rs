@@ -1,13 +1,83 @@
-use crate::util::debug::ValidationInfo;
-use ash::vk::{make_api_version, API_VERSION_1_2};
+use ash::vk::{
+    make_api_version, ExtDeviceFaultFn, ExtFullScreenExclusiveFn, ExtMemoryBudgetFn,
+    ExtSwapchainColorspaceFn, Format, KhrDynamicRenderingFn, KhrGetPhysicalDeviceProperties2Fn,
+    KhrGetSurfaceCapabilities2Fn, KhrPortabilityEnumerationFn, KhrPortabilitySubsetFn,
+    KhrPushDescriptorFn, KhrShaderNonSemanticInfoFn, KhrSwapchainFn, KhrSynchronization2Fn,
+    KhrTimelineSemaphoreFn, API_VERSION_1_1, API_VERSION_1_2,
+};
+use std::ffi::CStr;
+use std::time::Duration;
 
 pub const APPLICATION_NAME: &str = "Piston demo";
 
 pub const APPLICATION_VERSION: u32 = make_api_version(0, 0, 1, 0);
 
+/// Requested from the loader, lowered to what it supports.
 pub const VULKAN_API_VERSION: u32 = API_VERSION_1_2;
 
-pub const REQUIRED_EXTENSIONS: [&str; 1] = ["VK_KHR_swapchain"];
+/// Below this there is no `vkGetPhysicalDeviceFeatures2` or `VK_KHR_maintenance1` in core.
+pub const MIN_VULKAN_API_VERSION: u32 = API_VERSION_1_1;
+
+pub const REQUIRED_EXTENSIONS: [&CStr; 1] = [KhrSwapchainFn::name()];
+
+pub const DYNAMIC_RENDERING_EXTENSION: &CStr = KhrDynamicRenderingFn::name();
+
+pub const PORTABILITY_SUBSET_EXTENSION: &CStr = KhrPortabilitySubsetFn::name();
+
+pub const MEMORY_BUDGET_EXTENSION: &CStr = ExtMemoryBudgetFn::name();
+
+/// Only enabled together with its `deviceFault` feature.
+pub const DEVICE_FAULT_EXTENSION: &CStr = ExtDeviceFaultFn::name();
+
+/// Only exists on Windows, it needs `VK_KHR_get_surface_capabilities2` on the instance.
+pub const FULL_SCREEN_EXCLUSIVE_EXTENSION: &CStr = ExtFullScreenExclusiveFn::name();
+
+/// Lets shader modules carry `debugPrintfEXT` calls
+pub const SHADER_NON_SEMANTIC_INFO_EXTENSION: &CStr = KhrShaderNonSemanticInfoFn::name();
+
+/// Provided by the validation layer, ash doesn't know it yet
+pub const LAYER_SETTINGS_EXTENSION: &CStr = c"VK_EXT_layer_settings";
+
+/// Enabled on the instance when available. The swapchain colorspace extension adds the HDR and
+/// wide gamut surface color spaces, portability enumeration lists MoltenVK devices and the
+/// physical device properties 2 extension is core from Vulkan 1.1.
+pub const OPTIONAL_INSTANCE_EXTENSIONS: [&CStr; 4] = [
+    KhrPortabilityEnumerationFn::name(),
+    KhrGetPhysicalDeviceProperties2Fn::name(),
+    ExtSwapchainColorspaceFn::name(),
+    KhrGetSurfaceCapabilities2Fn::name(),
+];
+
+/// Enabled when the device has them, code that uses one checks `DeviceCapabilities` first.
+pub const OPTIONAL_EXTENSIONS: [&CStr; 8] = [
+    DYNAMIC_RENDERING_EXTENSION,
+    KhrSynchronization2Fn::name(),
+    KhrTimelineSemaphoreFn::name(),
+    MEMORY_BUDGET_EXTENSION,
+    KhrPushDescriptorFn::name(),
+    DEVICE_FAULT_EXTENSION,
+    FULL_SCREEN_EXCLUSIVE_EXTENSION,
+    SHADER_NON_SEMANTIC_INFO_EXTENSION,
+];
+
+/// The optional extensions whose dependencies are core in Vulkan 1.2, devices below it don't get
+/// them.
+pub const VULKAN_12_EXTENSIONS: [&CStr; 3] = [
+    DYNAMIC_RENDERING_EXTENSION,
+    KhrSynchronization2Fn::name(),
+    KhrTimelineSemaphoreFn::name(),
+];
+
+/// The first graphics queue renders, the second takes background work such as uploads. Devices
+/// with a single graphics queue only get the first.
+pub const GRAPHICS_QUEUE_PRIORITIES: [f32; 2] = [1.0, 0.5];
+
+/// How often debug builds log the memory budget
+pub const MEMORY_BUDGET_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A surface can be briefly unusable after a monitor change, swapchain recreation tries once more
+/// after this delay.
+pub const SWAPCHAIN_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub const ENGINE_NAME: &str = "Piston";
 
@@ -17,7 +87,158 @@ pub const WINDOW_WIDTH: u32 = 1024;
 
 pub const WINDOW_HEIGHT: u32 = 768;
 
-pub const VALIDATION: ValidationInfo = ValidationInfo {
-    is_enabled: true,
-    required_validation_layers: ["VK_LAYER_KHRONOS_validation"],
-};
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+pub const VALIDATION_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+
+pub const ALBEDO_TEXTURE_BINDING: u32 = 0;
+
+pub const SKYBOX_TEXTURE_BINDING: u32 = 1;
+
+pub const SCENE_COLOR_BINDING: u32 = 0;
+
+pub const POST_PROCESS_INPUT_BINDING: u32 = 0;
+
+pub const BLOOM_COLOR_BINDING: u32 = 1;
+
+pub const BLOOM_SOURCE_BINDING: u32 = 0;
+
+pub const BLOOM_DESTINATION_BINDING: u32 = 1;
+
+pub const BLOOM_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+pub const BLOOM_MIN_MIP_COUNT: u32 = 2;
+
+pub const BLOOM_MAX_MIP_COUNT: u32 = 6;
+
+pub const SHADOW_LIGHT_BINDING: u32 = 0;
+
+pub const SHADOW_MAP_BINDING: u32 = 1;
+
+/// The shadow map covers a sphere of this radius around the center, which holds the demo scene.
+pub const SHADOW_SCENE_CENTER: [f32; 3] = [0.0, 0.0, 0.5];
+
+pub const SHADOW_SCENE_RADIUS: f32 = 1.5;
+
+pub const PICKING_FORMAT: Format = Format::R32_UINT;
+
+/// Object IDs start at 1, pixels no object covers keep this one
+pub const PICKING_CLEAR_ID: u32 = 0;
+
+pub const SCENE_COLOR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// The swapchain formats to try, best first.
+pub const SWAPCHAIN_FORMATS: [Format; 4] = [
+    Format::B8G8R8A8_SRGB,
+    Format::R8G8B8A8_SRGB,
+    Format::B8G8R8A8_UNORM,
+    Format::R8G8B8A8_UNORM,
+];
+
+pub const SHADER_BUILD_DIR: &str = "shaders/build";
+
+pub const SHADER_DIR_ENV_VAR: &str = "PISTON_SHADER_DIR";
+
+/// Prints the device report and exits, without opening a window
+pub const PRINT_DEVICES_ARG: &str = "--print-devices";
+
+/// Brings up a device without a window, runs a compute check on it and exits
+pub const HEADLESS_ARG: &str = "--headless";
+
+pub const PIPELINE_DERIVATIVES_ENV_VAR: &str = "PISTON_PIPELINE_DERIVATIVES";
+
+pub const SHADER_LANGUAGE_ENV_VAR: &str = "PISTON_SHADER_LANGUAGE";
+
+pub const DYNAMIC_RENDERING_ENV_VAR: &str = "PISTON_DYNAMIC_RENDERING";
+
+pub const POST_PROCESS_SUBPASS_ENV_VAR: &str = "PISTON_POST_PROCESS_SUBPASS";
+
+pub const BLOOM_MIPS_ENV_VAR: &str = "PISTON_BLOOM_MIPS";
+
+pub const SHADOW_MAP_SIZE_ENV_VAR: &str = "PISTON_SHADOW_MAP_SIZE";
+
+pub const PICKING_ENV_VAR: &str = "PISTON_PICKING";
+
+pub const DEPTH_PREPASS_ENV_VAR: &str = "PISTON_DEPTH_PREPASS";
+
+pub const WINDOW_TRANSPARENCY_ENV_VAR: &str = "PISTON_WINDOW_TRANSPARENCY";
+
+pub const DEPTH_CONVENTION_ENV_VAR: &str = "PISTON_DEPTH_CONVENTION";
+
+pub const GPU_INDEX_ENV_VAR: &str = "PISTON_GPU_INDEX";
+
+pub const GPU_NAME_ENV_VAR: &str = "PISTON_GPU_NAME";
+
+pub const VALIDATION_ENV_VAR: &str = "PISTON_VALIDATION";
+
+pub const VK_LOG_ENV_VAR: &str = "PISTON_VK_LOG";
+
+pub const VK_LOG_TYPES_ENV_VAR: &str = "PISTON_VK_LOG_TYPES";
+
+pub const VK_SUPPRESS_ENV_VAR: &str = "PISTON_VK_SUPPRESS";
+
+pub const VALIDATION_POLICY_ENV_VAR: &str = "PISTON_VALIDATION_POLICY";
+
+pub const VALIDATION_EXTRA_ENV_VAR: &str = "PISTON_VALIDATION_EXTRA";
+
+/// Set to 1 to log through tracing with span timings instead of env_logger
+pub const TRACE_ENV_VAR: &str = "PISTON_TRACE";
+
+/// The IDs shader `debugPrintfEXT` output arrives with, in newer and older layers
+pub const DEBUG_PRINTF_MESSAGE_IDS: [&str; 2] = ["WARNING-DEBUG-PRINTF", "UNASSIGNED-DEBUG-PRINTF"];
+
+/// The engine enables `VK_EXT_debug_utils` on purpose
+pub const SUPPRESSED_VALIDATION_MESSAGES: [&str; 1] =
+    ["UNASSIGNED-BestPractices-vkCreateInstance-specialuse-extension-debugging"];
+
+/// Debug label colors, in linear RGBA
+pub const MARKER_LABEL_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub const RENDER_GRAPH_LABEL_COLOR: [f32; 4] = [0.6, 0.4, 0.8, 1.0];
+
+pub const QUEUE_LABEL_COLOR: [f32; 4] = [0.4, 0.6, 0.9, 1.0];
+
+pub const DEFAULT_ENTRY_POINT: &str = "main";
+
+pub const TONEMAP_MODE_CONSTANT_ID: u32 = 0;
+
+pub const OUTPUT_TRANSFER_CONSTANT_ID: u32 = 1;
+
+pub const PRE_ROTATION_COS_CONSTANT_ID: u32 = 0;
+
+pub const PRE_ROTATION_SIN_CONSTANT_ID: u32 = 1;
+
+pub const DEBUG_POINT_SIZE_CONSTANT_ID: u32 = 0;
+
+pub const DEBUG_PRINTF_CONSTANT_ID: u32 = 0;
+
+pub const DEBUG_POINT_SIZE: f32 = 4.0;
+
+pub const DEBUG_LINE_WIDTH: f32 = 2.0;
+
+pub const TESSELLATION_PATCH_CONTROL_POINTS: u32 = 4;
+
+pub const TESSELLATION_LEVEL_DEFAULT: f32 = 4.0;
+
+pub const TESSELLATION_LEVEL_STEP: f32 = 1.0;
+
+/// In stops
+pub const EXPOSURE_STEP: f32 = 0.5;
+
+pub const BLOOM_INTENSITY_STEP: f32 = 0.01;
+
+pub const BLOOM_THRESHOLD_STEP: f32 = 0.25;
+
+/// Added to both depth bias factors
+pub const SHADOW_BIAS_STEP: f32 = 0.25;
+
+/// In radians, around the Z axis
+pub const LIGHT_ROTATION_STEP: f32 = 0.1;
+
+/// Scene backgrounds the demo cycles through, in sRGB.
+pub const DEMO_CLEAR_COLORS: [[f32; 4]; 4] = [
+    [0.0, 0.0, 0.0, 1.0],
+    [0.1, 0.12, 0.16, 1.0],
+    [0.39, 0.58, 0.93, 1.0],
+    [0.96, 0.94, 0.88, 1.0],
+];
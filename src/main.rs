@@ -1,117 +1,1755 @@
-use anyhow::Result;
+use std::env;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
 use ash::extensions::ext::DebugUtils;
 use ash::extensions::khr::Swapchain;
 use ash::vk::{
-    DebugUtilsMessengerEXT, Extent2D, Format, Image, ImageView, PhysicalDevice, Pipeline,
-    PipelineLayout, Queue, RenderPass, SwapchainKHR,
+    AccessFlags, AttachmentStoreOp, Buffer, BufferUsageFlags, ClearColorValue, ClearValue,
+    CommandBuffer, CommandBufferBeginInfo, CommandBufferResetFlags, CompareOp, CullModeFlags,
+    DebugUtilsMessengerEXT, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool,
+    DescriptorPoolResetFlags, DescriptorPoolSize, DescriptorSet, DescriptorType, DeviceSize,
+    Extent2D, Fence, Format, Image, ImageLayout, ImageView, MemoryPropertyFlags, Pipeline,
+    PipelineBindPoint, PipelineStageFlags, PolygonMode, PresentInfoKHR, PresentModeKHR,
+    SamplerAddressMode, ShaderStageFlags, SurfaceTransformFlagsKHR, SwapchainKHR, WHOLE_SIZE,
 };
 use ash::{self, Device, Entry, Instance};
-use log::info;
-use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use log::{error, info, warn};
+use raw_window_handle::HasRawDisplayHandle;
+use tracing::info_span;
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use winit::event::{ElementState, Event, KeyEvent, MouseButton, WindowEvent};
 use winit::event_loop::EventLoop;
 use winit::keyboard::{Key, NamedKey};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 
+use piston::config::{
+    EngineConfig, OutputColorSpace, PostEffect, PresentPreference, ShaderLanguage, TonemapMode,
+};
 use piston::constants::*;
-use piston::util::debug::create_debug_utils;
+use piston::util::debug::{
+    create_debug_utils, queue_begin_label, queue_end_label, MessageFilter, ValidationPolicy,
+};
+use piston::util::logging::init_logging;
 use piston::util::util::vk_version_to_string;
-use piston::vulkan::device::{create_logical_device, select_physical_device};
-use piston::vulkan::instance::create_instance;
-use piston::vulkan::pipeline::create_graphics_pipeline;
-use piston::vulkan::render::create_render_pass;
+use piston::vulkan::barrier::{acquire_ownership, release_ownership, OwnedResource};
+use piston::vulkan::bloom::Bloom;
+use piston::vulkan::buffer::PistonBuffer;
+use piston::vulkan::command::allocate_command_buffers;
+use piston::vulkan::compute::{
+    record_compute_dispatch, run_gradient_check, submit_compute_commands, ComputeSubmission,
+};
+use piston::vulkan::context::{ContextOptions, VulkanContext};
+use piston::vulkan::debug_draw::{DebugGeometry, DebugPipelines, DebugVertex};
+use piston::vulkan::descriptor::{
+    allocate_descriptor_set, create_descriptor_pool, write_combined_image_sampler,
+    write_storage_buffer, write_uniform_buffer,
+};
+use piston::vulkan::device::{
+    create_logical_device, select_physical_device, supports_dynamic_rendering,
+};
+use piston::vulkan::device_lost::{log_device_fault, Breadcrumbs, FramePass};
+use piston::vulkan::device_report::{physical_device_report, report_physical_devices};
+use piston::vulkan::draw_list::{DrawItem, DrawList};
+use piston::vulkan::features::{DeviceFeature, RequestedFeatures};
+use piston::vulkan::format::srgb_to_linear;
+use piston::vulkan::frame::FrameSyncObjects;
+use piston::vulkan::full_screen_exclusive::{
+    acquire_full_screen_exclusive_mode, release_full_screen_exclusive_mode,
+};
+use piston::vulkan::headless::HeadlessContext;
+use piston::vulkan::hot_reload::{watched_shader_dir, ShaderWatcher};
+use piston::vulkan::image::{color_subresource_range, select_depth_format};
+use piston::vulkan::instance::{create_instance, negotiate_api_version, resolve_validation};
+use piston::vulkan::memory::log_memory_budget;
+use piston::vulkan::offscreen::OffscreenTarget;
+use piston::vulkan::picking::PickingTarget;
+use piston::vulkan::pipeline::{
+    create_compute_pipeline, BlendMode, ComputePipeline, PipelineBuilder, PipelineFamily,
+    PistonPipeline, SpecializationConstants,
+};
+use piston::vulkan::post_process::PostProcessSubpass;
+use piston::vulkan::render::{
+    record_render_pass, record_rendering, rendering_attachment_info, RenderTarget,
+};
+use piston::vulkan::render_graph::{GraphImage, ImageAccess, RenderGraph};
+use piston::vulkan::render_pass_cache::RenderPassDesc;
+use piston::vulkan::sampler::SamplerDesc;
+use piston::vulkan::screenshot::ScreenshotReadback;
+use piston::vulkan::shadow::ShadowMap;
 use piston::vulkan::surface::{create_surface, SurfaceEntities};
-use piston::vulkan::swapchain::create_swapchain;
+use piston::vulkan::swapchain::{create_swapchain, pre_rotation_matrix, recreate_swapchain};
+use piston::vulkan::tessellation::TessellatedQuad;
+
+/// Center x, center y, depth and color of the demo's transparent quads, nearest first.
+const TRANSPARENT_QUADS: [(f32, f32, f32, [f32; 4]); 2] = [
+    (-0.1, -0.1, 0.25, [1.0, 0.2, 0.2, 0.5]),
+    (0.1, 0.1, 0.5, [0.2, 0.4, 1.0, 0.5]),
+];
+
+const TRANSPARENT_QUAD_HALF_SIZE: f32 = 0.25;
+
+const TRIANGLE_OBJECT_ID: u32 = 1;
+
+/// The name the swapchain image is imported into render graphs with.
+const SWAPCHAIN_GRAPH_IMAGE: &str = "swapchain";
+
+/// The transparent quads take the IDs after this one, in `TRANSPARENT_QUADS` order.
+const FIRST_QUAD_OBJECT_ID: u32 = 2;
+
+/// The transparent quads cast shadows onto each other from the directional light.
+struct ShadowPass {
+    shadow_map: ShadowMap,
+    pipeline: PistonPipeline,
+    /// The light, for the shadow pipeline
+    caster_descriptor_set: DescriptorSet,
+    /// The light and the shadow map, for the shadowed transparent pipeline
+    receiver_descriptor_set: DescriptorSet,
+}
+
+/// Draws the triangle and the transparent quads with their object IDs.
+struct PickingPass {
+    target: PickingTarget,
+    triangle_pipeline: PistonPipeline,
+    quad_pipeline: PistonPipeline,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RenderMode {
+    Fill,
+    Wireframe,
+}
 
 struct PistonApp {
     _entry: Entry,
     instance: Instance,
-    _physical_device: PhysicalDevice,
-    device: Device,
-    _graphics_queue: Queue,
-    _present_queue: Queue,
+    context: VulkanContext,
     surface_entities: SurfaceEntities,
-    debug_utils_loader: DebugUtils,
-    debug_messenger: DebugUtilsMessengerEXT,
+    /// Created only with validation
+    debug_utils_loader: Option<DebugUtils>,
+    debug_messenger: Option<DebugUtilsMessengerEXT>,
+    /// Reached by the debug callbacks until the instance is destroyed
+    message_filter: Arc<MessageFilter>,
     swapchain_loader: Swapchain,
     swapchain: SwapchainKHR,
-    _swapchain_format: Format,
-    _swapchain_images: Vec<Image>,
-    _swapchain_extent: Extent2D,
+    swapchain_format: Format,
+    swapchain_images: Vec<Image>,
+    swapchain_extent: Extent2D,
+    /// The composite pass turns the scene to the orientation of the swapchain images
+    pre_transform: SurfaceTransformFlagsKHR,
     swapchain_image_views: Vec<ImageView>,
-    render_pass: RenderPass,
-    pipeline_layout: PipelineLayout,
-    pipeline: Pipeline,
+    present_mode: PresentModeKHR,
+    /// The composite shader encodes for it
+    output_color_space: OutputColorSpace,
+    /// Set when the swapchain no longer matches the window or the present preference, it's
+    /// recreated before the next frame
+    swapchain_stale: bool,
+    /// Whether the swapchain holds the display in exclusive fullscreen
+    full_screen_exclusive_acquired: bool,
+    /// Set when the display couldn't be taken or was taken away, fullscreen stays borderless
+    /// until the window leaves it
+    full_screen_exclusive_lost: bool,
+    /// Swapchain recreation creates targets from it again, with the present preference changed
+    /// at runtime
+    config: EngineConfig,
+    swapchain_target: RenderTarget,
+    scene_pipelines: PipelineFamily<RenderMode>,
+    /// `Some` when the offscreen target has a depth pre-pass
+    depth_prepass_pipeline: Option<PistonPipeline>,
+    offscreen_target: OffscreenTarget,
+    descriptor_pool: DescriptorPool,
+    /// Holds only the composite set, which is allocated again whenever the composite pipeline,
+    /// and with it the set layout, is rebuilt
+    composite_descriptor_pool: DescriptorPool,
+    composite_descriptor_set: DescriptorSet,
+    composite_pipeline: PistonPipeline,
+    tonemap_mode: TonemapMode,
+    exposure: f32,
+    /// `None` when the device can't run the bloom passes
+    bloom: Option<Bloom>,
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    /// `None` when the device can't sample a depth map with comparisons
+    shadow_pass: Option<ShadowPass>,
+    light_direction: [f32; 3],
+    shadow_bias_constant: f32,
+    shadow_bias_slope: f32,
+    post_process: Option<PostProcessSubpass>,
+    post_effect: PostEffect,
+    scene_shader_language: ShaderLanguage,
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    demo_clear_color_index: usize,
+    render_mode: RenderMode,
+    show_normals: bool,
+    /// The right half of SDR output gets the other sRGB encoding, to check the swapchain format
+    /// against the shader's encode side by side
+    compare_encoding: bool,
+    normals_pipeline: Option<PistonPipeline>,
+    debug_pipelines: DebugPipelines,
+    debug_geometry: DebugGeometry,
+    tessellated_quad: Option<TessellatedQuad>,
+    transparent_pipeline: PistonPipeline,
+    transparent_quads: PistonBuffer,
+    /// `None` once the compute job that generated the quads is complete
+    quad_generation: Option<QuadGeneration>,
+    command_buffers: Vec<CommandBuffer>,
+    frame_sync: FrameSyncObjects,
+    current_frame: usize,
+    /// Frames submitted so far, the number of the frame being recorded
+    submitted_frames: u64,
+    breadcrumbs: Breadcrumbs,
+    screenshot_readback: Option<ScreenshotReadback>,
+    pending_screenshot: Option<PathBuf>,
+    /// `None` when picking is disabled or unsupported
+    picking_pass: Option<PickingPass>,
+    cursor_position: Option<PhysicalPosition<f64>>,
+    /// The picking target pixel of the latest click, picked by the next frame
+    pending_pick: Option<(u32, u32)>,
+    /// Whether the readback buffer of each frame in flight waits for a picked ID
+    picks_in_flight: [bool; MAX_FRAMES_IN_FLIGHT],
+    on_pick: Box<dyn FnMut(Option<u32>)>,
+    shader_watcher: Option<ShaderWatcher>,
+    /// Debug builds log the memory budget every `MEMORY_BUDGET_LOG_INTERVAL`
+    memory_budget_logged_at: Instant,
 }
 
 impl PistonApp {
-    fn create_with_window(window: &Window) -> Result<PistonApp> {
+    fn create_with_window(window: &Window, config: &EngineConfig) -> Result<PistonApp> {
         let entry = unsafe { Entry::load() }?;
-        let instance = create_instance(&entry, &VALIDATION)?;
+        let api_version = negotiate_api_version(&entry)?;
+        let validation = resolve_validation(&entry, &config.validation);
+        let message_filter = Arc::new(MessageFilter::new(&validation));
+        let instance = create_instance(
+            &entry,
+            api_version,
+            &validation,
+            &message_filter,
+            Some(window.raw_display_handle()),
+        )?;
         let surface_entities = create_surface(&entry, &instance, &window)?;
-        let physical_device = select_physical_device(&instance, &surface_entities)?;
-        let (device, queue_family_indices) =
-            create_logical_device(&instance, physical_device, &surface_entities)?;
+        report_physical_devices(&instance, Some(&surface_entities))?;
+        let selected_device = select_physical_device(
+            &instance,
+            api_version,
+            Some(&surface_entities),
+            &config.gpu_selection,
+        )?;
+        let dynamic_rendering =
+            config.dynamic_rendering && supports_dynamic_rendering(&instance, &selected_device);
+        if config.dynamic_rendering && !dynamic_rendering {
+            info!("Dynamic rendering is not supported by this device");
+        }
+        info!(
+            "Rendering with {}",
+            if dynamic_rendering {
+                "dynamic rendering"
+            } else {
+                "render pass objects"
+            }
+        );
+        let (device, queue_family_indices, capabilities) = create_logical_device(
+            &instance,
+            &selected_device,
+            &RequestedFeatures::engine(),
+            dynamic_rendering,
+        )?;
         let (debug_utils_loader, debug_messenger) =
-            create_debug_utils(&entry, &instance, &VALIDATION)?;
-        let graphics_queue = unsafe {
-            device.get_device_queue(queue_family_indices.graphics_family_index.unwrap(), 0)
-        };
-        let present_queue = unsafe {
-            device.get_device_queue(queue_family_indices.present_family_index.unwrap(), 0)
-        };
+            create_debug_utils(&entry, &instance, &validation, &message_filter)?.unzip();
+        let context = VulkanContext::new(
+            &instance,
+            selected_device.physical_device,
+            device,
+            queue_family_indices,
+            capabilities,
+            ContextOptions {
+                dynamic_rendering,
+                debug_utils: debug_utils_loader.clone(),
+                debug_printf: validation.debug_printf(),
+            },
+            config,
+        )?;
 
         let (swapchain_entities, swapchain_image_views) = create_swapchain(
             &instance,
-            &device,
-            physical_device,
+            &context,
+            &selected_device,
             &surface_entities,
-            &queue_family_indices,
             window,
+            config,
         )?;
 
-        let render_pass = create_render_pass(&device, swapchain_entities.swapchain_format)?;
+        let post_process = if config.post_process_subpass {
+            Some(PostProcessSubpass::new(
+                &context,
+                swapchain_entities.swapchain_extent,
+                swapchain_entities.swapchain_format,
+            )?)
+        } else {
+            None
+        };
+        if context.dynamic_rendering.is_some() && post_process.is_some() {
+            info!("The post-process subpass needs a render pass, the swapchain pass keeps one");
+        }
+        let swapchain_target = create_swapchain_target(
+            &context,
+            post_process.as_ref(),
+            swapchain_entities.swapchain_format,
+        )?;
 
-        let (pipeline, pipeline_layout) =
-            create_graphics_pipeline(&device, render_pass, swapchain_entities.swapchain_extent)?;
+        let offscreen_target = OffscreenTarget::new(
+            &context,
+            swapchain_entities.render_extent(),
+            SCENE_COLOR_FORMAT,
+            Some(select_depth_format(&context)?),
+            config.depth_prepass,
+        )?;
+        let scene_color_sampler = context
+            .get_or_create_sampler(&SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let bloom = Bloom::new(
+            &context,
+            offscreen_target.descriptor_image_info(scene_color_sampler),
+            offscreen_target.extent,
+            config.bloom_mip_count,
+        )
+        .map_err(|error| warn!("Bloom is disabled: {}", error))
+        .ok();
+        let scene_pipelines =
+            create_scene_pipelines(&context, &offscreen_target, config.scene_shader_language)?;
+        let depth_prepass_pipeline = create_depth_prepass_pipeline(
+            &context,
+            &offscreen_target,
+            config.scene_shader_language,
+        )?;
+        let debug_pipelines = DebugPipelines::new(&context, &offscreen_target.render_target)?;
+        let debug_geometry = create_debug_geometry(&context)?;
+        let tessellated_quad = TessellatedQuad::new(&context, &offscreen_target.render_target)
+            .map_err(|error| warn!("Tessellation demo is disabled: {}", error))
+            .ok();
+        let shadow_map = ShadowMap::new(&context, config.shadow_map_size, config.light_direction)
+            .map_err(|error| warn!("Shadows are disabled: {}", error))
+            .ok();
+        let transparent_pipeline = create_transparent_pipeline(
+            &context,
+            &offscreen_target.render_target,
+            shadow_map.is_some(),
+        )?;
+        let (transparent_quads, quad_generation) = create_transparent_quads(&context)?;
+        let composite_pipeline = create_composite_pipeline(
+            &context,
+            &swapchain_target,
+            config.tonemap_mode,
+            swapchain_entities.output_color_space,
+            swapchain_entities.swapchain_format,
+            swapchain_entities.pre_rotation_matrix(),
+        )?;
+
+        let descriptor_pool = create_descriptor_pool(
+            &context.device,
+            &[
+                DescriptorPoolSize {
+                    ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+                DescriptorPoolSize {
+                    ty: DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 2,
+                },
+            ],
+            2,
+        )?;
+        let composite_descriptor_pool = create_descriptor_pool(
+            &context.device,
+            &[DescriptorPoolSize {
+                ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2,
+            }],
+            1,
+        )?;
+        let shadow_pass = match shadow_map {
+            Some(shadow_map) => Some(create_shadow_pass(
+                &context,
+                shadow_map,
+                descriptor_pool,
+                &transparent_pipeline,
+            )?),
+            None => None,
+        };
+        let composite_descriptor_set = create_composite_descriptor_set(
+            &context,
+            composite_descriptor_pool,
+            &composite_pipeline,
+            offscreen_target.descriptor_image_info(scene_color_sampler),
+            bloom.as_ref(),
+        )?;
+
+        let command_buffers = allocate_command_buffers(
+            &context.device,
+            context.command_pool,
+            MAX_FRAMES_IN_FLIGHT as u32,
+        )?;
+        for (frame, &command_buffer) in command_buffers.iter().enumerate() {
+            context.set_object_name(command_buffer, &format!("frame {} command buffer", frame));
+        }
+        let frame_sync = FrameSyncObjects::new(
+            &context,
+            MAX_FRAMES_IN_FLIGHT,
+            swapchain_entities.swapchain_images.len(),
+        )?;
+        let breadcrumbs = Breadcrumbs::new(&context, MAX_FRAMES_IN_FLIGHT)?;
+
+        let screenshot_readback = if swapchain_entities
+            .swapchain_image_usage
+            .contains(ScreenshotReadback::SWAPCHAIN_USAGE)
+        {
+            ScreenshotReadback::new(
+                &context,
+                swapchain_entities.swapchain_format,
+                swapchain_entities.swapchain_extent,
+            )
+            .map_err(|error| warn!("Screenshots are disabled: {}", error))
+            .ok()
+        } else {
+            warn!("Screenshots are disabled, the swapchain images can't be copied from");
+            None
+        };
+        let picking_pass = if config.picking {
+            create_picking_pass(
+                &context,
+                swapchain_entities.render_extent(),
+                select_depth_format(&context)?,
+            )
+            .map_err(|error| warn!("Picking is disabled: {}", error))
+            .ok()
+        } else {
+            None
+        };
+        context.retire_unused_shaders()?;
 
         Ok(PistonApp {
             _entry: entry,
             instance,
-            _physical_device: physical_device,
-            device,
-            _graphics_queue: graphics_queue,
-            _present_queue: present_queue,
+            context,
             surface_entities,
             debug_utils_loader,
             debug_messenger,
+            message_filter,
             swapchain_loader: swapchain_entities.swapchain_loader,
             swapchain: swapchain_entities.swapchain,
-            _swapchain_format: swapchain_entities.swapchain_format,
-            _swapchain_images: swapchain_entities.swapchain_images,
-            _swapchain_extent: swapchain_entities.swapchain_extent,
+            swapchain_format: swapchain_entities.swapchain_format,
+            swapchain_images: swapchain_entities.swapchain_images,
+            swapchain_extent: swapchain_entities.swapchain_extent,
+            pre_transform: swapchain_entities.pre_transform,
             swapchain_image_views,
-            render_pass,
-            pipeline_layout,
-            pipeline,
+            present_mode: swapchain_entities.present_mode,
+            output_color_space: swapchain_entities.output_color_space,
+            swapchain_stale: false,
+            full_screen_exclusive_acquired: false,
+            full_screen_exclusive_lost: false,
+            config: config.clone(),
+            swapchain_target,
+            scene_pipelines,
+            depth_prepass_pipeline,
+            offscreen_target,
+            descriptor_pool,
+            composite_descriptor_pool,
+            composite_descriptor_set,
+            composite_pipeline,
+            tonemap_mode: config.tonemap_mode,
+            exposure: config.exposure,
+            bloom,
+            bloom_intensity: config.bloom_intensity,
+            bloom_threshold: config.bloom_threshold,
+            shadow_pass,
+            light_direction: config.light_direction,
+            shadow_bias_constant: config.shadow_bias_constant,
+            shadow_bias_slope: config.shadow_bias_slope,
+            post_process,
+            post_effect: config.post_effect,
+            scene_shader_language: config.scene_shader_language,
+            clear_color: config.clear_color,
+            clear_depth: config.clear_depth,
+            demo_clear_color_index: 0,
+            render_mode: RenderMode::Fill,
+            show_normals: false,
+            compare_encoding: false,
+            normals_pipeline: None,
+            debug_pipelines,
+            debug_geometry,
+            tessellated_quad,
+            transparent_pipeline,
+            transparent_quads,
+            quad_generation: Some(quad_generation),
+            command_buffers,
+            frame_sync,
+            current_frame: 0,
+            submitted_frames: 0,
+            breadcrumbs,
+            screenshot_readback,
+            pending_screenshot: None,
+            picking_pass,
+            cursor_position: None,
+            pending_pick: None,
+            picks_in_flight: [false; MAX_FRAMES_IN_FLIGHT],
+            on_pick: Box::new(|_| {}),
+            shader_watcher: ShaderWatcher::new(&watched_shader_dir(&config.shader_dir))
+                .map_err(|error| warn!("Shader hot reload is disabled: {}", error))
+                .ok(),
+            memory_budget_logged_at: Instant::now(),
         })
     }
 
-    fn init_window(event_loop: &EventLoop<()>) -> Window {
+    /// A context without window or surface, for compute-only tools and tests.
+    fn create_headless(config: &EngineConfig) -> Result<HeadlessContext> {
+        HeadlessContext::new(config)
+    }
+
+    fn init_window(event_loop: &EventLoop<()>, config: &EngineConfig) -> Window {
         WindowBuilder::new()
             .with_title(WINDOW_TITLE)
+            .with_transparent(config.window_transparency)
             .with_inner_size(LogicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT))
             .build(&event_loop)
             .unwrap()
     }
 
-    fn draw_frame(&self) {}
+    fn draw_frame(&mut self) -> Result<()> {
+        self.context.poll_async_uploads()?;
+        self.retire_quad_generation()?;
+        if cfg!(debug_assertions)
+            && self.memory_budget_logged_at.elapsed() >= MEMORY_BUDGET_LOG_INTERVAL
+        {
+            log_memory_budget(&self.context.memory_budget());
+            self.memory_budget_logged_at = Instant::now();
+        }
+        self.reload_changed_shaders()?;
+        self.ensure_normals_pipeline();
+
+        let _frame_span = info_span!("frame", frame_number = self.submitted_frames).entered();
+        let device = &self.context.device;
+        let in_flight_fence = self.frame_sync.in_flight_fences[self.current_frame];
+        let image_available_semaphore =
+            self.frame_sync.image_available_semaphores[self.current_frame];
+        let render_finished_semaphore =
+            self.frame_sync.render_finished_semaphores[self.current_frame];
+
+        unsafe { device.wait_for_fences(&[in_flight_fence], true, u64::MAX) }?;
+        self.poll_picks()?;
+        let device = &self.context.device;
+        let image_index = match info_span!("acquire").in_scope(|| unsafe {
+            self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                image_available_semaphore,
+                Fence::null(),
+            )
+        }) {
+            Ok((image_index, suboptimal)) => {
+                if suboptimal {
+                    self.invalidate_surface()?;
+                    self.swapchain_stale = true;
+                }
+                image_index
+            }
+            // The fence is still signaled, so the frame can be retried after the recreation
+            Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.invalidate_surface()?;
+                self.swapchain_stale = true;
+                return Ok(());
+            }
+            Err(ash::vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.lose_full_screen_exclusive();
+                return Ok(());
+            }
+            Err(error) => return Err(error.into()),
+        };
+        self.frame_sync
+            .wait_for_image(device, image_index as usize, in_flight_fence)?;
+        unsafe { device.reset_fences(&[in_flight_fence]) }?;
+
+        let command_buffer = self.command_buffers[self.current_frame];
+        let screenshot_path = self.pending_screenshot.take();
+        let pick_pixel = self.pending_pick.take();
+        info_span!("record", image_index).in_scope(|| {
+            self.record_command_buffer(
+                command_buffer,
+                image_index as usize,
+                screenshot_path.is_some(),
+                pick_pixel,
+            )
+        })?;
+
+        let frame_number = self.submitted_frames;
+        let submit_span = info_span!("submit").entered();
+        let pending_quad_generation = self.pending_quad_generation();
+        let (quad_semaphores, quad_wait_stages) = match pending_quad_generation {
+            Some(quad_generation) => (
+                vec![quad_generation.submission.semaphore],
+                vec![quad_generation.wait_stage(&self.context)],
+            ),
+            None => (vec![], vec![]),
+        };
+        self.context
+            .submission(
+                self.context.graphics_queue,
+                &format!("frame {} graphics submit", frame_number),
+            )
+            .command_buffer(command_buffer)
+            .wait(
+                image_available_semaphore,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .waits(&quad_semaphores, &quad_wait_stages)
+            .signal(render_finished_semaphore)
+            .fence(in_flight_fence)
+            .submit()?;
+        if let Some(quad_generation) = &mut self.quad_generation {
+            quad_generation.awaited = true;
+        }
+        submit_span.exit();
+        self.submitted_frames += 1;
+        self.picks_in_flight[self.current_frame] = pick_pixel.is_some();
+
+        let signal_semaphores = [render_finished_semaphore];
+        let swapchains = [self.swapchain];
+        let image_indices = [image_index];
+        let present_info = PresentInfoKHR::builder()
+            .wait_semaphores(&signal_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices)
+            .build();
+        let present_queue = self
+            .context
+            .present_queue
+            .ok_or_else(|| anyhow!("The context has no present queue"))?;
+        let present_span = info_span!("present", image_index).entered();
+        let debug_utils = self.context.debug_utils.as_ref();
+        queue_begin_label(
+            debug_utils,
+            present_queue,
+            &format!("frame {} present", frame_number),
+            QUEUE_LABEL_COLOR,
+        );
+        let present_result = unsafe {
+            self.swapchain_loader
+                .queue_present(present_queue, &present_info)
+        };
+        queue_end_label(debug_utils, present_queue);
+        present_span.exit();
+        match present_result {
+            Ok(false) => {}
+            Ok(true) | Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.invalidate_surface()?;
+                self.swapchain_stale = true;
+            }
+            Err(ash::vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.lose_full_screen_exclusive()
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        if let Some(path) = screenshot_path {
+            self.capture_screenshot(&path)?;
+        }
+
+        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        Ok(())
+    }
+
+    /// The surface capabilities changed with the window, they are queried again when the
+    /// swapchain is recreated.
+    fn invalidate_surface(&self) -> Result<()> {
+        self.context
+            .invalidate_surface_capabilities(self.surface_entities.surface)
+    }
+
+    /// Switches the present mode from the next frame on, by recreating the swapchain with the
+    /// same extent.
+    pub fn set_present_preference(&mut self, present_preference: PresentPreference) {
+        self.config.present_preference = present_preference;
+        self.swapchain_stale = true;
+        info!("Present preference is now {:?}", present_preference);
+    }
+
+    fn cycle_present_preference(&mut self) {
+        self.set_present_preference(match self.config.present_preference {
+            PresentPreference::VsyncOn => PresentPreference::VsyncOff,
+            PresentPreference::VsyncOff => PresentPreference::LowLatency,
+            PresentPreference::LowLatency => PresentPreference::AdaptiveVsync,
+            PresentPreference::AdaptiveVsync => PresentPreference::VsyncOn,
+        });
+    }
+
+    /// Replaces the swapchain, after a resize, an out of date or suboptimal swapchain or a
+    /// present preference change. Waits for the frames in flight, so the old swapchain and every
+    /// target sized like it can be destroyed right away.
+    fn recreate_swapchain(
+        &mut self,
+        window_size: PhysicalSize<u32>,
+        fullscreen: bool,
+    ) -> Result<()> {
+        unsafe { self.context.device.device_wait_idle() }?;
+        self.release_full_screen_exclusive();
+        let recreate = |app: &Self, old_swapchain| {
+            recreate_swapchain(
+                &app.context,
+                &app.swapchain_loader,
+                &app.surface_entities,
+                window_size,
+                &app.config,
+                old_swapchain,
+                fullscreen && !app.full_screen_exclusive_lost,
+            )
+        };
+        let (swapchain_entities, swapchain_image_views) = match recreate(self, self.swapchain) {
+            Ok(swapchain) => swapchain,
+            Err(error) => {
+                warn!(
+                    "Failed to recreate the swapchain, retrying in {:?}: {:?}",
+                    SWAPCHAIN_RETRY_DELAY, error
+                );
+                // A failed creation may still have retired the old swapchain, which can't be
+                // passed again. Nothing is in flight, so it's destroyed and the retry starts over.
+                self.destroy_swapchain();
+                thread::sleep(SWAPCHAIN_RETRY_DELAY);
+                self.context
+                    .refresh_surface_capabilities(&self.surface_entities)?;
+                recreate(self, SwapchainKHR::null())?
+            }
+        };
+
+        self.destroy_swapchain();
+        let extent_changed = swapchain_entities.swapchain_extent != self.swapchain_extent;
+        // The swapchain pass, the post-process subpass, the screenshot readback and the composite
+        // pipeline are built for the format
+        let format_changed = swapchain_entities.swapchain_format != self.swapchain_format
+            || swapchain_entities.output_color_space != self.output_color_space;
+        if format_changed {
+            info!(
+                "The swapchain changed from {:?} in {:?} to {:?} in {:?}",
+                self.swapchain_format,
+                self.output_color_space,
+                swapchain_entities.swapchain_format,
+                swapchain_entities.output_color_space
+            );
+        }
+        let render_extent = swapchain_entities.render_extent();
+        self.swapchain = swapchain_entities.swapchain;
+        self.swapchain_format = swapchain_entities.swapchain_format;
+        self.output_color_space = swapchain_entities.output_color_space;
+        self.swapchain_images = swapchain_entities.swapchain_images;
+        self.swapchain_extent = swapchain_entities.swapchain_extent;
+        self.swapchain_image_views = swapchain_image_views;
+        self.frame_sync
+            .reset_images_in_flight(self.swapchain_images.len());
+        self.present_mode = swapchain_entities.present_mode;
+        if swapchain_entities.full_screen_exclusive {
+            self.acquire_full_screen_exclusive();
+        }
+        if extent_changed || format_changed {
+            self.recreate_sized_targets(render_extent)?;
+            // A post-process subpass was created again with its render pass
+            self.swapchain_target = create_swapchain_target(
+                &self.context,
+                self.post_process.as_ref(),
+                self.swapchain_format,
+            )?;
+        }
+        if format_changed || swapchain_entities.pre_transform != self.pre_transform {
+            self.pre_transform = swapchain_entities.pre_transform;
+            let composite_pipeline = create_composite_pipeline(
+                &self.context,
+                &self.swapchain_target,
+                self.tonemap_mode,
+                self.output_color_space,
+                self.swapchain_format,
+                pre_rotation_matrix(self.pre_transform),
+            )?;
+            self.composite_pipeline.destroy(&self.context.device);
+            self.composite_pipeline = composite_pipeline;
+            // The old set was allocated against the set layout destroyed with the pipeline
+            self.rewrite_composite_descriptor_set()?;
+        }
+        self.context.invalidate_framebuffers()?;
+        self.swapchain_stale = false;
+        info!(
+            "Recreated the swapchain, {}x{} presenting with {:?}",
+            self.swapchain_extent.width, self.swapchain_extent.height, self.present_mode
+        );
+
+        Ok(())
+    }
+
+    /// Destroys the swapchain and its image views, leaving a null swapchain. The GPU must be done
+    /// with them.
+    fn destroy_swapchain(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            for image_view in self.swapchain_image_views.drain(..) {
+                device.destroy_image_view(image_view, None);
+            }
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+        }
+        self.swapchain = SwapchainKHR::null();
+    }
+
+    /// Another application took the display, the swapchain is recreated for borderless
+    /// fullscreen.
+    fn lose_full_screen_exclusive(&mut self) {
+        warn!("Lost exclusive fullscreen, falling back to borderless");
+        self.full_screen_exclusive_acquired = false;
+        self.full_screen_exclusive_lost = true;
+        self.swapchain_stale = true;
+    }
+
+    /// Without the display, the swapchain presents like a borderless window.
+    fn acquire_full_screen_exclusive(&mut self) {
+        let full_screen_exclusive = match &self.context.full_screen_exclusive {
+            Some(full_screen_exclusive) => full_screen_exclusive,
+            None => return,
+        };
+        match acquire_full_screen_exclusive_mode(
+            &self.context.device,
+            full_screen_exclusive,
+            self.swapchain,
+        ) {
+            Ok(()) => {
+                info!("Acquired exclusive fullscreen");
+                self.full_screen_exclusive_acquired = true;
+            }
+            Err(error) => {
+                warn!(
+                    "Exclusive fullscreen is unavailable, staying borderless: {}",
+                    error
+                );
+                self.full_screen_exclusive_lost = true;
+            }
+        }
+    }
+
+    fn release_full_screen_exclusive(&mut self) {
+        let full_screen_exclusive = match &self.context.full_screen_exclusive {
+            Some(full_screen_exclusive) if self.full_screen_exclusive_acquired => {
+                full_screen_exclusive
+            }
+            _ => return,
+        };
+        if let Err(error) = release_full_screen_exclusive_mode(
+            &self.context.device,
+            full_screen_exclusive,
+            self.swapchain,
+        ) {
+            warn!("Failed to release exclusive fullscreen: {}", error);
+        }
+        self.full_screen_exclusive_acquired = false;
+    }
+
+    /// Switches between a window and borderless fullscreen, which is exclusive when the device
+    /// supports it.
+    fn toggle_fullscreen(&mut self, window: &Window) {
+        match window.fullscreen() {
+            Some(_) => {
+                window.set_fullscreen(None);
+                self.full_screen_exclusive_lost = false;
+            }
+            None => window.set_fullscreen(Some(Fullscreen::Borderless(None))),
+        }
+        if let Err(error) = self.invalidate_surface() {
+            warn!("Failed to invalidate the surface capabilities: {:?}", error);
+        }
+        self.swapchain_stale = true;
+    }
+
+    /// Recreates the targets sized like the swapchain. The offscreen render passes come from the
+    /// cache and stay the same, so the pipelines drawing into them are kept.
+    fn recreate_sized_targets(&mut self, render_extent: Extent2D) -> Result<()> {
+        let context = &self.context;
+        let device = &context.device;
+        let offscreen_target = OffscreenTarget::new(
+            context,
+            render_extent,
+            SCENE_COLOR_FORMAT,
+            Some(select_depth_format(context)?),
+            self.config.depth_prepass,
+        )?;
+        self.offscreen_target.destroy(device);
+        self.offscreen_target = offscreen_target;
+
+        let scene_color_sampler = context
+            .get_or_create_sampler(&SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE))?;
+        if let Some(bloom) = self.bloom.take() {
+            bloom.destroy(device);
+            self.bloom = Bloom::new(
+                context,
+                self.offscreen_target
+                    .descriptor_image_info(scene_color_sampler),
+                self.offscreen_target.extent,
+                self.config.bloom_mip_count,
+            )
+            .map_err(|error| warn!("Bloom is disabled: {}", error))
+            .ok();
+        }
+
+        if let Some(post_process) = &mut self.post_process {
+            let new_post_process =
+                PostProcessSubpass::new(context, self.swapchain_extent, self.swapchain_format)?;
+            post_process.destroy(device);
+            *post_process = new_post_process;
+        }
+        if let Some(picking_pass) = &mut self.picking_pass {
+            let target = PickingTarget::new(
+                context,
+                render_extent,
+                select_depth_format(context)?,
+                MAX_FRAMES_IN_FLIGHT,
+            )?;
+            picking_pass.target.destroy(device);
+            picking_pass.target = target;
+            // The readback buffers are new, the picks in flight are lost
+            self.pending_pick = None;
+            self.picks_in_flight = [false; MAX_FRAMES_IN_FLIGHT];
+        }
+        if let Some(screenshot_readback) = self.screenshot_readback.take() {
+            screenshot_readback.destroy(device);
+            self.screenshot_readback =
+                ScreenshotReadback::new(context, self.swapchain_format, self.swapchain_extent)
+                    .map_err(|error| warn!("Screenshots are disabled: {}", error))
+                    .ok();
+        }
+
+        self.rewrite_composite_descriptor_set()
+    }
+
+    /// Allocates the composite set against the layout of the current composite pipeline and points
+    /// it at the scene color and bloom. The GPU must be done with the previous set.
+    fn rewrite_composite_descriptor_set(&mut self) -> Result<()> {
+        let scene_color_sampler = self
+            .context
+            .get_or_create_sampler(&SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE))?;
+        self.composite_descriptor_set = create_composite_descriptor_set(
+            &self.context,
+            self.composite_descriptor_pool,
+            &self.composite_pipeline,
+            self.offscreen_target
+                .descriptor_image_info(scene_color_sampler),
+            self.bloom.as_ref(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines when the watcher saw shader changes. If either pipeline fails to
+    /// build, the previous pipelines stay in use.
+    fn reload_changed_shaders(&mut self) -> Result<()> {
+        let changed_files = match &self.shader_watcher {
+            Some(shader_watcher) => shader_watcher.changed_files(),
+            None => return Ok(()),
+        };
+        if changed_files.is_empty() {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let device = &self.context.device;
+        unsafe { device.device_wait_idle() }?;
+
+        let scene_pipelines = create_scene_pipelines(
+            &self.context,
+            &self.offscreen_target,
+            self.scene_shader_language,
+        );
+        let depth_prepass_pipeline = create_depth_prepass_pipeline(
+            &self.context,
+            &self.offscreen_target,
+            self.scene_shader_language,
+        );
+        let composite_pipeline = create_composite_pipeline(
+            &self.context,
+            &self.swapchain_target,
+            self.tonemap_mode,
+            self.output_color_space,
+            self.swapchain_format,
+            pre_rotation_matrix(self.pre_transform),
+        );
+        let transparent_pipeline = create_transparent_pipeline(
+            &self.context,
+            &self.offscreen_target.render_target,
+            self.shadow_pass.is_some(),
+        );
+        let debug_pipelines =
+            DebugPipelines::new(&self.context, &self.offscreen_target.render_target);
+        let changed_file_names = changed_files
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|file_name| file_name.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match (
+            scene_pipelines,
+            depth_prepass_pipeline,
+            composite_pipeline,
+            transparent_pipeline,
+            debug_pipelines,
+        ) {
+            (
+                Ok(scene_pipelines),
+                Ok(depth_prepass_pipeline),
+                Ok(composite_pipeline),
+                Ok(transparent_pipeline),
+                Ok(debug_pipelines),
+            ) => {
+                self.scene_pipelines.destroy(device);
+                if let Some(depth_prepass_pipeline) = &self.depth_prepass_pipeline {
+                    depth_prepass_pipeline.destroy(device);
+                }
+                self.composite_pipeline.destroy(device);
+                self.transparent_pipeline.destroy(device);
+                self.debug_pipelines.destroy(device);
+                self.scene_pipelines = scene_pipelines;
+                self.depth_prepass_pipeline = depth_prepass_pipeline;
+                self.composite_pipeline = composite_pipeline;
+                self.transparent_pipeline = transparent_pipeline;
+                self.debug_pipelines = debug_pipelines;
+                // Rebuilt from the new shaders the next time normals are drawn
+                if let Some(normals_pipeline) = self.normals_pipeline.take() {
+                    normals_pipeline.destroy(device);
+                }
+                // The old set was allocated against the set layout destroyed with the pipeline
+                self.rewrite_composite_descriptor_set()?;
+
+                info!(
+                    "Reloaded {} in {} ms",
+                    changed_file_names,
+                    started.elapsed().as_millis()
+                );
+            }
+            (
+                scene_pipelines,
+                depth_prepass_pipeline,
+                composite_pipeline,
+                transparent_pipeline,
+                debug_pipelines,
+            ) => {
+                let mut errors = vec![];
+                match scene_pipelines {
+                    Ok(scene_pipelines) => scene_pipelines.destroy(device),
+                    Err(error) => errors.push(error),
+                }
+                match depth_prepass_pipeline {
+                    Ok(Some(depth_prepass_pipeline)) => depth_prepass_pipeline.destroy(device),
+                    Ok(None) => {}
+                    Err(error) => errors.push(error),
+                }
+                for result in [composite_pipeline, transparent_pipeline] {
+                    match result {
+                        Ok(pipeline) => pipeline.destroy(device),
+                        Err(error) => errors.push(error),
+                    }
+                }
+                match debug_pipelines {
+                    Ok(debug_pipelines) => debug_pipelines.destroy(device),
+                    Err(error) => errors.push(error),
+                }
+                for error in errors {
+                    error!(
+                        "Failed to reload {}, keeping the previous pipelines: {:?}",
+                        changed_file_names, error
+                    );
+                }
+            }
+        }
+
+        // Either the old or the new modules lost their last pipeline
+        self.context.retire_unused_shaders()
+    }
+
+    fn toggle_wireframe(&mut self) {
+        self.render_mode = match self.render_mode {
+            RenderMode::Fill
+                if !self
+                    .context
+                    .capabilities
+                    .is_enabled(DeviceFeature::FillModeNonSolid) =>
+            {
+                warn!("Wireframe rendering is not supported by this device, staying in fill mode");
+                RenderMode::Fill
+            }
+            RenderMode::Fill => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Fill,
+        };
+        info!("Render mode is now {:?}", self.render_mode);
+    }
+
+    /// Sets the scene background from the next frame on, in linear RGBA.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+
+    fn cycle_clear_color(&mut self) {
+        self.demo_clear_color_index = (self.demo_clear_color_index + 1) % DEMO_CLEAR_COLORS.len();
+        let clear_color = DEMO_CLEAR_COLORS[self.demo_clear_color_index];
+        info!("Clear color is now {:?} (sRGB)", clear_color);
+        self.set_clear_color(srgb_to_linear(clear_color));
+    }
+
+    /// The operator is a specialization constant, so the composite pipeline is rebuilt.
+    fn cycle_tonemap_mode(&mut self) -> Result<()> {
+        let tonemap_mode = match self.tonemap_mode {
+            TonemapMode::None => TonemapMode::Reinhard,
+            TonemapMode::Reinhard => TonemapMode::Aces,
+            TonemapMode::Aces => TonemapMode::None,
+        };
+        let composite_pipeline = create_composite_pipeline(
+            &self.context,
+            &self.swapchain_target,
+            tonemap_mode,
+            self.output_color_space,
+            self.swapchain_format,
+            pre_rotation_matrix(self.pre_transform),
+        )?;
+
+        let device = &self.context.device;
+        unsafe { device.device_wait_idle() }?;
+        self.composite_pipeline.destroy(device);
+        self.composite_pipeline = composite_pipeline;
+        // The old set was allocated against the set layout destroyed with the pipeline
+        self.rewrite_composite_descriptor_set()?;
+        self.tonemap_mode = tonemap_mode;
+        info!("Tonemap mode is now {:?}", self.tonemap_mode);
+
+        Ok(())
+    }
+
+    fn adjust_exposure(&mut self, delta: f32) {
+        self.exposure += delta;
+        info!("Exposure is now {:+.1} stops", self.exposure);
+    }
+
+    fn adjust_bloom_intensity(&mut self, delta: f32) {
+        self.bloom_intensity = (self.bloom_intensity + delta).max(0.0);
+        info!("Bloom intensity is now {:.2}", self.bloom_intensity);
+    }
+
+    fn adjust_bloom_threshold(&mut self, delta: f32) {
+        self.bloom_threshold = (self.bloom_threshold + delta).max(0.0);
+        info!("Bloom threshold is now {:.2}", self.bloom_threshold);
+    }
+
+    fn adjust_shadow_bias(&mut self, delta: f32) {
+        self.shadow_bias_constant = (self.shadow_bias_constant + delta).max(0.0);
+        self.shadow_bias_slope = (self.shadow_bias_slope + delta).max(0.0);
+        info!(
+            "Shadow bias is now {:.2} constant, {:.2} slope",
+            self.shadow_bias_constant, self.shadow_bias_slope
+        );
+    }
+
+    /// Turns the light around the Z axis. Frames in flight read the light buffer, so this waits
+    /// for the device.
+    fn rotate_light(&mut self, angle: f32) -> Result<()> {
+        let shadow_pass = match &self.shadow_pass {
+            Some(shadow_pass) => shadow_pass,
+            None => {
+                warn!("Shadows are disabled, the light has nothing to rotate");
+                return Ok(());
+            }
+        };
+        let [x, y, z] = self.light_direction;
+        let (sin, cos) = angle.sin_cos();
+        let light_direction = [x * cos - y * sin, x * sin + y * cos, z];
+
+        let device = &self.context.device;
+        unsafe { device.device_wait_idle() }?;
+        shadow_pass
+            .shadow_map
+            .set_light_direction(device, light_direction)?;
+        self.light_direction = light_direction;
+        info!("Light direction is now {:.2?}", self.light_direction);
+
+        Ok(())
+    }
+
+    fn cycle_post_effect(&mut self) {
+        if self.post_process.is_none() {
+            warn!(
+                "The post-process subpass is disabled, set {}=1 to enable it",
+                POST_PROCESS_SUBPASS_ENV_VAR
+            );
+            return;
+        }
+        self.post_effect = match self.post_effect {
+            PostEffect::None => PostEffect::Grayscale,
+            PostEffect::Grayscale => PostEffect::Vignette,
+            PostEffect::Vignette => PostEffect::None,
+        };
+        info!("Post effect is now {:?}", self.post_effect);
+    }
+
+    fn toggle_encoding_comparison(&mut self) {
+        if self.output_color_space != OutputColorSpace::SdrSrgb {
+            warn!("The encoding comparison only applies to SDR output");
+            return;
+        }
+        self.compare_encoding = !self.compare_encoding;
+        info!("Encoding comparison is now {}", self.compare_encoding);
+    }
+
+    fn toggle_normals(&mut self) {
+        if !self.show_normals
+            && !self
+                .context
+                .capabilities
+                .is_enabled(DeviceFeature::GeometryShader)
+        {
+            warn!("Geometry shaders are not supported by this device, normals stay hidden");
+            return;
+        }
+        self.show_normals = !self.show_normals;
+        info!("Normal visualization is now {}", self.show_normals);
+    }
+
+    /// Creates the normal visualization pipeline, which adds a geometry stage to the scene
+    /// pipeline, the first time normals are drawn. Hides normals if the pipeline cannot be built.
+    fn ensure_normals_pipeline(&mut self) {
+        if !self.show_normals || self.normals_pipeline.is_some() {
+            return;
+        }
+
+        match create_normals_pipeline(
+            &self.context,
+            &self.offscreen_target.render_target,
+            self.scene_shader_language,
+        ) {
+            Ok(normals_pipeline) => self.normals_pipeline = Some(normals_pipeline),
+            Err(error) => {
+                error!("Failed to create normals pipeline: {:?}", error);
+                self.show_normals = false;
+            }
+        }
+    }
+
+    fn scene_pipeline(&self) -> Pipeline {
+        self.scene_pipelines
+            .pipeline(self.render_mode)
+            .unwrap_or_else(|| self.scene_pipelines.base())
+    }
+
+    /// The demo scene: the opaque triangle and two overlapping transparent quads, submitted
+    /// front to back so the sort has something to do.
+    fn scene_draw_list(&self) -> DrawList {
+        let mut draw_list = DrawList::new();
+        draw_list.push(DrawItem {
+            pipeline: self.scene_pipeline(),
+            blend_mode: BlendMode::Opaque,
+            vertex_buffer: Buffer::null(),
+            first_vertex: 0,
+            vertex_count: 3,
+            view_depth: None,
+        });
+        if let (true, Some(normals_pipeline)) = (self.show_normals, &self.normals_pipeline) {
+            draw_list.push(DrawItem {
+                pipeline: normals_pipeline.pipeline,
+                blend_mode: BlendMode::Opaque,
+                vertex_buffer: Buffer::null(),
+                first_vertex: 0,
+                vertex_count: 3,
+                view_depth: None,
+            });
+        }
+        for (quad_index, &(_, _, view_depth, _)) in TRANSPARENT_QUADS.iter().enumerate() {
+            draw_list.push(DrawItem {
+                pipeline: self.transparent_pipeline.pipeline,
+                blend_mode: BlendMode::AlphaBlend,
+                vertex_buffer: self.transparent_quads.buffer,
+                first_vertex: quad_index as u32 * 6,
+                vertex_count: 6,
+                view_depth: Some(view_depth),
+            });
+        }
+        draw_list.sort_transparent();
+
+        draw_list
+    }
+
+    /// The quad generation the next graphics submit has to wait on, if it's still pending.
+    fn pending_quad_generation(&self) -> Option<&QuadGeneration> {
+        self.quad_generation
+            .as_ref()
+            .filter(|quad_generation| !quad_generation.awaited)
+    }
+
+    /// Destroys the quad generation once a frame has waited on it and it's complete.
+    fn retire_quad_generation(&mut self) -> Result<()> {
+        let retired = match &self.quad_generation {
+            Some(quad_generation) => {
+                quad_generation.awaited
+                    && quad_generation
+                        .submission
+                        .is_complete(&self.context.device)?
+            }
+            None => false,
+        };
+        if retired {
+            if let Some(quad_generation) = self.quad_generation.take() {
+                quad_generation.destroy(&self.context);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A graph with the swapchain image imported, which it leaves ready to present.
+    fn swapchain_graph(&self, image_index: usize, initial_layout: ImageLayout) -> RenderGraph<'_> {
+        let mut graph = RenderGraph::new();
+        graph.import_image(
+            SWAPCHAIN_GRAPH_IMAGE,
+            GraphImage {
+                image: self.swapchain_images[image_index],
+                view: self.swapchain_image_views[image_index],
+                format: self.swapchain_format,
+                extent: self.swapchain_extent,
+                subresource_range: color_subresource_range(),
+            },
+            initial_layout,
+            Some(ImageLayout::PRESENT_SRC_KHR),
+        );
+        graph
+    }
+
+    fn record_command_buffer(
+        &self,
+        command_buffer: CommandBuffer,
+        image_index: usize,
+        copy_for_screenshot: bool,
+        pick_pixel: Option<(u32, u32)>,
+    ) -> Result<()> {
+        let device = &self.context.device;
+        unsafe {
+            device.reset_command_buffer(command_buffer, CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(command_buffer, &CommandBufferBeginInfo::default())?;
+        }
+        if let Some(quad_generation) = self.pending_quad_generation() {
+            quad_generation.record_acquire(&self.context, command_buffer, &self.transparent_quads);
+        }
+        // The label of each pass ends when its guard is dropped at the end of the block
+        let begin_pass = |pass: FramePass| {
+            self.breadcrumbs.record(
+                device,
+                command_buffer,
+                self.current_frame,
+                self.submitted_frames,
+                pass,
+            );
+            self.context
+                .begin_label(command_buffer, pass.label(), pass.label_color())
+        };
+
+        if let Some(shadow_pass) = &self.shadow_pass {
+            let _label = begin_pass(FramePass::Shadow);
+            shadow_pass.shadow_map.record_pass(
+                &self.context,
+                command_buffer,
+                (self.shadow_bias_constant, self.shadow_bias_slope),
+                |device, command_buffer| unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        shadow_pass.pipeline.pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        shadow_pass.pipeline.pipeline_layout,
+                        0,
+                        &[shadow_pass.caster_descriptor_set],
+                        &[],
+                    );
+                    device.cmd_bind_vertex_buffers(
+                        command_buffer,
+                        0,
+                        &[self.transparent_quads.buffer],
+                        &[0],
+                    );
+                    device.cmd_draw(command_buffer, TRANSPARENT_QUADS.len() as u32 * 6, 1, 0, 0);
+                },
+            )?;
+        }
+
+        if let (Some(picking_pass), Some(pick_pixel)) = (&self.picking_pass, pick_pixel) {
+            let _label = begin_pass(FramePass::Picking);
+            picking_pass.target.record_pick(
+                &self.context,
+                command_buffer,
+                self.current_frame,
+                pick_pixel,
+                |device, command_buffer| {
+                    self.record_pickable_objects(device, command_buffer, picking_pass)
+                },
+            )?;
+        }
+
+        if let Some(depth_prepass_pipeline) = &self.depth_prepass_pipeline {
+            let _label = begin_pass(FramePass::DepthPrepass);
+            // Only the triangle is opaque scene geometry, the debug draws and the transparent
+            // quads are left to the scene pass
+            self.offscreen_target.record_depth_prepass(
+                &self.context,
+                command_buffer,
+                self.clear_depth,
+                |device, command_buffer| unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        depth_prepass_pipeline.pipeline,
+                    );
+                    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                },
+            )?;
+        }
+
+        let scene_label = begin_pass(FramePass::Scene);
+        self.offscreen_target.record_pass(
+            &self.context,
+            command_buffer,
+            self.clear_color,
+            self.clear_depth,
+            |device, command_buffer| {
+                // Stays bound while the draw list switches pipelines, only the transparent
+                // quads read it
+                if let Some(shadow_pass) = &self.shadow_pass {
+                    unsafe {
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            PipelineBindPoint::GRAPHICS,
+                            self.transparent_pipeline.pipeline_layout,
+                            0,
+                            &[shadow_pass.receiver_descriptor_set],
+                            &[],
+                        )
+                    };
+                }
+                self.scene_draw_list().record(device, command_buffer);
+                {
+                    let _label = self.context.begin_label(
+                        command_buffer,
+                        "Debug geometry",
+                        FramePass::Scene.label_color(),
+                    );
+                    self.debug_geometry.record(
+                        device,
+                        command_buffer,
+                        &self.debug_pipelines,
+                        DEBUG_LINE_WIDTH,
+                    );
+                }
+                if let Some(tessellated_quad) = &self.tessellated_quad {
+                    tessellated_quad.record(device, command_buffer);
+                }
+            },
+        )?;
+        drop(scene_label);
+        if let Some(bloom) = &self.bloom {
+            let _label = begin_pass(FramePass::Bloom);
+            bloom.record(&self.context, command_buffer, self.bloom_threshold);
+        }
 
-    fn main_loop(self, event_loop: EventLoop<()>, window: Window) -> Result<()> {
-        let redraw_requested = false;
+        // The second value clears the intermediate attachment of the post-process subpass
+        let clear_values = [ClearValue {
+            color: ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        }; 2];
+        let composite_label = begin_pass(FramePass::Composite);
+        let bloom_intensity = match self.bloom {
+            Some(_) => self.bloom_intensity,
+            None => 0.0,
+        };
+        let mut composite_push_constants = self.exposure.exp2().to_ne_bytes().to_vec();
+        composite_push_constants.extend_from_slice(&bloom_intensity.to_ne_bytes());
+        composite_push_constants.extend_from_slice(&(self.compare_encoding as u32).to_ne_bytes());
+        let record_composite = |device: &Device, command_buffer: CommandBuffer| unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline.pipeline_layout,
+                0,
+                &[self.composite_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.composite_pipeline.pipeline_layout,
+                ShaderStageFlags::FRAGMENT,
+                0,
+                &composite_push_constants,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            if let Some(post_process) = &self.post_process {
+                let _label = self.context.begin_label(
+                    command_buffer,
+                    "Post-process",
+                    FramePass::Composite.label_color(),
+                );
+                post_process.record(device, command_buffer, self.post_effect);
+            }
+        };
+        match (
+            self.swapchain_target.render_pass(),
+            &self.context.dynamic_rendering,
+        ) {
+            (Some(render_pass), _) => {
+                let mut attachments = vec![self.swapchain_image_views[image_index]];
+                if let Some(post_process) = &self.post_process {
+                    attachments.push(post_process.intermediate.view);
+                }
+                let framebuffer = self.context.get_or_create_framebuffer(
+                    render_pass,
+                    &attachments,
+                    self.swapchain_extent,
+                )?;
+                record_render_pass(
+                    device,
+                    command_buffer,
+                    render_pass,
+                    framebuffer,
+                    self.swapchain_extent,
+                    &clear_values,
+                    record_composite,
+                );
+            }
+            (None, Some(dynamic_rendering)) => {
+                // Without a render pass the graph records the swapchain image transitions
+                let mut graph = self.swapchain_graph(image_index, ImageLayout::UNDEFINED);
+                graph.add_pass(
+                    "Composite",
+                    &[(SWAPCHAIN_GRAPH_IMAGE, ImageAccess::ColorAttachment)],
+                    |device, command_buffer, resources| {
+                        let swapchain_image = resources.image(SWAPCHAIN_GRAPH_IMAGE)?;
+                        let color_attachments = [rendering_attachment_info(
+                            swapchain_image.view,
+                            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            AttachmentStoreOp::STORE,
+                            clear_values[0],
+                        )];
+                        record_rendering(
+                            device,
+                            dynamic_rendering,
+                            command_buffer,
+                            &color_attachments,
+                            None,
+                            swapchain_image.extent,
+                            record_composite,
+                        );
+                        Ok(())
+                    },
+                );
+                graph.execute(&self.context, command_buffer)?;
+            }
+            (None, None) => {
+                return Err(anyhow!(
+                    "Swapchain has no render pass and dynamic rendering is disabled"
+                ))
+            }
+        }
+
+        drop(composite_label);
+
+        if let (true, Some(screenshot_readback)) = (copy_for_screenshot, &self.screenshot_readback)
+        {
+            let mut graph = self.swapchain_graph(image_index, ImageLayout::PRESENT_SRC_KHR);
+            graph.add_pass(
+                "Screenshot readback",
+                &[(SWAPCHAIN_GRAPH_IMAGE, ImageAccess::TransferSrc)],
+                |device, command_buffer, resources| {
+                    screenshot_readback.record_copy(
+                        device,
+                        command_buffer,
+                        resources.image(SWAPCHAIN_GRAPH_IMAGE)?.image,
+                    );
+                    Ok(())
+                },
+            );
+            graph.execute(&self.context, command_buffer)?;
+        }
+
+        unsafe { device.end_command_buffer(command_buffer) }?;
+
+        Ok(())
+    }
+
+    /// Calls `callback` with the ID of the object under the cursor after every left click, or
+    /// with `None` when the click hit the background.
+    pub fn on_pick<F>(&mut self, callback: F)
+    where
+        F: FnMut(Option<u32>) + 'static,
+    {
+        self.on_pick = Box::new(callback);
+    }
+
+    /// Picks the pixel under the cursor on the next frame. The window may have been resized
+    /// since the picking target was created, so the cursor is scaled to the target.
+    fn request_pick(&mut self, window_size: PhysicalSize<u32>) {
+        let picking_pass = match &self.picking_pass {
+            Some(picking_pass) => picking_pass,
+            None => {
+                warn!("Picking is disabled, the click is ignored");
+                return;
+            }
+        };
+        let position = match self.cursor_position {
+            Some(position) => position,
+            None => return,
+        };
+        if window_size.width == 0 || window_size.height == 0 {
+            info!("Ignoring a click while the window has no size");
+            return;
+        }
+
+        let extent = picking_pass.target.extent;
+        let x = position.x * extent.width as f64 / window_size.width as f64;
+        let y = position.y * extent.height as f64 / window_size.height as f64;
+        if x < 0.0 || y < 0.0 || x >= extent.width as f64 || y >= extent.height as f64 {
+            (self.on_pick)(None);
+            return;
+        }
+        self.pending_pick = Some((x as u32, y as u32));
+    }
+
+    /// Reads the IDs of the picks whose frames have finished, without waiting for the others.
+    fn poll_picks(&mut self) -> Result<()> {
+        let picking_pass = match &self.picking_pass {
+            Some(picking_pass) => picking_pass,
+            None => return Ok(()),
+        };
+        let device = &self.context.device;
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            let in_flight_fence = self.frame_sync.in_flight_fences[frame];
+            if !self.picks_in_flight[frame] || !unsafe { device.get_fence_status(in_flight_fence) }?
+            {
+                continue;
+            }
+            self.picks_in_flight[frame] = false;
+            let object_id = picking_pass.target.read_object_id(device, frame)?;
+            (self.on_pick)(object_id);
+        }
+
+        Ok(())
+    }
+
+    fn record_pickable_objects(
+        &self,
+        device: &Device,
+        command_buffer: CommandBuffer,
+        picking_pass: &PickingPass,
+    ) {
+        let push_object_id = |pipeline: &PistonPipeline, object_id: u32| unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                ShaderStageFlags::FRAGMENT,
+                0,
+                &object_id.to_ne_bytes(),
+            )
+        };
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                picking_pass.triangle_pipeline.pipeline,
+            );
+            push_object_id(&picking_pass.triangle_pipeline, TRIANGLE_OBJECT_ID);
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                picking_pass.quad_pipeline.pipeline,
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[self.transparent_quads.buffer],
+                &[0],
+            );
+            for quad_index in 0..TRANSPARENT_QUADS.len() as u32 {
+                push_object_id(
+                    &picking_pass.quad_pipeline,
+                    FIRST_QUAD_OBJECT_ID + quad_index,
+                );
+                device.cmd_draw(command_buffer, 6, 1, quad_index * 6, 0);
+            }
+        }
+    }
+
+    fn request_screenshot(&mut self) {
+        if self.screenshot_readback.is_none() {
+            warn!("Screenshots are not supported by the current swapchain");
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        self.pending_screenshot = Some(PathBuf::from(format!("screenshot-{}.png", timestamp)));
+    }
+
+    /// Saves the swapchain image rendered in the current frame. The copy into the readback buffer
+    /// is recorded in the frame's command buffer, so only that frame's fence is waited on.
+    fn capture_screenshot(&self, path: &Path) -> Result<()> {
+        let screenshot_readback = self
+            .screenshot_readback
+            .as_ref()
+            .ok_or_else(|| anyhow!("Screenshots are not supported by the current swapchain"))?;
+
+        let device = &self.context.device;
+        let in_flight_fence = self.frame_sync.in_flight_fences[self.current_frame];
+        unsafe { device.wait_for_fences(&[in_flight_fence], true, u64::MAX) }?;
+
+        screenshot_readback.save_png(device, path)
+    }
+
+    /// Logs the device fault with `VK_EXT_device_fault`, the breadcrumbs of the frames in flight
+    /// without it.
+    fn report_device_lost(&self) {
+        error!(
+            "Device lost, last submitted frame: {:?}",
+            self.submitted_frames.checked_sub(1)
+        );
+        let report = match &self.context.device_fault {
+            Some(device_fault) => log_device_fault(&self.context.device, device_fault),
+            None => self.breadcrumbs.log(&self.context.device),
+        };
+        if let Err(error) = report {
+            error!("Failed to report the device loss: {:?}", error);
+        }
+    }
+
+    /// Shows the present mode, so a switch can be confirmed without the log.
+    fn window_title(&self) -> String {
+        format!("{} ({:?})", WINDOW_TITLE, self.present_mode)
+    }
+
+    /// Fails when the validation policy counted errors, once the app is dropped.
+    fn main_loop(mut self, event_loop: EventLoop<()>, window: Window) -> Result<()> {
+        let redraw_requested = true;
         let mut close_requested = false;
+        window.set_title(&self.window_title());
+        let message_filter = self.message_filter.clone();
 
-        Ok(event_loop.run(move |event, event_loop| match event {
+        event_loop.run(move |event, event_loop| match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
                     info!("User closed window, terminating event loop");
@@ -130,11 +1768,118 @@ impl PistonApp {
                         info!("User pressed ESC, terminating event loop");
                         close_requested = true;
                     }
+                    Key::Named(NamedKey::F12) => {
+                        info!("User pressed F12, capturing screenshot");
+                        self.request_screenshot();
+                    }
+                    Key::Named(NamedKey::F3) => {
+                        info!("User pressed F3, toggling wireframe");
+                        self.toggle_wireframe();
+                    }
+                    Key::Named(NamedKey::F4) => {
+                        info!("User pressed F4, toggling normals");
+                        self.toggle_normals();
+                    }
+                    Key::Named(NamedKey::F5) => {
+                        info!("User pressed F5, cycling the clear color");
+                        self.cycle_clear_color();
+                    }
+                    Key::Named(NamedKey::F6) => {
+                        info!("User pressed F6, cycling the post effect");
+                        self.cycle_post_effect();
+                    }
+                    Key::Named(NamedKey::F7) => {
+                        info!("User pressed F7, cycling the tonemap mode");
+                        if let Err(error) = self.cycle_tonemap_mode() {
+                            error!("Failed to change the tonemap mode: {:?}", error);
+                        }
+                    }
+                    Key::Named(NamedKey::F8) => {
+                        info!("User pressed F8, toggling the encoding comparison");
+                        self.toggle_encoding_comparison();
+                    }
+                    Key::Named(NamedKey::F11) => {
+                        info!("User pressed F11, toggling fullscreen");
+                        self.toggle_fullscreen(&window);
+                    }
+                    Key::Character("v") => {
+                        info!("User pressed V, cycling the present preference");
+                        self.cycle_present_preference();
+                    }
+                    Key::Character("[") => self.adjust_exposure(-EXPOSURE_STEP),
+                    Key::Character("]") => self.adjust_exposure(EXPOSURE_STEP),
+                    Key::Character(",") => self.adjust_bloom_intensity(-BLOOM_INTENSITY_STEP),
+                    Key::Character(".") => self.adjust_bloom_intensity(BLOOM_INTENSITY_STEP),
+                    Key::Character(";") => self.adjust_bloom_threshold(-BLOOM_THRESHOLD_STEP),
+                    Key::Character("'") => self.adjust_bloom_threshold(BLOOM_THRESHOLD_STEP),
+                    Key::Character("9") => self.adjust_shadow_bias(-SHADOW_BIAS_STEP),
+                    Key::Character("0") => self.adjust_shadow_bias(SHADOW_BIAS_STEP),
+                    Key::Named(NamedKey::ArrowLeft) | Key::Named(NamedKey::ArrowRight) => {
+                        let angle = match key.as_ref() {
+                            Key::Named(NamedKey::ArrowLeft) => LIGHT_ROTATION_STEP,
+                            _ => -LIGHT_ROTATION_STEP,
+                        };
+                        if let Err(error) = self.rotate_light(angle) {
+                            error!("Failed to rotate the light: {:?}", error);
+                        }
+                    }
+                    Key::Character("+") | Key::Character("=") => {
+                        if let Some(tessellated_quad) = &mut self.tessellated_quad {
+                            tessellated_quad.adjust_levels(TESSELLATION_LEVEL_STEP);
+                        }
+                    }
+                    Key::Character("-") => {
+                        if let Some(tessellated_quad) = &mut self.tessellated_quad {
+                            tessellated_quad.adjust_levels(-TESSELLATION_LEVEL_STEP);
+                        }
+                    }
                     _ => {}
                 },
+                WindowEvent::Resized(_) => {
+                    if let Err(error) = self.invalidate_surface() {
+                        warn!("Failed to invalidate the surface capabilities: {:?}", error);
+                    }
+                    self.swapchain_stale = true;
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = Some(position);
+                }
+                WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } => self.request_pick(window.inner_size()),
                 WindowEvent::RedrawRequested => {
+                    let window_size = window.inner_size();
+                    // A minimized window has no extent to create a swapchain with
+                    if window_size.width == 0 || window_size.height == 0 {
+                        return;
+                    }
+                    if self.swapchain_stale {
+                        if let Err(error) =
+                            self.recreate_swapchain(window_size, window.fullscreen().is_some())
+                        {
+                            error!("Failed to recreate the swapchain: {:?}", error);
+                            close_requested = true;
+                            return;
+                        }
+                        window.set_title(&self.window_title());
+                    }
                     window.pre_present_notify();
-                    self.draw_frame();
+                    if let Err(error) = self.draw_frame() {
+                        if error.downcast_ref::<ash::vk::Result>()
+                            == Some(&ash::vk::Result::ERROR_DEVICE_LOST)
+                        {
+                            self.report_device_lost();
+                        }
+                        error!("Failed to draw frame: {:?}", error);
+                        close_requested = true;
+                    }
+                    if self.message_filter.error_raised() {
+                        error!("Stopping at the first validation error");
+                        close_requested = true;
+                    }
                 }
                 _ => {}
             },
@@ -147,42 +1892,668 @@ impl PistonApp {
                 }
             }
             _ => {}
-        })?)
+        })?;
+
+        message_filter.check()
+    }
+}
+
+/// Frees whatever `composite_descriptor_pool` held, so only one composite set exists at a time.
+fn create_composite_descriptor_set(
+    context: &VulkanContext,
+    composite_descriptor_pool: DescriptorPool,
+    composite_pipeline: &PistonPipeline,
+    scene_color_info: DescriptorImageInfo,
+    bloom: Option<&Bloom>,
+) -> Result<DescriptorSet> {
+    let device = &context.device;
+    unsafe {
+        device.reset_descriptor_pool(composite_descriptor_pool, DescriptorPoolResetFlags::empty())
+    }?;
+    let composite_descriptor_set = allocate_descriptor_set(
+        device,
+        composite_descriptor_pool,
+        composite_pipeline.descriptor_set_layouts[0],
+    )?;
+    write_combined_image_sampler(
+        device,
+        composite_descriptor_set,
+        SCENE_COLOR_BINDING,
+        scene_color_info,
+    );
+    // Without bloom the composite adds nothing from this binding, but it must stay valid
+    write_combined_image_sampler(
+        device,
+        composite_descriptor_set,
+        BLOOM_COLOR_BINDING,
+        match bloom {
+            Some(bloom) => bloom.descriptor_image_info(),
+            None => context.default_textures().white.descriptor_image_info(),
+        },
+    );
+
+    Ok(composite_descriptor_set)
+}
+
+/// The post-process subpass's render pass when there is one, or else dynamic rendering when the
+/// device has it and a cached single subpass render pass when it hasn't.
+fn create_swapchain_target(
+    context: &VulkanContext,
+    post_process: Option<&PostProcessSubpass>,
+    format: Format,
+) -> Result<RenderTarget> {
+    Ok(match (&context.dynamic_rendering, post_process) {
+        (_, Some(post_process)) => post_process.render_target(),
+        (Some(_), None) => RenderTarget::Dynamic {
+            color_formats: vec![format],
+            depth_format: None,
+        },
+        (None, None) => RenderTarget::RenderPass(
+            context.get_or_create_render_pass(&RenderPassDesc::swapchain(format))?,
+        ),
+    })
+}
+
+fn create_composite_pipeline(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+    tonemap_mode: TonemapMode,
+    output_color_space: OutputColorSpace,
+    swapchain_format: Format,
+    pre_rotation: [[f32; 4]; 4],
+) -> Result<PistonPipeline> {
+    PipelineBuilder::new()
+        .shaders(
+            context.load_shader("fullscreen-vert.spv")?,
+            context.load_shader("composite-frag.spv")?,
+        )
+        .vertex_constants(
+            SpecializationConstants::new()
+                .with_f32(PRE_ROTATION_COS_CONSTANT_ID, pre_rotation[0][0])
+                .with_f32(PRE_ROTATION_SIN_CONSTANT_ID, pre_rotation[0][1]),
+        )
+        .fragment_constants(
+            SpecializationConstants::new()
+                .with_u32(TONEMAP_MODE_CONSTANT_ID, tonemap_mode as u32)
+                .with_u32(
+                    OUTPUT_TRANSFER_CONSTANT_ID,
+                    output_color_space.output_transfer(swapchain_format),
+                ),
+        )
+        .render_target(render_target)
+        .build(context)
+}
+
+/// The scene pipeline in fill mode, plus a wireframe variant when the device can draw lines.
+/// After a depth pre-pass the filled triangle only shades the pixels whose depth the pre-pass
+/// left. The wireframe doesn't test depth, its lines cover pixels the fill doesn't.
+fn create_scene_pipelines(
+    context: &VulkanContext,
+    offscreen_target: &OffscreenTarget,
+    shader_language: ShaderLanguage,
+) -> Result<PipelineFamily<RenderMode>> {
+    let pipeline_builder =
+        scene_pipeline_builder(context, &offscreen_target.render_target, shader_language)?;
+    let fill_pipeline_builder = match offscreen_target.depth_prepass_target {
+        Some(_) => pipeline_builder
+            .clone()
+            .depth_test(true)
+            .depth_write(false)
+            .depth_compare_op(CompareOp::EQUAL),
+        None => pipeline_builder.clone(),
+    };
+    let mut members = vec![(RenderMode::Fill, fill_pipeline_builder)];
+    if context
+        .capabilities
+        .is_enabled(DeviceFeature::FillModeNonSolid)
+    {
+        members.push((
+            RenderMode::Wireframe,
+            pipeline_builder.polygon_mode(PolygonMode::LINE),
+        ));
     }
+
+    PipelineFamily::new(context, &members)
+}
+
+/// The scene pipeline without its fragment stage, for the depth pre-pass. It runs the same
+/// vertex shader, so the scene pass finds the depth it wrote `EQUAL`.
+fn create_depth_prepass_pipeline(
+    context: &VulkanContext,
+    offscreen_target: &OffscreenTarget,
+    shader_language: ShaderLanguage,
+) -> Result<Option<PistonPipeline>> {
+    let depth_prepass_target = match &offscreen_target.depth_prepass_target {
+        Some(depth_prepass_target) => depth_prepass_target,
+        None => return Ok(None),
+    };
+
+    scene_pipeline_builder(context, depth_prepass_target, shader_language)?
+        .depth_only()
+        .depth_test(true)
+        .build(context)
+        .map(Some)
+}
+
+/// The scene pipeline with a geometry stage that draws the triangle's edge normals.
+fn create_normals_pipeline(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+    shader_language: ShaderLanguage,
+) -> Result<PistonPipeline> {
+    scene_pipeline_builder(context, render_target, shader_language)?
+        .geometry_shader(context.load_shader("normals-geom.spv")?)
+        .build(context)
+}
+
+/// The scene shaders in every language draw the same triangle. The HLSL pair has `VSMain` and
+/// `PSMain` entry points, and the WGSL source holds both stages. Without the wgsl feature the
+/// WGSL stages come from `triangle-combined.spv`, one module with both entry points.
+fn scene_pipeline_builder(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+    shader_language: ShaderLanguage,
+) -> Result<PipelineBuilder> {
+    let (vertex_shader, fragment_shader, (vertex_entry_point, fragment_entry_point)) =
+        match shader_language {
+            ShaderLanguage::Glsl => (
+                context.load_shader("vert-shader.spv")?,
+                context.load_shader(match context.debug_printf {
+                    true => "printf-frag.spv",
+                    false => "frag-shader.spv",
+                })?,
+                (DEFAULT_ENTRY_POINT, DEFAULT_ENTRY_POINT),
+            ),
+            ShaderLanguage::Hlsl => (
+                context.load_shader("triangle-vert.spv")?,
+                context.load_shader("triangle-frag.spv")?,
+                ("VSMain", "PSMain"),
+            ),
+            #[cfg(feature = "wgsl")]
+            ShaderLanguage::Wgsl => (
+                context.load_wgsl_shader("triangle.wgsl", ShaderStageFlags::VERTEX)?,
+                context.load_wgsl_shader("triangle.wgsl", ShaderStageFlags::FRAGMENT)?,
+                ("vs_main", "fs_main"),
+            ),
+            #[cfg(not(feature = "wgsl"))]
+            ShaderLanguage::Wgsl => {
+                let combined_shader = context.load_shader("triangle-combined.spv")?;
+                (
+                    combined_shader.clone(),
+                    combined_shader,
+                    ("vs_main", "fs_main"),
+                )
+            }
+        };
+
+    // Only the GLSL fragment shader prints, other shaders have no such constant
+    let fragment_constants = match (shader_language, context.debug_printf) {
+        (ShaderLanguage::Glsl, true) => {
+            SpecializationConstants::new().with_u32(DEBUG_PRINTF_CONSTANT_ID, 1)
+        }
+        _ => SpecializationConstants::new(),
+    };
+    Ok(PipelineBuilder::new()
+        .shaders(vertex_shader, fragment_shader)
+        .fragment_constants(fragment_constants)
+        .entry_point(ShaderStageFlags::VERTEX, vertex_entry_point)
+        .entry_point(ShaderStageFlags::FRAGMENT, fragment_entry_point)
+        .render_target(render_target))
+}
+
+/// With shadows the quads are lit by the directional light and darkened where the shadow map
+/// says they are occluded.
+fn create_transparent_pipeline(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+    shadowed: bool,
+) -> Result<PistonPipeline> {
+    let (vertex_shader, fragment_shader) = match shadowed {
+        true => ("shadowed-vert.spv", "shadowed-frag.spv"),
+        false => ("debug-vert.spv", "debug-frag.spv"),
+    };
+    PipelineBuilder::new()
+        .shaders(
+            context.load_shader(vertex_shader)?,
+            context.load_shader(fragment_shader)?,
+        )
+        .vertex_layout(DebugVertex::vertex_layout())
+        .cull_mode(CullModeFlags::NONE)
+        .depth_test(true)
+        .blend_mode(BlendMode::AlphaBlend)
+        .render_target(render_target)
+        .build(context)
+}
+
+/// Depth-only, the quads are drawn from the light with the vertex layout of the scene pass.
+fn create_shadow_pipeline(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+) -> Result<PistonPipeline> {
+    PipelineBuilder::new()
+        .shaders(
+            context.load_shader("shadow-vert.spv")?,
+            context.load_shader("shadow-frag.spv")?,
+        )
+        .vertex_layout(DebugVertex::vertex_layout())
+        .cull_mode(CullModeFlags::NONE)
+        .depth_test(true)
+        .depth_bias(true)
+        .render_target(render_target)
+        .build(context)
+}
+
+fn create_shadow_pass(
+    context: &VulkanContext,
+    shadow_map: ShadowMap,
+    descriptor_pool: DescriptorPool,
+    transparent_pipeline: &PistonPipeline,
+) -> Result<ShadowPass> {
+    let device = &context.device;
+    let pipeline = create_shadow_pipeline(context, &shadow_map.render_target)?;
+    let caster_descriptor_set =
+        allocate_descriptor_set(device, descriptor_pool, pipeline.descriptor_set_layouts[0])?;
+    write_uniform_buffer(
+        device,
+        caster_descriptor_set,
+        SHADOW_LIGHT_BINDING,
+        shadow_map.light_buffer_info(),
+    );
+    let receiver_descriptor_set = allocate_descriptor_set(
+        device,
+        descriptor_pool,
+        transparent_pipeline.descriptor_set_layouts[0],
+    )?;
+    write_uniform_buffer(
+        device,
+        receiver_descriptor_set,
+        SHADOW_LIGHT_BINDING,
+        shadow_map.light_buffer_info(),
+    );
+    write_combined_image_sampler(
+        device,
+        receiver_descriptor_set,
+        SHADOW_MAP_BINDING,
+        shadow_map.descriptor_image_info(),
+    );
+
+    Ok(ShadowPass {
+        shadow_map,
+        pipeline,
+        caster_descriptor_set,
+        receiver_descriptor_set,
+    })
+}
+
+/// The picking pipelines draw the scene's geometry with the picking fragment shader, which
+/// writes the object ID pushed before each draw.
+fn create_picking_pass(
+    context: &VulkanContext,
+    extent: Extent2D,
+    depth_format: Format,
+) -> Result<PickingPass> {
+    let target = PickingTarget::new(context, extent, depth_format, MAX_FRAMES_IN_FLIGHT)?;
+    let pipelines = context
+        .load_shader("picking-frag.spv")
+        .and_then(|fragment_shader| {
+            let triangle_pipeline = PipelineBuilder::new()
+                .shaders(
+                    context.load_shader("vert-shader.spv")?,
+                    fragment_shader.clone(),
+                )
+                .depth_test(true)
+                .render_target(&target.render_target)
+                .build(context)?;
+            let quad_pipeline = PipelineBuilder::new()
+                .shaders(context.load_shader("debug-vert.spv")?, fragment_shader)
+                .vertex_layout(DebugVertex::vertex_layout())
+                .cull_mode(CullModeFlags::NONE)
+                .depth_test(true)
+                .render_target(&target.render_target)
+                .build(context);
+            match quad_pipeline {
+                Ok(quad_pipeline) => Ok((triangle_pipeline, quad_pipeline)),
+                Err(error) => {
+                    triangle_pipeline.destroy(&context.device);
+                    Err(error)
+                }
+            }
+        });
+
+    match pipelines {
+        Ok((triangle_pipeline, quad_pipeline)) => Ok(PickingPass {
+            target,
+            triangle_pipeline,
+            quad_pipeline,
+        }),
+        Err(error) => {
+            target.destroy(&context.device);
+            Err(error)
+        }
+    }
+}
+
+fn object_name(object_id: u32) -> String {
+    match object_id {
+        TRIANGLE_OBJECT_ID => "the triangle".to_string(),
+        object_id => format!("transparent quad {}", object_id - FIRST_QUAD_OBJECT_ID),
+    }
+}
+
+/// The compute job that generates the transparent quads' vertices. The first frame's graphics
+/// submit waits on it, and it's destroyed once complete.
+struct QuadGeneration {
+    submission: ComputeSubmission,
+    pipeline: ComputePipeline,
+    descriptor_pool: DescriptorPool,
+    /// Whether a graphics submit has waited on the semaphore
+    awaited: bool,
+}
+
+impl QuadGeneration {
+    /// An ownership acquire waits for everything, like the async uploads, the vertices are
+    /// otherwise first read as vertex input.
+    fn wait_stage(&self, context: &VulkanContext) -> PipelineStageFlags {
+        match context.has_dedicated_compute_queue() {
+            true => PipelineStageFlags::ALL_COMMANDS,
+            false => PipelineStageFlags::VERTEX_INPUT,
+        }
+    }
+
+    /// Moves the vertices to the graphics family, recorded in the submit that waits on the
+    /// generation. Records nothing without a dedicated compute queue.
+    fn record_acquire(
+        &self,
+        context: &VulkanContext,
+        command_buffer: CommandBuffer,
+        vertex_buffer: &PistonBuffer,
+    ) {
+        acquire_ownership(
+            &context.device,
+            command_buffer,
+            &generated_quads(vertex_buffer),
+            compute_to_graphics_families(context),
+            (
+                AccessFlags::VERTEX_ATTRIBUTE_READ,
+                PipelineStageFlags::VERTEX_INPUT,
+            ),
+        );
+    }
+
+    /// The submission must be complete.
+    fn destroy(&self, context: &VulkanContext) {
+        self.submission.destroy(context);
+        unsafe {
+            context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None)
+        };
+        self.pipeline.destroy(&context.device);
+    }
+}
+
+fn compute_to_graphics_families(context: &VulkanContext) -> (u32, u32) {
+    let indices = &context.queue_family_indices;
+    (
+        indices.compute_family_index.unwrap_or_default(),
+        indices.graphics_family_index.unwrap_or_default(),
+    )
+}
+
+fn generated_quads(vertex_buffer: &PistonBuffer) -> OwnedResource {
+    OwnedResource::Buffer {
+        buffer: vertex_buffer.buffer,
+        offset: 0,
+        size: WHOLE_SIZE,
+    }
+}
+
+/// Generates the quads on the compute queue, which runs alongside the rest of the setup on
+/// devices with a dedicated compute family.
+fn create_transparent_quads(context: &VulkanContext) -> Result<(PistonBuffer, QuadGeneration)> {
+    let device = &context.device;
+    let vertex_count = TRANSPARENT_QUADS.len() * 6;
+    let vertex_buffer = PistonBuffer::new(
+        context,
+        (vertex_count * size_of::<DebugVertex>()) as DeviceSize,
+        BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::VERTEX_BUFFER,
+        MemoryPropertyFlags::DEVICE_LOCAL,
+        "transparent quad vertices",
+    )?;
+    let pipeline = context
+        .load_shader("quads-comp.spv")
+        .and_then(|shader| {
+            create_compute_pipeline(context, shader, &SpecializationConstants::new())
+        })
+        .inspect_err(|_| vertex_buffer.destroy(device))?;
+    let descriptor_pool = create_descriptor_pool(
+        device,
+        &[DescriptorPoolSize {
+            ty: DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+        }],
+        1,
+    )
+    .inspect_err(|_| {
+        pipeline.destroy(device);
+        vertex_buffer.destroy(device);
+    })?;
+
+    let submission = allocate_descriptor_set(
+        device,
+        descriptor_pool,
+        pipeline.pipeline.descriptor_set_layouts[0],
+    )
+    .and_then(|descriptor_set| {
+        write_storage_buffer(
+            device,
+            descriptor_set,
+            0,
+            DescriptorBufferInfo::builder()
+                .buffer(vertex_buffer.buffer)
+                .offset(0)
+                .range(WHOLE_SIZE)
+                .build(),
+        );
+        submit_compute_commands(context, |device, command_buffer| {
+            record_quad_generation(device, command_buffer, &pipeline, descriptor_set);
+            release_ownership(
+                device,
+                command_buffer,
+                &generated_quads(&vertex_buffer),
+                compute_to_graphics_families(context),
+                (
+                    AccessFlags::SHADER_WRITE,
+                    PipelineStageFlags::COMPUTE_SHADER,
+                ),
+            );
+            Ok(())
+        })
+    });
+
+    match submission {
+        Ok(submission) => Ok((
+            vertex_buffer,
+            QuadGeneration {
+                submission,
+                pipeline,
+                descriptor_pool,
+                awaited: false,
+            },
+        )),
+        Err(error) => {
+            unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+            pipeline.destroy(device);
+            vertex_buffer.destroy(device);
+            Err(error)
+        }
+    }
+}
+
+/// One dispatch per quad, with a workgroup invocation per vertex.
+fn record_quad_generation(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    pipeline: &ComputePipeline,
+    descriptor_set: DescriptorSet,
+) {
+    for (quad_index, &(center_x, center_y, depth, color)) in TRANSPARENT_QUADS.iter().enumerate() {
+        let mut push_constants = Vec::with_capacity(36);
+        for value in
+            color
+                .into_iter()
+                .chain([center_x, center_y, depth, TRANSPARENT_QUAD_HALF_SIZE])
+        {
+            push_constants.extend_from_slice(&value.to_ne_bytes());
+        }
+        push_constants.extend_from_slice(&(quad_index as u32 * 6).to_ne_bytes());
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline.pipeline_layout,
+                ShaderStageFlags::COMPUTE,
+                0,
+                &push_constants,
+            )
+        };
+        record_compute_dispatch(
+            device,
+            command_buffer,
+            pipeline,
+            &[descriptor_set],
+            [6, 1, 1],
+        );
+    }
+}
+
+/// Outlines the demo triangle's bounds and marks its corners.
+fn create_debug_geometry(context: &VulkanContext) -> Result<DebugGeometry> {
+    let outline_color = [1.0, 1.0, 0.0, 1.0];
+    let corners = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+    let line_vertices = (0..corners.len())
+        .flat_map(|index| [corners[index], corners[(index + 1) % corners.len()]])
+        .map(|[x, y]| DebugVertex {
+            position: [x, y, 0.0],
+            color: outline_color,
+        })
+        .collect::<Vec<_>>();
+    let point_vertices = [[0.0, -0.5], [0.5, 0.5], [-0.5, 0.5]].map(|[x, y]| DebugVertex {
+        position: [x, y, 0.0],
+        color: [1.0, 1.0, 1.0, 1.0],
+    });
+
+    DebugGeometry::new(context, &line_vertices, &point_vertices)
 }
 
 impl Drop for PistonApp {
     fn drop(&mut self) {
         unsafe {
-            if VALIDATION.is_enabled {
-                self.debug_utils_loader
-                    .destroy_debug_utils_messenger(self.debug_messenger, None);
+            let device = &self.context.device;
+            if let Err(error) = device.device_wait_idle() {
+                error!("Failed to wait for device idle: {}", error);
             }
 
-            self.device.destroy_pipeline(self.pipeline, None);
-            self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_render_pass(self.render_pass, None);
+            if let Some(picking_pass) = &self.picking_pass {
+                picking_pass.quad_pipeline.destroy(device);
+                picking_pass.triangle_pipeline.destroy(device);
+                picking_pass.target.destroy(device);
+            }
+            if let Some(screenshot_readback) = &self.screenshot_readback {
+                screenshot_readback.destroy(device);
+            }
+            self.frame_sync.destroy(device);
+            self.breadcrumbs.destroy(device);
+
+            if let Some(post_process) = &self.post_process {
+                post_process.destroy(device);
+            }
+            if let Some(bloom) = &self.bloom {
+                bloom.destroy(device);
+            }
+            if let Some(shadow_pass) = &self.shadow_pass {
+                shadow_pass.pipeline.destroy(device);
+                shadow_pass.shadow_map.destroy(device);
+            }
+            self.composite_pipeline.destroy(device);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_pool(self.composite_descriptor_pool, None);
+            if let Some(normals_pipeline) = &self.normals_pipeline {
+                normals_pipeline.destroy(device);
+            }
+            if let Some(quad_generation) = &self.quad_generation {
+                quad_generation.destroy(&self.context);
+            }
+            self.transparent_quads.destroy(device);
+            self.transparent_pipeline.destroy(device);
+            self.debug_geometry.destroy(device);
+            if let Some(tessellated_quad) = &self.tessellated_quad {
+                tessellated_quad.destroy(device);
+            }
+            self.debug_pipelines.destroy(device);
+            if let Some(depth_prepass_pipeline) = &self.depth_prepass_pipeline {
+                depth_prepass_pipeline.destroy(device);
+            }
+            self.scene_pipelines.destroy(device);
+            self.offscreen_target.destroy(device);
 
             for &image_view in self.swapchain_image_views.iter() {
-                self.device.destroy_image_view(image_view, None);
+                device.destroy_image_view(image_view, None);
             }
 
             self.swapchain_loader
                 .destroy_swapchain(self.swapchain, None);
 
-            self.device.destroy_device(None);
+            self.context.destroy();
 
-            self.surface_entities
-                .surface_loader
-                .destroy_surface(self.surface_entities.surface, None);
+            self.surface_entities.destroy();
+            // Last, so validation still reports on the teardown, such as leaked objects
+            if let (Some(debug_utils_loader), Some(debug_messenger)) =
+                (&self.debug_utils_loader, self.debug_messenger)
+            {
+                debug_utils_loader.destroy_debug_utils_messenger(debug_messenger, None);
+            }
             self.instance.destroy_instance(None);
+            self.message_filter.log_summary();
         }
     }
 }
 
+/// Only needs an instance, so present support is left out of the report.
+fn print_devices() -> Result<()> {
+    let entry = unsafe { Entry::load() }?;
+    let api_version = negotiate_api_version(&entry)?;
+    let validation = resolve_validation(&entry, &EngineConfig::default().validation);
+    let message_filter = MessageFilter::new(&validation);
+    let instance = create_instance(&entry, api_version, &validation, &message_filter, None)?;
+    let report = physical_device_report(&instance, None);
+    unsafe { instance.destroy_instance(None) };
+    print!("{}", report?);
+
+    Ok(())
+}
+
+/// Runs the compute gradient check on the GPU, then counts validation errors and fails on them
+/// unless `PISTON_VALIDATION_POLICY` says otherwise. Either failure exits with a non-zero status.
+fn run_headless() -> Result<()> {
+    let mut config = EngineConfig::default();
+    if env::var_os(VALIDATION_POLICY_ENV_VAR).is_none() {
+        config.validation.policy = ValidationPolicy::CountAndReport;
+    }
+    let headless = PistonApp::create_headless(&config)?;
+    info!(
+        "Headless context ready on {}",
+        headless.context.device_info.name
+    );
+    let gradient_check = run_gradient_check(&headless.context);
+    let message_filter = headless.message_filter.clone();
+    drop(headless);
+
+    gradient_check.context("The compute gradient check failed")?;
+    message_filter.check()
+}
+
 fn main() -> Result<()> {
-    env_logger::init();
+    init_logging();
 
     info!(
         "Starting {} v{}, built for Vulkan v{}",
@@ -190,9 +2561,65 @@ fn main() -> Result<()> {
         vk_version_to_string(APPLICATION_VERSION),
         vk_version_to_string(VULKAN_API_VERSION)
     );
+    if env::args().skip(1).any(|arg| arg == PRINT_DEVICES_ARG) {
+        return print_devices();
+    }
+    if env::args().skip(1).any(|arg| arg == HEADLESS_ARG) {
+        return run_headless();
+    }
     let event_loop = EventLoop::new()?;
-    let window = PistonApp::init_window(&event_loop);
-    let piston_app = PistonApp::create_with_window(&window)?;
+    let config = EngineConfig::default();
+    let window = PistonApp::init_window(&event_loop, &config);
+    let mut piston_app = PistonApp::create_with_window(&window, &config)?;
+    piston_app.on_pick(|object_id| match object_id {
+        Some(object_id) => info!("Picked {}", object_name(object_id)),
+        None => info!("Picked nothing"),
+    });
     piston_app.main_loop(event_loop, window)?;
     Ok(())
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use winit::event_loop::EventLoopBuilder;
+    use winit::platform::x11::EventLoopBuilderExtX11;
+
+    const PRESENT_TOGGLE_FRAMES: usize = 100;
+
+    /// Flips the present mode every frame under validation, which reports any swapchain or
+    /// semaphore left behind when the device is destroyed. Skips, passing, without a display or a
+    /// Vulkan driver.
+    #[test]
+    fn present_mode_toggle_every_frame() {
+        let event_loop = match EventLoopBuilder::new().with_any_thread(true).build() {
+            Ok(event_loop) => event_loop,
+            Err(error) => {
+                eprintln!("Skipping, no display: {:?}", error);
+                return;
+            }
+        };
+        let mut config = EngineConfig::default();
+        config.validation.policy = ValidationPolicy::CountAndReport;
+        let window = PistonApp::init_window(&event_loop, &config);
+        let mut piston_app = match PistonApp::create_with_window(&window, &config) {
+            Ok(piston_app) => piston_app,
+            Err(error) => {
+                eprintln!("Skipping, no Vulkan device for the window: {:?}", error);
+                return;
+            }
+        };
+        let message_filter = Arc::clone(&piston_app.message_filter);
+
+        for _ in 0..PRESENT_TOGGLE_FRAMES {
+            piston_app.cycle_present_preference();
+            piston_app
+                .recreate_swapchain(window.inner_size(), false)
+                .unwrap();
+            piston_app.draw_frame().unwrap();
+        }
+        drop(piston_app);
+
+        message_filter.check().unwrap();
+    }
+}
@@ -1,47 +1,489 @@
+use anyhow::anyhow;
 use ash::extensions::ext::DebugUtils;
 use ash::vk::{
-    DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
-    DebugUtilsMessengerCallbackDataEXT, DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT,
-    FALSE,
+    CommandBuffer, DebugUtilsLabelEXT, DebugUtilsMessageSeverityFlagsEXT,
+    DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
+    DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, DebugUtilsObjectNameInfoEXT, Handle,
+    Queue, ValidationFeatureEnableEXT, FALSE,
 };
-use ash::{vk, Entry, Instance};
-use log::{debug, error, info, warn};
+use ash::{vk, Device, Entry, Instance};
+use log::{debug, info, warn};
 use std::borrow::Cow;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::Level;
 
+use crate::constants::DEBUG_PRINTF_MESSAGE_IDS;
+use crate::vulkan::layer_settings::LayerSettingValue;
+
+#[derive(Clone, Debug)]
 pub struct ValidationInfo {
     pub is_enabled: bool,
     pub required_validation_layers: [&'static str; 1],
+    /// Messages below this severity aren't reported
+    pub min_severity: DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: DebugUtilsMessageTypeFlagsEXT,
+    /// Known noise the callback drops
+    pub suppressed_messages: Vec<MessageId>,
+    pub policy: ValidationPolicy,
+    /// Chained into instance creation through `VK_EXT_validation_features`
+    pub extra_features: Vec<ValidationFeatureEnableEXT>,
+    /// Chained into instance creation through `VK_EXT_layer_settings` when the layer has it
+    pub settings: ValidationSettings,
+}
+
+impl ValidationInfo {
+    /// Whether shader `debugPrintfEXT` output reaches the log.
+    pub fn debug_printf(&self) -> bool {
+        self.is_enabled
+            && self
+                .extra_features
+                .contains(&ValidationFeatureEnableEXT::DEBUG_PRINTF)
+    }
+}
+
+/// Validation layer settings, `None` and empty lists keep the layer's defaults. Layers without
+/// `VK_EXT_layer_settings` run with their defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationSettings {
+    /// Checks that externally synchronized objects aren't used from several threads at once
+    pub thread_safety: Option<bool>,
+    /// Stops reporting a message once it was reported `duplicate_message_limit` times
+    pub message_limit: Option<bool>,
+    pub duplicate_message_limit: Option<u32>,
+    /// Message ID names or numbers the layer doesn't report at all
+    pub message_id_filter: Vec<String>,
+}
+
+impl ValidationSettings {
+    /// The settings that are set, by their names in the validation layer.
+    pub fn layer_setting_values(&self) -> Vec<(&'static str, LayerSettingValue)> {
+        let mut values = vec![];
+        if let Some(thread_safety) = self.thread_safety {
+            values.push(("thread_safety", LayerSettingValue::Bool(thread_safety)));
+        }
+        if let Some(message_limit) = self.message_limit {
+            values.push((
+                "enable_message_limit",
+                LayerSettingValue::Bool(message_limit),
+            ));
+        }
+        if let Some(duplicate_message_limit) = self.duplicate_message_limit {
+            values.push((
+                "duplicate_message_limit",
+                LayerSettingValue::Uint32(duplicate_message_limit),
+            ));
+        }
+        if !self.message_id_filter.is_empty() {
+            values.push((
+                "message_id_filter",
+                LayerSettingValue::Strings(self.message_id_filter.clone()),
+            ));
+        }
+
+        values
+    }
+}
+
+/// What happens to the errors the callback receives. Panicking in the callback would unwind
+/// across the FFI boundary, so `PanicOnError` raises a flag the frame loop checks instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    #[default]
+    LogOnly,
+    /// Errors and warnings are counted and reported at shutdown, errors fail the run
+    CountAndReport,
+    /// The first error stops the run
+    PanicOnError,
+}
+
+impl ValidationPolicy {
+    /// `log`, `count` or `panic`.
+    pub fn parse(policy: &str) -> Option<ValidationPolicy> {
+        match policy.trim().to_ascii_lowercase().as_str() {
+            "log" => Some(ValidationPolicy::LogOnly),
+            "count" => Some(ValidationPolicy::CountAndReport),
+            "panic" => Some(ValidationPolicy::PanicOnError),
+            _ => None,
+        }
+    }
+}
+
+/// A validation message by its ID name, like `VUID-vkCmdDraw-None-02699`, or its ID number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MessageId {
+    Name(String),
+    Number(i32),
+}
+
+impl MessageId {
+    /// A decimal or `0x` prefixed hexadecimal number, a name otherwise.
+    pub fn parse(id: &str) -> MessageId {
+        let id = id.trim();
+        let number = match id.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16)
+                .ok()
+                .map(|number| number as i32),
+            None => id.parse().ok(),
+        };
+        match number {
+            Some(number) => MessageId::Number(number),
+            None => MessageId::Name(id.to_string()),
+        }
+    }
+
+    fn matches(&self, message_id_name: &str, message_id_number: i32) -> bool {
+        match self {
+            MessageId::Name(name) => name == message_id_name,
+            MessageId::Number(number) => *number == message_id_number,
+        }
+    }
+}
+
+/// The suppressed messages and the errors and warnings seen under the validation policy, which
+/// the debug callback reaches through its user data. It must stay at the same address until the
+/// instance and every messenger using it are destroyed.
+pub struct MessageFilter {
+    /// The messenger also listens to info messages for debug printf, the others below this are
+    /// dropped
+    min_severity: DebugUtilsMessageSeverityFlagsEXT,
+    suppressed_messages: Vec<(MessageId, AtomicUsize)>,
+    policy: ValidationPolicy,
+    error_count: AtomicUsize,
+    warning_count: AtomicUsize,
+    first_error: Mutex<Option<String>>,
+}
+
+impl MessageFilter {
+    pub fn new(validation_info: &ValidationInfo) -> MessageFilter {
+        MessageFilter {
+            min_severity: validation_info.min_severity,
+            suppressed_messages: validation_info
+                .suppressed_messages
+                .iter()
+                .map(|message_id| (message_id.clone(), AtomicUsize::new(0)))
+                .collect(),
+            policy: validation_info.policy,
+            error_count: AtomicUsize::new(0),
+            warning_count: AtomicUsize::new(0),
+            first_error: Mutex::new(None),
+        }
+    }
+
+    fn record(&self, message_severity: DebugUtilsMessageSeverityFlagsEXT, message: &str) {
+        if self.policy == ValidationPolicy::LogOnly {
+            return;
+        }
+        match message_severity {
+            DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+                self.error_count.fetch_add(1, Ordering::Relaxed);
+                if let Ok(mut first_error) = self.first_error.lock() {
+                    first_error.get_or_insert_with(|| message.to_string());
+                }
+            }
+            DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+                self.warning_count.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Under `PanicOnError`, whether the run must stop.
+    pub fn error_raised(&self) -> bool {
+        self.policy == ValidationPolicy::PanicOnError
+            && self.error_count.load(Ordering::Relaxed) > 0
+    }
+
+    /// Fails when errors were counted, so the process exits with a non-zero status.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let error_count = self.error_count.load(Ordering::Relaxed);
+        if error_count == 0 {
+            return Ok(());
+        }
+
+        let first_error = self
+            .first_error
+            .lock()
+            .ok()
+            .and_then(|first_error| first_error.clone())
+            .unwrap_or_default();
+        Err(anyhow!(
+            "{} validation errors under {:?}, the first: {}",
+            error_count,
+            self.policy,
+            first_error
+        ))
+    }
+
+    /// Counts the message when it's suppressed.
+    fn suppresses(&self, message_id_name: &str, message_id_number: i32) -> bool {
+        match self
+            .suppressed_messages
+            .iter()
+            .find(|(message_id, _)| message_id.matches(message_id_name, message_id_number))
+        {
+            Some((_, count)) => {
+                count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn log_summary(&self) {
+        if self.policy != ValidationPolicy::LogOnly {
+            info!(
+                "Validation reported {} errors and {} warnings",
+                self.error_count.load(Ordering::Relaxed),
+                self.warning_count.load(Ordering::Relaxed)
+            );
+        }
+        for (message_id, count) in &self.suppressed_messages {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                info!("Suppressed {:?} {} times", message_id, count);
+            }
+        }
+    }
+}
+
+const SEVERITIES: [DebugUtilsMessageSeverityFlagsEXT; 4] = [
+    DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+    DebugUtilsMessageSeverityFlagsEXT::INFO,
+    DebugUtilsMessageSeverityFlagsEXT::WARNING,
+    DebugUtilsMessageSeverityFlagsEXT::ERROR,
+];
+
+/// The severity a log level names: `verbose`, `info`, `warn` or `error`.
+pub fn parse_min_severity(level: &str) -> Option<DebugUtilsMessageSeverityFlagsEXT> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "verbose" => Some(DebugUtilsMessageSeverityFlagsEXT::VERBOSE),
+        "info" => Some(DebugUtilsMessageSeverityFlagsEXT::INFO),
+        "warn" | "warning" => Some(DebugUtilsMessageSeverityFlagsEXT::WARNING),
+        "error" => Some(DebugUtilsMessageSeverityFlagsEXT::ERROR),
+        _ => None,
+    }
+}
+
+/// `min_severity` and every severity above it.
+pub fn severities_from(
+    min_severity: DebugUtilsMessageSeverityFlagsEXT,
+) -> DebugUtilsMessageSeverityFlagsEXT {
+    SEVERITIES
+        .into_iter()
+        .filter(|severity| severity.as_raw() >= min_severity.as_raw())
+        .fold(
+            DebugUtilsMessageSeverityFlagsEXT::empty(),
+            |severities, severity| severities | severity,
+        )
+}
+
+/// A comma separated list of `general`, `validation` and `performance`. `None` when a name is
+/// unknown or the list is empty.
+pub fn parse_message_types(names: &str) -> Option<DebugUtilsMessageTypeFlagsEXT> {
+    let mut message_types = DebugUtilsMessageTypeFlagsEXT::empty();
+    for name in names.split(',') {
+        message_types |= match name.trim().to_ascii_lowercase().as_str() {
+            "general" => DebugUtilsMessageTypeFlagsEXT::GENERAL,
+            "validation" => DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            "performance" => DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            _ => return None,
+        };
+    }
+
+    Some(message_types)
+}
+
+/// A comma separated list of `gpu`, `bestpractices`, `sync` and `printf`. GPU-assisted validation
+/// also reserves a descriptor set binding slot for the layer. `None` when a name is unknown.
+pub fn parse_validation_features(names: &str) -> Option<Vec<ValidationFeatureEnableEXT>> {
+    let mut features = vec![];
+    for name in names.split(',').filter(|name| !name.trim().is_empty()) {
+        features.extend_from_slice(match name.trim().to_ascii_lowercase().as_str() {
+            "gpu" => &[
+                ValidationFeatureEnableEXT::GPU_ASSISTED,
+                ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+            ],
+            "bestpractices" => &[ValidationFeatureEnableEXT::BEST_PRACTICES],
+            "sync" => &[ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION],
+            "printf" => &[ValidationFeatureEnableEXT::DEBUG_PRINTF],
+            _ => return None,
+        });
+    }
+    features.sort_by_key(|feature| feature.as_raw());
+    features.dedup();
+
+    Some(features)
 }
 
+/// The loader and the messenger, `None` without validation since the instance then lacks the
+/// extension.
 pub fn create_debug_utils(
     entry: &Entry,
     instance: &Instance,
     validation_info: &ValidationInfo,
-) -> anyhow::Result<(DebugUtils, DebugUtilsMessengerEXT)> {
-    let debug_utils_loader = DebugUtils::new(&entry, &instance);
-    let debug_messenger = if validation_info.is_enabled {
-        unsafe { debug_utils_loader.create_debug_utils_messenger(&create_debug_info(), None) }?
-    } else {
-        DebugUtilsMessengerEXT::null()
+    message_filter: &MessageFilter,
+) -> anyhow::Result<Option<(DebugUtils, DebugUtilsMessengerEXT)>> {
+    if !validation_info.is_enabled {
+        return Ok(None);
+    }
+
+    let debug_utils_loader = DebugUtils::new(entry, instance);
+    let debug_messenger = unsafe {
+        debug_utils_loader
+            .create_debug_utils_messenger(&create_debug_info(validation_info, message_filter), None)
+    }?;
+
+    Ok(Some((debug_utils_loader, debug_messenger)))
+}
+
+/// Names `handle` in validation messages and graphics debuggers. Does nothing without debug
+/// utils, a failure is only logged.
+pub fn set_object_name<T: Handle>(
+    debug_utils: Option<&DebugUtils>,
+    device: &Device,
+    handle: T,
+    name: &str,
+) {
+    let debug_utils = match debug_utils {
+        Some(debug_utils) => debug_utils,
+        None => return,
+    };
+    let object_type = T::TYPE;
+    let object_name = match CString::new(name) {
+        Ok(object_name) => object_name,
+        Err(error) => {
+            warn!("Invalid name {:?} for a {:?}: {}", name, object_type, error);
+            return;
+        }
+    };
+    let name_info = DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(object_type)
+        .object_handle(handle.as_raw())
+        .object_name(&object_name)
+        .build();
+    if let Err(error) =
+        unsafe { debug_utils.set_debug_utils_object_name(device.handle(), &name_info) }
+    {
+        warn!("Failed to name {:?} {:?}: {}", object_type, name, error);
+    }
+}
+
+/// A label with a nul byte is recorded empty, so the begin and end still pair up.
+fn create_label(name: &str, color: [f32; 4]) -> (CString, DebugUtilsLabelEXT) {
+    let label_name = CString::new(name).unwrap_or_default();
+    let label = DebugUtilsLabelEXT {
+        p_label_name: label_name.as_ptr(),
+        color,
+        ..Default::default()
     };
 
-    Ok((debug_utils_loader, debug_messenger))
+    (label_name, label)
 }
 
-pub fn create_debug_info() -> DebugUtilsMessengerCreateInfoEXT {
+/// Opens a region of `command_buffer` for captures and validation messages. Does nothing
+/// without debug utils.
+pub fn cmd_begin_label(
+    debug_utils: Option<&DebugUtils>,
+    command_buffer: CommandBuffer,
+    name: &str,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils) = debug_utils {
+        let (_label_name, label) = create_label(name, color);
+        unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+pub fn cmd_end_label(debug_utils: Option<&DebugUtils>, command_buffer: CommandBuffer) {
+    if let Some(debug_utils) = debug_utils {
+        unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+    }
+}
+
+/// Marks a single point of `command_buffer`, such as a readback.
+pub fn cmd_insert_label(
+    debug_utils: Option<&DebugUtils>,
+    command_buffer: CommandBuffer,
+    name: &str,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils) = debug_utils {
+        let (_label_name, label) = create_label(name, color);
+        unsafe { debug_utils.cmd_insert_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+/// Opens a region of `queue`, around submissions and presents. Does nothing without debug utils.
+pub fn queue_begin_label(
+    debug_utils: Option<&DebugUtils>,
+    queue: Queue,
+    name: &str,
+    color: [f32; 4],
+) {
+    if let Some(debug_utils) = debug_utils {
+        let (_label_name, label) = create_label(name, color);
+        unsafe { debug_utils.queue_begin_debug_utils_label(queue, &label) };
+    }
+}
+
+pub fn queue_end_label(debug_utils: Option<&DebugUtils>, queue: Queue) {
+    if let Some(debug_utils) = debug_utils {
+        unsafe { debug_utils.queue_end_debug_utils_label(queue) };
+    }
+}
+
+/// A label region that ends when dropped, so early returns can't leave it open.
+pub struct ScopedLabel<'a> {
+    debug_utils: Option<&'a DebugUtils>,
+    command_buffer: CommandBuffer,
+}
+
+impl<'a> ScopedLabel<'a> {
+    pub fn begin(
+        debug_utils: Option<&'a DebugUtils>,
+        command_buffer: CommandBuffer,
+        name: &str,
+        color: [f32; 4],
+    ) -> ScopedLabel<'a> {
+        cmd_begin_label(debug_utils, command_buffer, name, color);
+
+        ScopedLabel {
+            debug_utils,
+            command_buffer,
+        }
+    }
+}
+
+impl Drop for ScopedLabel<'_> {
+    fn drop(&mut self) {
+        cmd_end_label(self.debug_utils, self.command_buffer);
+    }
+}
+
+/// Used for the messenger and for the messages of instance creation and destruction.
+pub fn create_debug_info(
+    validation_info: &ValidationInfo,
+    message_filter: &MessageFilter,
+) -> DebugUtilsMessengerCreateInfoEXT {
+    // Printf messages are validation infos
+    let (severities, message_types) = match validation_info.debug_printf() {
+        true => (
+            severities_from(validation_info.min_severity) | DebugUtilsMessageSeverityFlagsEXT::INFO,
+            validation_info.message_types | DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        ),
+        false => (
+            severities_from(validation_info.min_severity),
+            validation_info.message_types,
+        ),
+    };
     DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(
-            DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | DebugUtilsMessageSeverityFlagsEXT::INFO,
-        )
-        .message_type(
-            DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-        )
+        .message_severity(severities)
+        .message_type(message_types)
         .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(message_filter as *const MessageFilter as *mut c_void)
         .build()
 }
 
@@ -49,7 +491,7 @@ unsafe extern "system" fn vulkan_debug_callback(
     message_severity: DebugUtilsMessageSeverityFlagsEXT,
     message_type: DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
     let message_id_number = callback_data.message_id_number;
@@ -60,23 +502,235 @@ unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
     };
 
+    let message_filter = (user_data as *const MessageFilter).as_ref();
+    if message_filter.is_some_and(|message_filter| {
+        message_filter.suppresses(&message_id_name, message_id_number)
+    }) {
+        return FALSE;
+    }
+
     let message = if callback_data.p_message.is_null() {
         Cow::from("")
     } else {
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
+    // The layer puts the printf output after its own prefix, in the last `|` separated part
+    if DEBUG_PRINTF_MESSAGE_IDS.contains(&message_id_name.as_ref()) {
+        debug!(
+            "Shader printf: {}",
+            message.rsplit('|').next().unwrap_or_default().trim()
+        );
+        return FALSE;
+    }
+    if message_filter.is_some_and(|message_filter| {
+        message_severity.as_raw() < message_filter.min_severity.as_raw()
+    }) {
+        return FALSE;
+    }
+
     let log_message = format!(
         "{:?} [{} ({})]: {}",
         message_type, message_id_name, message_id_number, message
     );
+    // The level of a tracing event has to be known at compile time
+    macro_rules! message_event {
+        ($level:expr) => {
+            tracing::event!(
+                $level,
+                message_id = %message_id_name,
+                message_id_number,
+                "{:?}: {}",
+                message_type,
+                message
+            )
+        };
+    }
     match message_severity {
-        DebugUtilsMessageSeverityFlagsEXT::VERBOSE => debug!("{}", log_message),
-        DebugUtilsMessageSeverityFlagsEXT::INFO => info!("{}", log_message),
-        DebugUtilsMessageSeverityFlagsEXT::WARNING => warn!("{}", log_message),
-        DebugUtilsMessageSeverityFlagsEXT::ERROR => error!("{}", log_message),
-        _ => error!("{}", log_message),
+        DebugUtilsMessageSeverityFlagsEXT::VERBOSE => message_event!(Level::DEBUG),
+        DebugUtilsMessageSeverityFlagsEXT::INFO => message_event!(Level::INFO),
+        DebugUtilsMessageSeverityFlagsEXT::WARNING => message_event!(Level::WARN),
+        _ => message_event!(Level::ERROR),
+    }
+    if let Some(message_filter) = message_filter {
+        message_filter.record(message_severity, &log_message);
     }
 
     FALSE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_severity_parses_each_level() {
+        assert_eq!(
+            parse_min_severity("verbose"),
+            Some(DebugUtilsMessageSeverityFlagsEXT::VERBOSE)
+        );
+        assert_eq!(
+            parse_min_severity("info"),
+            Some(DebugUtilsMessageSeverityFlagsEXT::INFO)
+        );
+        assert_eq!(
+            parse_min_severity(" Warning "),
+            Some(DebugUtilsMessageSeverityFlagsEXT::WARNING)
+        );
+        assert_eq!(
+            parse_min_severity("ERROR"),
+            Some(DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        );
+        assert_eq!(parse_min_severity("debug"), None);
+        assert_eq!(parse_min_severity(""), None);
+    }
+
+    #[test]
+    fn severities_include_everything_above_the_minimum() {
+        assert_eq!(
+            severities_from(DebugUtilsMessageSeverityFlagsEXT::WARNING),
+            DebugUtilsMessageSeverityFlagsEXT::WARNING | DebugUtilsMessageSeverityFlagsEXT::ERROR
+        );
+        assert_eq!(
+            severities_from(DebugUtilsMessageSeverityFlagsEXT::ERROR),
+            DebugUtilsMessageSeverityFlagsEXT::ERROR
+        );
+    }
+
+    #[test]
+    fn message_types_parse_a_list() {
+        assert_eq!(
+            parse_message_types("general, Validation"),
+            Some(
+                DebugUtilsMessageTypeFlagsEXT::GENERAL | DebugUtilsMessageTypeFlagsEXT::VALIDATION
+            )
+        );
+        assert_eq!(
+            parse_message_types("performance"),
+            Some(DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
+        );
+    }
+
+    #[test]
+    fn message_types_reject_unknown_and_empty_lists() {
+        assert_eq!(parse_message_types("general,shader"), None);
+        assert_eq!(parse_message_types(""), None);
+        assert_eq!(parse_message_types("validation,"), None);
+    }
+
+    #[test]
+    fn validation_features_drop_non_adjacent_duplicates() {
+        assert_eq!(
+            parse_validation_features("sync,printf,Sync"),
+            Some(vec![
+                ValidationFeatureEnableEXT::DEBUG_PRINTF,
+                ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+            ])
+        );
+        assert_eq!(
+            parse_validation_features("gpu,bestpractices,gpu"),
+            Some(vec![
+                ValidationFeatureEnableEXT::GPU_ASSISTED,
+                ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+                ValidationFeatureEnableEXT::BEST_PRACTICES,
+            ])
+        );
+    }
+
+    #[test]
+    fn validation_policy_parses_each_spelling() {
+        assert_eq!(
+            ValidationPolicy::parse("log"),
+            Some(ValidationPolicy::LogOnly)
+        );
+        assert_eq!(
+            ValidationPolicy::parse(" Count "),
+            Some(ValidationPolicy::CountAndReport)
+        );
+        assert_eq!(
+            ValidationPolicy::parse("PANIC"),
+            Some(ValidationPolicy::PanicOnError)
+        );
+    }
+
+    #[test]
+    fn validation_policy_rejects_unknown_values() {
+        assert_eq!(ValidationPolicy::parse("abort"), None);
+        assert_eq!(ValidationPolicy::parse("count,panic"), None);
+        assert_eq!(ValidationPolicy::parse(""), None);
+    }
+
+    #[test]
+    fn validation_policy_defaults_to_log_only() {
+        assert_eq!(ValidationPolicy::default(), ValidationPolicy::LogOnly);
+    }
+
+    #[test]
+    fn message_ids_parse_numbers_and_names() {
+        let cases = [
+            ("0x1a2b", MessageId::Number(0x1a2b)),
+            ("0xffffffff", MessageId::Number(-1)),
+            ("1234", MessageId::Number(1234)),
+            (" -56 ", MessageId::Number(-56)),
+            (
+                "VUID-vkCmdDraw-None-02699",
+                MessageId::Name("VUID-vkCmdDraw-None-02699".to_string()),
+            ),
+            ("0xnothex", MessageId::Name("0xnothex".to_string())),
+            ("0x100000000", MessageId::Name("0x100000000".to_string())),
+            ("12ab", MessageId::Name("12ab".to_string())),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(MessageId::parse(id), expected, "{:?}", id);
+        }
+    }
+
+    fn count_and_report_filter(suppressed_messages: Vec<MessageId>) -> MessageFilter {
+        MessageFilter::new(&ValidationInfo {
+            is_enabled: true,
+            required_validation_layers: ["VK_LAYER_KHRONOS_validation"],
+            min_severity: DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            message_types: DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            suppressed_messages,
+            policy: ValidationPolicy::CountAndReport,
+            extra_features: vec![],
+            settings: ValidationSettings::default(),
+        })
+    }
+
+    fn report_error(
+        message_filter: &MessageFilter,
+        message_id_name: &CStr,
+        message_id_number: i32,
+    ) {
+        let callback_data = DebugUtilsMessengerCallbackDataEXT::builder()
+            .message_id_name(message_id_name)
+            .message_id_number(message_id_number)
+            .message(c"test error")
+            .build();
+        unsafe {
+            vulkan_debug_callback(
+                DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                &callback_data,
+                message_filter as *const MessageFilter as *mut c_void,
+            )
+        };
+    }
+
+    #[test]
+    fn suppressed_messages_are_not_counted() {
+        let message_filter = count_and_report_filter(vec![
+            MessageId::Number(0x1234),
+            MessageId::Name("VUID-suppressed".to_string()),
+        ]);
+        report_error(&message_filter, c"VUID-other", 0x1234);
+        report_error(&message_filter, c"VUID-suppressed", 7);
+        assert!(message_filter.check().is_ok());
+        assert_eq!(message_filter.error_count.load(Ordering::Relaxed), 0);
+
+        report_error(&message_filter, c"VUID-other", 7);
+        assert_eq!(message_filter.error_count.load(Ordering::Relaxed), 1);
+        assert!(message_filter.check().is_err());
+    }
+}
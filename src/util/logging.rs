@@ -0,0 +1,19 @@
+use std::env;
+
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+use crate::constants::TRACE_ENV_VAR;
+
+/// Logs through env_logger, unless `PISTON_TRACE=1` asks for a tracing subscriber that also prints
+/// how long each span took. `RUST_LOG` filters either way, and `log` records reach the subscriber
+/// like tracing events do.
+pub fn init_logging() {
+    match env::var(TRACE_ENV_VAR).as_deref() {
+        Ok("1") => tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_span_events(FmtSpan::CLOSE)
+            .init(),
+        _ => env_logger::init(),
+    }
+}
@@ -1,2 +1,3 @@
 pub mod debug;
+pub mod logging;
 pub mod util;
@@ -1,7 +1,7 @@
+use anyhow::{Context, Result};
 use ash::vk::{api_version_major, api_version_minor, api_version_patch};
 use std::ffi::{c_char, CStr};
-use std::fs::File;
-use std::io::Read;
+use std::fs;
 use std::path::Path;
 
 pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
@@ -13,6 +13,11 @@ pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
     }
 }
 
+/// Borrows the string, unlike `vk_to_string`, for comparing names without allocating.
+pub fn vk_to_cstr(raw_string_array: &[c_char]) -> &CStr {
+    unsafe { CStr::from_ptr(raw_string_array.as_ptr()) }
+}
+
 pub fn vk_version_to_string(version: u32) -> String {
     let major = api_version_major(version);
     let minor = api_version_minor(version);
@@ -28,9 +33,6 @@ pub fn yes_no<'value>(value: bool) -> &'value str {
     }
 }
 
-pub fn load_file_bytes(file_path: &Path) -> Vec<u8> {
-    let file = File::open(file_path).expect(&format!("Error loading file {:?}", file_path));
-    file.bytes()
-        .filter_map(|b| b.ok().unwrap().into())
-        .collect()
+pub fn load_file_bytes(file_path: &Path) -> Result<Vec<u8>> {
+    fs::read(file_path).with_context(|| format!("Error loading file {:?}", file_path))
 }
@@ -0,0 +1,206 @@
+use ash::vk::{
+    AccessFlags, Buffer, BufferMemoryBarrier, CommandBuffer, DependencyFlags, DeviceSize, Image,
+    ImageLayout, ImageMemoryBarrier, ImageSubresourceRange, PipelineStageFlags,
+};
+use ash::Device;
+
+/// A resource moving between queue families. The release and the acquire must describe it the
+/// same way, layouts included.
+#[derive(Clone, Copy, Debug)]
+pub enum OwnedResource {
+    Buffer {
+        buffer: Buffer,
+        offset: DeviceSize,
+        size: DeviceSize,
+    },
+    Image {
+        image: Image,
+        subresource_range: ImageSubresourceRange,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OwnershipHalf {
+    Release,
+    Acquire,
+}
+
+impl OwnershipHalf {
+    /// The source and destination access and stage of the barrier. The release only makes
+    /// `access` available and the acquire only makes it visible, the other side of each is
+    /// empty.
+    fn masks(
+        self,
+        access: AccessFlags,
+        stage: PipelineStageFlags,
+    ) -> (
+        (AccessFlags, PipelineStageFlags),
+        (AccessFlags, PipelineStageFlags),
+    ) {
+        match self {
+            OwnershipHalf::Release => (
+                (access, stage),
+                (AccessFlags::empty(), PipelineStageFlags::BOTTOM_OF_PIPE),
+            ),
+            OwnershipHalf::Acquire => (
+                (AccessFlags::empty(), PipelineStageFlags::TOP_OF_PIPE),
+                (access, stage),
+            ),
+        }
+    }
+}
+
+/// Within a family there is nothing to transfer.
+pub fn is_ownership_transfer(src_family_index: u32, dst_family_index: u32) -> bool {
+    src_family_index != dst_family_index
+}
+
+/// Records the release half on a queue of the source family, after the writes of `access` in
+/// `stage`. The acquire half must follow on the destination family, ordered by a semaphore or a
+/// wait. Records nothing when the families are the same, any layout change is then left to the
+/// caller.
+pub fn release_ownership(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    resource: &OwnedResource,
+    families: (u32, u32),
+    (access, stage): (AccessFlags, PipelineStageFlags),
+) {
+    record_ownership_barrier(
+        device,
+        command_buffer,
+        resource,
+        families,
+        OwnershipHalf::Release.masks(access, stage),
+    );
+}
+
+/// Records the acquire half on a queue of the destination family, before its uses of `access` in
+/// `stage`. Records nothing when the families are the same.
+pub fn acquire_ownership(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    resource: &OwnedResource,
+    families: (u32, u32),
+    (access, stage): (AccessFlags, PipelineStageFlags),
+) {
+    record_ownership_barrier(
+        device,
+        command_buffer,
+        resource,
+        families,
+        OwnershipHalf::Acquire.masks(access, stage),
+    );
+}
+
+fn record_ownership_barrier(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    resource: &OwnedResource,
+    (src_family_index, dst_family_index): (u32, u32),
+    ((src_access_mask, src_stage), (dst_access_mask, dst_stage)): (
+        (AccessFlags, PipelineStageFlags),
+        (AccessFlags, PipelineStageFlags),
+    ),
+) {
+    if !is_ownership_transfer(src_family_index, dst_family_index) {
+        return;
+    }
+
+    let (buffer_memory_barriers, image_memory_barriers) = match *resource {
+        OwnedResource::Buffer {
+            buffer,
+            offset,
+            size,
+        } => (
+            vec![BufferMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_family_index)
+                .dst_queue_family_index(dst_family_index)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size)
+                .build()],
+            vec![],
+        ),
+        OwnedResource::Image {
+            image,
+            subresource_range,
+            old_layout,
+            new_layout,
+        } => (
+            vec![],
+            vec![ImageMemoryBarrier::builder()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(src_family_index)
+                .dst_queue_family_index(dst_family_index)
+                .image(image)
+                .subresource_range(subresource_range)
+                .build()],
+        ),
+    };
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            DependencyFlags::empty(),
+            &[],
+            &buffer_memory_barriers,
+            &image_memory_barriers,
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_family_is_no_transfer() {
+        assert!(!is_ownership_transfer(0, 0));
+        assert!(!is_ownership_transfer(2, 2));
+        assert!(is_ownership_transfer(0, 2));
+        assert!(is_ownership_transfer(2, 0));
+    }
+
+    #[test]
+    fn release_only_makes_writes_available() {
+        let (source, destination) =
+            OwnershipHalf::Release.masks(AccessFlags::TRANSFER_WRITE, PipelineStageFlags::TRANSFER);
+        assert_eq!(
+            source,
+            (AccessFlags::TRANSFER_WRITE, PipelineStageFlags::TRANSFER)
+        );
+        assert_eq!(
+            destination,
+            (AccessFlags::empty(), PipelineStageFlags::BOTTOM_OF_PIPE)
+        );
+    }
+
+    #[test]
+    fn acquire_only_makes_reads_visible() {
+        let (source, destination) = OwnershipHalf::Acquire.masks(
+            AccessFlags::SHADER_READ,
+            PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        assert_eq!(
+            source,
+            (AccessFlags::empty(), PipelineStageFlags::TOP_OF_PIPE)
+        );
+        assert_eq!(
+            destination,
+            (
+                AccessFlags::SHADER_READ,
+                PipelineStageFlags::FRAGMENT_SHADER
+            )
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, CommandBuffer, DependencyFlags, DescriptorImageInfo, DescriptorPool,
+    DescriptorPoolSize, DescriptorSet, DescriptorType, Extent2D, FormatFeatureFlags, ImageLayout,
+    ImageUsageFlags, MemoryBarrier, PipelineStageFlags, Sampler, SamplerAddressMode,
+    ShaderStageFlags,
+};
+use ash::Device;
+use log::info;
+
+use crate::constants::{
+    BLOOM_DESTINATION_BINDING, BLOOM_FORMAT, BLOOM_MAX_MIP_COUNT, BLOOM_MIN_MIP_COUNT,
+    BLOOM_SOURCE_BINDING,
+};
+use crate::vulkan::command::execute_single_time_commands;
+use crate::vulkan::compute::record_compute_dispatch;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::descriptor::{
+    allocate_descriptor_set, create_descriptor_pool, write_combined_image_sampler,
+    write_storage_image,
+};
+use crate::vulkan::image::{record_image_layout_transition, ImageDesc, PistonImage};
+use crate::vulkan::pipeline::{create_compute_pipeline, ComputePipeline, SpecializationConstants};
+use crate::vulkan::sampler::SamplerDesc;
+use crate::vulkan::texture::check_format_features;
+
+const BLOOM_FORMAT_FEATURES: FormatFeatureFlags = FormatFeatureFlags::from_raw(
+    FormatFeatureFlags::SAMPLED_IMAGE.as_raw()
+        | FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR.as_raw()
+        | FormatFeatureFlags::STORAGE_IMAGE.as_raw(),
+);
+
+/// Blurs the bright parts of the scene through a chain of mips, the first at half the scene
+/// resolution. The first pass keeps what is brighter than the threshold, the next ones
+/// downsample it mip by mip and the last ones add each mip, upsampled, onto the next larger one.
+/// The mips stay in the general layout, the composite pass samples `mips[0]`.
+///
+/// The mips are sized from the scene color, so the chain has to be recreated with it.
+pub struct Bloom {
+    pub mips: Vec<PistonImage>,
+    downsample_pipeline: ComputePipeline,
+    upsample_pipeline: ComputePipeline,
+    descriptor_pool: DescriptorPool,
+    /// Set `i` reads the scene color or mip `i - 1` and writes mip `i`
+    downsample_descriptor_sets: Vec<DescriptorSet>,
+    /// Set `i` reads mip `i + 1` and adds it to mip `i`
+    upsample_descriptor_sets: Vec<DescriptorSet>,
+    sampler: Sampler,
+}
+
+impl Bloom {
+    /// `mip_count` is clamped to the supported range and to what the scene extent allows.
+    pub fn new(
+        context: &VulkanContext,
+        scene_color: DescriptorImageInfo,
+        scene_extent: Extent2D,
+        mip_count: u32,
+    ) -> Result<Bloom> {
+        let device = &context.device;
+        check_format_features(context, BLOOM_FORMAT, BLOOM_FORMAT_FEATURES)?;
+
+        let max_mip_count = scene_extent.width.min(scene_extent.height).max(1).ilog2();
+        let mip_count = mip_count
+            .clamp(BLOOM_MIN_MIP_COUNT, BLOOM_MAX_MIP_COUNT)
+            .min(max_mip_count);
+        if mip_count < BLOOM_MIN_MIP_COUNT {
+            return Err(anyhow!(
+                "A {}x{} scene is too small for {} bloom mips",
+                scene_extent.width,
+                scene_extent.height,
+                BLOOM_MIN_MIP_COUNT
+            ));
+        }
+        let mips = (1..=mip_count)
+            .map(|level| {
+                let extent = Extent2D {
+                    width: (scene_extent.width >> level).max(1),
+                    height: (scene_extent.height >> level).max(1),
+                };
+                PistonImage::new(
+                    context,
+                    &ImageDesc {
+                        usage: ImageUsageFlags::STORAGE | ImageUsageFlags::SAMPLED,
+                        ..ImageDesc::texture_2d(extent, BLOOM_FORMAT)
+                    },
+                    &format!("bloom mip {}", level),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        execute_single_time_commands(context, |device, command_buffer| {
+            for mip in mips.iter() {
+                record_image_layout_transition(
+                    device,
+                    command_buffer,
+                    mip.image,
+                    mip.subresource_range,
+                    ImageLayout::UNDEFINED,
+                    ImageLayout::GENERAL,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        let downsample_pipeline = create_compute_pipeline(
+            context,
+            context.load_shader("bloom-downsample-comp.spv")?,
+            &SpecializationConstants::new(),
+        )?;
+        let upsample_pipeline = create_compute_pipeline(
+            context,
+            context.load_shader("bloom-upsample-comp.spv")?,
+            &SpecializationConstants::new(),
+        )?;
+
+        let set_count = 2 * mip_count - 1;
+        let descriptor_pool = create_descriptor_pool(
+            device,
+            &[
+                DescriptorPoolSize {
+                    ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: set_count,
+                },
+                DescriptorPoolSize {
+                    ty: DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: set_count,
+                },
+            ],
+            set_count,
+        )?;
+        let sampler = context
+            .get_or_create_sampler(&SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE))?;
+
+        let mut downsample_descriptor_sets = vec![];
+        for (index, mip) in mips.iter().enumerate() {
+            let source = match index {
+                0 => scene_color,
+                _ => mip_image_info(&mips[index - 1], sampler),
+            };
+            downsample_descriptor_sets.push(create_pass_descriptor_set(
+                device,
+                descriptor_pool,
+                &downsample_pipeline,
+                source,
+                mip,
+            )?);
+        }
+        let mut upsample_descriptor_sets = vec![];
+        for (index, mip) in mips.iter().enumerate().take(mips.len() - 1) {
+            upsample_descriptor_sets.push(create_pass_descriptor_set(
+                device,
+                descriptor_pool,
+                &upsample_pipeline,
+                mip_image_info(&mips[index + 1], sampler),
+                mip,
+            )?);
+        }
+
+        info!(
+            "Created a bloom chain of {} mips, starting at {}x{}",
+            mips.len(),
+            mips[0].extent.width,
+            mips[0].extent.height
+        );
+
+        Ok(Bloom {
+            mips,
+            downsample_pipeline,
+            upsample_pipeline,
+            descriptor_pool,
+            downsample_descriptor_sets,
+            upsample_descriptor_sets,
+            sampler,
+        })
+    }
+
+    /// Records the chain after the scene pass. Afterwards `mips[0]` is ready to be sampled by
+    /// fragment shaders.
+    pub fn record(&self, context: &VulkanContext, command_buffer: CommandBuffer, threshold: f32) {
+        let device = &context.device;
+        // The scene color was just written, and the previous frame's composite may still read
+        // `mips[0]`
+        record_memory_barrier(
+            device,
+            command_buffer,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::FRAGMENT_SHADER,
+            PipelineStageFlags::COMPUTE_SHADER,
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+        );
+
+        context.insert_label(command_buffer, "Bloom downsample");
+        for (index, mip) in self.mips.iter().enumerate() {
+            if index > 0 {
+                record_compute_barrier(device, command_buffer);
+            }
+            let prefilter = (index == 0) as u32;
+            let mut push_constants = threshold.to_ne_bytes().to_vec();
+            push_constants.extend_from_slice(&prefilter.to_ne_bytes());
+            unsafe {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.downsample_pipeline.pipeline.pipeline_layout,
+                    ShaderStageFlags::COMPUTE,
+                    0,
+                    &push_constants,
+                )
+            };
+            record_compute_dispatch(
+                device,
+                command_buffer,
+                &self.downsample_pipeline,
+                &[self.downsample_descriptor_sets[index]],
+                [mip.extent.width, mip.extent.height, 1],
+            );
+        }
+
+        context.insert_label(command_buffer, "Bloom upsample");
+        for (index, descriptor_set) in self.upsample_descriptor_sets.iter().enumerate().rev() {
+            record_compute_barrier(device, command_buffer);
+            let mip = &self.mips[index];
+            record_compute_dispatch(
+                device,
+                command_buffer,
+                &self.upsample_pipeline,
+                &[*descriptor_set],
+                [mip.extent.width, mip.extent.height, 1],
+            );
+        }
+
+        record_memory_barrier(
+            device,
+            command_buffer,
+            PipelineStageFlags::COMPUTE_SHADER,
+            PipelineStageFlags::FRAGMENT_SHADER,
+            AccessFlags::SHADER_WRITE,
+            AccessFlags::SHADER_READ,
+        );
+    }
+
+    pub fn descriptor_image_info(&self) -> DescriptorImageInfo {
+        mip_image_info(&self.mips[0], self.sampler)
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe { device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.upsample_pipeline.destroy(device);
+        self.downsample_pipeline.destroy(device);
+        for mip in self.mips.iter() {
+            mip.destroy(device);
+        }
+    }
+}
+
+fn mip_image_info(mip: &PistonImage, sampler: Sampler) -> DescriptorImageInfo {
+    DescriptorImageInfo::builder()
+        .sampler(sampler)
+        .image_view(mip.view)
+        .image_layout(ImageLayout::GENERAL)
+        .build()
+}
+
+fn create_pass_descriptor_set(
+    device: &Device,
+    descriptor_pool: DescriptorPool,
+    pipeline: &ComputePipeline,
+    source: DescriptorImageInfo,
+    destination: &PistonImage,
+) -> Result<DescriptorSet> {
+    let descriptor_set = allocate_descriptor_set(
+        device,
+        descriptor_pool,
+        pipeline.pipeline.descriptor_set_layouts[0],
+    )?;
+    write_combined_image_sampler(device, descriptor_set, BLOOM_SOURCE_BINDING, source);
+    write_storage_image(
+        device,
+        descriptor_set,
+        BLOOM_DESTINATION_BINDING,
+        DescriptorImageInfo::builder()
+            .image_view(destination.view)
+            .image_layout(ImageLayout::GENERAL)
+            .build(),
+    );
+
+    Ok(descriptor_set)
+}
+
+/// Each pass reads what the previous one wrote.
+fn record_compute_barrier(device: &Device, command_buffer: CommandBuffer) {
+    record_memory_barrier(
+        device,
+        command_buffer,
+        PipelineStageFlags::COMPUTE_SHADER,
+        PipelineStageFlags::COMPUTE_SHADER,
+        AccessFlags::SHADER_WRITE,
+        AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+    );
+}
+
+fn record_memory_barrier(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    src_stage: PipelineStageFlags,
+    dst_stage: PipelineStageFlags,
+    src_access_mask: AccessFlags,
+    dst_access_mask: AccessFlags,
+) {
+    let memory_barrier = MemoryBarrier::builder()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        )
+    };
+}
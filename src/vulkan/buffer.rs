@@ -0,0 +1,117 @@
+use std::ptr::copy_nonoverlapping;
+use std::slice::from_raw_parts;
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    Buffer, BufferCreateInfo, BufferUsageFlags, DeviceMemory, DeviceSize, MemoryMapFlags,
+    MemoryPropertyFlags, PhysicalDeviceMemoryProperties, SharingMode,
+};
+use ash::Device;
+
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::memory::{allocate_memory, free_memory};
+
+pub struct PistonBuffer {
+    pub buffer: Buffer,
+    pub memory: DeviceMemory,
+    pub size: DeviceSize,
+    heap_index: u32,
+    allocation_size: DeviceSize,
+}
+
+impl PistonBuffer {
+    pub fn new(
+        context: &VulkanContext,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+        memory_property_flags: MemoryPropertyFlags,
+        name: &str,
+    ) -> Result<PistonBuffer> {
+        let device = &context.device;
+        let buffer_create_info = BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(SharingMode::EXCLUSIVE)
+            .build();
+        let buffer = unsafe { device.create_buffer(&buffer_create_info, None) }?;
+
+        let memory_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let (memory, heap_index) =
+            allocate_memory(context, &memory_requirements, memory_property_flags)?;
+        unsafe { device.bind_buffer_memory(buffer, memory, 0) }?;
+        context.set_object_name(buffer, name);
+
+        Ok(PistonBuffer {
+            buffer,
+            memory,
+            size,
+            heap_index,
+            allocation_size: memory_requirements.size,
+        })
+    }
+
+    pub fn new_staging_with_data(context: &VulkanContext, data: &[u8]) -> Result<PistonBuffer> {
+        let staging_buffer = PistonBuffer::new(
+            context,
+            data.len() as DeviceSize,
+            BufferUsageFlags::TRANSFER_SRC,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            "staging buffer",
+        )?;
+        staging_buffer.write(&context.device, data)?;
+
+        Ok(staging_buffer)
+    }
+
+    pub fn write(&self, device: &Device, data: &[u8]) -> Result<()> {
+        if data.len() as DeviceSize > self.size {
+            return Err(anyhow!(
+                "Cannot write {} bytes into a buffer of {} bytes",
+                data.len(),
+                self.size
+            ));
+        }
+
+        unsafe {
+            let mapped_memory =
+                device.map_memory(self.memory, 0, self.size, MemoryMapFlags::empty())?;
+            copy_nonoverlapping(data.as_ptr(), mapped_memory as *mut u8, data.len());
+            device.unmap_memory(self.memory);
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, device: &Device) -> Result<Vec<u8>> {
+        unsafe {
+            let mapped_memory =
+                device.map_memory(self.memory, 0, self.size, MemoryMapFlags::empty())?;
+            let data = from_raw_parts(mapped_memory as *const u8, self.size as usize).to_vec();
+            device.unmap_memory(self.memory);
+
+            Ok(data)
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+        }
+        free_memory(device, self.memory, self.heap_index, self.allocation_size);
+    }
+}
+
+pub fn find_memory_type(
+    memory_properties: &PhysicalDeviceMemoryProperties,
+    type_filter: u32,
+    required_flags: MemoryPropertyFlags,
+) -> Result<u32> {
+    (0..memory_properties.memory_type_count)
+        .find(|&index| {
+            type_filter & (1 << index) != 0
+                && memory_properties.memory_types[index as usize]
+                    .property_flags
+                    .contains(required_flags)
+        })
+        .ok_or_else(|| anyhow!("No memory type found with flags {:?}", required_flags))
+}
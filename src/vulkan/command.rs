@@ -0,0 +1,112 @@
+use anyhow::Result;
+use ash::vk::{
+    CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
+    CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo, Queue,
+};
+use ash::Device;
+
+use crate::vulkan::context::VulkanContext;
+
+pub fn create_command_pool(device: &Device, queue_family_index: u32) -> Result<CommandPool> {
+    let command_pool_create_info = CommandPoolCreateInfo::builder()
+        .flags(CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(queue_family_index)
+        .build();
+
+    Ok(unsafe { device.create_command_pool(&command_pool_create_info, None) }?)
+}
+
+pub fn allocate_command_buffers(
+    device: &Device,
+    command_pool: CommandPool,
+    command_buffer_count: u32,
+) -> Result<Vec<CommandBuffer>> {
+    let command_buffer_allocate_info = CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(CommandBufferLevel::PRIMARY)
+        .command_buffer_count(command_buffer_count)
+        .build();
+
+    Ok(unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }?)
+}
+
+pub fn execute_single_time_commands<F>(context: &VulkanContext, record: F) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    execute_single_time_commands_on(
+        context,
+        context.command_pool,
+        context.graphics_queue,
+        "single time commands",
+        record,
+    )
+}
+
+/// Like `execute_single_time_commands`, on the background queue when the device has one, so
+/// load-time work doesn't contend with rendering.
+pub fn execute_single_time_commands_in_background<F>(
+    context: &VulkanContext,
+    record: F,
+) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    execute_single_time_commands_on(
+        context,
+        context.command_pool,
+        context.background_queue.unwrap_or(context.graphics_queue),
+        "background commands",
+        record,
+    )
+}
+
+/// `label` names the submission in errors and debug labels.
+pub fn execute_single_time_commands_on<F>(
+    context: &VulkanContext,
+    command_pool: CommandPool,
+    queue: Queue,
+    label: &str,
+    record: F,
+) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    let device = &context.device;
+    let command_buffers = allocate_command_buffers(device, command_pool, 1)?;
+    let command_buffer = command_buffers[0];
+
+    let result = record_and_submit(context, command_buffer, queue, label, record);
+
+    unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+
+    result
+}
+
+fn record_and_submit<F>(
+    context: &VulkanContext,
+    command_buffer: CommandBuffer,
+    queue: Queue,
+    label: &str,
+    record: F,
+) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    let device = &context.device;
+    let begin_info = CommandBufferBeginInfo::builder()
+        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .build();
+    unsafe { device.begin_command_buffer(command_buffer, &begin_info) }?;
+
+    record(device, command_buffer)?;
+
+    unsafe { device.end_command_buffer(command_buffer) }?;
+    context
+        .submission(queue, label)
+        .command_buffer(command_buffer)
+        .submit()?;
+    unsafe { device.queue_wait_idle(queue) }?;
+
+    Ok(())
+}
@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, BufferUsageFlags, CommandBuffer, CommandBufferBeginInfo, CommandBufferUsageFlags,
+    DependencyFlags, DescriptorBufferInfo, DescriptorPoolSize, DescriptorSet, DescriptorType,
+    DeviceSize, Fence, FenceCreateInfo, MemoryBarrier, MemoryPropertyFlags, PipelineBindPoint,
+    PipelineStageFlags, Semaphore, SemaphoreCreateInfo, ShaderStageFlags, WHOLE_SIZE,
+};
+use ash::Device;
+use log::info;
+
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::command::{allocate_command_buffers, execute_single_time_commands_on};
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::descriptor::{
+    allocate_descriptor_set, create_descriptor_pool, write_storage_buffer,
+};
+use crate::vulkan::pipeline::{create_compute_pipeline, ComputePipeline, SpecializationConstants};
+
+const GRADIENT_VALUE_COUNT: u32 = 1000;
+
+const GRADIENT_TOLERANCE: f32 = 1e-5;
+
+/// The number of workgroups needed to cover `problem_size` invocations.
+pub fn group_counts(problem_size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|axis| problem_size[axis].div_ceil(local_size[axis]))
+}
+
+/// Binds the pipeline and its descriptor sets, starting at set 0, and dispatches enough
+/// workgroups to cover `problem_size` invocations.
+pub fn record_compute_dispatch(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    pipeline: &ComputePipeline,
+    descriptor_sets: &[DescriptorSet],
+    problem_size: [u32; 3],
+) {
+    let [group_count_x, group_count_y, group_count_z] =
+        group_counts(problem_size, pipeline.local_size);
+    unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            PipelineBindPoint::COMPUTE,
+            pipeline.pipeline.pipeline,
+        );
+        if !descriptor_sets.is_empty() {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::COMPUTE,
+                pipeline.pipeline.pipeline_layout,
+                0,
+                descriptor_sets,
+                &[],
+            );
+        }
+        device.cmd_dispatch(command_buffer, group_count_x, group_count_y, group_count_z);
+    }
+}
+
+/// Records and submits commands on the compute queue and waits for them to finish.
+pub fn execute_compute_commands<F>(context: &VulkanContext, record: F) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    execute_single_time_commands_on(
+        context,
+        context.compute_command_pool,
+        context.compute_queue,
+        "compute commands",
+        record,
+    )
+}
+
+/// Compute work that was submitted without waiting for it. Graphics submits that consume its
+/// results wait on `semaphore`, in the stage that reads them. On a dedicated compute queue,
+/// resources with exclusive sharing also need a queue family ownership transfer, recorded with
+/// `release_ownership` here and `acquire_ownership` in the consuming submit.
+pub struct ComputeSubmission {
+    pub semaphore: Semaphore,
+    fence: Fence,
+    command_buffer: CommandBuffer,
+}
+
+impl ComputeSubmission {
+    /// Once complete, the submission can be destroyed.
+    pub fn is_complete(&self, device: &Device) -> Result<bool> {
+        Ok(unsafe { device.get_fence_status(self.fence) }?)
+    }
+
+    /// The submission must be complete, and every submit waiting on the semaphore must have
+    /// been submitted.
+    pub fn destroy(&self, context: &VulkanContext) {
+        unsafe {
+            context.device.destroy_fence(self.fence, None);
+            context.device.destroy_semaphore(self.semaphore, None);
+            context
+                .device
+                .free_command_buffers(context.compute_command_pool, &[self.command_buffer]);
+        }
+    }
+}
+
+/// Records and submits commands on the compute queue, which runs alongside rendering when the
+/// device has a dedicated compute family, see `VulkanContext::has_dedicated_compute_queue`.
+pub fn submit_compute_commands<F>(context: &VulkanContext, record: F) -> Result<ComputeSubmission>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    let device = &context.device;
+    let command_buffer = allocate_command_buffers(device, context.compute_command_pool, 1)?[0];
+    let begin_info = CommandBufferBeginInfo::builder()
+        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .build();
+    let recorded = unsafe { device.begin_command_buffer(command_buffer, &begin_info) }
+        .map_err(anyhow::Error::from)
+        .and_then(|()| record(device, command_buffer))
+        .and_then(|()| Ok(unsafe { device.end_command_buffer(command_buffer) }?));
+    if let Err(error) = recorded {
+        unsafe { device.free_command_buffers(context.compute_command_pool, &[command_buffer]) };
+        return Err(error);
+    }
+
+    let semaphore = unsafe { device.create_semaphore(&SemaphoreCreateInfo::default(), None) }?;
+    let fence = unsafe { device.create_fence(&FenceCreateInfo::default(), None) }?;
+    context.set_object_name(semaphore, "compute semaphore");
+    context.set_object_name(fence, "compute fence");
+    let submission = ComputeSubmission {
+        semaphore,
+        fence,
+        command_buffer,
+    };
+    if let Err(error) = context
+        .submission(context.compute_queue, "async compute")
+        .command_buffer(command_buffer)
+        .signal(semaphore)
+        .fence(fence)
+        .submit()
+    {
+        submission.destroy(context);
+        return Err(error);
+    }
+
+    Ok(submission)
+}
+
+/// Fills a storage buffer with a 0..1 gradient on the compute queue, reads it back and checks
+/// every value. Exercises the compute path without rendering anything.
+pub fn run_gradient_check(context: &VulkanContext) -> Result<()> {
+    let device = &context.device;
+    let pipeline = create_compute_pipeline(
+        context,
+        context.load_shader("gradient-comp.spv")?,
+        &SpecializationConstants::new(),
+    )?;
+    let buffer = PistonBuffer::new(
+        context,
+        GRADIENT_VALUE_COUNT as DeviceSize * 4,
+        BufferUsageFlags::STORAGE_BUFFER,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        "gradient buffer",
+    )?;
+    let descriptor_pool = create_descriptor_pool(
+        device,
+        &[DescriptorPoolSize {
+            ty: DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+        }],
+        1,
+    )?;
+
+    let result = allocate_descriptor_set(
+        device,
+        descriptor_pool,
+        pipeline.pipeline.descriptor_set_layouts[0],
+    )
+    .and_then(|descriptor_set| {
+        write_storage_buffer(
+            device,
+            descriptor_set,
+            0,
+            DescriptorBufferInfo::builder()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(WHOLE_SIZE)
+                .build(),
+        );
+        execute_compute_commands(context, |device, command_buffer| {
+            record_gradient(device, command_buffer, &pipeline, descriptor_set);
+            Ok(())
+        })
+    })
+    .and_then(|()| buffer.read(device))
+    .and_then(|bytes| check_gradient(&bytes));
+
+    unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+    buffer.destroy(device);
+    pipeline.destroy(device);
+
+    result
+}
+
+fn record_gradient(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    pipeline: &ComputePipeline,
+    descriptor_set: DescriptorSet,
+) {
+    unsafe {
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline.pipeline.pipeline_layout,
+            ShaderStageFlags::COMPUTE,
+            0,
+            &GRADIENT_VALUE_COUNT.to_ne_bytes(),
+        )
+    };
+    record_compute_dispatch(
+        device,
+        command_buffer,
+        pipeline,
+        &[descriptor_set],
+        [GRADIENT_VALUE_COUNT, 1, 1],
+    );
+
+    let memory_barrier = MemoryBarrier::builder()
+        .src_access_mask(AccessFlags::SHADER_WRITE)
+        .dst_access_mask(AccessFlags::HOST_READ)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            PipelineStageFlags::COMPUTE_SHADER,
+            PipelineStageFlags::HOST,
+            DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        )
+    };
+}
+
+fn check_gradient(bytes: &[u8]) -> Result<()> {
+    for (index, value_bytes) in bytes.chunks_exact(4).enumerate() {
+        let value = f32::from_ne_bytes(value_bytes.try_into()?);
+        let expected = index as f32 / GRADIENT_VALUE_COUNT as f32;
+        if (value - expected).abs() > GRADIENT_TOLERANCE {
+            return Err(anyhow!(
+                "Gradient value {} is {}, expected {}",
+                index,
+                value,
+                expected
+            ));
+        }
+    }
+    info!(
+        "Compute gradient check passed for {} values",
+        GRADIENT_VALUE_COUNT
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+        values.flat_map(f32::to_ne_bytes).collect()
+    }
+
+    #[test]
+    fn exact_gradient_passes() {
+        let bytes = gradient_bytes(
+            (0..GRADIENT_VALUE_COUNT).map(|index| index as f32 / GRADIENT_VALUE_COUNT as f32),
+        );
+        assert!(check_gradient(&bytes).is_ok());
+    }
+
+    #[test]
+    fn wrong_value_fails() {
+        let bytes = gradient_bytes((0..GRADIENT_VALUE_COUNT).map(|index| match index {
+            500 => 0.0,
+            _ => index as f32 / GRADIENT_VALUE_COUNT as f32,
+        }));
+        let error = check_gradient(&bytes).unwrap_err();
+        assert!(error.to_string().starts_with("Gradient value 500 is 0"));
+    }
+
+    #[test]
+    fn group_counts_round_up() {
+        assert_eq!(group_counts([1000, 1, 1], [64, 1, 1]), [16, 1, 1]);
+        assert_eq!(group_counts([64, 17, 1], [64, 8, 1]), [1, 3, 1]);
+    }
+}
@@ -0,0 +1,554 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use ash::extensions::ext::DebugUtils;
+use ash::extensions::khr::DynamicRendering;
+#[cfg(feature = "wgsl")]
+use ash::vk::ShaderStageFlags;
+use ash::vk::{
+    CommandBuffer, CommandPool, ExtDeviceFaultFn, ExtFullScreenExclusiveFn, Extent2D, Framebuffer,
+    Handle, ImageView, PhysicalDevice, PhysicalDeviceMemoryBudgetPropertiesEXT,
+    PhysicalDeviceMemoryProperties2, PipelineCache, Queue, RenderPass, Sampler, SurfaceKHR,
+};
+use ash::{Device, Instance};
+use log::{info, warn};
+use winit::dpi::PhysicalSize;
+
+use crate::config::{DepthConvention, EngineConfig};
+use crate::constants::{
+    DEVICE_FAULT_EXTENSION, FULL_SCREEN_EXCLUSIVE_EXTENSION, MARKER_LABEL_COLOR,
+    MEMORY_BUDGET_EXTENSION, SHADER_NON_SEMANTIC_INFO_EXTENSION,
+};
+use crate::util::debug::{cmd_insert_label, set_object_name, ScopedLabel};
+use crate::util::util::yes_no;
+use crate::vulkan::command::create_command_pool;
+use crate::vulkan::device::QueueFamilyIndices;
+use crate::vulkan::device_info::DeviceInfo;
+use crate::vulkan::device_lost::load_device_fault;
+use crate::vulkan::features::{DeviceCapabilities, DeviceFeature};
+use crate::vulkan::format::CompressedFormatSupport;
+use crate::vulkan::framebuffer::FramebufferManager;
+use crate::vulkan::full_screen_exclusive::load_full_screen_exclusive;
+use crate::vulkan::memory::{allocated_bytes, HeapBudget};
+use crate::vulkan::pipeline::load_shader_code;
+use crate::vulkan::pipeline_cache::{create_pipeline_cache, save_pipeline_cache};
+use crate::vulkan::reflect::{ReflectionCache, ShaderReflection};
+use crate::vulkan::render_graph::{GraphImage, TransientImageDesc, TransientImagePool};
+use crate::vulkan::render_pass_cache::{RenderPassCache, RenderPassDesc};
+use crate::vulkan::sampler::{SamplerCache, SamplerDesc};
+use crate::vulkan::shader_cache::{ShaderCache, ShaderHandle};
+use crate::vulkan::submit::SubmitBuilder;
+use crate::vulkan::surface::SurfaceEntities;
+use crate::vulkan::swapchain::{SurfaceSupportCache, SwapchainSupportDetails};
+use crate::vulkan::texture::DefaultTextures;
+use crate::vulkan::upload::AsyncUpload;
+#[cfg(feature = "wgsl")]
+use crate::vulkan::wgsl::translate_wgsl_shader;
+
+pub struct VulkanContext {
+    pub instance: Instance,
+    pub physical_device: PhysicalDevice,
+    pub device: Device,
+    pub queue_family_indices: QueueFamilyIndices,
+    pub graphics_queue: Queue,
+    /// `None` for headless contexts
+    pub present_queue: Option<Queue>,
+    pub transfer_queue: Queue,
+    pub compute_queue: Queue,
+    /// A second, lower priority queue of the graphics family, for background work
+    pub background_queue: Option<Queue>,
+    pub command_pool: CommandPool,
+    pub transfer_command_pool: CommandPool,
+    pub compute_command_pool: CommandPool,
+    pub device_info: DeviceInfo,
+    /// The features the device was created with
+    pub capabilities: DeviceCapabilities,
+    pub compressed_format_support: CompressedFormatSupport,
+    pub pipeline_cache: PipelineCache,
+    pipeline_cache_path: Option<PathBuf>,
+    pub shader_dir: PathBuf,
+    pub pipeline_derivatives: bool,
+    pub depth_convention: DepthConvention,
+    /// Loaded when the device was created with dynamic rendering enabled
+    pub dynamic_rendering: Option<DynamicRendering>,
+    /// Loaded when `VK_EXT_device_fault` is enabled
+    pub device_fault: Option<ExtDeviceFaultFn>,
+    /// Loaded when `VK_EXT_full_screen_exclusive` is enabled
+    pub full_screen_exclusive: Option<ExtFullScreenExclusiveFn>,
+    /// Set by the application when validation is enabled, labels submissions
+    pub debug_utils: Option<DebugUtils>,
+    /// Shaders may call `debugPrintfEXT`, the layer prints them and the device accepts them
+    pub debug_printf: bool,
+    pub sampler_cache: Mutex<SamplerCache>,
+    pub render_pass_cache: Mutex<RenderPassCache>,
+    pub framebuffer_manager: Mutex<FramebufferManager>,
+    pub transient_image_pool: Mutex<TransientImagePool>,
+    pub reflection_cache: Mutex<ReflectionCache>,
+    pub shader_cache: Mutex<ShaderCache>,
+    pub async_uploads: Mutex<Vec<AsyncUpload>>,
+    pub surface_support_cache: Mutex<SurfaceSupportCache>,
+    default_textures: Option<DefaultTextures>,
+}
+
+/// How the device was set up beyond its features, decided before the context is created.
+pub struct ContextOptions {
+    /// Whether the device was created with dynamic rendering enabled
+    pub dynamic_rendering: bool,
+    /// Set when validation is enabled
+    pub debug_utils: Option<DebugUtils>,
+    /// Whether the validation layer was asked to print `debugPrintfEXT` messages
+    pub debug_printf: bool,
+}
+
+impl VulkanContext {
+    pub fn new(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        device: Device,
+        queue_family_indices: QueueFamilyIndices,
+        capabilities: DeviceCapabilities,
+        options: ContextOptions,
+        config: &EngineConfig,
+    ) -> Result<VulkanContext> {
+        let graphics_family_index = queue_family_indices
+            .primary_family_index()
+            .ok_or_else(|| anyhow!("The device has no graphics or compute queue family"))?;
+        let transfer_family_index = queue_family_indices
+            .transfer_family_index
+            .ok_or_else(|| anyhow!("The device has no transfer queue family"))?;
+        let compute_family_index = queue_family_indices
+            .compute_family_index
+            .ok_or_else(|| anyhow!("The device has no compute queue family"))?;
+
+        let graphics_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
+        let present_queue =
+            queue_family_indices
+                .present_family_index
+                .map(|present_family_index| unsafe {
+                    device.get_device_queue(present_family_index, 0)
+                });
+        let transfer_queue = unsafe { device.get_device_queue(transfer_family_index, 0) };
+        let compute_queue = unsafe { device.get_device_queue(compute_family_index, 0) };
+        let background_queue = (queue_family_indices.graphics_queue_count > 1)
+            .then(|| unsafe { device.get_device_queue(graphics_family_index, 1) });
+        let command_pool = create_command_pool(&device, graphics_family_index)?;
+        let transfer_command_pool = create_command_pool(&device, transfer_family_index)?;
+        let compute_command_pool = create_command_pool(&device, compute_family_index)?;
+        info!(
+            "Using queue family {} for transfers (dedicated: {})",
+            transfer_family_index,
+            yes_no(transfer_family_index != graphics_family_index)
+        );
+        info!(
+            "Using queue family {} for compute (dedicated: {})",
+            compute_family_index,
+            yes_no(compute_family_index != graphics_family_index)
+        );
+        info!(
+            "Background queue available: {}",
+            yes_no(queue_family_indices.graphics_queue_count > 1)
+        );
+
+        let device_info = DeviceInfo::new(instance, physical_device, &capabilities);
+        device_info.log_summary();
+        let compressed_format_support =
+            CompressedFormatSupport::from_features(&capabilities.features());
+        info!(
+            "Compressed texture families supported: {:?}",
+            compressed_format_support.supported_families()
+        );
+        let max_sampler_anisotropy = capabilities
+            .is_enabled(DeviceFeature::SamplerAnisotropy)
+            .then_some(device_info.max_sampler_anisotropy());
+        let pipeline_cache =
+            create_pipeline_cache(&device, &device_info, config.pipeline_cache_path.as_deref())?;
+        let dynamic_rendering = options
+            .dynamic_rendering
+            .then(|| DynamicRendering::new(instance, &device));
+        let device_fault = capabilities
+            .is_extension_enabled(DEVICE_FAULT_EXTENSION)
+            .then(|| load_device_fault(instance, &device));
+        let full_screen_exclusive = capabilities
+            .is_extension_enabled(FULL_SCREEN_EXCLUSIVE_EXTENSION)
+            .then(|| load_full_screen_exclusive(instance, &device));
+        let debug_printf = match (
+            options.debug_printf,
+            capabilities.is_extension_enabled(SHADER_NON_SEMANTIC_INFO_EXTENSION),
+        ) {
+            (true, false) => {
+                warn!(
+                    "Debug printf needs {:?}, shaders won't print",
+                    SHADER_NON_SEMANTIC_INFO_EXTENSION
+                );
+                false
+            }
+            (debug_printf, _) => debug_printf,
+        };
+
+        let mut context = VulkanContext {
+            instance: instance.clone(),
+            physical_device,
+            device,
+            queue_family_indices,
+            graphics_queue,
+            present_queue,
+            transfer_queue,
+            compute_queue,
+            background_queue,
+            command_pool,
+            transfer_command_pool,
+            compute_command_pool,
+            device_info,
+            capabilities,
+            compressed_format_support,
+            pipeline_cache,
+            pipeline_cache_path: config.pipeline_cache_path.clone(),
+            shader_dir: config.shader_dir.clone(),
+            pipeline_derivatives: config.pipeline_derivatives,
+            depth_convention: config.depth_convention,
+            dynamic_rendering,
+            device_fault,
+            full_screen_exclusive,
+            debug_utils: options.debug_utils,
+            debug_printf,
+            sampler_cache: Mutex::new(SamplerCache::new(max_sampler_anisotropy)),
+            render_pass_cache: Mutex::new(RenderPassCache::new()),
+            framebuffer_manager: Mutex::new(FramebufferManager::new()),
+            transient_image_pool: Mutex::new(TransientImagePool::new()),
+            reflection_cache: Mutex::new(ReflectionCache::new()),
+            shader_cache: Mutex::new(ShaderCache::new(config.validate_shaders)),
+            async_uploads: Mutex::new(vec![]),
+            surface_support_cache: Mutex::new(SurfaceSupportCache::new()),
+            default_textures: None,
+        };
+        context.set_object_name(context.command_pool, "graphics command pool");
+        context.set_object_name(context.transfer_command_pool, "transfer command pool");
+        context.set_object_name(context.compute_command_pool, "compute command pool");
+        context.set_object_name(context.pipeline_cache, "pipeline cache");
+        let default_textures = DefaultTextures::new(&context).inspect_err(|_| context.destroy())?;
+        context.default_textures = Some(default_textures);
+
+        Ok(context)
+    }
+
+    /// Names `handle` in validation messages, when validation is enabled.
+    pub fn set_object_name<T: Handle>(&self, handle: T, name: &str) {
+        set_object_name(self.debug_utils.as_ref(), &self.device, handle, name);
+    }
+
+    /// Labels the commands recorded until the returned guard is dropped.
+    pub fn begin_label(
+        &self,
+        command_buffer: CommandBuffer,
+        name: &str,
+        color: [f32; 4],
+    ) -> ScopedLabel<'_> {
+        ScopedLabel::begin(self.debug_utils.as_ref(), command_buffer, name, color)
+    }
+
+    pub fn insert_label(&self, command_buffer: CommandBuffer, name: &str) {
+        cmd_insert_label(
+            self.debug_utils.as_ref(),
+            command_buffer,
+            name,
+            MARKER_LABEL_COLOR,
+        );
+    }
+
+    pub fn default_textures(&self) -> &DefaultTextures {
+        self.default_textures
+            .as_ref()
+            .expect("Default textures are created with the context")
+    }
+
+    pub fn shader_path(&self, file_name: &str) -> PathBuf {
+        self.shader_dir.join(file_name)
+    }
+
+    /// Starts a submission to `queue`, `label` names it in errors and debug labels.
+    pub fn submission(&self, queue: Queue, label: &str) -> SubmitBuilder<'_> {
+        SubmitBuilder::new(self, queue, label)
+    }
+
+    pub fn has_dedicated_transfer_queue(&self) -> bool {
+        self.queue_family_indices.transfer_family_index
+            != self.queue_family_indices.graphics_family_index
+    }
+
+    pub fn has_dedicated_compute_queue(&self) -> bool {
+        self.queue_family_indices.compute_family_index
+            != self.queue_family_indices.graphics_family_index
+    }
+
+    /// One entry per memory heap, with the budget when `VK_EXT_memory_budget` is enabled.
+    pub fn memory_budget(&self) -> Vec<HeapBudget> {
+        let memory_properties = &self.device_info.memory_properties;
+        let has_budget = self
+            .capabilities
+            .is_extension_enabled(MEMORY_BUDGET_EXTENSION);
+        let mut budget_properties = PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        if has_budget {
+            let mut memory_properties2 = PhysicalDeviceMemoryProperties2::builder()
+                .push_next(&mut budget_properties)
+                .build();
+            unsafe {
+                self.instance.get_physical_device_memory_properties2(
+                    self.physical_device,
+                    &mut memory_properties2,
+                )
+            };
+        }
+
+        memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| HeapBudget {
+                heap_index: index as u32,
+                size: heap.size,
+                flags: heap.flags,
+                allocated: allocated_bytes(index as u32),
+                usage: has_budget.then_some(budget_properties.heap_usage[index]),
+                budget: has_budget.then_some(budget_properties.heap_budget[index]),
+            })
+            .collect()
+    }
+
+    /// Releases the staging resources of async uploads that have finished and marks them ready.
+    /// Meant to be called once per frame, returns the number of uploads that completed.
+    pub fn poll_async_uploads(&self) -> Result<usize> {
+        let mut async_uploads = self
+            .async_uploads
+            .lock()
+            .map_err(|_| anyhow!("Async upload list lock is poisoned"))?;
+
+        let mut completed = 0;
+        let mut index = 0;
+        while index < async_uploads.len() {
+            if async_uploads[index].is_complete(&self.device)? {
+                async_uploads.swap_remove(index).complete(&self.device);
+                completed += 1;
+            } else {
+                index += 1;
+            }
+        }
+
+        Ok(completed)
+    }
+
+    pub fn get_or_create_sampler(&self, desc: &SamplerDesc) -> Result<Sampler> {
+        self.sampler_cache
+            .lock()
+            .map_err(|_| anyhow!("Sampler cache lock is poisoned"))?
+            .get_or_create(&self.device, self.debug_utils.as_ref(), desc)
+    }
+
+    /// Render passes from the cache are owned by the context, they are destroyed with it. A
+    /// sample count the device doesn't support is lowered, like the pipelines' counts.
+    pub fn get_or_create_render_pass(&self, desc: &RenderPassDesc) -> Result<RenderPass> {
+        let desc = RenderPassDesc {
+            samples: self.device_info.clamp_sample_count(desc.samples),
+            ..desc.clone()
+        };
+        self.render_pass_cache
+            .lock()
+            .map_err(|_| anyhow!("Render pass cache lock is poisoned"))?
+            .get_or_create(&self.device, self.debug_utils.as_ref(), &desc)
+    }
+
+    /// The desc `render_pass` was created for, if it came from the render pass cache.
+    pub fn render_pass_desc(&self, render_pass: RenderPass) -> Result<Option<RenderPassDesc>> {
+        Ok(self
+            .render_pass_cache
+            .lock()
+            .map_err(|_| anyhow!("Render pass cache lock is poisoned"))?
+            .desc(render_pass)
+            .cloned())
+    }
+
+    pub fn get_or_create_framebuffer(
+        &self,
+        render_pass: RenderPass,
+        attachments: &[ImageView],
+        extent: Extent2D,
+    ) -> Result<Framebuffer> {
+        self.framebuffer_manager
+            .lock()
+            .map_err(|_| anyhow!("Framebuffer manager lock is poisoned"))?
+            .get_or_create(
+                &self.device,
+                self.debug_utils.as_ref(),
+                render_pass,
+                attachments,
+                extent,
+            )
+    }
+
+    /// Call from the swapchain recreation path and whenever attachment images are recreated,
+    /// after waiting for the device to go idle.
+    pub fn invalidate_framebuffers(&self) -> Result<()> {
+        self.framebuffer_manager
+            .lock()
+            .map_err(|_| anyhow!("Framebuffer manager lock is poisoned"))?
+            .invalidate(&self.device);
+
+        Ok(())
+    }
+
+    /// The cached support details of the surface, queried on the first call after an
+    /// invalidation or when they no longer match `window_size`.
+    pub fn surface_capabilities(
+        &self,
+        surface_entities: &SurfaceEntities,
+        window_size: PhysicalSize<u32>,
+    ) -> Result<SwapchainSupportDetails> {
+        self.surface_support_cache
+            .lock()
+            .map_err(|_| anyhow!("Surface support cache lock is poisoned"))?
+            .get_or_query(self.physical_device, surface_entities, window_size)
+    }
+
+    /// Queries the support details of the surface again, whether or not they are cached.
+    pub fn refresh_surface_capabilities(
+        &self,
+        surface_entities: &SurfaceEntities,
+    ) -> Result<SwapchainSupportDetails> {
+        self.surface_support_cache
+            .lock()
+            .map_err(|_| anyhow!("Surface support cache lock is poisoned"))?
+            .refresh(self.physical_device, surface_entities)
+    }
+
+    /// Call when the window is resized, the swapchain is out of date or suboptimal, or the
+    /// surface is recreated.
+    pub fn invalidate_surface_capabilities(&self, surface: SurfaceKHR) -> Result<()> {
+        self.surface_support_cache
+            .lock()
+            .map_err(|_| anyhow!("Surface support cache lock is poisoned"))?
+            .invalidate(surface);
+
+        Ok(())
+    }
+
+    /// One image per desc, images of the same desc are distinct. The pool owns them, a call with
+    /// the same descs returns the same images.
+    pub fn acquire_transient_images(
+        &self,
+        descs: &[TransientImageDesc],
+    ) -> Result<Vec<GraphImage>> {
+        self.transient_image_pool
+            .lock()
+            .map_err(|_| anyhow!("Transient image pool lock is poisoned"))?
+            .acquire(self, descs)
+    }
+
+    pub fn reflect_shader(
+        &self,
+        shader_code: &[u32],
+        entry_point: &str,
+    ) -> Result<ShaderReflection> {
+        self.reflection_cache
+            .lock()
+            .map_err(|_| anyhow!("Reflection cache lock is poisoned"))?
+            .get_or_reflect(shader_code, entry_point)
+    }
+
+    /// Loads a shader from the shader directory, or its embedded copy, through the shader cache.
+    pub fn load_shader(&self, file_name: &str) -> Result<ShaderHandle> {
+        let shader_path = self.shader_path(file_name);
+        let shader_code = load_shader_code(&shader_path)
+            .with_context(|| format!("Failed to load shader from {:?}", shader_path))?;
+        self.shader_cache
+            .lock()
+            .map_err(|_| anyhow!("Shader cache lock is poisoned"))?
+            .get_or_create(
+                &self.device,
+                self.debug_utils.as_ref(),
+                file_name,
+                &shader_code,
+            )
+    }
+
+    /// Loads the `stage` entry point of a WGSL source through the shader cache. naga's reflection
+    /// of the entry point stands in for reflecting the translated SPIR-V.
+    #[cfg(feature = "wgsl")]
+    pub fn load_wgsl_shader(
+        &self,
+        file_name: &str,
+        stage: ShaderStageFlags,
+    ) -> Result<ShaderHandle> {
+        let shader = translate_wgsl_shader(&self.shader_dir, file_name, stage)?;
+        self.reflection_cache
+            .lock()
+            .map_err(|_| anyhow!("Reflection cache lock is poisoned"))?
+            .insert(&shader.code, shader.reflection);
+        self.shader_cache
+            .lock()
+            .map_err(|_| anyhow!("Shader cache lock is poisoned"))?
+            .get_or_create(
+                &self.device,
+                self.debug_utils.as_ref(),
+                &shader.name,
+                &shader.code,
+            )
+    }
+
+    /// Destroys the shader modules that no pipeline uses any more and logs the cache stats.
+    pub fn retire_unused_shaders(&self) -> Result<()> {
+        let mut shader_cache = self
+            .shader_cache
+            .lock()
+            .map_err(|_| anyhow!("Shader cache lock is poisoned"))?;
+        shader_cache.retire_unused(&self.device);
+        shader_cache.log_stats();
+
+        Ok(())
+    }
+
+    /// Destroys the device-level objects owned by the context, including the logical device
+    /// itself. The instance is left alone, it is owned by the application.
+    pub fn destroy(&self) {
+        if let Some(default_textures) = &self.default_textures {
+            default_textures.destroy(&self.device);
+        }
+        if let Ok(mut sampler_cache) = self.sampler_cache.lock() {
+            sampler_cache.destroy_all(&self.device);
+        }
+        if let Ok(mut framebuffer_manager) = self.framebuffer_manager.lock() {
+            framebuffer_manager.destroy_all(&self.device);
+        }
+        if let Ok(mut transient_image_pool) = self.transient_image_pool.lock() {
+            transient_image_pool.destroy_all(&self.device);
+        }
+        if let Ok(mut render_pass_cache) = self.render_pass_cache.lock() {
+            render_pass_cache.destroy_all(&self.device);
+        }
+        if let Ok(mut shader_cache) = self.shader_cache.lock() {
+            shader_cache.destroy_all(&self.device);
+        }
+        if let Ok(mut async_uploads) = self.async_uploads.lock() {
+            for async_upload in async_uploads.drain(..) {
+                async_upload.destroy(&self.device);
+            }
+        }
+
+        if let Some(pipeline_cache_path) = &self.pipeline_cache_path {
+            if let Err(error) =
+                save_pipeline_cache(&self.device, self.pipeline_cache, pipeline_cache_path)
+            {
+                warn!("Failed to save the pipeline cache: {:?}", error);
+            }
+        }
+
+        unsafe {
+            self.device
+                .destroy_pipeline_cache(self.pipeline_cache, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device
+                .destroy_command_pool(self.transfer_command_pool, None);
+            self.device
+                .destroy_command_pool(self.compute_command_pool, None);
+            self.device.destroy_device(None);
+        }
+    }
+}
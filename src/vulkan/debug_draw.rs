@@ -0,0 +1,290 @@
+use std::mem::{size_of, size_of_val};
+use std::slice::from_raw_parts;
+use std::sync::Once;
+
+use anyhow::Result;
+use ash::vk::{
+    BufferUsageFlags, CommandBuffer, DeviceSize, DynamicState, Format, MemoryPropertyFlags,
+    PipelineBindPoint, PrimitiveTopology, VertexInputAttributeDescription,
+    VertexInputBindingDescription, VertexInputRate,
+};
+use ash::Device;
+use log::info;
+
+use crate::constants::{DEBUG_POINT_SIZE, DEBUG_POINT_SIZE_CONSTANT_ID};
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::features::DeviceFeature;
+use crate::vulkan::pipeline::{
+    PipelineBuilder, PistonPipeline, SpecializationConstants, VertexLayout,
+};
+use crate::vulkan::render::RenderTarget;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl DebugVertex {
+    pub fn vertex_layout() -> VertexLayout {
+        VertexLayout {
+            bindings: vec![VertexInputBindingDescription::builder()
+                .binding(0)
+                .stride(size_of::<DebugVertex>() as u32)
+                .input_rate(VertexInputRate::VERTEX)
+                .build()],
+            attributes: vec![
+                VertexInputAttributeDescription::builder()
+                    .binding(0)
+                    .location(0)
+                    .format(Format::R32G32B32_SFLOAT)
+                    .offset(0)
+                    .build(),
+                VertexInputAttributeDescription::builder()
+                    .binding(0)
+                    .location(1)
+                    .format(Format::R32G32B32A32_SFLOAT)
+                    .offset(size_of::<[f32; 3]>() as u32)
+                    .build(),
+            ],
+        }
+    }
+}
+
+static NARROW_LINES_LOGGED: Once = Once::new();
+
+/// The line widths the device can rasterize.
+#[derive(Clone, Copy, Debug)]
+pub struct LineWidthLimits {
+    pub wide_lines: bool,
+    pub range: [f32; 2],
+    pub granularity: f32,
+}
+
+impl LineWidthLimits {
+    pub fn new(context: &VulkanContext) -> LineWidthLimits {
+        LineWidthLimits {
+            wide_lines: context.capabilities.is_enabled(DeviceFeature::WideLines),
+            range: context.device_info.limits.line_width_range,
+            granularity: context.device_info.limits.line_width_granularity,
+        }
+    }
+
+    /// Snaps `width` to the nearest supported step and clamps it to the supported range. Without
+    /// the `wide_lines` feature the only valid width is 1.0.
+    pub fn clamp(&self, width: f32) -> f32 {
+        if !self.wide_lines {
+            return 1.0;
+        }
+
+        let [min_width, max_width] = self.range;
+        let snapped_width = if self.granularity > 0.0 {
+            min_width + ((width - min_width) / self.granularity).round() * self.granularity
+        } else {
+            width
+        };
+
+        snapped_width.clamp(min_width, max_width)
+    }
+}
+
+/// Line and point list pipelines sharing the position + color debug vertex layout.
+pub struct DebugPipelines {
+    pub line: PistonPipeline,
+    pub point: PistonPipeline,
+    pub line_width_limits: LineWidthLimits,
+}
+
+impl DebugPipelines {
+    pub fn new(context: &VulkanContext, render_target: &RenderTarget) -> Result<DebugPipelines> {
+        let line = create_debug_pipeline(context, render_target, PrimitiveTopology::LINE_LIST)?;
+        let point = create_debug_pipeline(context, render_target, PrimitiveTopology::POINT_LIST)
+            .inspect_err(|_| line.destroy(&context.device))?;
+
+        Ok(DebugPipelines {
+            line,
+            point,
+            line_width_limits: LineWidthLimits::new(context),
+        })
+    }
+
+    /// Sets the width of the lines drawn with the line pipeline, clamped to what the device
+    /// supports.
+    pub fn set_line_width(&self, device: &Device, command_buffer: CommandBuffer, width: f32) {
+        let line_width = self.line_width_limits.clamp(width);
+        if !self.line_width_limits.wide_lines && width != line_width {
+            NARROW_LINES_LOGGED.call_once(|| {
+                info!(
+                    "Wide lines are not supported by this device, drawing {} pixel lines at 1.0",
+                    width
+                )
+            });
+        }
+
+        unsafe { device.cmd_set_line_width(command_buffer, line_width) };
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.line.destroy(device);
+        self.point.destroy(device);
+    }
+}
+
+fn create_debug_pipeline(
+    context: &VulkanContext,
+    render_target: &RenderTarget,
+    topology: PrimitiveTopology,
+) -> Result<PistonPipeline> {
+    let dynamic_states: &[DynamicState] = if topology == PrimitiveTopology::LINE_LIST {
+        &[DynamicState::LINE_WIDTH]
+    } else {
+        &[]
+    };
+
+    // Point size is written by the vertex shader, the line pipeline ignores it
+    PipelineBuilder::new()
+        .shaders(
+            context.load_shader("debug-vert.spv")?,
+            context.load_shader("debug-frag.spv")?,
+        )
+        .vertex_constants(
+            SpecializationConstants::new().with_f32(DEBUG_POINT_SIZE_CONSTANT_ID, DEBUG_POINT_SIZE),
+        )
+        .vertex_layout(DebugVertex::vertex_layout())
+        .topology(topology)
+        .depth_test(true)
+        .dynamic_states(dynamic_states)
+        .render_target(render_target)
+        .build(context)
+}
+
+/// Creates a host visible vertex buffer holding `vertices`.
+pub fn create_debug_vertex_buffer(
+    context: &VulkanContext,
+    vertices: &[DebugVertex],
+) -> Result<PistonBuffer> {
+    let vertex_bytes =
+        unsafe { from_raw_parts(vertices.as_ptr() as *const u8, size_of_val(vertices)) };
+    let vertex_buffer = PistonBuffer::new(
+        context,
+        vertex_bytes.len() as DeviceSize,
+        BufferUsageFlags::VERTEX_BUFFER,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        "debug draw vertices",
+    )?;
+    vertex_buffer.write(&context.device, vertex_bytes)?;
+
+    Ok(vertex_buffer)
+}
+
+/// Static debug lines and points stored in one host visible vertex buffer, lines first.
+pub struct DebugGeometry {
+    vertex_buffer: PistonBuffer,
+    line_vertex_count: u32,
+    point_vertex_count: u32,
+}
+
+impl DebugGeometry {
+    pub fn new(
+        context: &VulkanContext,
+        line_vertices: &[DebugVertex],
+        point_vertices: &[DebugVertex],
+    ) -> Result<DebugGeometry> {
+        let vertex_buffer =
+            create_debug_vertex_buffer(context, &[line_vertices, point_vertices].concat())?;
+
+        Ok(DebugGeometry {
+            vertex_buffer,
+            line_vertex_count: line_vertices.len() as u32,
+            point_vertex_count: point_vertices.len() as u32,
+        })
+    }
+
+    /// Records the lines and then the points, switching pipelines in between. Must be recorded
+    /// inside the render pass the pipelines were created for.
+    pub fn record(
+        &self,
+        device: &Device,
+        command_buffer: CommandBuffer,
+        pipelines: &DebugPipelines,
+        line_width: f32,
+    ) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.buffer], &[0]);
+            if self.line_vertex_count > 0 {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipelines.line.pipeline,
+                );
+                pipelines.set_line_width(device, command_buffer, line_width);
+                device.cmd_draw(command_buffer, self.line_vertex_count, 1, 0, 0);
+            }
+            if self.point_vertex_count > 0 {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipelines.point.pipeline,
+                );
+                device.cmd_draw(
+                    command_buffer,
+                    self.point_vertex_count,
+                    1,
+                    self.line_vertex_count,
+                    0,
+                );
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.vertex_buffer.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide_lines(range: [f32; 2], granularity: f32) -> LineWidthLimits {
+        LineWidthLimits {
+            wide_lines: true,
+            range,
+            granularity,
+        }
+    }
+
+    #[test]
+    fn narrow_lines_are_always_one_pixel() {
+        let limits = LineWidthLimits {
+            wide_lines: false,
+            range: [1.0, 8.0],
+            granularity: 0.125,
+        };
+        assert_eq!(limits.clamp(4.0), 1.0);
+        assert_eq!(limits.clamp(0.5), 1.0);
+    }
+
+    #[test]
+    fn line_width_snaps_to_the_granularity() {
+        let limits = wide_lines([1.0, 8.0], 0.5);
+        assert_eq!(limits.clamp(2.3), 2.5);
+        assert_eq!(limits.clamp(2.2), 2.0);
+        assert_eq!(limits.clamp(3.0), 3.0);
+    }
+
+    #[test]
+    fn line_width_is_clamped_to_the_range() {
+        let limits = wide_lines([1.0, 8.0], 0.5);
+        assert_eq!(limits.clamp(0.1), 1.0);
+        assert_eq!(limits.clamp(100.0), 8.0);
+    }
+
+    #[test]
+    fn zero_granularity_keeps_the_width() {
+        let limits = wide_lines([0.5, 10.0], 0.0);
+        assert_eq!(limits.clamp(3.3), 3.3);
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use ash::vk::{
+    DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorPoolCreateInfo,
+    DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout,
+    DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType, WriteDescriptorSet,
+};
+use ash::Device;
+
+pub fn create_descriptor_set_layout(
+    device: &Device,
+    bindings: &[DescriptorSetLayoutBinding],
+) -> Result<DescriptorSetLayout> {
+    let descriptor_set_layout_create_info = DescriptorSetLayoutCreateInfo::builder()
+        .bindings(bindings)
+        .build();
+
+    Ok(unsafe { device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None) }?)
+}
+
+pub fn create_descriptor_pool(
+    device: &Device,
+    pool_sizes: &[DescriptorPoolSize],
+    max_sets: u32,
+) -> Result<DescriptorPool> {
+    let descriptor_pool_create_info = DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(max_sets)
+        .build();
+
+    Ok(unsafe { device.create_descriptor_pool(&descriptor_pool_create_info, None) }?)
+}
+
+pub fn allocate_descriptor_set(
+    device: &Device,
+    descriptor_pool: DescriptorPool,
+    descriptor_set_layout: DescriptorSetLayout,
+) -> Result<DescriptorSet> {
+    let descriptor_set_layouts = [descriptor_set_layout];
+    let descriptor_set_allocate_info = DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&descriptor_set_layouts)
+        .build();
+    let descriptor_sets =
+        unsafe { device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?;
+
+    Ok(descriptor_sets[0])
+}
+
+pub fn write_combined_image_sampler(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    image_info: DescriptorImageInfo,
+) {
+    write_image_descriptor(
+        device,
+        descriptor_set,
+        binding,
+        DescriptorType::COMBINED_IMAGE_SAMPLER,
+        image_info,
+    )
+}
+
+pub fn write_storage_image(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    image_info: DescriptorImageInfo,
+) {
+    write_image_descriptor(
+        device,
+        descriptor_set,
+        binding,
+        DescriptorType::STORAGE_IMAGE,
+        image_info,
+    )
+}
+
+pub fn write_input_attachment(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    image_info: DescriptorImageInfo,
+) {
+    write_image_descriptor(
+        device,
+        descriptor_set,
+        binding,
+        DescriptorType::INPUT_ATTACHMENT,
+        image_info,
+    )
+}
+
+pub fn write_uniform_buffer(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    buffer_info: DescriptorBufferInfo,
+) {
+    write_buffer_descriptor(
+        device,
+        descriptor_set,
+        binding,
+        DescriptorType::UNIFORM_BUFFER,
+        buffer_info,
+    )
+}
+
+pub fn write_storage_buffer(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    buffer_info: DescriptorBufferInfo,
+) {
+    write_buffer_descriptor(
+        device,
+        descriptor_set,
+        binding,
+        DescriptorType::STORAGE_BUFFER,
+        buffer_info,
+    )
+}
+
+fn write_buffer_descriptor(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    descriptor_type: DescriptorType,
+    buffer_info: DescriptorBufferInfo,
+) {
+    let buffer_infos = [buffer_info];
+    let write_descriptor_set = WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(descriptor_type)
+        .buffer_info(&buffer_infos)
+        .build();
+
+    unsafe { device.update_descriptor_sets(&[write_descriptor_set], &[]) };
+}
+
+fn write_image_descriptor(
+    device: &Device,
+    descriptor_set: DescriptorSet,
+    binding: u32,
+    descriptor_type: DescriptorType,
+    image_info: DescriptorImageInfo,
+) {
+    let image_infos = [image_info];
+    let write_descriptor_set = WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(binding)
+        .dst_array_element(0)
+        .descriptor_type(descriptor_type)
+        .image_info(&image_infos)
+        .build();
+
+    unsafe { device.update_descriptor_sets(&[write_descriptor_set], &[]) };
+}
@@ -1,23 +1,37 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
+use std::ffi::CStr;
 
-use anyhow::{anyhow, Result};
-use ash::extensions::khr::Swapchain;
+use anyhow::{anyhow, Context, Result};
 use ash::vk::{
-    DeviceCreateInfo, DeviceQueueCreateInfo, KhrPortabilitySubsetFn, PhysicalDevice,
-    PhysicalDeviceFeatures, QueueFlags,
+    DeviceCreateInfo, DeviceQueueCreateInfo, DeviceSize, MemoryHeapFlags, PhysicalDevice,
+    PhysicalDeviceDynamicRenderingFeatures, PhysicalDeviceFaultFeaturesEXT, PhysicalDeviceFeatures,
+    PhysicalDeviceFeatures2, PhysicalDeviceMemoryProperties,
+    PhysicalDevicePortabilitySubsetFeaturesKHR, PhysicalDeviceProperties, QueueFamilyProperties,
+    QueueFlags, API_VERSION_1_2, TRUE,
 };
 use ash::{vk, Device, Instance};
-use log::{debug, info};
+use log::{debug, info, warn};
+use tracing::{field, info_span};
 use vk::PhysicalDeviceType;
 
-use crate::constants::REQUIRED_EXTENSIONS;
-use crate::util::util::{vk_to_string, vk_version_to_string, yes_no};
+use crate::config::GpuSelection;
+use crate::constants::{
+    DEVICE_FAULT_EXTENSION, DYNAMIC_RENDERING_EXTENSION, GRAPHICS_QUEUE_PRIORITIES,
+    OPTIONAL_EXTENSIONS, PORTABILITY_SUBSET_EXTENSION, REQUIRED_EXTENSIONS, VULKAN_12_EXTENSIONS,
+};
+use crate::util::util::{vk_to_cstr, vk_to_string, vk_version_to_string, yes_no};
+use crate::vulkan::features::{DeviceCapabilities, RequestedFeatures};
 use crate::vulkan::surface::SurfaceEntities;
-use crate::vulkan::swapchain::get_swapchain_support_details;
+use crate::vulkan::swapchain::{get_swapchain_support_details, SwapchainSupportDetails};
 
+#[derive(Clone, Debug)]
 pub struct QueueFamilyIndices {
     pub graphics_family_index: Option<u32>,
     pub present_family_index: Option<u32>,
+    pub transfer_family_index: Option<u32>,
+    pub compute_family_index: Option<u32>,
+    /// How many queues the device is created with in the primary family
+    pub graphics_queue_count: u32,
 }
 
 impl QueueFamilyIndices {
@@ -25,6 +39,9 @@ impl QueueFamilyIndices {
         QueueFamilyIndices {
             graphics_family_index: None,
             present_family_index: None,
+            transfer_family_index: None,
+            compute_family_index: None,
+            graphics_queue_count: 1,
         }
     }
 
@@ -32,77 +49,509 @@ impl QueueFamilyIndices {
         self.graphics_family_index.is_some() && self.present_family_index.is_some()
     }
 
-    pub fn unique_indices(&self) -> HashSet<u32> {
-        let mut unique_indices = HashSet::new();
-        unique_indices.insert(self.graphics_family_index.unwrap());
-        unique_indices.insert(self.present_family_index.unwrap());
-        unique_indices
+    /// Without a surface there is nothing to present to, a family that can dispatch compute is
+    /// needed instead.
+    pub fn is_complete_headless(&self) -> bool {
+        self.compute_family_index.is_some()
+    }
+
+    /// The family of the main queue and command pool, the compute family on headless devices
+    /// without a graphics family.
+    pub fn primary_family_index(&self) -> Option<u32> {
+        self.graphics_family_index.or(self.compute_family_index)
+    }
+
+    /// The families that need a queue, each once and in ascending order, so the device is
+    /// created the same way on every run.
+    pub fn unique_indices(&self) -> BTreeSet<u32> {
+        [
+            self.graphics_family_index,
+            self.present_family_index,
+            self.transfer_family_index,
+            self.compute_family_index,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// The device selection chose and what it learned querying it, so device and swapchain creation
+/// don't query again.
+pub struct SelectedDevice {
+    pub physical_device: PhysicalDevice,
+    pub queue_family_indices: QueueFamilyIndices,
+    pub properties: PhysicalDeviceProperties,
+    pub features: PhysicalDeviceFeatures,
+    /// The version the instance was created with
+    pub instance_api_version: u32,
+    /// The version the device can be used at, the lower of its own and the instance's
+    pub api_version: u32,
+    /// `None` when the device was selected without a surface
+    pub swapchain_support: Option<SwapchainSupportDetails>,
+}
+
+impl SelectedDevice {
+    fn query(
+        instance: &Instance,
+        instance_api_version: u32,
+        physical_device: PhysicalDevice,
+        surface_entities: Option<&SurfaceEntities>,
+    ) -> Result<SelectedDevice> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        Ok(SelectedDevice {
+            physical_device,
+            queue_family_indices: find_queue_family(instance, physical_device, surface_entities)?,
+            properties,
+            features: unsafe { instance.get_physical_device_features(physical_device) },
+            instance_api_version,
+            api_version: properties.api_version.min(instance_api_version),
+            swapchain_support: surface_entities
+                .map(|surface_entities| {
+                    get_swapchain_support_details(physical_device, surface_entities)
+                })
+                .transpose()?,
+        })
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.swapchain_support.is_none()
+    }
+
+    pub fn name(&self) -> String {
+        vk_to_string(&self.properties.device_name)
     }
 }
 
+/// How a suitable device ranks, compared field by field. A discrete GPU wins over any amount of
+/// memory, and memory over the API version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DeviceScore {
+    device_type_rank: u32,
+    device_local_heap_size: DeviceSize,
+    api_version: u32,
+}
+
+/// Picks the device `gpu_selection` names, or else the best scoring of the devices that can
+/// present to the surface and have the required extensions. Devices are numbered in enumeration
+/// order, which is what `PISTON_GPU_INDEX` refers to.
+///
+/// Without a surface, for compute-only and headless use, presenting and the swapchain aren't
+/// checked.
 pub fn select_physical_device(
     instance: &Instance,
-    surface_entities: &SurfaceEntities,
-) -> Result<PhysicalDevice> {
+    instance_api_version: u32,
+    surface_entities: Option<&SurfaceEntities>,
+    gpu_selection: &GpuSelection,
+) -> Result<SelectedDevice> {
+    let span = info_span!("select_physical_device", device = field::Empty).entered();
     let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
     info!(
         "{} devices (GPU) found with Vulkan support",
         physical_devices.len()
     );
 
-    for &physical_device in physical_devices.iter() {
-        if is_suitable_physical_device(instance, physical_device, surface_entities) {
-            return Ok(physical_device);
+    let mut devices = physical_devices
+        .iter()
+        .enumerate()
+        .map(|(index, &physical_device)| {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            let name = vk_to_string(&properties.device_name);
+            info!(
+                "Device {}: {} ({})",
+                index,
+                name,
+                device_type_name(properties.device_type)
+            );
+            // A device that fails to answer, such as one being unplugged, is skipped while
+            // others remain
+            let (device, rejection_reasons) = match check_physical_device(
+                instance,
+                instance_api_version,
+                physical_device,
+                surface_entities,
+            ) {
+                Ok((device, rejection_reasons)) => (Some(device), rejection_reasons),
+                Err(error) => {
+                    warn!("Failed to query device {} ({}): {:?}", index, name, error);
+                    (None, vec!["device queries failed"])
+                }
+            };
+            EnumeratedDevice {
+                index,
+                name,
+                device,
+                rejection_reasons,
+            }
+        })
+        .collect::<Vec<_>>();
+    let device_list = devices
+        .iter()
+        .map(EnumeratedDevice::describe)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let forced_device_index = match gpu_selection {
+        GpuSelection::Auto => None,
+        GpuSelection::Index(index) if *index < devices.len() => Some(*index),
+        GpuSelection::Index(index) => {
+            return Err(anyhow!(
+                "PISTON_GPU_INDEX is {}, but there is no such device:\n{}",
+                index,
+                device_list
+            ))
+        }
+        GpuSelection::Name(name) => Some(
+            devices
+                .iter()
+                .position(|device| device.name.to_lowercase().contains(&name.to_lowercase()))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "PISTON_GPU_NAME is {:?}, but no device name contains it:\n{}",
+                        name,
+                        device_list
+                    )
+                })?,
+        ),
+    };
+    if let Some(index) = forced_device_index {
+        let device = devices.swap_remove(index);
+        let selected_device = device
+            .device
+            .filter(|_| device.rejection_reasons.is_empty())
+            .ok_or_else(|| anyhow!("The forced device is not suitable:\n{}", device_list))?;
+        info!(
+            "Selected device {}: {}, forced by {:?}",
+            device.index, device.name, gpu_selection
+        );
+        span.record("device", selected_device.name().as_str());
+        return Ok(selected_device);
+    }
+
+    let mut candidates = devices
+        .into_iter()
+        .filter(|device| device.rejection_reasons.is_empty())
+        .filter_map(|device| {
+            let selected_device = device.device?;
+            Some((
+                score_physical_device(instance, &selected_device),
+                device.index,
+                selected_device,
+            ))
+        })
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Err(anyhow!(
+            "No suitable supported device found among {} devices:\n{}",
+            physical_devices.len(),
+            device_list
+        ));
+    }
+
+    sort_best_first(&mut candidates);
+    info!("Suitable devices, best first:");
+    info!("Rank\tIndex\tType\t\tDevice-local memory\tAPI version\tName");
+    for (rank, (score, index, device)) in candidates.iter().enumerate() {
+        info!(
+            "{}\t{}\t{}\t{} MiB\t\t{}\t\t{}",
+            rank + 1,
+            index,
+            device_type_name(device.properties.device_type),
+            score.device_local_heap_size / (1024 * 1024),
+            vk_version_to_string(score.api_version),
+            device.name()
+        );
+    }
+    let (_, index, device) = candidates.swap_remove(0);
+    info!(
+        "Selected device {}: {}, the best scoring one",
+        index,
+        device.name()
+    );
+    span.record("device", device.name().as_str());
+
+    Ok(device)
+}
+
+struct EnumeratedDevice {
+    /// In enumeration order
+    index: usize,
+    name: String,
+    /// `None` when the device couldn't be queried
+    device: Option<SelectedDevice>,
+    /// Empty when the device is suitable
+    rejection_reasons: Vec<&'static str>,
+}
+
+impl EnumeratedDevice {
+    fn describe(&self) -> String {
+        match self.rejection_reasons.is_empty() {
+            true => format!(" {}: {}, suitable", self.index, self.name),
+            false => format!(
+                " {}: {}, rejected: {}",
+                self.index,
+                self.name,
+                self.rejection_reasons.join(", ")
+            ),
         }
     }
+}
 
-    return Err(anyhow!("No suitable supported device found"));
+/// Whether the device can render without render pass and framebuffer objects. The engine targets
+/// Vulkan 1.2, so this is `VK_KHR_dynamic_rendering` even on 1.3 devices, which all expose it.
+/// Its dependencies are core in 1.2, so a device used at 1.1 goes without.
+pub fn supports_dynamic_rendering(instance: &Instance, selected_device: &SelectedDevice) -> bool {
+    let physical_device = selected_device.physical_device;
+    let has_extension =
+        has_device_extension(instance, physical_device, DYNAMIC_RENDERING_EXTENSION);
+    if selected_device.api_version < API_VERSION_1_2 || !has_extension {
+        return false;
+    }
+
+    let mut dynamic_rendering_features = PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut features = PhysicalDeviceFeatures2::builder()
+        .push_next(&mut dynamic_rendering_features)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+    dynamic_rendering_features.dynamic_rendering == 1
 }
 
+/// Enables what the device supports of `requested_features`, the returned capabilities say
+/// which features that is.
 pub fn create_logical_device(
     instance: &Instance,
-    physical_device: PhysicalDevice,
-    surface_entities: &SurfaceEntities,
-) -> Result<(Device, QueueFamilyIndices)> {
-    let queue_family_indices = find_queue_family(instance, physical_device, surface_entities);
+    selected_device: &SelectedDevice,
+    requested_features: &RequestedFeatures,
+    dynamic_rendering: bool,
+) -> Result<(Device, QueueFamilyIndices, DeviceCapabilities)> {
+    let _span = info_span!("create_logical_device", device = %selected_device.name()).entered();
+    let physical_device = selected_device.physical_device;
+    let mut queue_family_indices = selected_device.queue_family_indices.clone();
+    let queue_families =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let primary_family_index = queue_family_indices
+        .primary_family_index()
+        .ok_or_else(|| anyhow!("The device has no graphics or compute queue family"))?;
+    queue_family_indices.graphics_queue_count = queue_families[primary_family_index as usize]
+        .queue_count
+        .min(GRAPHICS_QUEUE_PRIORITIES.len() as u32);
+    let graphics_queue_priorities =
+        &GRAPHICS_QUEUE_PRIORITIES[..queue_family_indices.graphics_queue_count as usize];
     let queue_priorities = [1.0f32];
-    let mut queue_create_infos = vec![];
-    for &index in queue_family_indices.unique_indices().iter() {
-        queue_create_infos.push(
+    let unique_indices = queue_family_indices.unique_indices();
+    info!(
+        "Creating a queue in each of the families {:?}, {} in primary family {}",
+        unique_indices, queue_family_indices.graphics_queue_count, primary_family_index
+    );
+    let queue_create_infos = unique_indices
+        .iter()
+        .map(|&index| {
             DeviceQueueCreateInfo::builder()
                 .queue_family_index(index)
-                .queue_priorities(&queue_priorities)
-                .build(),
-        )
-    }
+                .queue_priorities(match index == primary_family_index {
+                    true => graphics_queue_priorities,
+                    false => &queue_priorities,
+                })
+                .build()
+        })
+        .collect::<Vec<_>>();
 
-    let physical_device_features = PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true)
+    let mut capabilities =
+        requested_features.resolve(instance, physical_device, selected_device.api_version)?;
+    capabilities.instance_api_version = selected_device.instance_api_version;
+    let physical_device_features = capabilities.features();
+    let mut vulkan_12_features = capabilities.vulkan_12_features();
+    let mut portability_subset_features =
+        query_portability_subset_features(instance, physical_device);
+    if let Some(portability_subset_features) = &portability_subset_features {
+        capabilities.missing_portability_features =
+            missing_portability_features(portability_subset_features);
+        info!(
+            "Device implements the portability subset, missing features: {:?}",
+            capabilities.missing_portability_features
+        );
+    }
+    let required_extensions = required_extensions(selected_device.is_headless());
+    let extension_support =
+        check_extension_support(instance, physical_device, required_extensions)?;
+    let mut enabled_extensions = required_extensions.to_vec();
+    enabled_extensions.extend(enabled_optional_extensions(
+        &extension_support.available_optional,
+        selected_device.api_version,
+    ));
+    let mut device_fault_features = query_device_fault_features(instance, physical_device)
+        .filter(|_| enabled_extensions.contains(&DEVICE_FAULT_EXTENSION));
+    if device_fault_features.is_none() {
+        enabled_extensions.retain(|&extension| extension != DEVICE_FAULT_EXTENSION);
+    }
+    let mut dynamic_rendering_features = PhysicalDeviceDynamicRenderingFeatures::builder()
+        .dynamic_rendering(true)
         .build();
-    let enabled_extensions = [
-        Swapchain::name().as_ptr(),
-        KhrPortabilitySubsetFn::name().as_ptr(),
-    ];
-    let device_create_info = DeviceCreateInfo::builder()
+    let mut device_create_info_builder = DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
-        .enabled_extension_names(&enabled_extensions)
-        .enabled_features(&physical_device_features)
+        .enabled_features(&physical_device_features);
+    // `supports_dynamic_rendering` implies the extension is among the optional ones enabled
+    if dynamic_rendering {
+        device_create_info_builder =
+            device_create_info_builder.push_next(&mut dynamic_rendering_features);
+    }
+    if let Some(vulkan_12_features) = vulkan_12_features.as_mut() {
+        device_create_info_builder = device_create_info_builder.push_next(vulkan_12_features);
+    }
+    if let Some(device_fault_features) = device_fault_features.as_mut() {
+        device_create_info_builder = device_create_info_builder.push_next(device_fault_features);
+    }
+    // Enables every portability feature the device has
+    if let Some(portability_subset_features) = portability_subset_features.as_mut() {
+        enabled_extensions.push(PORTABILITY_SUBSET_EXTENSION);
+        device_create_info_builder =
+            device_create_info_builder.push_next(portability_subset_features);
+    }
+    let enabled_extension_names = enabled_extensions
+        .iter()
+        .map(|extension| extension.as_ptr())
+        .collect::<Vec<_>>();
+    info!(
+        "Enabling device extensions {:?}, optional ones not available: {:?}",
+        enabled_extensions, extension_support.unavailable_optional
+    );
+    capabilities.enabled_extensions = enabled_extensions;
+    let device_create_info = device_create_info_builder
+        .enabled_extension_names(&enabled_extension_names)
         .build();
 
     let device = unsafe { instance.create_device(physical_device, &device_create_info, None) }?;
 
-    Ok((device, queue_family_indices))
+    Ok((device, queue_family_indices, capabilities))
+}
+
+/// The portability subset features of a device that layers Vulkan on another API, as MoltenVK
+/// does on Metal. Such a device exposes `VK_KHR_portability_subset`, which must then be enabled.
+/// Only Apple platforms have them.
+fn query_portability_subset_features(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+) -> Option<PhysicalDevicePortabilitySubsetFeaturesKHR> {
+    let is_apple = cfg!(any(target_os = "macos", target_os = "ios"));
+    if !is_apple || !has_device_extension(instance, physical_device, PORTABILITY_SUBSET_EXTENSION) {
+        return None;
+    }
+
+    let mut portability_subset_features = PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+    let mut features = PhysicalDeviceFeatures2::builder()
+        .push_next(&mut portability_subset_features)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    portability_subset_features.p_next = std::ptr::null_mut();
+
+    Some(portability_subset_features)
 }
 
-fn is_suitable_physical_device(
+/// `None` when the device can't report faults. The vendor binary is left disabled, the engine
+/// doesn't read it.
+fn query_device_fault_features(
     instance: &Instance,
     physical_device: PhysicalDevice,
-    surface_entities: &SurfaceEntities,
+) -> Option<PhysicalDeviceFaultFeaturesEXT> {
+    if !has_device_extension(instance, physical_device, DEVICE_FAULT_EXTENSION) {
+        return None;
+    }
+
+    let mut device_fault_features = PhysicalDeviceFaultFeaturesEXT::default();
+    let mut features = PhysicalDeviceFeatures2::builder()
+        .push_next(&mut device_fault_features)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+    (device_fault_features.device_fault == TRUE).then(|| {
+        PhysicalDeviceFaultFeaturesEXT::builder()
+            .device_fault(true)
+            .build()
+    })
+}
+
+fn missing_portability_features(
+    features: &PhysicalDevicePortabilitySubsetFeaturesKHR,
+) -> Vec<&'static str> {
+    [
+        (
+            "constantAlphaColorBlendFactors",
+            features.constant_alpha_color_blend_factors,
+        ),
+        ("events", features.events),
+        (
+            "imageViewFormatReinterpretation",
+            features.image_view_format_reinterpretation,
+        ),
+        ("imageViewFormatSwizzle", features.image_view_format_swizzle),
+        ("imageView2DOn3DImage", features.image_view2_d_on3_d_image),
+        ("multisampleArrayImage", features.multisample_array_image),
+        (
+            "mutableComparisonSamplers",
+            features.mutable_comparison_samplers,
+        ),
+        ("pointPolygons", features.point_polygons),
+        ("samplerMipLodBias", features.sampler_mip_lod_bias),
+        ("separateStencilMaskRef", features.separate_stencil_mask_ref),
+        (
+            "shaderSampleRateInterpolationFunctions",
+            features.shader_sample_rate_interpolation_functions,
+        ),
+        ("tessellationIsolines", features.tessellation_isolines),
+        ("tessellationPointMode", features.tessellation_point_mode),
+        ("triangleFans", features.triangle_fans),
+        (
+            "vertexAttributeAccessBeyondStride",
+            features.vertex_attribute_access_beyond_stride,
+        ),
+    ]
+    .into_iter()
+    .filter(|&(_, supported)| supported != TRUE)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+fn has_device_extension(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    extension_name: &CStr,
 ) -> bool {
-    let queue_families_ok = check_queue_families(instance, physical_device, surface_entities);
-    let extension_support_ok = check_extension_support(instance, physical_device);
-    let swapchain_support_ok =
-        extension_support_ok && check_swapchain_support(physical_device, surface_entities);
+    unsafe { instance.enumerate_device_extension_properties(physical_device) }
+        .unwrap_or_default()
+        .iter()
+        .any(|extension| vk_to_cstr(&extension.extension_name) == extension_name)
+}
+
+/// The queried device and why it can't be used, no reasons when it is suitable. Fails when the
+/// device can't be queried.
+fn check_physical_device(
+    instance: &Instance,
+    instance_api_version: u32,
+    physical_device: PhysicalDevice,
+    surface_entities: Option<&SurfaceEntities>,
+) -> Result<(SelectedDevice, Vec<&'static str>)> {
+    log_queue_families(instance, physical_device);
+    let device = SelectedDevice::query(
+        instance,
+        instance_api_version,
+        physical_device,
+        surface_entities,
+    )?;
+    let queue_families_ok = match device.is_headless() {
+        true => device.queue_family_indices.is_complete_headless(),
+        false => device.queue_family_indices.is_complete(),
+    };
+    let required_extensions = required_extensions(device.is_headless());
+    let extension_support_ok =
+        check_extension_support(instance, physical_device, required_extensions)?
+            .missing_required
+            .is_empty();
+    let swapchain_support_ok = extension_support_ok
+        && device
+            .swapchain_support
+            .as_ref()
+            .is_none_or(|details| !details.formats.is_empty() && !details.present_modes.is_empty());
 
     info!("Queue families supported: {}", yes_no(queue_families_ok));
     info!(
@@ -111,74 +560,158 @@ fn is_suitable_physical_device(
     );
     info!("Swap chain supported: {}", yes_no(swapchain_support_ok));
 
-    queue_families_ok && extension_support_ok && swapchain_support_ok
+    let mut rejection_reasons = vec![];
+    if !queue_families_ok {
+        rejection_reasons.push(match device.is_headless() {
+            true => "no compute queue family",
+            false => "no graphics or present queue family",
+        });
+    }
+    if !extension_support_ok {
+        rejection_reasons.push("missing required extensions");
+    }
+    if extension_support_ok && !swapchain_support_ok {
+        rejection_reasons.push("no surface formats or present modes");
+    }
+
+    Ok((device, rejection_reasons))
 }
 
-fn check_extension_support(instance: &Instance, physical_device: PhysicalDevice) -> bool {
-    let available_extensions =
-        unsafe { instance.enumerate_device_extension_properties(physical_device) }.unwrap();
+fn score_physical_device(instance: &Instance, device: &SelectedDevice) -> DeviceScore {
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(device.physical_device) };
+    DeviceScore::new(&device.properties, &memory_properties)
+}
 
-    let mut available_extension_names = vec![];
-    debug!("Available device extensions:");
-    for extension in available_extensions.iter() {
-        let extension_name = vk_to_string(&extension.extension_name);
-        debug!(" - {} version {}", extension_name, extension.spec_version);
-        available_extension_names.push(extension_name);
-    }
+impl DeviceScore {
+    fn new(
+        properties: &PhysicalDeviceProperties,
+        memory_properties: &PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        let device_type_rank = match properties.device_type {
+            PhysicalDeviceType::DISCRETE_GPU => 4,
+            PhysicalDeviceType::INTEGRATED_GPU => 3,
+            PhysicalDeviceType::VIRTUAL_GPU => 2,
+            PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+        let device_local_heap_size = memory_properties.memory_heaps
+            [..memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
 
-    let mut required_extensions = HashSet::new();
-    for required_extension in REQUIRED_EXTENSIONS.iter() {
-        required_extensions.insert(required_extension.to_string());
+        DeviceScore {
+            device_type_rank,
+            device_local_heap_size,
+            api_version: properties.api_version,
+        }
     }
+}
+
+/// Orders candidates best first. Stable, so equal scores keep the enumeration order.
+fn sort_best_first<T>(candidates: &mut [(DeviceScore, usize, T)]) {
+    candidates.sort_by(|(score, _, _), (other_score, _, _)| other_score.cmp(score));
+}
 
-    for available_extension in available_extension_names.iter() {
-        required_extensions.remove(available_extension);
+pub fn device_type_name(device_type: PhysicalDeviceType) -> &'static str {
+    match device_type {
+        PhysicalDeviceType::CPU => "CPU",
+        PhysicalDeviceType::INTEGRATED_GPU => "Integrated GPU",
+        PhysicalDeviceType::DISCRETE_GPU => "Discrete GPU",
+        PhysicalDeviceType::VIRTUAL_GPU => "Virtual GPU",
+        _ => "Unknown",
     }
+}
 
-    required_extensions.is_empty()
+/// Which of the extensions the engine asks for a device has.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ExtensionSupport {
+    missing_required: Vec<&'static CStr>,
+    available_optional: Vec<&'static CStr>,
+    unavailable_optional: Vec<&'static CStr>,
 }
 
-fn check_swapchain_support(
-    physical_device: PhysicalDevice,
-    surface_entities: &SurfaceEntities,
-) -> bool {
-    let details = get_swapchain_support_details(physical_device, &surface_entities).unwrap();
-    !(details.formats.is_empty() || details.present_modes.is_empty())
+/// Presenting needs `VK_KHR_swapchain`, headless devices require nothing.
+fn required_extensions(headless: bool) -> &'static [&'static CStr] {
+    match headless {
+        true => &[],
+        false => &REQUIRED_EXTENSIONS,
+    }
 }
 
-fn check_queue_families(
+fn check_extension_support(
     instance: &Instance,
     physical_device: PhysicalDevice,
-    surface_entities: &SurfaceEntities,
-) -> bool {
-    let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
-    let device_features = unsafe { instance.get_physical_device_features(physical_device) };
-    let device_queue_families =
-        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    required_extensions: &[&'static CStr],
+) -> Result<ExtensionSupport> {
+    let available_extensions =
+        unsafe { instance.enumerate_device_extension_properties(physical_device) }
+            .context("Failed to enumerate the device extensions")?;
 
-    let device_type = match device_properties.device_type {
-        PhysicalDeviceType::CPU => "CPU",
-        PhysicalDeviceType::INTEGRATED_GPU => "Integrated GPU",
-        PhysicalDeviceType::DISCRETE_GPU => "Discrete GPU",
-        PhysicalDeviceType::VIRTUAL_GPU => "Virtual GPU",
-        PhysicalDeviceType::OTHER => "Unknown",
-        _ => panic!(),
-    };
+    debug!("Available device extensions:");
+    for extension in available_extensions.iter() {
+        debug!(
+            " - {:?} version {}",
+            vk_to_cstr(&extension.extension_name),
+            extension.spec_version
+        );
+    }
+    let available_extension_names = available_extensions
+        .iter()
+        .map(|extension| vk_to_cstr(&extension.extension_name))
+        .collect::<Vec<_>>();
 
-    let device_name = vk_to_string(&device_properties.device_name);
-    info!(
-        "Device: {}, id: {}, type: {}",
-        device_name, device_properties.device_id, device_type
-    );
-    info!(
-        "API version: {}",
-        vk_version_to_string(device_properties.api_version)
-    );
-    info!("Support queue family: {}", device_queue_families.len());
-    info!("# queues\tGraphics\tCompute\tTransfer\tSparse Binding");
+    Ok(extension_support(
+        &available_extension_names,
+        required_extensions,
+        &OPTIONAL_EXTENSIONS,
+    ))
+}
 
+fn extension_support(
+    available: &[&CStr],
+    required: &[&'static CStr],
+    optional: &[&'static CStr],
+) -> ExtensionSupport {
+    let is_available = |extension: &&CStr| available.contains(extension);
+    let (available_optional, unavailable_optional) =
+        optional.iter().copied().partition(is_available);
+
+    ExtensionSupport {
+        missing_required: required
+            .iter()
+            .copied()
+            .filter(|extension| !is_available(extension))
+            .collect(),
+        available_optional,
+        unavailable_optional,
+    }
+}
+
+/// The available optional extensions the device's API version allows.
+fn enabled_optional_extensions(
+    available_optional: &[&'static CStr],
+    api_version: u32,
+) -> Vec<&'static CStr> {
+    available_optional
+        .iter()
+        .copied()
+        .filter(|extension| {
+            api_version >= API_VERSION_1_2 || !VULKAN_12_EXTENSIONS.contains(extension)
+        })
+        .collect()
+}
+
+fn log_queue_families(instance: &Instance, physical_device: PhysicalDevice) {
+    let device_queue_families =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    debug!("{} queue families:", device_queue_families.len());
+    debug!("# queues\tGraphics\tCompute\tTransfer\tSparse Binding");
     for queue_family in device_queue_families.iter() {
-        info!(
+        debug!(
             "{}\t\t{}\t\t{}\t{}\t\t{}",
             queue_family.queue_count,
             yes_no(queue_family.queue_flags.contains(QueueFlags::GRAPHICS)),
@@ -191,51 +724,346 @@ fn check_queue_families(
             )
         );
     }
-    info!(
-        "Geometry shader support: {}",
-        yes_no(device_features.geometry_shader == 1)
-    );
-
-    find_queue_family(instance, physical_device, surface_entities).is_complete()
 }
 
+/// Without a surface no family can present.
 fn find_queue_family(
     instance: &Instance,
     physical_device: PhysicalDevice,
-    surface_entities: &SurfaceEntities,
-) -> QueueFamilyIndices {
-    let mut queue_family_indices = QueueFamilyIndices::new();
-
+    surface_entities: Option<&SurfaceEntities>,
+) -> Result<QueueFamilyIndices> {
     let queue_families =
         unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let present_support = match surface_entities {
+        Some(surface_entities) => (0..queue_families.len() as u32)
+            .map(|index| {
+                surface_entities
+                    .query_support(physical_device, index)
+                    .with_context(|| {
+                        format!("Failed to query present support of queue family {}", index)
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![false; queue_families.len()],
+    };
 
-    let mut index = 0;
-    for queue_family in queue_families.iter() {
-        if queue_family.queue_count > 0 {
-            if queue_family.queue_flags.contains(QueueFlags::GRAPHICS) {
-                queue_family_indices.graphics_family_index = Some(index);
-            }
+    Ok(select_queue_families(&queue_families, &present_support))
+}
 
-            let is_present_supported = unsafe {
-                surface_entities
-                    .surface_loader
-                    .get_physical_device_surface_support(
-                        physical_device,
-                        index,
-                        surface_entities.surface,
-                    )
-            }
-            .unwrap();
-            if is_present_supported {
-                queue_family_indices.present_family_index = Some(index);
-            }
+/// Picks the families from their properties and whether each can present to the surface,
+/// without querying the device.
+fn select_queue_families(
+    queue_families: &[QueueFamilyProperties],
+    present_support: &[bool],
+) -> QueueFamilyIndices {
+    let mut queue_family_indices = QueueFamilyIndices::new();
+    let can_present = present_support.contains(&true);
 
-            if queue_family_indices.is_complete() {
-                break;
-            }
+    for (index, (queue_family, &is_present_supported)) in
+        queue_families.iter().zip(present_support).enumerate()
+    {
+        if queue_family.queue_count == 0 {
+            continue;
+        }
+        if queue_family.queue_flags.contains(QueueFlags::GRAPHICS) {
+            queue_family_indices.graphics_family_index = Some(index as u32);
+        }
+        if is_present_supported {
+            queue_family_indices.present_family_index = Some(index as u32);
+        }
+        // Without present support the first graphics family is as good as any
+        if queue_family_indices.is_complete()
+            || (!can_present && queue_family_indices.graphics_family_index.is_some())
+        {
+            break;
         }
-        index += 1;
     }
 
+    // An async compute family runs dispatches alongside rendering, graphics families always
+    // support compute as well.
+    let dedicated_compute_family_index = queue_families.iter().position(|queue_family| {
+        queue_family.queue_count > 0
+            && queue_family.queue_flags.contains(QueueFlags::COMPUTE)
+            && !queue_family.queue_flags.contains(QueueFlags::GRAPHICS)
+    });
+    queue_family_indices.compute_family_index = dedicated_compute_family_index
+        .map(|index| index as u32)
+        .or(queue_family_indices.graphics_family_index);
+
+    // A family that supports transfers but neither graphics nor compute is a dedicated DMA
+    // queue, uploads on it can overlap with rendering.
+    let dedicated_transfer_family_index = queue_families.iter().position(|queue_family| {
+        queue_family.queue_count > 0
+            && queue_family.queue_flags.contains(QueueFlags::TRANSFER)
+            && !queue_family
+                .queue_flags
+                .intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+    });
+    queue_family_indices.transfer_family_index = dedicated_transfer_family_index
+        .map(|index| index as u32)
+        .or(queue_family_indices.primary_family_index());
+
     queue_family_indices
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::SHADER_NON_SEMANTIC_INFO_EXTENSION;
+
+    fn family(queue_flags: QueueFlags, queue_count: u32) -> QueueFamilyProperties {
+        QueueFamilyProperties {
+            queue_flags,
+            queue_count,
+            ..Default::default()
+        }
+    }
+
+    fn all_purpose() -> QueueFamilyProperties {
+        family(
+            QueueFlags::GRAPHICS | QueueFlags::COMPUTE | QueueFlags::TRANSFER,
+            1,
+        )
+    }
+
+    fn score(
+        device_type: PhysicalDeviceType,
+        heaps: &[(DeviceSize, bool)],
+        api_version: u32,
+    ) -> DeviceScore {
+        let properties = PhysicalDeviceProperties {
+            device_type,
+            api_version,
+            ..Default::default()
+        };
+        let mut memory_properties = PhysicalDeviceMemoryProperties {
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (heap, &(size, device_local)) in memory_properties.memory_heaps.iter_mut().zip(heaps) {
+            heap.size = size;
+            heap.flags = match device_local {
+                true => MemoryHeapFlags::DEVICE_LOCAL,
+                false => MemoryHeapFlags::empty(),
+            };
+        }
+        DeviceScore::new(&properties, &memory_properties)
+    }
+
+    const GIB: DeviceSize = 1024 * 1024 * 1024;
+
+    #[test]
+    fn score_sums_only_device_local_heaps() {
+        let score = score(
+            PhysicalDeviceType::DISCRETE_GPU,
+            &[(8 * GIB, true), (32 * GIB, false), (GIB / 4, true)],
+            vk::API_VERSION_1_3,
+        );
+        assert_eq!(score.device_type_rank, 4);
+        assert_eq!(score.device_local_heap_size, 8 * GIB + GIB / 4);
+        assert_eq!(score.api_version, vk::API_VERSION_1_3);
+    }
+
+    #[test]
+    fn score_ranks_device_types() {
+        let ranks = [
+            PhysicalDeviceType::OTHER,
+            PhysicalDeviceType::CPU,
+            PhysicalDeviceType::VIRTUAL_GPU,
+            PhysicalDeviceType::INTEGRATED_GPU,
+            PhysicalDeviceType::DISCRETE_GPU,
+        ]
+        .map(|device_type| score(device_type, &[], API_VERSION_1_2).device_type_rank);
+        assert!(ranks.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn discrete_beats_more_memory_and_memory_beats_api_version() {
+        let discrete = score(
+            PhysicalDeviceType::DISCRETE_GPU,
+            &[(GIB, true)],
+            API_VERSION_1_2,
+        );
+        let integrated = score(
+            PhysicalDeviceType::INTEGRATED_GPU,
+            &[(16 * GIB, true)],
+            vk::API_VERSION_1_3,
+        );
+        let newer = score(
+            PhysicalDeviceType::DISCRETE_GPU,
+            &[(GIB / 2, true)],
+            vk::API_VERSION_1_3,
+        );
+        assert!(discrete > integrated);
+        assert!(discrete > newer);
+    }
+
+    #[test]
+    fn selection_sorts_best_first_keeping_enumeration_order_on_ties() {
+        let integrated = score(
+            PhysicalDeviceType::INTEGRATED_GPU,
+            &[(GIB, true)],
+            API_VERSION_1_2,
+        );
+        let discrete = score(
+            PhysicalDeviceType::DISCRETE_GPU,
+            &[(GIB, true)],
+            API_VERSION_1_2,
+        );
+        let mut candidates = [
+            (integrated, 0, "integrated"),
+            (discrete, 1, "first discrete"),
+            (discrete, 2, "second discrete"),
+        ];
+        sort_best_first(&mut candidates);
+        let order = candidates.map(|(_, index, _)| index);
+        assert_eq!(order, [1, 2, 0]);
+    }
+
+    #[test]
+    fn transfer_family_prefers_dedicated_dma() {
+        let families = [
+            all_purpose(),
+            family(QueueFlags::COMPUTE | QueueFlags::TRANSFER, 1),
+            family(QueueFlags::TRANSFER | QueueFlags::SPARSE_BINDING, 1),
+        ];
+        let indices = select_queue_families(&families, &[true, false, false]);
+        assert_eq!(indices.transfer_family_index, Some(2));
+    }
+
+    #[test]
+    fn transfer_family_falls_back_to_graphics() {
+        let families = [
+            family(QueueFlags::COMPUTE | QueueFlags::TRANSFER, 1),
+            all_purpose(),
+        ];
+        let indices = select_queue_families(&families, &[false, true]);
+        assert_eq!(indices.transfer_family_index, Some(1));
+    }
+
+    #[test]
+    fn transfer_family_skips_empty_families() {
+        let families = [family(QueueFlags::TRANSFER, 0), all_purpose()];
+        let indices = select_queue_families(&families, &[false, true]);
+        assert_eq!(indices.transfer_family_index, Some(1));
+    }
+
+    #[test]
+    fn compute_family_prefers_async_compute() {
+        let families = [
+            all_purpose(),
+            family(QueueFlags::COMPUTE | QueueFlags::TRANSFER, 2),
+        ];
+        let indices = select_queue_families(&families, &[true, false]);
+        assert_eq!(indices.compute_family_index, Some(1));
+        assert_eq!(indices.graphics_family_index, Some(0));
+    }
+
+    #[test]
+    fn compute_family_falls_back_to_graphics() {
+        let families = [family(QueueFlags::TRANSFER, 1), all_purpose()];
+        let indices = select_queue_families(&families, &[false, true]);
+        assert_eq!(indices.compute_family_index, Some(1));
+    }
+
+    #[test]
+    fn compute_family_skips_empty_families() {
+        let families = [all_purpose(), family(QueueFlags::COMPUTE, 0)];
+        let indices = select_queue_families(&families, &[true, false]);
+        assert_eq!(indices.compute_family_index, Some(0));
+    }
+
+    #[test]
+    fn compute_only_family_list_is_complete_headless() {
+        let families = [family(QueueFlags::COMPUTE | QueueFlags::TRANSFER, 1)];
+        let indices = select_queue_families(&families, &[false]);
+        assert!(indices.is_complete_headless());
+        assert!(!indices.is_complete());
+        assert_eq!(indices.graphics_family_index, None);
+        assert_eq!(indices.compute_family_index, Some(0));
+        assert_eq!(indices.transfer_family_index, Some(0));
+        assert_eq!(indices.primary_family_index(), Some(0));
+    }
+
+    #[test]
+    fn unique_indices_deduplicates_a_shared_family() {
+        let indices = select_queue_families(&[all_purpose()], &[true]);
+        assert_eq!(indices.unique_indices(), BTreeSet::from([0]));
+    }
+
+    #[test]
+    fn unique_indices_are_ascending() {
+        let indices = QueueFamilyIndices {
+            graphics_family_index: Some(2),
+            present_family_index: Some(0),
+            transfer_family_index: Some(2),
+            compute_family_index: Some(1),
+            ..QueueFamilyIndices::new()
+        };
+        let unique = indices.unique_indices().into_iter().collect::<Vec<_>>();
+        assert_eq!(unique, [0, 1, 2]);
+    }
+
+    #[test]
+    fn unique_indices_skip_missing_families() {
+        let indices = QueueFamilyIndices {
+            graphics_family_index: Some(0),
+            compute_family_index: Some(0),
+            ..QueueFamilyIndices::new()
+        };
+        assert_eq!(indices.unique_indices(), BTreeSet::from([0]));
+    }
+
+    const SWAPCHAIN: &CStr = c"VK_KHR_swapchain";
+    const FAULT: &CStr = c"VK_EXT_device_fault";
+    const MEMORY_BUDGET: &CStr = c"VK_EXT_memory_budget";
+
+    #[test]
+    fn extension_support_reports_missing_required() {
+        let support = extension_support(&[FAULT], &[SWAPCHAIN], &[FAULT]);
+        assert_eq!(support.missing_required, [SWAPCHAIN]);
+    }
+
+    #[test]
+    fn extension_support_partitions_optional() {
+        let support = extension_support(
+            &[MEMORY_BUDGET, SWAPCHAIN],
+            &[SWAPCHAIN],
+            &[FAULT, MEMORY_BUDGET],
+        );
+        assert_eq!(
+            support,
+            ExtensionSupport {
+                missing_required: vec![],
+                available_optional: vec![MEMORY_BUDGET],
+                unavailable_optional: vec![FAULT],
+            }
+        );
+    }
+
+    #[test]
+    fn headless_requires_no_extensions() {
+        assert!(required_extensions(true).is_empty());
+        assert!(required_extensions(false).contains(&SWAPCHAIN));
+        let support = extension_support(&[], required_extensions(true), &[]);
+        assert!(support.missing_required.is_empty());
+    }
+
+    #[test]
+    fn vulkan_11_devices_get_the_extensions_without_12_dependencies() {
+        let available = [
+            DYNAMIC_RENDERING_EXTENSION,
+            MEMORY_BUDGET,
+            FAULT,
+            SHADER_NON_SEMANTIC_INFO_EXTENSION,
+        ];
+        assert_eq!(
+            enabled_optional_extensions(&available, vk::API_VERSION_1_1),
+            [MEMORY_BUDGET, FAULT, SHADER_NON_SEMANTIC_INFO_EXTENSION]
+        );
+        assert_eq!(
+            enabled_optional_extensions(&available, API_VERSION_1_2),
+            available
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use ash::vk::{
+    DeviceSize, MemoryHeapFlags, PhysicalDevice, PhysicalDeviceLimits,
+    PhysicalDeviceMemoryProperties, PhysicalDeviceType, SampleCountFlags, UUID_SIZE,
+};
+use ash::Instance;
+use log::info;
+
+use crate::util::util::{vk_to_string, vk_version_to_string};
+use crate::vulkan::device::device_type_name;
+use crate::vulkan::features::{DeviceCapabilities, DeviceFeature};
+
+const NVIDIA_VENDOR_ID: u32 = 0x10de;
+
+/// What the selected device is and what it was created with, queried once after device creation.
+/// Capability checks read it from the context instead of querying the physical device again.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub api_version: u32,
+    /// The version the instance was created with
+    pub instance_api_version: u32,
+    pub driver_version: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub pipeline_cache_uuid: [u8; UUID_SIZE],
+    pub limits: PhysicalDeviceLimits,
+    pub memory_properties: PhysicalDeviceMemoryProperties,
+    pub enabled_extensions: Vec<String>,
+    pub enabled_features: Vec<DeviceFeature>,
+}
+
+impl DeviceInfo {
+    pub fn new(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        capabilities: &DeviceCapabilities,
+    ) -> DeviceInfo {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+        DeviceInfo {
+            name: vk_to_string(&properties.device_name),
+            device_type: properties.device_type,
+            api_version: properties.api_version,
+            instance_api_version: capabilities.instance_api_version,
+            driver_version: properties.driver_version,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+            limits: properties.limits,
+            memory_properties,
+            enabled_extensions: capabilities
+                .enabled_extensions
+                .iter()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .collect(),
+            enabled_features: capabilities.enabled_features().collect(),
+        }
+    }
+
+    /// The lower of the device and instance versions, what device-level code may rely on.
+    pub fn usable_api_version(&self) -> u32 {
+        self.api_version.min(self.instance_api_version)
+    }
+
+    /// 1.0 when the device can't filter anisotropically.
+    pub fn max_sampler_anisotropy(&self) -> f32 {
+        self.limits.max_sampler_anisotropy
+    }
+
+    pub fn max_push_constants_size(&self) -> u32 {
+        self.limits.max_push_constants_size
+    }
+
+    pub fn min_uniform_buffer_offset_alignment(&self) -> DeviceSize {
+        self.limits.min_uniform_buffer_offset_alignment
+    }
+
+    /// The sample counts both color and depth attachments support.
+    pub fn framebuffer_sample_counts(&self) -> SampleCountFlags {
+        self.limits.framebuffer_color_sample_counts & self.limits.framebuffer_depth_sample_counts
+    }
+
+    /// The highest sample count a framebuffer with both color and depth can use.
+    pub fn max_usable_sample_count(&self) -> SampleCountFlags {
+        highest_sample_count(self.framebuffer_sample_counts())
+    }
+
+    /// The highest usable sample count that isn't above `requested`, so MSAA degrades instead of
+    /// failing on devices with fewer samples.
+    pub fn clamp_sample_count(&self, requested: SampleCountFlags) -> SampleCountFlags {
+        let supported = self.framebuffer_sample_counts();
+        let sample_count = clamp_to_supported(requested, supported);
+        if sample_count != requested {
+            info!(
+                "Requested {:?} samples, the device supports {:?}, using {:?}",
+                requested, supported, sample_count
+            );
+        }
+
+        sample_count
+    }
+
+    pub fn max_image_dimension_2d(&self) -> u32 {
+        self.limits.max_image_dimension2_d
+    }
+
+    pub fn device_local_memory_size(&self) -> DeviceSize {
+        self.memory_properties.memory_heaps[..self.memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    pub fn log_summary(&self) {
+        info!(
+            "Device: {} ({})",
+            self.name,
+            device_type_name(self.device_type)
+        );
+        info!(
+            "  Vulkan {} on a {} instance, used at {}, driver {}",
+            vk_version_to_string(self.api_version),
+            vk_version_to_string(self.instance_api_version),
+            vk_version_to_string(self.usable_api_version()),
+            driver_version_to_string(self.vendor_id, self.driver_version)
+        );
+        let heaps = &self.memory_properties.memory_heaps
+            [..self.memory_properties.memory_heap_count as usize];
+        for (index, heap) in heaps.iter().enumerate() {
+            info!(
+                "  Memory heap {}: {} MiB, {:?}",
+                index,
+                heap.size / (1024 * 1024),
+                heap.flags
+            );
+        }
+        info!(
+            "  Max 2D image dimension: {}",
+            self.max_image_dimension_2d()
+        );
+        info!(
+            "  Max push constants size: {} bytes",
+            self.max_push_constants_size()
+        );
+        info!(
+            "  Min uniform buffer offset alignment: {} bytes",
+            self.min_uniform_buffer_offset_alignment()
+        );
+        info!(
+            "  Max sampler anisotropy: {}",
+            self.max_sampler_anisotropy()
+        );
+        info!(
+            "  Framebuffer sample counts: {:?}, max usable {:?}",
+            self.framebuffer_sample_counts(),
+            self.max_usable_sample_count()
+        );
+        info!("  Extensions: {}", self.enabled_extensions.join(", "));
+        info!(
+            "  Features: {}",
+            self.enabled_features
+                .iter()
+                .map(|feature| feature.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+/// The highest bit of `sample_counts`, one sample when it's empty since every device supports
+/// that.
+/// The highest of `supported` that isn't above `requested`, a single sample when none is.
+fn clamp_to_supported(
+    requested: SampleCountFlags,
+    supported: SampleCountFlags,
+) -> SampleCountFlags {
+    // The bits below and including the requested one
+    let at_most_requested = SampleCountFlags::from_raw(
+        highest_sample_count(requested)
+            .as_raw()
+            .wrapping_shl(1)
+            .wrapping_sub(1),
+    );
+    highest_sample_count(supported & at_most_requested)
+}
+
+fn highest_sample_count(sample_counts: SampleCountFlags) -> SampleCountFlags {
+    match sample_counts.as_raw() {
+        0 => SampleCountFlags::TYPE_1,
+        raw => SampleCountFlags::from_raw(1 << (u32::BITS - 1 - raw.leading_zeros())),
+    }
+}
+
+/// NVIDIA packs the driver version differently, everyone else follows the API version.
+pub fn driver_version_to_string(vendor_id: u32, driver_version: u32) -> String {
+    match vendor_id {
+        NVIDIA_VENDOR_ID => format!(
+            "{}.{}.{}.{}",
+            driver_version >> 22,
+            (driver_version >> 14) & 0xff,
+            (driver_version >> 6) & 0xff,
+            driver_version & 0x3f
+        ),
+        _ => vk_version_to_string(driver_version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP_TO_4: SampleCountFlags = SampleCountFlags::from_raw(0b111);
+    const UP_TO_64: SampleCountFlags = SampleCountFlags::from_raw(0b111_1111);
+
+    #[test]
+    fn highest_sample_count_picks_top_bit() {
+        assert_eq!(highest_sample_count(UP_TO_4), SampleCountFlags::TYPE_4);
+        assert_eq!(highest_sample_count(UP_TO_64), SampleCountFlags::TYPE_64);
+        assert_eq!(
+            highest_sample_count(SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_8),
+            SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            highest_sample_count(SampleCountFlags::empty()),
+            SampleCountFlags::TYPE_1
+        );
+    }
+
+    #[test]
+    fn supported_request_is_kept() {
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_4, UP_TO_64),
+            SampleCountFlags::TYPE_4
+        );
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_1, UP_TO_4),
+            SampleCountFlags::TYPE_1
+        );
+    }
+
+    #[test]
+    fn request_above_limit_is_lowered() {
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_8, UP_TO_4),
+            SampleCountFlags::TYPE_4
+        );
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_64, UP_TO_4),
+            SampleCountFlags::TYPE_4
+        );
+    }
+
+    #[test]
+    fn gaps_in_support_fall_to_the_next_lower_count() {
+        let supported =
+            SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_2 | SampleCountFlags::TYPE_8;
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_4, supported),
+            SampleCountFlags::TYPE_2
+        );
+    }
+
+    #[test]
+    fn color_and_depth_support_is_intersected() {
+        let color = UP_TO_64;
+        let depth = SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_4;
+        assert_eq!(
+            clamp_to_supported(SampleCountFlags::TYPE_8, color & depth),
+            SampleCountFlags::TYPE_4
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::mem;
+use std::ptr;
+
+use anyhow::Result;
+use ash::vk::{
+    BufferUsageFlags, CommandBuffer, DeviceFaultAddressInfoEXT, DeviceFaultCountsEXT,
+    DeviceFaultInfoEXT, DeviceFaultVendorInfoEXT, DeviceSize, ExtDeviceFaultFn,
+    MemoryPropertyFlags,
+};
+use ash::{vk, Device, Instance};
+use log::error;
+
+use crate::util::util::vk_to_string;
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::context::VulkanContext;
+
+/// The passes of a frame in recording order, for breadcrumbs and debug labels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramePass {
+    Shadow,
+    Picking,
+    DepthPrepass,
+    Scene,
+    Bloom,
+    Composite,
+}
+
+impl FramePass {
+    const ALL: [FramePass; 6] = [
+        FramePass::Shadow,
+        FramePass::Picking,
+        FramePass::DepthPrepass,
+        FramePass::Scene,
+        FramePass::Bloom,
+        FramePass::Composite,
+    ];
+
+    /// Zero is left for a breadcrumb that was never written.
+    fn marker(self) -> u32 {
+        self as u32 + 1
+    }
+
+    fn from_marker(marker: u32) -> Option<FramePass> {
+        FramePass::ALL
+            .get((marker as usize).checked_sub(1)?)
+            .copied()
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FramePass::Shadow => "Shadow pass",
+            FramePass::Picking => "Picking pass",
+            FramePass::DepthPrepass => "Depth pre-pass",
+            FramePass::Scene => "Scene pass",
+            FramePass::Bloom => "Bloom",
+            FramePass::Composite => "Composite",
+        }
+    }
+
+    /// In linear RGBA, for captures.
+    pub fn label_color(self) -> [f32; 4] {
+        match self {
+            FramePass::Shadow => [0.3, 0.3, 0.3, 1.0],
+            FramePass::Picking => [0.9, 0.6, 0.1, 1.0],
+            FramePass::DepthPrepass => [0.2, 0.4, 0.6, 1.0],
+            FramePass::Scene => [0.2, 0.7, 0.3, 1.0],
+            FramePass::Bloom => [0.9, 0.9, 0.4, 1.0],
+            FramePass::Composite => [0.8, 0.3, 0.3, 1.0],
+        }
+    }
+}
+
+/// A frame number and a pass marker.
+const BREADCRUMB_SIZE: DeviceSize = 2 * size_of::<u32>() as DeviceSize;
+
+/// A host-visible breadcrumb per frame in flight, which the GPU overwrites before each pass. After
+/// a device loss it holds the pass each frame had reached. The writes aren't synchronized with the
+/// passes, so a breadcrumb may run a pass ahead.
+pub struct Breadcrumbs {
+    buffer: PistonBuffer,
+}
+
+impl Breadcrumbs {
+    pub fn new(context: &VulkanContext, frames_in_flight: usize) -> Result<Breadcrumbs> {
+        let buffer = PistonBuffer::new(
+            context,
+            BREADCRUMB_SIZE * frames_in_flight as DeviceSize,
+            BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            "breadcrumbs",
+        )?;
+        buffer.write(&context.device, &vec![0; buffer.size as usize])?;
+
+        Ok(Breadcrumbs { buffer })
+    }
+
+    /// Records the breadcrumb of `pass`, outside of a render pass. Only the low 32 bits of
+    /// `frame_number` are kept.
+    pub fn record(
+        &self,
+        device: &Device,
+        command_buffer: CommandBuffer,
+        frame: usize,
+        frame_number: u64,
+        pass: FramePass,
+    ) {
+        let offset = frame as DeviceSize * BREADCRUMB_SIZE;
+        let marker_size = size_of::<u32>() as DeviceSize;
+        unsafe {
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.buffer.buffer,
+                offset,
+                marker_size,
+                frame_number as u32,
+            );
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.buffer.buffer,
+                offset + marker_size,
+                marker_size,
+                pass.marker(),
+            );
+        }
+    }
+
+    pub fn log(&self, device: &Device) -> Result<()> {
+        let bytes = self.buffer.read(device)?;
+        for (frame, breadcrumb) in bytes.chunks_exact(BREADCRUMB_SIZE as usize).enumerate() {
+            let frame_number = u32::from_ne_bytes(breadcrumb[..4].try_into()?);
+            match FramePass::from_marker(u32::from_ne_bytes(breadcrumb[4..].try_into()?)) {
+                Some(pass) => error!(
+                    "Frame in flight {}: frame {} had reached the {:?} pass",
+                    frame, frame_number, pass
+                ),
+                None => error!("Frame in flight {}: no pass recorded", frame),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.buffer.destroy(device);
+    }
+}
+
+pub fn load_device_fault(instance: &Instance, device: &Device) -> ExtDeviceFaultFn {
+    ExtDeviceFaultFn::load(|name| unsafe {
+        mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+    })
+}
+
+/// Logs what `VK_EXT_device_fault` reports about the loss of `device`. The vendor binary isn't
+/// read, only its size is logged.
+pub fn log_device_fault(device: &Device, device_fault: &ExtDeviceFaultFn) -> Result<()> {
+    let mut counts = DeviceFaultCountsEXT::default();
+    unsafe {
+        (device_fault.get_device_fault_info_ext)(device.handle(), &mut counts, ptr::null_mut())
+    }
+    .result()?;
+    error!(
+        "Device fault: {} addresses, {} vendor infos, {} bytes of vendor binary",
+        counts.address_info_count, counts.vendor_info_count, counts.vendor_binary_size
+    );
+
+    let mut address_infos =
+        vec![DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+    let mut vendor_infos =
+        vec![DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+    let mut fault_info = DeviceFaultInfoEXT {
+        p_address_infos: address_infos.as_mut_ptr(),
+        p_vendor_infos: vendor_infos.as_mut_ptr(),
+        ..Default::default()
+    };
+    // Incomplete when there is a vendor binary, since it isn't read
+    counts.vendor_binary_size = 0;
+    match unsafe {
+        (device_fault.get_device_fault_info_ext)(device.handle(), &mut counts, &mut fault_info)
+    } {
+        vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+        result => return Err(result.into()),
+    }
+
+    error!("Device fault: {}", vk_to_string(&fault_info.description));
+    let address_infos = &address_infos[..counts.address_info_count as usize];
+    for address_info in address_infos {
+        error!(
+            "  {:?} at {:#x}, precision {:#x}",
+            address_info.address_type,
+            address_info.reported_address,
+            address_info.address_precision
+        );
+    }
+    for vendor_info in &vendor_infos[..counts.vendor_info_count as usize] {
+        error!(
+            "  {}: code {:#x}, data {:#x}",
+            vk_to_string(&vendor_info.description),
+            vendor_info.vendor_fault_code,
+            vendor_info.vendor_fault_data
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use ash::vk::{PhysicalDevice, QueueFlags};
+use ash::Instance;
+use log::info;
+
+use crate::constants::{OPTIONAL_EXTENSIONS, REQUIRED_EXTENSIONS};
+use crate::util::util::{vk_to_cstr, vk_to_string, vk_version_to_string, yes_no};
+use crate::vulkan::device::device_type_name;
+use crate::vulkan::device_info::driver_version_to_string;
+use crate::vulkan::surface::SurfaceEntities;
+
+/// Logs `physical_device_report`, one line at a time.
+pub fn report_physical_devices(
+    instance: &Instance,
+    surface_entities: Option<&SurfaceEntities>,
+) -> Result<()> {
+    for line in physical_device_report(instance, surface_entities)?.lines() {
+        info!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// Describes every device the instance enumerates, meant for bug reports. Present support is
+/// only known with a surface.
+pub fn physical_device_report(
+    instance: &Instance,
+    surface_entities: Option<&SurfaceEntities>,
+) -> Result<String> {
+    let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+    let mut report = format!("{} devices with Vulkan support\n", physical_devices.len());
+    for (index, &physical_device) in physical_devices.iter().enumerate() {
+        write_device_report(
+            &mut report,
+            instance,
+            physical_device,
+            index,
+            surface_entities,
+        )
+        .with_context(|| format!("Failed to report device {}", index))?;
+    }
+
+    Ok(report)
+}
+
+fn write_device_report(
+    report: &mut String,
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    index: usize,
+    surface_entities: Option<&SurfaceEntities>,
+) -> Result<()> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let queue_families =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) }?;
+
+    writeln!(
+        report,
+        "Device {}: {}",
+        index,
+        vk_to_string(&properties.device_name)
+    )?;
+    writeln!(
+        report,
+        "  Type: {}, Vulkan {}, driver {}, vendor {:#x}, id {:#x}",
+        device_type_name(properties.device_type),
+        vk_version_to_string(properties.api_version),
+        driver_version_to_string(properties.vendor_id, properties.driver_version),
+        properties.vendor_id,
+        properties.device_id
+    )?;
+
+    writeln!(
+        report,
+        "  Family\tQueues\tGraphics\tCompute\tTransfer\tPresent"
+    )?;
+    for (family_index, queue_family) in queue_families.iter().enumerate() {
+        let present = match surface_entities {
+            Some(surface_entities) => {
+                yes_no(surface_entities.query_support(physical_device, family_index as u32)?)
+            }
+            None => "n/a",
+        };
+        writeln!(
+            report,
+            "  {}\t{}\t{}\t\t{}\t{}\t\t{}",
+            family_index,
+            queue_family.queue_count,
+            yes_no(queue_family.queue_flags.contains(QueueFlags::GRAPHICS)),
+            yes_no(queue_family.queue_flags.contains(QueueFlags::COMPUTE)),
+            yes_no(queue_family.queue_flags.contains(QueueFlags::TRANSFER)),
+            present
+        )?;
+    }
+
+    let heaps = &memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize];
+    for (heap_index, heap) in heaps.iter().enumerate() {
+        writeln!(
+            report,
+            "  Memory heap {}: {} MiB, {:?}",
+            heap_index,
+            heap.size / (1024 * 1024),
+            heap.flags
+        )?;
+    }
+
+    for extension_name in REQUIRED_EXTENSIONS.iter().chain(OPTIONAL_EXTENSIONS.iter()) {
+        let is_available = extensions
+            .iter()
+            .any(|extension| vk_to_cstr(&extension.extension_name) == *extension_name);
+        writeln!(
+            report,
+            "  {}: {}",
+            extension_name.to_string_lossy(),
+            yes_no(is_available)
+        )?;
+    }
+
+    Ok(())
+}
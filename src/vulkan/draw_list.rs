@@ -0,0 +1,137 @@
+use std::sync::Once;
+
+use ash::vk::{Buffer, CommandBuffer, Pipeline, PipelineBindPoint};
+use ash::Device;
+use log::warn;
+
+use crate::vulkan::pipeline::BlendMode;
+
+static MISSING_VIEW_DEPTH_LOGGED: Once = Once::new();
+
+/// A non-indexed draw from a single vertex buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    pub pipeline: Pipeline,
+    pub blend_mode: BlendMode,
+    pub vertex_buffer: Buffer,
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    /// Distance from the camera along the view direction, transparent draws are ordered by it.
+    pub view_depth: Option<f32>,
+}
+
+/// Splits draws by blend mode. Opaque draws are recorded first in submission order, then the
+/// blended draws back to front.
+#[derive(Default)]
+pub struct DrawList {
+    opaque: Vec<DrawItem>,
+    transparent: Vec<DrawItem>,
+}
+
+impl DrawList {
+    pub fn new() -> DrawList {
+        DrawList::default()
+    }
+
+    pub fn push(&mut self, draw_item: DrawItem) {
+        if draw_item.blend_mode == BlendMode::Opaque {
+            self.opaque.push(draw_item);
+            return;
+        }
+
+        if draw_item.view_depth.is_none() {
+            MISSING_VIEW_DEPTH_LOGGED.call_once(|| {
+                warn!("Transparent draws without a view depth are drawn behind everything");
+            });
+        }
+        self.transparent.push(draw_item);
+    }
+
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+    }
+
+    /// Orders the transparent draws back to front. Has to run every frame the view changes.
+    pub fn sort_transparent(&mut self) {
+        self.transparent.sort_by(|a, b| {
+            let a_depth = a.view_depth.unwrap_or(f32::MAX);
+            let b_depth = b.view_depth.unwrap_or(f32::MAX);
+            b_depth.total_cmp(&a_depth)
+        });
+    }
+
+    /// Records all draws, only rebinding the pipeline and vertex buffer when they change.
+    pub fn record(&self, device: &Device, command_buffer: CommandBuffer) {
+        let mut bound_pipeline = Pipeline::null();
+        let mut bound_vertex_buffer = Buffer::null();
+        for draw_item in self.opaque.iter().chain(self.transparent.iter()) {
+            unsafe {
+                if draw_item.pipeline != bound_pipeline {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        draw_item.pipeline,
+                    );
+                    bound_pipeline = draw_item.pipeline;
+                }
+                if draw_item.vertex_buffer != bound_vertex_buffer {
+                    device.cmd_bind_vertex_buffers(
+                        command_buffer,
+                        0,
+                        &[draw_item.vertex_buffer],
+                        &[0],
+                    );
+                    bound_vertex_buffer = draw_item.vertex_buffer;
+                }
+                device.cmd_draw(
+                    command_buffer,
+                    draw_item.vertex_count,
+                    1,
+                    draw_item.first_vertex,
+                    0,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn draw_item(id: u64, blend_mode: BlendMode, view_depth: Option<f32>) -> DrawItem {
+        DrawItem {
+            pipeline: Pipeline::from_raw(id),
+            blend_mode,
+            vertex_buffer: Buffer::null(),
+            first_vertex: 0,
+            vertex_count: 3,
+            view_depth,
+        }
+    }
+
+    fn ids(draw_items: &[DrawItem]) -> Vec<u64> {
+        draw_items
+            .iter()
+            .map(|draw_item| draw_item.pipeline.as_raw())
+            .collect()
+    }
+
+    #[test]
+    fn transparent_draws_sort_back_to_front_keeping_ties_in_order() {
+        let mut draw_list = DrawList::new();
+        draw_list.push(draw_item(1, BlendMode::AlphaBlend, Some(1.0)));
+        draw_list.push(draw_item(2, BlendMode::Additive, Some(3.0)));
+        draw_list.push(draw_item(3, BlendMode::Opaque, Some(5.0)));
+        draw_list.push(draw_item(4, BlendMode::AlphaBlend, None));
+        draw_list.push(draw_item(5, BlendMode::AlphaBlend, Some(3.0)));
+        draw_list.push(draw_item(6, BlendMode::Opaque, Some(0.5)));
+        draw_list.push(draw_item(7, BlendMode::AlphaBlend, Some(2.0)));
+        draw_list.sort_transparent();
+
+        assert_eq!(ids(&draw_list.opaque), [3, 6]);
+        assert_eq!(ids(&draw_list.transparent), [4, 2, 5, 7, 1]);
+    }
+}
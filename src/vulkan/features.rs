@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use std::ffi::CStr;
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    Bool32, PhysicalDevice, PhysicalDeviceFeatures, PhysicalDeviceFeatures2,
+    PhysicalDeviceVulkan12Features, API_VERSION_1_2, TRUE,
+};
+use ash::Instance;
+use log::info;
+
+/// A device feature the engine knows how to enable, from the core features or the Vulkan 1.2
+/// ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeviceFeature {
+    SamplerAnisotropy,
+    FillModeNonSolid,
+    WideLines,
+    GeometryShader,
+    TessellationShader,
+    IndependentBlend,
+    MultiDrawIndirect,
+    TextureCompressionBc,
+    TextureCompressionAstcLdr,
+    TextureCompressionEtc2,
+    TimelineSemaphore,
+    DrawIndirectCount,
+    ScalarBlockLayout,
+}
+
+impl DeviceFeature {
+    /// The name of the field in the Vulkan feature structs.
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceFeature::SamplerAnisotropy => "samplerAnisotropy",
+            DeviceFeature::FillModeNonSolid => "fillModeNonSolid",
+            DeviceFeature::WideLines => "wideLines",
+            DeviceFeature::GeometryShader => "geometryShader",
+            DeviceFeature::TessellationShader => "tessellationShader",
+            DeviceFeature::IndependentBlend => "independentBlend",
+            DeviceFeature::MultiDrawIndirect => "multiDrawIndirect",
+            DeviceFeature::TextureCompressionBc => "textureCompressionBC",
+            DeviceFeature::TextureCompressionAstcLdr => "textureCompressionASTC_LDR",
+            DeviceFeature::TextureCompressionEtc2 => "textureCompressionETC2",
+            DeviceFeature::TimelineSemaphore => "timelineSemaphore",
+            DeviceFeature::DrawIndirectCount => "drawIndirectCount",
+            DeviceFeature::ScalarBlockLayout => "scalarBlockLayout",
+        }
+    }
+
+    fn is_vulkan_12(self) -> bool {
+        matches!(
+            self,
+            DeviceFeature::TimelineSemaphore
+                | DeviceFeature::DrawIndirectCount
+                | DeviceFeature::ScalarBlockLayout
+        )
+    }
+
+    fn field<'a>(
+        self,
+        features: &'a mut PhysicalDeviceFeatures,
+        vulkan_12_features: &'a mut PhysicalDeviceVulkan12Features,
+    ) -> &'a mut Bool32 {
+        match self {
+            DeviceFeature::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            DeviceFeature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            DeviceFeature::WideLines => &mut features.wide_lines,
+            DeviceFeature::GeometryShader => &mut features.geometry_shader,
+            DeviceFeature::TessellationShader => &mut features.tessellation_shader,
+            DeviceFeature::IndependentBlend => &mut features.independent_blend,
+            DeviceFeature::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            DeviceFeature::TextureCompressionBc => &mut features.texture_compression_bc,
+            DeviceFeature::TextureCompressionAstcLdr => &mut features.texture_compression_astc_ldr,
+            DeviceFeature::TextureCompressionEtc2 => &mut features.texture_compression_etc2,
+            DeviceFeature::TimelineSemaphore => &mut vulkan_12_features.timeline_semaphore,
+            DeviceFeature::DrawIndirectCount => &mut vulkan_12_features.draw_indirect_count,
+            DeviceFeature::ScalarBlockLayout => &mut vulkan_12_features.scalar_block_layout,
+        }
+    }
+}
+
+/// The features device creation asks for. A device without one of the required features can't
+/// be used, optional features are enabled when the device has them.
+#[derive(Clone, Debug)]
+pub struct RequestedFeatures {
+    pub required: Vec<DeviceFeature>,
+    pub optional: Vec<DeviceFeature>,
+}
+
+impl RequestedFeatures {
+    /// What the engine uses. Everything has a fallback, so nothing is required.
+    pub fn engine() -> RequestedFeatures {
+        RequestedFeatures {
+            required: vec![],
+            optional: vec![
+                DeviceFeature::SamplerAnisotropy,
+                DeviceFeature::FillModeNonSolid,
+                DeviceFeature::WideLines,
+                DeviceFeature::GeometryShader,
+                DeviceFeature::TessellationShader,
+                DeviceFeature::IndependentBlend,
+                DeviceFeature::MultiDrawIndirect,
+                DeviceFeature::TextureCompressionBc,
+                DeviceFeature::TextureCompressionAstcLdr,
+                DeviceFeature::TextureCompressionEtc2,
+                DeviceFeature::TimelineSemaphore,
+                DeviceFeature::DrawIndirectCount,
+                DeviceFeature::ScalarBlockLayout,
+            ],
+        }
+    }
+
+    /// Intersects the request with what `physical_device` supports when used at `api_version`.
+    /// Fails naming the required features it lacks.
+    pub fn resolve(
+        &self,
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        api_version: u32,
+    ) -> Result<DeviceCapabilities> {
+        let (mut features, mut vulkan_12_features) =
+            query_supported_features(instance, physical_device, api_version);
+        let mut is_supported =
+            |feature: DeviceFeature| *feature.field(&mut features, &mut vulkan_12_features) == TRUE;
+
+        let missing_features = self
+            .required
+            .iter()
+            .filter(|&&feature| !is_supported(feature))
+            .map(|feature| feature.name())
+            .collect::<Vec<_>>();
+        if !missing_features.is_empty() {
+            return Err(anyhow!(
+                "The device lacks the required features {}",
+                missing_features.join(", ")
+            ));
+        }
+        let (available_features, unavailable_features): (Vec<DeviceFeature>, Vec<DeviceFeature>) =
+            self.optional
+                .iter()
+                .copied()
+                .partition(|&feature| is_supported(feature));
+        if !unavailable_features.is_empty() {
+            info!(
+                "Optional device features not available: {}",
+                feature_names(&unavailable_features)
+            );
+        }
+
+        Ok(DeviceCapabilities {
+            enabled_features: self
+                .required
+                .iter()
+                .chain(available_features.iter())
+                .copied()
+                .collect(),
+            enabled_extensions: vec![],
+            missing_portability_features: vec![],
+            instance_api_version: 0,
+            api_version,
+        })
+    }
+}
+
+/// The features the device was created with. Code that depends on a feature checks here rather
+/// than what the physical device supports, since only enabled features may be used.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilities {
+    enabled_features: BTreeSet<DeviceFeature>,
+    pub enabled_extensions: Vec<&'static CStr>,
+    /// The `VK_KHR_portability_subset` features the device lacks, by their Vulkan name. Empty
+    /// on conformant devices.
+    pub missing_portability_features: Vec<&'static str>,
+    /// The version the instance was created with
+    pub instance_api_version: u32,
+    /// The version the device is used at, core features above it are only there as extensions
+    pub api_version: u32,
+}
+
+impl DeviceCapabilities {
+    pub fn is_enabled(&self, feature: DeviceFeature) -> bool {
+        self.enabled_features.contains(&feature)
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_extensions.contains(&name)
+    }
+
+    /// Whether the device lacks the portability subset feature `name`, such as `triangleFans`.
+    pub fn lacks_portability_feature(&self, name: &str) -> bool {
+        self.missing_portability_features.contains(&name)
+    }
+
+    pub fn enabled_features(&self) -> impl Iterator<Item = DeviceFeature> + '_ {
+        self.enabled_features.iter().copied()
+    }
+
+    /// The core features to create the device with.
+    pub fn features(&self) -> PhysicalDeviceFeatures {
+        self.feature_structs().0
+    }
+
+    /// The Vulkan 1.2 features to create the device with, `None` when none of them is enabled,
+    /// so devices below 1.2 never see the struct.
+    pub fn vulkan_12_features(&self) -> Option<PhysicalDeviceVulkan12Features> {
+        self.enabled_features()
+            .any(DeviceFeature::is_vulkan_12)
+            .then(|| self.feature_structs().1)
+    }
+
+    fn feature_structs(&self) -> (PhysicalDeviceFeatures, PhysicalDeviceVulkan12Features) {
+        let mut features = PhysicalDeviceFeatures::default();
+        let mut vulkan_12_features = PhysicalDeviceVulkan12Features::default();
+        for feature in self.enabled_features() {
+            *feature.field(&mut features, &mut vulkan_12_features) = TRUE;
+        }
+
+        (features, vulkan_12_features)
+    }
+}
+
+/// The Vulkan 1.2 features are only queried when the device is used at 1.2, below that they are
+/// all unsupported.
+fn query_supported_features(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    api_version: u32,
+) -> (PhysicalDeviceFeatures, PhysicalDeviceVulkan12Features) {
+    if api_version < API_VERSION_1_2 {
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        return (features, PhysicalDeviceVulkan12Features::default());
+    }
+
+    let mut vulkan_12_features = PhysicalDeviceVulkan12Features::default();
+    let mut features = PhysicalDeviceFeatures2::builder()
+        .push_next(&mut vulkan_12_features)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    // The returned struct is only read from, it must not point into this frame
+    vulkan_12_features.p_next = std::ptr::null_mut();
+
+    (features.features, vulkan_12_features)
+}
+
+fn feature_names(features: &[DeviceFeature]) -> String {
+    match features.is_empty() {
+        true => "none".to_string(),
+        false => features
+            .iter()
+            .map(|feature| feature.name())
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
@@ -0,0 +1,258 @@
+use ash::vk::{DeviceSize, Extent2D, Format, PhysicalDeviceFeatures};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionFamily {
+    Bc,
+    Astc,
+    Etc2,
+}
+
+/// How the texel values of an image should be interpreted. Color data such as albedo maps is
+/// authored in sRGB and must use an `_SRGB` format so sampling returns linear values, while data
+/// textures such as normal maps must use `_UNORM` so they are read back unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpaceIntent {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpaceIntent {
+    pub fn of_format(format: Format) -> Option<ColorSpaceIntent> {
+        srgb_unorm_pair(format).map(|(srgb, _)| {
+            if format == srgb {
+                ColorSpaceIntent::Srgb
+            } else {
+                ColorSpaceIntent::Linear
+            }
+        })
+    }
+
+    pub fn apply_to(&self, format: Format) -> Format {
+        match (srgb_unorm_pair(format), self) {
+            (Some((srgb, _)), ColorSpaceIntent::Srgb) => srgb,
+            (Some((_, unorm)), ColorSpaceIntent::Linear) => unorm,
+            (None, _) => format,
+        }
+    }
+}
+
+/// Converts an sRGB encoded color, such as one picked in an image editor, to the linear values
+/// shaders and clear values work with. Alpha is already linear.
+pub fn srgb_to_linear(color: [f32; 4]) -> [f32; 4] {
+    let decode = |channel: f32| {
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    [
+        decode(color[0]),
+        decode(color[1]),
+        decode(color[2]),
+        color[3],
+    ]
+}
+
+const SRGB_UNORM_PAIRS: [(Format, Format); 11] = [
+    (Format::R8G8B8A8_SRGB, Format::R8G8B8A8_UNORM),
+    (Format::B8G8R8A8_SRGB, Format::B8G8R8A8_UNORM),
+    (Format::A8B8G8R8_SRGB_PACK32, Format::A8B8G8R8_UNORM_PACK32),
+    (Format::BC1_RGB_SRGB_BLOCK, Format::BC1_RGB_UNORM_BLOCK),
+    (Format::BC1_RGBA_SRGB_BLOCK, Format::BC1_RGBA_UNORM_BLOCK),
+    (Format::BC2_SRGB_BLOCK, Format::BC2_UNORM_BLOCK),
+    (Format::BC3_SRGB_BLOCK, Format::BC3_UNORM_BLOCK),
+    (Format::BC7_SRGB_BLOCK, Format::BC7_UNORM_BLOCK),
+    (
+        Format::ETC2_R8G8B8_SRGB_BLOCK,
+        Format::ETC2_R8G8B8_UNORM_BLOCK,
+    ),
+    (
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK,
+    ),
+    (
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+    ),
+];
+
+fn srgb_unorm_pair(format: Format) -> Option<(Format, Format)> {
+    if let Some(&pair) = SRGB_UNORM_PAIRS
+        .iter()
+        .find(|&&(srgb, unorm)| srgb == format || unorm == format)
+    {
+        return Some(pair);
+    }
+
+    // ASTC formats come in UNORM/SRGB pairs with consecutive values for every block size.
+    if compression_family(format) == Some(CompressionFamily::Astc) {
+        let first_unorm = Format::ASTC_4X4_UNORM_BLOCK.as_raw();
+        let unorm = first_unorm + (format.as_raw() - first_unorm) / 2 * 2;
+        return Some((Format::from_raw(unorm + 1), Format::from_raw(unorm)));
+    }
+
+    None
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompressedFormatSupport {
+    pub bc: bool,
+    pub astc_ldr: bool,
+    pub etc2: bool,
+}
+
+impl CompressedFormatSupport {
+    pub fn from_features(features: &PhysicalDeviceFeatures) -> CompressedFormatSupport {
+        CompressedFormatSupport {
+            bc: features.texture_compression_bc == 1,
+            astc_ldr: features.texture_compression_astc_ldr == 1,
+            etc2: features.texture_compression_etc2 == 1,
+        }
+    }
+
+    pub fn supports(&self, family: CompressionFamily) -> bool {
+        match family {
+            CompressionFamily::Bc => self.bc,
+            CompressionFamily::Astc => self.astc_ldr,
+            CompressionFamily::Etc2 => self.etc2,
+        }
+    }
+
+    pub fn supports_format(&self, format: Format) -> bool {
+        match compression_family(format) {
+            Some(family) => self.supports(family),
+            None => true,
+        }
+    }
+
+    pub fn supported_families(&self) -> Vec<CompressionFamily> {
+        [
+            CompressionFamily::Bc,
+            CompressionFamily::Astc,
+            CompressionFamily::Etc2,
+        ]
+        .into_iter()
+        .filter(|&family| self.supports(family))
+        .collect()
+    }
+}
+
+pub fn has_stencil_component(format: Format) -> bool {
+    matches!(
+        format,
+        Format::S8_UINT
+            | Format::D16_UNORM_S8_UINT
+            | Format::D24_UNORM_S8_UINT
+            | Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+pub fn compression_family(format: Format) -> Option<CompressionFamily> {
+    let raw_format = format.as_raw();
+    if (Format::BC1_RGB_UNORM_BLOCK.as_raw()..=Format::BC7_SRGB_BLOCK.as_raw())
+        .contains(&raw_format)
+    {
+        Some(CompressionFamily::Bc)
+    } else if (Format::ETC2_R8G8B8_UNORM_BLOCK.as_raw()..=Format::EAC_R11G11_SNORM_BLOCK.as_raw())
+        .contains(&raw_format)
+    {
+        Some(CompressionFamily::Etc2)
+    } else if (Format::ASTC_4X4_UNORM_BLOCK.as_raw()..=Format::ASTC_12X12_SRGB_BLOCK.as_raw())
+        .contains(&raw_format)
+    {
+        Some(CompressionFamily::Astc)
+    } else {
+        None
+    }
+}
+
+pub fn block_dimensions(format: Format) -> (u32, u32) {
+    match format {
+        Format::ASTC_4X4_UNORM_BLOCK | Format::ASTC_4X4_SRGB_BLOCK => (4, 4),
+        Format::ASTC_5X4_UNORM_BLOCK | Format::ASTC_5X4_SRGB_BLOCK => (5, 4),
+        Format::ASTC_5X5_UNORM_BLOCK | Format::ASTC_5X5_SRGB_BLOCK => (5, 5),
+        Format::ASTC_6X5_UNORM_BLOCK | Format::ASTC_6X5_SRGB_BLOCK => (6, 5),
+        Format::ASTC_6X6_UNORM_BLOCK | Format::ASTC_6X6_SRGB_BLOCK => (6, 6),
+        Format::ASTC_8X5_UNORM_BLOCK | Format::ASTC_8X5_SRGB_BLOCK => (8, 5),
+        Format::ASTC_8X6_UNORM_BLOCK | Format::ASTC_8X6_SRGB_BLOCK => (8, 6),
+        Format::ASTC_8X8_UNORM_BLOCK | Format::ASTC_8X8_SRGB_BLOCK => (8, 8),
+        Format::ASTC_10X5_UNORM_BLOCK | Format::ASTC_10X5_SRGB_BLOCK => (10, 5),
+        Format::ASTC_10X6_UNORM_BLOCK | Format::ASTC_10X6_SRGB_BLOCK => (10, 6),
+        Format::ASTC_10X8_UNORM_BLOCK | Format::ASTC_10X8_SRGB_BLOCK => (10, 8),
+        Format::ASTC_10X10_UNORM_BLOCK | Format::ASTC_10X10_SRGB_BLOCK => (10, 10),
+        Format::ASTC_12X10_UNORM_BLOCK | Format::ASTC_12X10_SRGB_BLOCK => (12, 10),
+        Format::ASTC_12X12_UNORM_BLOCK | Format::ASTC_12X12_SRGB_BLOCK => (12, 12),
+        _ if compression_family(format).is_some() => (4, 4),
+        _ => (1, 1),
+    }
+}
+
+pub fn compressed_block_size(format: Format) -> Option<DeviceSize> {
+    match format {
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK
+        | Format::BC4_UNORM_BLOCK
+        | Format::BC4_SNORM_BLOCK
+        | Format::ETC2_R8G8B8_UNORM_BLOCK
+        | Format::ETC2_R8G8B8_SRGB_BLOCK
+        | Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | Format::EAC_R11_UNORM_BLOCK
+        | Format::EAC_R11_SNORM_BLOCK => Some(8),
+        _ if compression_family(format).is_some() => Some(16),
+        _ => None,
+    }
+}
+
+pub fn texel_size(format: Format) -> Option<DeviceSize> {
+    match format {
+        Format::R8G8B8A8_UNORM
+        | Format::R8G8B8A8_SRGB
+        | Format::B8G8R8A8_UNORM
+        | Format::B8G8R8A8_SRGB => Some(4),
+        Format::R16G16B16A16_SFLOAT => Some(8),
+        Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+pub fn block_aligned_extent(extent: Extent2D, block_dimensions: (u32, u32)) -> Extent2D {
+    let (block_width, block_height) = block_dimensions;
+    Extent2D {
+        width: extent.width.div_ceil(block_width) * block_width,
+        height: extent.height.div_ceil(block_height) * block_height,
+    }
+}
+
+pub fn compressed_image_size(format: Format, extent: Extent2D) -> Option<DeviceSize> {
+    let block_size = compressed_block_size(format)?;
+    let (block_width, block_height) = block_dimensions(format);
+    let aligned_extent = block_aligned_extent(extent, (block_width, block_height));
+    let block_count = (aligned_extent.width / block_width) as DeviceSize
+        * (aligned_extent.height / block_height) as DeviceSize;
+
+    Some(block_count * block_size)
+}
+
+pub fn select_preferred_format(
+    formats: &[Format],
+    support: &CompressedFormatSupport,
+) -> Option<usize> {
+    let rank = |format: Format| match compression_family(format) {
+        Some(CompressionFamily::Bc) => 0,
+        Some(CompressionFamily::Astc) => 1,
+        Some(CompressionFamily::Etc2) => 2,
+        None => 3,
+    };
+
+    formats
+        .iter()
+        .enumerate()
+        .filter(|(_, &format)| support.supports_format(format))
+        .min_by_key(|(_, &format)| rank(format))
+        .map(|(index, _)| index)
+}
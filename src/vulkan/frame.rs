@@ -0,0 +1,94 @@
+use anyhow::Result;
+use ash::vk::{Fence, FenceCreateFlags, FenceCreateInfo, Semaphore, SemaphoreCreateInfo};
+use ash::Device;
+
+use crate::vulkan::context::VulkanContext;
+
+pub struct FrameSyncObjects {
+    pub image_available_semaphores: Vec<Semaphore>,
+    pub render_finished_semaphores: Vec<Semaphore>,
+    pub in_flight_fences: Vec<Fence>,
+    /// Per swapchain image, the fence of the frame that last rendered to it. Images can be
+    /// acquired out of order, so an image may still be in use by another frame in flight.
+    pub images_in_flight: Vec<Option<Fence>>,
+}
+
+impl FrameSyncObjects {
+    pub fn new(
+        context: &VulkanContext,
+        frames_in_flight: usize,
+        swapchain_image_count: usize,
+    ) -> Result<FrameSyncObjects> {
+        let device = &context.device;
+        let semaphore_create_info = SemaphoreCreateInfo::default();
+        let fence_create_info = FenceCreateInfo::builder()
+            .flags(FenceCreateFlags::SIGNALED)
+            .build();
+
+        let mut sync_objects = FrameSyncObjects {
+            image_available_semaphores: vec![],
+            render_finished_semaphores: vec![],
+            in_flight_fences: vec![],
+            images_in_flight: vec![None; swapchain_image_count],
+        };
+        for frame in 0..frames_in_flight {
+            let (image_available, render_finished, in_flight) = unsafe {
+                (
+                    device.create_semaphore(&semaphore_create_info, None)?,
+                    device.create_semaphore(&semaphore_create_info, None)?,
+                    device.create_fence(&fence_create_info, None)?,
+                )
+            };
+            context.set_object_name(image_available, &format!("image available {}", frame));
+            context.set_object_name(render_finished, &format!("render finished {}", frame));
+            context.set_object_name(in_flight, &format!("in flight {}", frame));
+            sync_objects
+                .image_available_semaphores
+                .push(image_available);
+            sync_objects
+                .render_finished_semaphores
+                .push(render_finished);
+            sync_objects.in_flight_fences.push(in_flight);
+        }
+
+        Ok(sync_objects)
+    }
+
+    /// Call after swapchain recreation, the fences of the old images mean nothing for the new ones.
+    pub fn reset_images_in_flight(&mut self, swapchain_image_count: usize) {
+        self.images_in_flight.clear();
+        self.images_in_flight.resize(swapchain_image_count, None);
+    }
+
+    /// Waits for the frame still rendering to the acquired image, if any, then records the
+    /// frame that renders to it now. Call before resetting `fence`.
+    pub fn wait_for_image(
+        &mut self,
+        device: &Device,
+        image_index: usize,
+        fence: Fence,
+    ) -> Result<()> {
+        if let Some(image_fence) = self.images_in_flight[image_index] {
+            if image_fence != fence {
+                unsafe { device.wait_for_fences(&[image_fence], true, u64::MAX) }?;
+            }
+        }
+        self.images_in_flight[image_index] = Some(fence);
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for &semaphore in self.image_available_semaphores.iter() {
+                device.destroy_semaphore(semaphore, None);
+            }
+            for &semaphore in self.render_finished_semaphores.iter() {
+                device.destroy_semaphore(semaphore, None);
+            }
+            for &fence in self.in_flight_fences.iter() {
+                device.destroy_fence(fence, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use ash::extensions::ext::DebugUtils;
+use ash::vk::{Extent2D, Framebuffer, FramebufferCreateInfo, ImageView, RenderPass};
+use ash::Device;
+use log::{debug, info};
+
+use crate::util::debug::set_object_name;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct FramebufferKey {
+    render_pass: RenderPass,
+    attachments: Vec<ImageView>,
+    extent: Extent2D,
+}
+
+/// Framebuffers are created on first use and kept until `invalidate` is called, which has to
+/// happen whenever image views they use are destroyed, such as when the swapchain is recreated.
+pub struct FramebufferManager {
+    framebuffers: HashMap<FramebufferKey, Framebuffer>,
+    /// How many framebuffers the last `invalidate` destroyed
+    invalidated: usize,
+    rebuilt: usize,
+}
+
+impl FramebufferManager {
+    pub fn new() -> FramebufferManager {
+        FramebufferManager {
+            framebuffers: HashMap::new(),
+            invalidated: 0,
+            rebuilt: 0,
+        }
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        debug_utils: Option<&DebugUtils>,
+        render_pass: RenderPass,
+        attachments: &[ImageView],
+        extent: Extent2D,
+    ) -> Result<Framebuffer> {
+        let key = FramebufferKey {
+            render_pass,
+            attachments: attachments.to_vec(),
+            extent,
+        };
+        if let Some(&framebuffer) = self.framebuffers.get(&key) {
+            return Ok(framebuffer);
+        }
+
+        let framebuffer_create_info = FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None) }?;
+        self.framebuffers.insert(key, framebuffer);
+        set_object_name(
+            debug_utils,
+            device,
+            framebuffer,
+            &format!("framebuffer {}", self.framebuffers.len()),
+        );
+        if self.invalidated > 0 {
+            self.rebuilt += 1;
+            debug!(
+                "Rebuilt {} framebuffers since {} were invalidated",
+                self.rebuilt, self.invalidated
+            );
+        }
+
+        Ok(framebuffer)
+    }
+
+    /// Destroys every framebuffer, the device must be idle. They are recreated by the next
+    /// `get_or_create` calls.
+    pub fn invalidate(&mut self, device: &Device) {
+        info!("Invalidating {} framebuffers", self.framebuffers.len());
+        self.invalidated = self.framebuffers.len();
+        self.rebuilt = 0;
+        self.destroy_framebuffers(device);
+    }
+
+    pub fn len(&self) -> usize {
+        self.framebuffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.framebuffers.is_empty()
+    }
+
+    pub fn destroy_all(&mut self, device: &Device) {
+        info!("Destroying {} framebuffers", self.framebuffers.len());
+        self.destroy_framebuffers(device);
+    }
+
+    fn destroy_framebuffers(&mut self, device: &Device) {
+        for (_, framebuffer) in self.framebuffers.drain() {
+            unsafe { device.destroy_framebuffer(framebuffer, None) };
+        }
+    }
+}
+
+impl Default for FramebufferManager {
+    fn default() -> FramebufferManager {
+        FramebufferManager::new()
+    }
+}
@@ -0,0 +1,45 @@
+use std::mem;
+
+use anyhow::{Error, Result};
+use ash::vk::{ExtFullScreenExclusiveFn, SwapchainKHR};
+use ash::{vk, Device, Instance};
+
+pub fn load_full_screen_exclusive(
+    instance: &Instance,
+    device: &Device,
+) -> ExtFullScreenExclusiveFn {
+    ExtFullScreenExclusiveFn::load(|name| unsafe {
+        mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+    })
+}
+
+/// Takes the display for `swapchain`, which must have been created application controlled.
+/// Fails with `ERROR_INITIALIZATION_FAILED` or `ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT` when the
+/// display can't be taken, the swapchain then keeps presenting like a borderless window.
+pub fn acquire_full_screen_exclusive_mode(
+    device: &Device,
+    full_screen_exclusive: &ExtFullScreenExclusiveFn,
+    swapchain: SwapchainKHR,
+) -> Result<()> {
+    Ok(unsafe {
+        (full_screen_exclusive.acquire_full_screen_exclusive_mode_ext)(device.handle(), swapchain)
+    }
+    .result()?)
+}
+
+pub fn release_full_screen_exclusive_mode(
+    device: &Device,
+    full_screen_exclusive: &ExtFullScreenExclusiveFn,
+    swapchain: SwapchainKHR,
+) -> Result<()> {
+    Ok(unsafe {
+        (full_screen_exclusive.release_full_screen_exclusive_mode_ext)(device.handle(), swapchain)
+    }
+    .result()?)
+}
+
+/// Whether acquiring an image or presenting failed because another application took the display.
+pub fn is_full_screen_exclusive_lost(error: &Error) -> bool {
+    error.downcast_ref::<vk::Result>()
+        == Some(&vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT)
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::extensions::ext::DebugUtils;
+use ash::vk::DebugUtilsMessengerEXT;
+use ash::{Entry, Instance};
+use log::{error, info};
+
+use crate::config::EngineConfig;
+use crate::util::debug::{create_debug_utils, MessageFilter};
+use crate::vulkan::context::{ContextOptions, VulkanContext};
+use crate::vulkan::device::{
+    create_logical_device, select_physical_device, supports_dynamic_rendering,
+};
+use crate::vulkan::features::RequestedFeatures;
+use crate::vulkan::instance::{create_instance, negotiate_api_version, resolve_validation};
+
+/// A context on a device selected without a surface, for compute-only tools and tests. It owns
+/// the instance and destroys everything when dropped.
+pub struct HeadlessContext {
+    pub context: VulkanContext,
+    instance: Instance,
+    /// Created only with validation
+    debug_utils_loader: Option<DebugUtils>,
+    debug_messenger: Option<DebugUtilsMessengerEXT>,
+    /// Reached by the debug callbacks until the instance is destroyed
+    pub message_filter: Arc<MessageFilter>,
+    /// Keeps the Vulkan library loaded
+    _entry: Entry,
+}
+
+impl HeadlessContext {
+    pub fn new(config: &EngineConfig) -> Result<HeadlessContext> {
+        let entry = unsafe { Entry::load() }?;
+        let api_version = negotiate_api_version(&entry)?;
+        let validation = resolve_validation(&entry, &config.validation);
+        let message_filter = Arc::new(MessageFilter::new(&validation));
+        let instance = create_instance(&entry, api_version, &validation, &message_filter, None)?;
+        let selected_device =
+            select_physical_device(&instance, api_version, None, &config.gpu_selection)?;
+        let dynamic_rendering =
+            config.dynamic_rendering && supports_dynamic_rendering(&instance, &selected_device);
+        let (device, queue_family_indices, capabilities) = create_logical_device(
+            &instance,
+            &selected_device,
+            &RequestedFeatures::engine(),
+            dynamic_rendering,
+        )?;
+        let (debug_utils_loader, debug_messenger) =
+            create_debug_utils(&entry, &instance, &validation, &message_filter)?.unzip();
+        let context = VulkanContext::new(
+            &instance,
+            selected_device.physical_device,
+            device,
+            queue_family_indices,
+            capabilities,
+            ContextOptions {
+                dynamic_rendering,
+                debug_utils: debug_utils_loader.clone(),
+                debug_printf: validation.debug_printf(),
+            },
+            config,
+        )?;
+        info!("Created a headless context");
+
+        Ok(HeadlessContext {
+            context,
+            instance,
+            debug_utils_loader,
+            debug_messenger,
+            message_filter,
+            _entry: entry,
+        })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        if let Err(error) = unsafe { self.context.device.device_wait_idle() } {
+            error!("Failed to wait for device idle: {}", error);
+        }
+        self.context.destroy();
+        if let (Some(debug_utils_loader), Some(debug_messenger)) =
+            (&self.debug_utils_loader, self.debug_messenger)
+        {
+            unsafe { debug_utils_loader.destroy_debug_utils_messenger(debug_messenger, None) };
+        }
+        unsafe { self.instance.destroy_instance(None) };
+        self.message_filter.log_summary();
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use anyhow::Result;
+use log::{info, warn};
+use notify::{recommended_watcher, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// With runtime compilation or WGSL translation the sources next to the SPIR-V directory are
+/// watched, otherwise the SPIR-V directory itself.
+pub fn watched_shader_dir(shader_dir: &Path) -> PathBuf {
+    #[cfg(any(feature = "shaderc", feature = "wgsl"))]
+    if let Some(parent) = shader_dir.parent() {
+        return parent.join("src");
+    }
+
+    shader_dir.to_path_buf()
+}
+
+/// Watches a shader directory and collects the files that were created or modified since the
+/// last call to `changed_files`.
+pub struct ShaderWatcher {
+    _watcher: RecommendedWatcher,
+    changes: Receiver<PathBuf>,
+}
+
+impl ShaderWatcher {
+    pub fn new(shader_dir: &Path) -> Result<ShaderWatcher> {
+        let (sender, changes) = channel();
+        let mut watcher = recommended_watcher(move |result: notify::Result<Event>| match result {
+            Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(error) => warn!("Shader watcher error: {}", error),
+        })?;
+        watcher.watch(shader_dir, RecursiveMode::Recursive)?;
+
+        info!("Watching {:?} for shader changes", shader_dir);
+
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Returns every changed file once, editors tend to produce several events per save.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut changed_files: Vec<PathBuf> = vec![];
+        for path in self.changes.try_iter() {
+            if !changed_files.contains(&path) {
+                changed_files.push(path);
+            }
+        }
+
+        changed_files
+    }
+}
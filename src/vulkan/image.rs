@@ -0,0 +1,354 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, CommandBuffer, ComponentMapping, DependencyFlags, DeviceMemory, DeviceSize,
+    Extent2D, Extent3D, Format, FormatFeatureFlags, Image, ImageAspectFlags, ImageCreateFlags,
+    ImageCreateInfo, ImageLayout, ImageMemoryBarrier, ImageSubresourceRange, ImageTiling,
+    ImageType, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, MemoryPropertyFlags,
+    PipelineStageFlags, SampleCountFlags, SharingMode, QUEUE_FAMILY_IGNORED,
+};
+use ash::Device;
+
+use crate::config::DepthConvention;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::memory::{allocate_memory, free_memory};
+
+const DEPTH_FORMAT_CANDIDATES: [Format; 3] = [
+    Format::D32_SFLOAT,
+    Format::D32_SFLOAT_S8_UINT,
+    Format::D24_UNORM_S8_UINT,
+];
+
+const REVERSE_Z_DEPTH_FORMAT_CANDIDATES: [Format; 1] = [Format::D32_SFLOAT];
+
+pub struct ImageDesc {
+    pub extent: Extent2D,
+    pub format: Format,
+    pub usage: ImageUsageFlags,
+    pub aspect_mask: ImageAspectFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub flags: ImageCreateFlags,
+    pub view_type: ImageViewType,
+}
+
+impl ImageDesc {
+    pub fn texture_2d(extent: Extent2D, format: Format) -> ImageDesc {
+        ImageDesc {
+            extent,
+            format,
+            usage: ImageUsageFlags::TRANSFER_DST | ImageUsageFlags::SAMPLED,
+            aspect_mask: ImageAspectFlags::COLOR,
+            mip_levels: 1,
+            array_layers: 1,
+            flags: ImageCreateFlags::empty(),
+            view_type: ImageViewType::TYPE_2D,
+        }
+    }
+
+    pub fn cubemap(face_size: u32, format: Format) -> ImageDesc {
+        ImageDesc {
+            array_layers: 6,
+            flags: ImageCreateFlags::CUBE_COMPATIBLE,
+            view_type: ImageViewType::CUBE,
+            ..ImageDesc::texture_2d(
+                Extent2D {
+                    width: face_size,
+                    height: face_size,
+                },
+                format,
+            )
+        }
+    }
+
+    pub fn color_attachment(extent: Extent2D, format: Format) -> ImageDesc {
+        ImageDesc {
+            usage: ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+            ..ImageDesc::texture_2d(extent, format)
+        }
+    }
+
+    /// A color attachment that is only read as an input attachment within its render pass, so
+    /// tiled GPUs can keep it in tile memory.
+    pub fn input_attachment(extent: Extent2D, format: Format) -> ImageDesc {
+        ImageDesc {
+            usage: ImageUsageFlags::COLOR_ATTACHMENT
+                | ImageUsageFlags::INPUT_ATTACHMENT
+                | ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            ..ImageDesc::texture_2d(extent, format)
+        }
+    }
+
+    pub fn depth_attachment(extent: Extent2D, format: Format) -> ImageDesc {
+        ImageDesc {
+            usage: ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            aspect_mask: ImageAspectFlags::DEPTH,
+            ..ImageDesc::texture_2d(extent, format)
+        }
+    }
+
+    pub fn subresource_range(&self) -> ImageSubresourceRange {
+        ImageSubresourceRange::builder()
+            .aspect_mask(self.aspect_mask)
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(self.array_layers)
+            .build()
+    }
+}
+
+pub struct PistonImage {
+    pub image: Image,
+    pub memory: DeviceMemory,
+    pub view: ImageView,
+    pub format: Format,
+    pub extent: Extent2D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub subresource_range: ImageSubresourceRange,
+    heap_index: u32,
+    allocation_size: DeviceSize,
+}
+
+impl PistonImage {
+    pub fn new(context: &VulkanContext, desc: &ImageDesc, name: &str) -> Result<PistonImage> {
+        let device = &context.device;
+        let image_create_info = ImageCreateInfo::builder()
+            .flags(desc.flags)
+            .image_type(ImageType::TYPE_2D)
+            .format(desc.format)
+            .extent(Extent3D {
+                width: desc.extent.width,
+                height: desc.extent.height,
+                depth: 1,
+            })
+            .mip_levels(desc.mip_levels)
+            .array_layers(desc.array_layers)
+            .samples(SampleCountFlags::TYPE_1)
+            .tiling(ImageTiling::OPTIMAL)
+            .usage(desc.usage)
+            .sharing_mode(SharingMode::EXCLUSIVE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .build();
+        let image = unsafe { device.create_image(&image_create_info, None) }?;
+
+        let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let (memory, heap_index) = allocate_memory(
+            context,
+            &memory_requirements,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        unsafe { device.bind_image_memory(image, memory, 0) }?;
+
+        let subresource_range = desc.subresource_range();
+        let view = create_image_view(
+            device,
+            image,
+            desc.format,
+            desc.view_type,
+            subresource_range,
+        )?;
+        context.set_object_name(image, name);
+        context.set_object_name(view, &format!("{} view", name));
+
+        Ok(PistonImage {
+            image,
+            memory,
+            view,
+            format: desc.format,
+            extent: desc.extent,
+            mip_levels: desc.mip_levels,
+            array_layers: desc.array_layers,
+            subresource_range,
+            heap_index,
+            allocation_size: memory_requirements.size,
+        })
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+        free_memory(device, self.memory, self.heap_index, self.allocation_size);
+    }
+}
+
+pub fn create_image_view(
+    device: &Device,
+    image: Image,
+    format: Format,
+    view_type: ImageViewType,
+    subresource_range: ImageSubresourceRange,
+) -> Result<ImageView> {
+    let image_view_create_info = ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .components(ComponentMapping::default())
+        .subresource_range(subresource_range)
+        .build();
+
+    Ok(unsafe { device.create_image_view(&image_view_create_info, None) }?)
+}
+
+/// Reverse-Z depth is only precise in a float format, so it only takes `D32_SFLOAT`.
+pub fn select_depth_format(context: &VulkanContext) -> Result<Format> {
+    let candidates: &[Format] = match context.depth_convention {
+        DepthConvention::Standard => &DEPTH_FORMAT_CANDIDATES,
+        DepthConvention::ReverseZ => &REVERSE_Z_DEPTH_FORMAT_CANDIDATES,
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| {
+            let format_properties = unsafe {
+                context
+                    .instance
+                    .get_physical_device_format_properties(context.physical_device, format)
+            };
+            format_properties
+                .optimal_tiling_features
+                .contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "None of {:?} is supported as a {:?} depth attachment",
+                candidates,
+                context.depth_convention
+            )
+        })
+}
+
+/// The single mip level and layer of a color image, such as a swapchain image.
+pub fn color_subresource_range() -> ImageSubresourceRange {
+    ImageSubresourceRange::builder()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+pub fn record_image_layout_transition(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    image: Image,
+    subresource_range: ImageSubresourceRange,
+    old_layout: ImageLayout,
+    new_layout: ImageLayout,
+) -> Result<()> {
+    let (src_access_mask, dst_access_mask, src_stage, dst_stage) = match (old_layout, new_layout) {
+        (ImageLayout::UNDEFINED, ImageLayout::TRANSFER_DST_OPTIMAL) => (
+            AccessFlags::empty(),
+            AccessFlags::TRANSFER_WRITE,
+            PipelineStageFlags::TOP_OF_PIPE,
+            PipelineStageFlags::TRANSFER,
+        ),
+        (ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            AccessFlags::TRANSFER_WRITE,
+            AccessFlags::SHADER_READ,
+            PipelineStageFlags::TRANSFER,
+            PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        (ImageLayout::UNDEFINED, ImageLayout::GENERAL) => (
+            AccessFlags::empty(),
+            AccessFlags::SHADER_WRITE,
+            PipelineStageFlags::TOP_OF_PIPE,
+            PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        (ImageLayout::GENERAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            AccessFlags::SHADER_WRITE,
+            AccessFlags::SHADER_READ,
+            PipelineStageFlags::COMPUTE_SHADER,
+            PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        // Waits for the image-available semaphore of swapchain images, and for the reads of the
+        // previous frame when the image is sampled or copied later
+        (ImageLayout::UNDEFINED, ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
+            AccessFlags::empty(),
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | PipelineStageFlags::FRAGMENT_SHADER
+                | PipelineStageFlags::TRANSFER,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        (ImageLayout::COLOR_ATTACHMENT_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessFlags::SHADER_READ,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        (ImageLayout::COLOR_ATTACHMENT_OPTIMAL, ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessFlags::TRANSFER_READ,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            PipelineStageFlags::TRANSFER,
+        ),
+        (ImageLayout::COLOR_ATTACHMENT_OPTIMAL, ImageLayout::PRESENT_SRC_KHR) => (
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessFlags::empty(),
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        // Also waits for the reads of the previous frame when the depth is sampled later
+        (ImageLayout::UNDEFINED, ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL) => (
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            PipelineStageFlags::LATE_FRAGMENT_TESTS | PipelineStageFlags::FRAGMENT_SHADER,
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        (ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            AccessFlags::SHADER_READ,
+            PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        (ImageLayout::PRESENT_SRC_KHR, ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessFlags::TRANSFER_READ,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            PipelineStageFlags::TRANSFER,
+        ),
+        (ImageLayout::TRANSFER_SRC_OPTIMAL, ImageLayout::PRESENT_SRC_KHR) => (
+            AccessFlags::TRANSFER_READ,
+            AccessFlags::empty(),
+            PipelineStageFlags::TRANSFER,
+            PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "Unsupported image layout transition from {:?} to {:?}",
+                old_layout,
+                new_layout
+            ))
+        }
+    };
+
+    let image_memory_barrier = ImageMemoryBarrier::builder()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .build();
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            DependencyFlags::empty(),
+            &[],
+            &[],
+            &[image_memory_barrier],
+        )
+    };
+
+    Ok(())
+}
@@ -1,24 +1,160 @@
-use std::ffi::CString;
-use std::os::raw::c_void;
-use std::ptr;
-
-use crate::constants::{APPLICATION_NAME, APPLICATION_VERSION, ENGINE_NAME, VULKAN_API_VERSION};
-use crate::util::debug::{create_debug_info, ValidationInfo};
-use crate::util::util::vk_to_string;
-use ash::extensions::ext::{DebugUtils, MetalSurface};
-use ash::extensions::khr::Surface;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+
+use crate::constants::{
+    APPLICATION_NAME, APPLICATION_VERSION, ENGINE_NAME, LAYER_SETTINGS_EXTENSION,
+    MIN_VULKAN_API_VERSION, OPTIONAL_INSTANCE_EXTENSIONS, VULKAN_API_VERSION,
+};
+use crate::util::debug::{create_debug_info, MessageFilter, ValidationInfo};
+use crate::util::util::{vk_to_cstr, vk_to_string, vk_version_to_string};
+use crate::vulkan::layer_settings::LayerSettings;
+use anyhow::{anyhow, Context, Result};
+use ash::extensions::ext::DebugUtils;
 use ash::vk::{
-    DebugUtilsMessengerCreateInfoEXT, InstanceCreateFlags, InstanceCreateInfo,
-    KhrGetPhysicalDeviceProperties2Fn, KhrPortabilityEnumerationFn, StructureType,
+    ExtValidationFeaturesFn, InstanceCreateFlags, InstanceCreateInfo, KhrPortabilityEnumerationFn,
+    LayerProperties, ValidationFeatureEnableEXT, ValidationFeaturesEXT, API_VERSION_1_0,
 };
 use ash::{vk, Entry, Instance};
+use log::{debug, info, warn};
+use raw_window_handle::RawDisplayHandle;
+use tracing::info_span;
+
+/// The instance version to request, `VULKAN_API_VERSION` lowered to what the loader supports.
+/// Fails when that is below `MIN_VULKAN_API_VERSION`.
+pub fn negotiate_api_version(entry: &Entry) -> Result<u32> {
+    // Only 1.0 loaders lack `vkEnumerateInstanceVersion`
+    let loader_version = entry
+        .try_enumerate_instance_version()?
+        .unwrap_or(API_VERSION_1_0);
+    // The patch version of the loader means nothing to the instance
+    let api_version = vk::make_api_version(
+        0,
+        vk::api_version_major(loader_version),
+        vk::api_version_minor(loader_version),
+        0,
+    )
+    .min(VULKAN_API_VERSION);
+    info!(
+        "Vulkan loader {}, requesting instance version {}",
+        vk_version_to_string(loader_version),
+        vk_version_to_string(api_version)
+    );
+    if api_version < MIN_VULKAN_API_VERSION {
+        return Err(anyhow!(
+            "The Vulkan loader only supports Vulkan {}, the engine needs at least {}. Update the \
+             graphics driver or the Vulkan runtime",
+            vk_version_to_string(loader_version),
+            vk_version_to_string(MIN_VULKAN_API_VERSION)
+        ));
+    }
+
+    Ok(api_version)
+}
 
+/// Turns validation off when the layers aren't installed, so a missing SDK doesn't stop the
+/// engine, and drops the extra features when the layer doesn't support them. Logs whether
+/// validation ends up enabled.
+pub fn resolve_validation(entry: &Entry, validation_info: &ValidationInfo) -> ValidationInfo {
+    let is_enabled = match validation_info.is_enabled {
+        true => match check_validation_layers(entry, validation_info) {
+            Ok(()) => true,
+            Err(error) => {
+                warn!("{}, continuing without validation", error);
+                false
+            }
+        },
+        false => false,
+    };
+    info!(
+        "Validation is {}",
+        if is_enabled { "enabled" } else { "disabled" }
+    );
+
+    let extra_features = match (is_enabled, validation_info.extra_features.is_empty()) {
+        (true, false) => match supports_validation_features(entry, validation_info) {
+            Ok(true) => {
+                let extra_features = without_conflicts(&validation_info.extra_features);
+                info!("Extra validation features: {:?}", extra_features);
+                extra_features
+            }
+            Ok(false) => {
+                warn!(
+                    "The validation layer doesn't support {:?}, continuing with plain validation",
+                    ExtValidationFeaturesFn::name()
+                );
+                vec![]
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to query the validation layer extensions, using plain validation: {}",
+                    error
+                );
+                vec![]
+            }
+        },
+        _ => vec![],
+    };
+
+    ValidationInfo {
+        is_enabled,
+        extra_features,
+        ..validation_info.clone()
+    }
+}
+
+/// Debug printf and GPU-assisted validation both instrument shaders and can't run together, debug
+/// printf wins since it has to be asked for explicitly.
+fn without_conflicts(features: &[ValidationFeatureEnableEXT]) -> Vec<ValidationFeatureEnableEXT> {
+    let gpu_assisted = [
+        ValidationFeatureEnableEXT::GPU_ASSISTED,
+        ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+    ];
+    match features.contains(&ValidationFeatureEnableEXT::DEBUG_PRINTF)
+        && features.contains(&ValidationFeatureEnableEXT::GPU_ASSISTED)
+    {
+        true => {
+            warn!("Debug printf and GPU-assisted validation are exclusive, dropping GPU-assisted");
+            features
+                .iter()
+                .filter(|feature| !gpu_assisted.contains(feature))
+                .copied()
+                .collect()
+        }
+        false => features.to_vec(),
+    }
+}
+
+fn supports_validation_features(entry: &Entry, validation_info: &ValidationInfo) -> Result<bool> {
+    for layer_name in validation_info.required_validation_layers {
+        let layer_name = CString::new(layer_name)?;
+        let layer_extensions = entry.enumerate_instance_extension_properties(Some(&layer_name))?;
+        if layer_extensions.iter().any(|extension| {
+            vk_to_cstr(&extension.extension_name) == ExtValidationFeaturesFn::name()
+        }) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Enables the surface extensions of the window system `display_handle` belongs to. Without one
+/// the instance can't present, which is enough for headless use. Fails naming the required
+/// extensions that aren't available, optional ones are enabled when they are.
 pub fn create_instance(
     entry: &Entry,
+    api_version: u32,
     validation_info: &ValidationInfo,
-) -> anyhow::Result<Instance> {
-    if validation_info.is_enabled && !is_validation_layer_supported(entry, validation_info) {
-        panic!("Validation layers requested, but not available!")
+    message_filter: &MessageFilter,
+    display_handle: Option<RawDisplayHandle>,
+) -> Result<Instance> {
+    let _span = info_span!(
+        "create_instance",
+        api_version = %vk_version_to_string(api_version)
+    )
+    .entered();
+    if validation_info.is_enabled {
+        check_validation_layers(entry, validation_info)?;
     }
 
     let application_name = &CString::new(APPLICATION_NAME)?;
@@ -27,73 +163,231 @@ pub fn create_instance(
         .application_name(application_name)
         .application_version(APPLICATION_VERSION)
         .engine_name(engine_name)
-        .api_version(VULKAN_API_VERSION)
+        .api_version(api_version)
         .build();
 
-    let extension_names = vec![
-        DebugUtils::name().as_ptr(),
-        KhrPortabilityEnumerationFn::name().as_ptr(),
-        KhrGetPhysicalDeviceProperties2Fn::name().as_ptr(),
-        MetalSurface::name().as_ptr(),
-        Surface::name().as_ptr(),
-    ];
-
-    let required_validation_layer_names: Vec<CString> = validation_info
-        .required_validation_layers
-        .to_vec()
+    let mut required_extensions: Vec<&CStr> = vec![];
+    if let Some(display_handle) = display_handle {
+        required_extensions.extend(
+            ash_window::enumerate_required_extensions(display_handle)?
+                .iter()
+                .map(|&extension_name| unsafe { CStr::from_ptr(extension_name) }),
+        );
+    }
+    if validation_info.is_enabled {
+        required_extensions.push(DebugUtils::name());
+    }
+    let extra_validation = validation_info.is_enabled && !validation_info.extra_features.is_empty();
+    if extra_validation {
+        required_extensions.push(ExtValidationFeaturesFn::name());
+    }
+    let available_extensions = available_instance_extensions(entry, validation_info)?;
+    let layer_settings = encode_layer_settings(validation_info, &available_extensions)?;
+    if layer_settings.is_some() {
+        required_extensions.push(LAYER_SETTINGS_EXTENSION);
+    }
+    let missing_extensions = required_extensions
         .iter()
-        .map(|layer_name| CString::new(*layer_name).unwrap())
-        .collect();
+        .filter(|&&extension_name| !available_extensions.contains(extension_name))
+        .collect::<Vec<_>>();
+    if !missing_extensions.is_empty() {
+        let mut available_extensions = available_extensions.into_iter().collect::<Vec<_>>();
+        available_extensions.sort();
+        return Err(anyhow!(
+            "The instance extensions {:?} are not available, the available ones are {:?}",
+            missing_extensions,
+            available_extensions
+        ));
+    }
+    let enabled_extensions = required_extensions
+        .into_iter()
+        .chain(
+            OPTIONAL_INSTANCE_EXTENSIONS
+                .into_iter()
+                .filter(|&extension_name| available_extensions.contains(extension_name)),
+        )
+        .collect::<Vec<_>>();
+    info!("Enabling instance extensions {:?}", enabled_extensions);
+    // Only valid with the extension enabled
+    let flags = match enabled_extensions.contains(&KhrPortabilityEnumerationFn::name()) {
+        true => InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR,
+        false => InstanceCreateFlags::empty(),
+    };
+    let extension_names = enabled_extensions
+        .iter()
+        .map(|extension_name| extension_name.as_ptr())
+        .collect::<Vec<_>>();
 
-    let layer_names: Vec<*const i8> = required_validation_layer_names
+    let layer_names = match validation_info.is_enabled {
+        true => validation_info
+            .required_validation_layers
+            .iter()
+            .map(|&layer_name| CString::new(layer_name))
+            .collect::<Result<Vec<_>, _>>()?,
+        false => vec![],
+    };
+    let layer_name_pointers = layer_names
         .iter()
         .map(|layer_name| layer_name.as_ptr())
+        .collect::<Vec<_>>();
+
+    // The messenger reports on instance creation and destruction, which the one created after the
+    // instance can't
+    let mut debug_messenger_create_info = create_debug_info(validation_info, message_filter);
+    let mut validation_features = ValidationFeaturesEXT::builder()
+        .enabled_validation_features(&validation_info.extra_features);
+    let mut create_info = InstanceCreateInfo::builder()
+        .flags(flags)
+        .application_info(&application_info)
+        .enabled_layer_names(&layer_name_pointers)
+        .enabled_extension_names(&extension_names);
+    if validation_info.is_enabled {
+        create_info = create_info.push_next(&mut debug_messenger_create_info);
+    }
+    if extra_validation {
+        create_info = create_info.push_next(&mut validation_features);
+    }
+    let mut layer_settings_create_info = layer_settings.as_ref().map(LayerSettings::create_info);
+    if let Some(layer_settings_create_info) = layer_settings_create_info.as_mut() {
+        create_info = create_info.push_next(layer_settings_create_info);
+    }
+
+    unsafe { entry.create_instance(&create_info, None) }.with_context(|| {
+        format!(
+            "Failed to create the instance with the layers {:?} and the extensions {:?}",
+            layer_names, enabled_extensions
+        )
+    })
+}
+
+/// The required layers missing from `available_layers`, in the order they are required.
+pub fn missing_validation_layers(
+    available_layers: &[LayerProperties],
+    validation_info: &ValidationInfo,
+) -> Vec<String> {
+    let available_layer_names: HashSet<String> = available_layers
+        .iter()
+        .map(|layer| vk_to_string(&layer.layer_name))
         .collect();
 
-    let debug_util_messenger_create_info = create_debug_info();
+    validation_info
+        .required_validation_layers
+        .iter()
+        .filter(|layer_name| !available_layer_names.contains(**layer_name))
+        .map(|layer_name| layer_name.to_string())
+        .collect()
+}
 
-    let create_info = InstanceCreateInfo {
-        s_type: StructureType::INSTANCE_CREATE_INFO,
-        p_next: if validation_info.is_enabled {
-            &debug_util_messenger_create_info as *const DebugUtilsMessengerCreateInfoEXT
-                as *const c_void
-        } else {
-            ptr::null()
-        },
-        flags: InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR,
-        p_application_info: &application_info,
-        enabled_layer_count: if validation_info.is_enabled {
-            layer_names.len() as u32
-        } else {
-            0
-        },
-        pp_enabled_layer_names: if validation_info.is_enabled {
-            layer_names.as_ptr()
-        } else {
-            ptr::null()
-        },
-        enabled_extension_count: extension_names.len() as u32,
-        pp_enabled_extension_names: extension_names.as_ptr(),
-    };
+/// The validation settings for the first validation layer, `None` when there are none or the
+/// layers lack `VK_EXT_layer_settings`, in which case they keep their defaults.
+fn encode_layer_settings(
+    validation_info: &ValidationInfo,
+    available_extensions: &HashSet<CString>,
+) -> Result<Option<LayerSettings>> {
+    let values = validation_info.settings.layer_setting_values();
+    if !validation_info.is_enabled || values.is_empty() {
+        return Ok(None);
+    }
+    if !available_extensions.contains(LAYER_SETTINGS_EXTENSION) {
+        debug!(
+            "The validation layer lacks {:?}, its settings are left at their defaults",
+            LAYER_SETTINGS_EXTENSION
+        );
+        return Ok(None);
+    }
 
-    Ok(unsafe { entry.create_instance(&create_info, None) }.expect("Error creating instance"))
+    info!("Validation layer settings: {:?}", values);
+    LayerSettings::encode(validation_info.required_validation_layers[0], &values).map(Some)
 }
 
-fn is_validation_layer_supported(entry: &Entry, validation_info: &ValidationInfo) -> bool {
-    let layer_properties = entry
-        .enumerate_instance_layer_properties()
-        .expect("Failed to enumerate layer properties");
-    let mut layer_found = false;
-
-    for required_layer_name in validation_info.required_validation_layers.iter() {
-        for layer_property in layer_properties.iter() {
-            let layer_name = vk_to_string(&layer_property.layer_name);
-            if *required_layer_name == layer_name {
-                layer_found = true;
-                break;
-            }
+/// The instance extensions of the loader and drivers, and with validation those of the validation
+/// layers, which provide debug utils and the validation features.
+fn available_instance_extensions(
+    entry: &Entry,
+    validation_info: &ValidationInfo,
+) -> Result<HashSet<CString>> {
+    let mut extensions = entry.enumerate_instance_extension_properties(None)?;
+    if validation_info.is_enabled {
+        for layer_name in validation_info.required_validation_layers {
+            let layer_name = CString::new(layer_name)?;
+            extensions.extend(entry.enumerate_instance_extension_properties(Some(&layer_name))?);
+        }
+    }
+
+    Ok(extensions
+        .iter()
+        .map(|extension| vk_to_cstr(&extension.extension_name).to_owned())
+        .collect())
+}
+
+/// Fails with the missing layers and the installed ones when any required layer is missing.
+fn check_validation_layers(entry: &Entry, validation_info: &ValidationInfo) -> Result<()> {
+    let available_layers = entry.enumerate_instance_layer_properties()?;
+    let missing_layers = missing_validation_layers(&available_layers, validation_info);
+    if missing_layers.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Validation layers {:?} are not available, the installed layers are {:?}",
+        missing_layers,
+        available_layers
+            .iter()
+            .map(|layer| vk_to_string(&layer.layer_name))
+            .collect::<Vec<_>>()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+
+    fn layer(name: &str) -> LayerProperties {
+        let mut layer = LayerProperties::default();
+        for (c_char, byte) in layer.layer_name.iter_mut().zip(name.bytes()) {
+            *c_char = byte as _;
         }
+        layer
+    }
+
+    fn validation_info(required_layer: &'static str) -> ValidationInfo {
+        let mut validation_info = EngineConfig::default().validation;
+        validation_info.required_validation_layers = [required_layer];
+        validation_info
     }
 
-    layer_found
+    #[test]
+    fn no_layers_missing_when_installed() {
+        let available = [
+            layer("VK_LAYER_MESA_device_select"),
+            layer("VK_LAYER_KHRONOS_validation"),
+        ];
+        let missing =
+            missing_validation_layers(&available, &validation_info("VK_LAYER_KHRONOS_validation"));
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn missing_layers_are_named() {
+        let available = [layer("VK_LAYER_MESA_device_select")];
+        let missing =
+            missing_validation_layers(&available, &validation_info("VK_LAYER_KHRONOS_validation"));
+        assert_eq!(missing, ["VK_LAYER_KHRONOS_validation"]);
+        assert_eq!(
+            missing_validation_layers(&[], &validation_info("VK_LAYER_KHRONOS_validation")),
+            ["VK_LAYER_KHRONOS_validation"]
+        );
+    }
+
+    #[test]
+    fn layer_names_match_exactly() {
+        let available = [
+            layer("VK_LAYER_KHRONOS_validation_extra"),
+            layer("VK_LAYER_KHRONOS"),
+        ];
+        let missing =
+            missing_validation_layers(&available, &validation_info("VK_LAYER_KHRONOS_validation"));
+        assert_eq!(missing, ["VK_LAYER_KHRONOS_validation"]);
+    }
 }
@@ -0,0 +1,281 @@
+use std::ffi::{c_char, c_void, CString};
+use std::ptr;
+
+use anyhow::Result;
+use ash::vk::{Bool32, ExtendsInstanceCreateInfo, StructureType, FALSE, TRUE};
+
+/// ash predates `VK_EXT_layer_settings`, so its structures are declared here.
+const LAYER_SETTINGS_CREATE_INFO_EXT: StructureType = StructureType::from_raw(1_000_496_000);
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerSettingTypeEXT(i32);
+
+impl LayerSettingTypeEXT {
+    pub const BOOL32: LayerSettingTypeEXT = LayerSettingTypeEXT(0);
+    pub const INT32: LayerSettingTypeEXT = LayerSettingTypeEXT(1);
+    pub const UINT32: LayerSettingTypeEXT = LayerSettingTypeEXT(3);
+    pub const STRING: LayerSettingTypeEXT = LayerSettingTypeEXT(7);
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LayerSettingEXT {
+    pub p_layer_name: *const c_char,
+    pub p_setting_name: *const c_char,
+    pub ty: LayerSettingTypeEXT,
+    pub value_count: u32,
+    pub p_values: *const c_void,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LayerSettingsCreateInfoEXT {
+    pub s_type: StructureType,
+    pub p_next: *const c_void,
+    pub setting_count: u32,
+    pub p_settings: *const LayerSettingEXT,
+}
+
+// Starts with the type and next pointer like every chained structure
+unsafe impl ExtendsInstanceCreateInfo for LayerSettingsCreateInfoEXT {}
+
+/// A setting as the layer reads it from its settings file, a string list is comma separated
+/// there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayerSettingValue {
+    Bool(bool),
+    Int32(i32),
+    Uint32(u32),
+    Strings(Vec<String>),
+}
+
+/// A value in the form `pValues` points to. The string pointers point into the `CString`s.
+#[derive(Debug)]
+enum EncodedValue {
+    Bool32(Bool32),
+    Int32(i32),
+    Uint32(u32),
+    Strings {
+        _strings: Vec<CString>,
+        pointers: Vec<*const c_char>,
+    },
+}
+
+impl EncodedValue {
+    fn new(value: &LayerSettingValue) -> Result<EncodedValue> {
+        Ok(match value {
+            LayerSettingValue::Bool(value) => EncodedValue::Bool32(match value {
+                true => TRUE,
+                false => FALSE,
+            }),
+            LayerSettingValue::Int32(value) => EncodedValue::Int32(*value),
+            LayerSettingValue::Uint32(value) => EncodedValue::Uint32(*value),
+            LayerSettingValue::Strings(values) => {
+                let strings = values
+                    .iter()
+                    .map(|value| CString::new(value.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let pointers = strings.iter().map(|string| string.as_ptr()).collect();
+                EncodedValue::Strings {
+                    _strings: strings,
+                    pointers,
+                }
+            }
+        })
+    }
+
+    /// The type, count and values of the setting.
+    fn raw(&self) -> (LayerSettingTypeEXT, u32, *const c_void) {
+        match self {
+            EncodedValue::Bool32(value) => (
+                LayerSettingTypeEXT::BOOL32,
+                1,
+                value as *const Bool32 as *const c_void,
+            ),
+            EncodedValue::Int32(value) => (
+                LayerSettingTypeEXT::INT32,
+                1,
+                value as *const i32 as *const c_void,
+            ),
+            EncodedValue::Uint32(value) => (
+                LayerSettingTypeEXT::UINT32,
+                1,
+                value as *const u32 as *const c_void,
+            ),
+            EncodedValue::Strings { pointers, .. } => (
+                LayerSettingTypeEXT::STRING,
+                pointers.len() as u32,
+                pointers.as_ptr() as *const c_void,
+            ),
+        }
+    }
+}
+
+/// Settings of one layer encoded for instance creation. The settings point into the names and
+/// values, which are kept in place on the heap, so the whole must outlive the create info.
+pub struct LayerSettings {
+    _layer_name: CString,
+    _names: Vec<CString>,
+    _values: Vec<EncodedValue>,
+    settings: Vec<LayerSettingEXT>,
+}
+
+impl LayerSettings {
+    pub fn encode(layer_name: &str, values: &[(&str, LayerSettingValue)]) -> Result<LayerSettings> {
+        let layer_name = CString::new(layer_name)?;
+        let names = values
+            .iter()
+            .map(|(name, _)| CString::new(*name))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Neither list grows after this, so the pointers into them stay valid
+        let values = values
+            .iter()
+            .map(|(_, value)| EncodedValue::new(value))
+            .collect::<Result<Vec<_>>>()?;
+        let settings = names
+            .iter()
+            .zip(&values)
+            .map(|(name, value)| {
+                let (ty, value_count, p_values) = value.raw();
+                LayerSettingEXT {
+                    p_layer_name: layer_name.as_ptr(),
+                    p_setting_name: name.as_ptr(),
+                    ty,
+                    value_count,
+                    p_values,
+                }
+            })
+            .collect();
+
+        Ok(LayerSettings {
+            _layer_name: layer_name,
+            _names: names,
+            _values: values,
+            settings,
+        })
+    }
+
+    pub fn create_info(&self) -> LayerSettingsCreateInfoEXT {
+        LayerSettingsCreateInfoEXT {
+            s_type: LAYER_SETTINGS_CREATE_INFO_EXT,
+            p_next: ptr::null(),
+            setting_count: self.settings.len() as u32,
+            p_settings: self.settings.as_ptr(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// Reads a setting back through its pointers, as the layer would.
+    fn decode(setting: &LayerSettingEXT) -> (String, String, LayerSettingValue) {
+        let string = |pointer| {
+            unsafe { CStr::from_ptr(pointer) }
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let value = unsafe {
+            match setting.ty {
+                LayerSettingTypeEXT::BOOL32 => {
+                    LayerSettingValue::Bool(*(setting.p_values as *const Bool32) == TRUE)
+                }
+                LayerSettingTypeEXT::INT32 => {
+                    LayerSettingValue::Int32(*(setting.p_values as *const i32))
+                }
+                LayerSettingTypeEXT::UINT32 => {
+                    LayerSettingValue::Uint32(*(setting.p_values as *const u32))
+                }
+                LayerSettingTypeEXT::STRING => LayerSettingValue::Strings(
+                    std::slice::from_raw_parts(
+                        setting.p_values as *const *const c_char,
+                        setting.value_count as usize,
+                    )
+                    .iter()
+                    .map(|&pointer| string(pointer))
+                    .collect(),
+                ),
+                ty => panic!("Unexpected setting type {:?}", ty),
+            }
+        };
+
+        (
+            string(setting.p_layer_name),
+            string(setting.p_setting_name),
+            value,
+        )
+    }
+
+    fn decode_all(
+        create_info: &LayerSettingsCreateInfoEXT,
+    ) -> Vec<(String, String, LayerSettingValue)> {
+        unsafe {
+            std::slice::from_raw_parts(create_info.p_settings, create_info.setting_count as usize)
+        }
+        .iter()
+        .map(decode)
+        .collect()
+    }
+
+    #[test]
+    fn settings_read_back_through_the_create_info() {
+        let values = [
+            ("thread_safety", LayerSettingValue::Bool(false)),
+            ("offset", LayerSettingValue::Int32(-3)),
+            ("duplicate_message_limit", LayerSettingValue::Uint32(10)),
+            (
+                "message_id_filter",
+                LayerSettingValue::Strings(vec!["VUID-a".to_string(), "0x1234".to_string()]),
+            ),
+        ];
+        let layer_settings = LayerSettings::encode("VK_LAYER_KHRONOS_validation", &values).unwrap();
+        let create_info = layer_settings.create_info();
+        assert_eq!(create_info.s_type, LAYER_SETTINGS_CREATE_INFO_EXT);
+        assert!(create_info.p_next.is_null());
+
+        let decoded = decode_all(&create_info);
+        assert_eq!(decoded.len(), values.len());
+        for ((layer_name, name, value), (expected_name, expected_value)) in
+            decoded.iter().zip(&values)
+        {
+            assert_eq!(layer_name, "VK_LAYER_KHRONOS_validation");
+            assert_eq!(name, expected_name);
+            assert_eq!(value, expected_value);
+        }
+    }
+
+    #[test]
+    fn settings_stay_valid_when_moved() {
+        let layer_settings = LayerSettings::encode(
+            "VK_LAYER_KHRONOS_validation",
+            &[(
+                "message_id_filter",
+                LayerSettingValue::Strings(vec!["VUID-b".to_string()]),
+            )],
+        )
+        .unwrap();
+        let moved = Box::new(layer_settings);
+        let decoded = decode_all(&moved.create_info());
+        assert_eq!(
+            decoded[0].2,
+            LayerSettingValue::Strings(vec!["VUID-b".to_string()])
+        );
+    }
+
+    #[test]
+    fn interior_nul_is_an_error() {
+        assert!(LayerSettings::encode("VK_LAYER\0", &[]).is_err());
+        assert!(LayerSettings::encode(
+            "VK_LAYER_KHRONOS_validation",
+            &[(
+                "filter",
+                LayerSettingValue::Strings(vec!["a\0b".to_string()])
+            )]
+        )
+        .is_err());
+    }
+}
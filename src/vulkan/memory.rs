@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use ash::vk::{
+    DeviceMemory, DeviceSize, MemoryAllocateInfo, MemoryHeapFlags, MemoryPropertyFlags,
+    MemoryRequirements, MAX_MEMORY_HEAPS,
+};
+use ash::Device;
+use log::info;
+
+use crate::vulkan::buffer::find_memory_type;
+use crate::vulkan::context::VulkanContext;
+
+/// What the engine has allocated from each heap, so a budget report can tell the engine's own
+/// usage apart from other processes'.
+static ALLOCATED_BYTES: [AtomicU64; MAX_MEMORY_HEAPS] =
+    [const { AtomicU64::new(0) }; MAX_MEMORY_HEAPS];
+
+/// The usage of one memory heap. `usage` and `budget` come from `VK_EXT_memory_budget` and
+/// cover every process, `allocated` is what the engine allocated itself.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub heap_index: u32,
+    pub size: DeviceSize,
+    pub flags: MemoryHeapFlags,
+    pub allocated: DeviceSize,
+    /// `None` without `VK_EXT_memory_budget`
+    pub usage: Option<DeviceSize>,
+    /// `None` without `VK_EXT_memory_budget`
+    pub budget: Option<DeviceSize>,
+}
+
+/// Allocates memory for `memory_requirements` from the first memory type with
+/// `memory_property_flags`. Returns the memory and the heap it counts against, which
+/// `free_memory` needs.
+pub fn allocate_memory(
+    context: &VulkanContext,
+    memory_requirements: &MemoryRequirements,
+    memory_property_flags: MemoryPropertyFlags,
+) -> Result<(DeviceMemory, u32)> {
+    let memory_properties = &context.device_info.memory_properties;
+    let memory_type_index = find_memory_type(
+        memory_properties,
+        memory_requirements.memory_type_bits,
+        memory_property_flags,
+    )?;
+    let memory_allocate_info = MemoryAllocateInfo::builder()
+        .allocation_size(memory_requirements.size)
+        .memory_type_index(memory_type_index)
+        .build();
+    let memory = unsafe { context.device.allocate_memory(&memory_allocate_info, None) }?;
+
+    let heap_index = memory_properties.memory_types[memory_type_index as usize].heap_index;
+    ALLOCATED_BYTES[heap_index as usize].fetch_add(memory_requirements.size, Ordering::Relaxed);
+
+    Ok((memory, heap_index))
+}
+
+/// `size` must be the size `memory` was allocated with.
+pub fn free_memory(device: &Device, memory: DeviceMemory, heap_index: u32, size: DeviceSize) {
+    unsafe { device.free_memory(memory, None) };
+    ALLOCATED_BYTES[heap_index as usize].fetch_sub(size, Ordering::Relaxed);
+}
+
+/// What the engine has allocated from heap `heap_index` and not freed yet.
+pub fn allocated_bytes(heap_index: u32) -> DeviceSize {
+    ALLOCATED_BYTES[heap_index as usize].load(Ordering::Relaxed)
+}
+
+pub fn log_memory_budget(heaps: &[HeapBudget]) {
+    let to_mib = |bytes: DeviceSize| bytes / (1024 * 1024);
+    for heap in heaps {
+        match heap.usage.zip(heap.budget) {
+            Some((usage, budget)) => info!(
+                "Memory heap {}: {} MiB allocated by the engine, {} of {} MiB budget used",
+                heap.heap_index,
+                to_mib(heap.allocated),
+                to_mib(usage),
+                to_mib(budget)
+            ),
+            None => info!(
+                "Memory heap {}: {} of {} MiB allocated by the engine",
+                heap.heap_index,
+                to_mib(heap.allocated),
+                to_mib(heap.size)
+            ),
+        }
+    }
+}
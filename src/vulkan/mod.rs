@@ -1,6 +1,48 @@
+pub mod barrier;
+pub mod bloom;
+pub mod buffer;
+pub mod command;
+pub mod compute;
+pub mod context;
+pub mod debug_draw;
+pub mod descriptor;
 pub mod device;
+pub mod device_info;
+pub mod device_lost;
+pub mod device_report;
+pub mod draw_list;
+pub mod features;
+pub mod format;
+pub mod frame;
+pub mod framebuffer;
+pub mod full_screen_exclusive;
+pub mod headless;
+pub mod hot_reload;
+pub mod image;
 pub mod instance;
+pub mod layer_settings;
+pub mod memory;
+pub mod offscreen;
+pub mod picking;
 pub mod pipeline;
+pub mod pipeline_cache;
+pub mod post_process;
+pub mod reflect;
 pub mod render;
+pub mod render_graph;
+pub mod render_pass_cache;
+pub mod sampler;
+pub mod screenshot;
+pub mod shader_cache;
+#[cfg(feature = "shaderc")]
+pub mod shader_compiler;
+pub mod shader_validation;
+pub mod shadow;
+pub mod submit;
 pub mod surface;
 pub mod swapchain;
+pub mod tessellation;
+pub mod texture;
+pub mod upload;
+#[cfg(feature = "wgsl")]
+pub mod wgsl;
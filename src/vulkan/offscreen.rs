@@ -0,0 +1,340 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, AttachmentLoadOp, AttachmentStoreOp, ClearColorValue, ClearDepthStencilValue,
+    ClearValue, CommandBuffer, DependencyFlags, DescriptorImageInfo, Extent2D, Format,
+    ImageAspectFlags, ImageLayout, ImageSubresourceRange, ImageView, MemoryBarrier,
+    PipelineStageFlags, RenderingAttachmentInfo, Sampler,
+};
+use ash::Device;
+use log::info;
+
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::format::has_stencil_component;
+use crate::vulkan::image::{record_image_layout_transition, ImageDesc, PistonImage};
+use crate::vulkan::render::{
+    record_render_pass, record_rendering, rendering_attachment_info, RenderTarget,
+};
+use crate::vulkan::render_pass_cache::RenderPassDesc;
+
+pub struct OffscreenTarget {
+    pub color: PistonImage,
+    pub depth: Option<PistonImage>,
+    pub render_target: RenderTarget,
+    /// Set when the scene pass keeps the depth of a depth-only pre-pass instead of clearing it
+    pub depth_prepass_target: Option<RenderTarget>,
+    pub extent: Extent2D,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        context: &VulkanContext,
+        extent: Extent2D,
+        color_format: Format,
+        depth_format: Option<Format>,
+        depth_prepass: bool,
+    ) -> Result<OffscreenTarget> {
+        if depth_prepass && depth_format.is_none() {
+            return Err(anyhow!(
+                "A depth pre-pass needs an offscreen target with depth"
+            ));
+        }
+        let color = PistonImage::new(
+            context,
+            &ImageDesc::color_attachment(extent, color_format),
+            "offscreen color",
+        )?;
+        let depth = match depth_format {
+            Some(depth_format) => Some(PistonImage::new(
+                context,
+                &ImageDesc::depth_attachment(extent, depth_format),
+                "offscreen depth",
+            )?),
+            None => None,
+        };
+        let depth_prepass_format = depth_format.filter(|_| depth_prepass);
+        let render_target = match (&context.dynamic_rendering, depth_prepass_format) {
+            (Some(_), _) => RenderTarget::Dynamic {
+                color_formats: vec![color_format],
+                depth_format,
+            },
+            (None, Some(depth_format)) => {
+                RenderTarget::RenderPass(context.get_or_create_render_pass(
+                    &RenderPassDesc::offscreen_after_depth_prepass(color_format, depth_format),
+                )?)
+            }
+            (None, None) => RenderTarget::RenderPass(context.get_or_create_render_pass(
+                &RenderPassDesc::offscreen(color_format, depth_format),
+            )?),
+        };
+        let depth_prepass_target = match (&context.dynamic_rendering, depth_prepass_format) {
+            (_, None) => None,
+            (Some(_), Some(depth_format)) => Some(RenderTarget::Dynamic {
+                color_formats: vec![],
+                depth_format: Some(depth_format),
+            }),
+            (None, Some(depth_format)) => Some(RenderTarget::RenderPass(
+                context.get_or_create_render_pass(&RenderPassDesc::depth_prepass(depth_format))?,
+            )),
+        };
+
+        info!(
+            "Created {}x{} offscreen target with color format {:?} and depth format {:?}{}",
+            extent.width,
+            extent.height,
+            color_format,
+            depth_format,
+            if depth_prepass {
+                ", after a depth pre-pass"
+            } else {
+                ""
+            }
+        );
+
+        Ok(OffscreenTarget {
+            color,
+            depth,
+            render_target,
+            depth_prepass_target,
+            extent,
+        })
+    }
+
+    /// Records the depth pre-pass, which must come before `record_pass` when the target has one.
+    /// `record` draws the opaque geometry with depth-only pipelines.
+    pub fn record_depth_prepass<F>(
+        &self,
+        context: &VulkanContext,
+        command_buffer: CommandBuffer,
+        clear_depth: f32,
+        record: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&Device, CommandBuffer),
+    {
+        let device = &context.device;
+        let (depth_prepass_target, depth) = match (&self.depth_prepass_target, &self.depth) {
+            (Some(depth_prepass_target), Some(depth)) => (depth_prepass_target, depth),
+            _ => return Err(anyhow!("Offscreen target has no depth pre-pass")),
+        };
+        let clear_value = ClearValue {
+            depth_stencil: ClearDepthStencilValue {
+                depth: clear_depth,
+                stencil: 0,
+            },
+        };
+        if let Some(render_pass) = depth_prepass_target.render_pass() {
+            let framebuffer =
+                context.get_or_create_framebuffer(render_pass, &[depth.view], self.extent)?;
+            record_render_pass(
+                device,
+                command_buffer,
+                render_pass,
+                framebuffer,
+                self.extent,
+                &[clear_value],
+                record,
+            );
+            return Ok(());
+        }
+
+        let dynamic_rendering = context
+            .dynamic_rendering
+            .as_ref()
+            .ok_or_else(|| anyhow!("Offscreen target has no render pass or dynamic rendering"))?;
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            depth.image,
+            depth_subresource_range(depth),
+            ImageLayout::UNDEFINED,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        )?;
+        let depth_attachment = rendering_attachment_info(
+            depth.view,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            AttachmentStoreOp::STORE,
+            clear_value,
+        );
+        record_rendering(
+            device,
+            dynamic_rendering,
+            command_buffer,
+            &[],
+            Some(&depth_attachment),
+            self.extent,
+            record,
+        );
+
+        // The scene pass tests against the depth the pre-pass wrote
+        let memory_barrier = MemoryBarrier::builder()
+            .src_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Records the scene pass into the target. Afterwards the color image is ready to be
+    /// sampled by fragment shaders, either way the pass is recorded. With a depth pre-pass the
+    /// depth it wrote is tested against instead of `clear_depth`.
+    pub fn record_pass<F>(
+        &self,
+        context: &VulkanContext,
+        command_buffer: CommandBuffer,
+        clear_color: [f32; 4],
+        clear_depth: f32,
+        record: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&Device, CommandBuffer),
+    {
+        let device = &context.device;
+        let clear_values = self.clear_values(clear_color, clear_depth);
+        if let Some(render_pass) = self.render_target.render_pass() {
+            let framebuffer =
+                context.get_or_create_framebuffer(render_pass, &self.views(), self.extent)?;
+            record_render_pass(
+                device,
+                command_buffer,
+                render_pass,
+                framebuffer,
+                self.extent,
+                &clear_values,
+                record,
+            );
+            return Ok(());
+        }
+
+        let dynamic_rendering = context
+            .dynamic_rendering
+            .as_ref()
+            .ok_or_else(|| anyhow!("Offscreen target has no render pass or dynamic rendering"))?;
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            self.color.image,
+            self.color.subresource_range,
+            ImageLayout::UNDEFINED,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )?;
+        let color_attachments = [rendering_attachment_info(
+            self.color.view,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            AttachmentStoreOp::STORE,
+            clear_values[0],
+        )];
+        let depth_attachment = match (&self.depth, &self.depth_prepass_target) {
+            (Some(depth), Some(_)) => Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::LOAD,
+                ..rendering_attachment_info(
+                    depth.view,
+                    ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    AttachmentStoreOp::DONT_CARE,
+                    clear_values[1],
+                )
+            }),
+            (Some(depth), None) => {
+                record_image_layout_transition(
+                    device,
+                    command_buffer,
+                    depth.image,
+                    depth_subresource_range(depth),
+                    ImageLayout::UNDEFINED,
+                    ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )?;
+                Some(rendering_attachment_info(
+                    depth.view,
+                    ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    AttachmentStoreOp::DONT_CARE,
+                    clear_values[1],
+                ))
+            }
+            (None, _) => None,
+        };
+
+        record_rendering(
+            device,
+            dynamic_rendering,
+            command_buffer,
+            &color_attachments,
+            depth_attachment.as_ref(),
+            self.extent,
+            record,
+        );
+
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            self.color.image,
+            self.color.subresource_range,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+
+    fn views(&self) -> Vec<ImageView> {
+        let mut views = vec![self.color.view];
+        if let Some(depth) = &self.depth {
+            views.push(depth.view);
+        }
+
+        views
+    }
+
+    pub fn clear_values(&self, clear_color: [f32; 4], clear_depth: f32) -> Vec<ClearValue> {
+        let mut clear_values = vec![ClearValue {
+            color: ClearColorValue {
+                float32: clear_color,
+            },
+        }];
+        if self.depth.is_some() {
+            clear_values.push(ClearValue {
+                depth_stencil: ClearDepthStencilValue {
+                    depth: clear_depth,
+                    stencil: 0,
+                },
+            });
+        }
+
+        clear_values
+    }
+
+    pub fn descriptor_image_info(&self, sampler: Sampler) -> DescriptorImageInfo {
+        DescriptorImageInfo::builder()
+            .sampler(sampler)
+            .image_view(self.color.view)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        if let Some(depth) = &self.depth {
+            depth.destroy(device);
+        }
+        self.color.destroy(device);
+    }
+}
+
+/// Without separate depth and stencil layouts both aspects change layout together.
+fn depth_subresource_range(depth: &PistonImage) -> ImageSubresourceRange {
+    let mut subresource_range = depth.subresource_range;
+    if has_stencil_component(depth.format) {
+        subresource_range.aspect_mask |= ImageAspectFlags::STENCIL;
+    }
+
+    subresource_range
+}
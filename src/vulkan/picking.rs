@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, AttachmentStoreOp, BufferImageCopy, BufferUsageFlags, ClearColorValue,
+    ClearDepthStencilValue, ClearValue, CommandBuffer, DependencyFlags, DeviceSize, Extent2D,
+    Extent3D, Format, FormatFeatureFlags, ImageAspectFlags, ImageLayout, ImageSubresourceLayers,
+    ImageUsageFlags, MemoryBarrier, MemoryPropertyFlags, Offset3D, PipelineStageFlags,
+};
+use ash::Device;
+use log::info;
+
+use crate::constants::{PICKING_CLEAR_ID, PICKING_FORMAT};
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::image::{record_image_layout_transition, ImageDesc, PistonImage};
+use crate::vulkan::render::{
+    record_render_pass, record_rendering, rendering_attachment_info, RenderTarget,
+};
+use crate::vulkan::render_pass_cache::RenderPassDesc;
+use crate::vulkan::texture::check_format_features;
+
+/// Renders the object ID of every pixel, on the frames that pick something, and copies the
+/// picked pixel into one of a ring of readback buffers. The buffer of a frame is read once its
+/// fence has signaled, so picking never waits for the GPU.
+///
+/// The IDs are sized from the swapchain, so the target has to be recreated with it.
+pub struct PickingTarget {
+    pub object_ids: PistonImage,
+    pub depth: PistonImage,
+    pub render_target: RenderTarget,
+    pub extent: Extent2D,
+    readback_buffers: Vec<PistonBuffer>,
+}
+
+impl PickingTarget {
+    /// One readback buffer per frame in flight.
+    pub fn new(
+        context: &VulkanContext,
+        extent: Extent2D,
+        depth_format: Format,
+        frames_in_flight: usize,
+    ) -> Result<PickingTarget> {
+        check_format_features(
+            context,
+            PICKING_FORMAT,
+            FormatFeatureFlags::COLOR_ATTACHMENT | FormatFeatureFlags::TRANSFER_SRC,
+        )?;
+        let object_ids = PistonImage::new(
+            context,
+            &ImageDesc {
+                usage: ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+                ..ImageDesc::texture_2d(extent, PICKING_FORMAT)
+            },
+            "picking object IDs",
+        )?;
+        let depth = PistonImage::new(
+            context,
+            &ImageDesc::depth_attachment(extent, depth_format),
+            "picking depth",
+        )?;
+        let render_target = match context.dynamic_rendering {
+            Some(_) => RenderTarget::Dynamic {
+                color_formats: vec![PICKING_FORMAT],
+                depth_format: Some(depth_format),
+            },
+            None => RenderTarget::RenderPass(context.get_or_create_render_pass(
+                &RenderPassDesc::picking(PICKING_FORMAT, depth_format),
+            )?),
+        };
+        let readback_buffers = (0..frames_in_flight)
+            .map(|frame| {
+                PistonBuffer::new(
+                    context,
+                    size_of::<u32>() as DeviceSize,
+                    BufferUsageFlags::TRANSFER_DST,
+                    MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+                    &format!("picking readback {}", frame),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        info!(
+            "Created {}x{} picking target with {} readback buffers",
+            extent.width,
+            extent.height,
+            readback_buffers.len()
+        );
+
+        Ok(PickingTarget {
+            object_ids,
+            depth,
+            render_target,
+            extent,
+            readback_buffers,
+        })
+    }
+
+    /// Records the ID pass and the copy of `pixel` into the readback buffer of `frame`. `record`
+    /// draws every pickable object with its ID.
+    pub fn record_pick<F>(
+        &self,
+        context: &VulkanContext,
+        command_buffer: CommandBuffer,
+        frame: usize,
+        pixel: (u32, u32),
+        record: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&Device, CommandBuffer),
+    {
+        let device = &context.device;
+        let (x, y) = pixel;
+        if x >= self.extent.width || y >= self.extent.height {
+            return Err(anyhow!(
+                "Pixel ({}, {}) is outside the {}x{} picking target",
+                x,
+                y,
+                self.extent.width,
+                self.extent.height
+            ));
+        }
+        let readback_buffer = self
+            .readback_buffers
+            .get(frame)
+            .ok_or_else(|| anyhow!("Picking target has no readback buffer for frame {}", frame))?;
+
+        let clear_values = [
+            ClearValue {
+                color: ClearColorValue {
+                    uint32: [PICKING_CLEAR_ID; 4],
+                },
+            },
+            ClearValue {
+                depth_stencil: ClearDepthStencilValue {
+                    depth: context.depth_convention.clear_depth(),
+                    stencil: 0,
+                },
+            },
+        ];
+        match (self.render_target.render_pass(), &context.dynamic_rendering) {
+            (Some(render_pass), _) => {
+                let framebuffer = context.get_or_create_framebuffer(
+                    render_pass,
+                    &[self.object_ids.view, self.depth.view],
+                    self.extent,
+                )?;
+                record_render_pass(
+                    device,
+                    command_buffer,
+                    render_pass,
+                    framebuffer,
+                    self.extent,
+                    &clear_values,
+                    record,
+                );
+            }
+            (None, Some(dynamic_rendering)) => {
+                record_image_layout_transition(
+                    device,
+                    command_buffer,
+                    self.object_ids.image,
+                    self.object_ids.subresource_range,
+                    ImageLayout::UNDEFINED,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )?;
+                record_image_layout_transition(
+                    device,
+                    command_buffer,
+                    self.depth.image,
+                    self.depth.subresource_range,
+                    ImageLayout::UNDEFINED,
+                    ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )?;
+                let color_attachments = [rendering_attachment_info(
+                    self.object_ids.view,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    AttachmentStoreOp::STORE,
+                    clear_values[0],
+                )];
+                let depth_attachment = rendering_attachment_info(
+                    self.depth.view,
+                    ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    AttachmentStoreOp::DONT_CARE,
+                    clear_values[1],
+                );
+                record_rendering(
+                    device,
+                    dynamic_rendering,
+                    command_buffer,
+                    &color_attachments,
+                    Some(&depth_attachment),
+                    self.extent,
+                    record,
+                );
+                record_image_layout_transition(
+                    device,
+                    command_buffer,
+                    self.object_ids.image,
+                    self.object_ids.subresource_range,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ImageLayout::TRANSFER_SRC_OPTIMAL,
+                )?;
+            }
+            (None, None) => {
+                return Err(anyhow!(
+                    "Picking target has no render pass or dynamic rendering"
+                ))
+            }
+        }
+
+        let buffer_image_copy = BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                ImageSubresourceLayers::builder()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            })
+            .image_extent(Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build();
+        // The host reads the buffer after the frame's fence has signaled
+        let memory_barrier = MemoryBarrier::builder()
+            .src_access_mask(AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(AccessFlags::HOST_READ)
+            .build();
+        context.insert_label(command_buffer, "Picking readback");
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.object_ids.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback_buffer.buffer,
+                &[buffer_image_copy],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::HOST,
+                DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The ID copied by the pick recorded for `frame`, `None` if no object covered the pixel.
+    /// The frame's fence must have signaled.
+    pub fn read_object_id(&self, device: &Device, frame: usize) -> Result<Option<u32>> {
+        let readback_buffer = self
+            .readback_buffers
+            .get(frame)
+            .ok_or_else(|| anyhow!("Picking target has no readback buffer for frame {}", frame))?;
+        let bytes = readback_buffer.read(device)?;
+        let object_id = u32::from_ne_bytes(
+            bytes[..size_of::<u32>()]
+                .try_into()
+                .map_err(|_| anyhow!("Picking readback buffer is too small"))?,
+        );
+
+        Ok(match object_id {
+            PICKING_CLEAR_ID => None,
+            object_id => Some(object_id),
+        })
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        for readback_buffer in self.readback_buffers.iter() {
+            readback_buffer.destroy(device);
+        }
+        self.depth.destroy(device);
+        self.object_ids.destroy(device);
+    }
+}
@@ -1,159 +1,1296 @@
-use std::ffi::CString;
+use std::ffi::{c_void, CStr, CString};
+use std::fmt::Debug;
 use std::io::Cursor;
 use std::path::Path;
+use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use ash::util::read_spv;
 use ash::vk::{
-    BlendFactor, BlendOp, ColorComponentFlags, CompareOp, CullModeFlags, Extent2D, FrontFace,
-    GraphicsPipelineCreateInfo, LogicOp, Offset2D, Pipeline, PipelineCache,
-    PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
-    PipelineDepthStencilStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout,
-    PipelineLayoutCreateInfo, PipelineMultisampleStateCreateInfo,
-    PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo,
-    PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode,
-    PrimitiveTopology, Rect2D, RenderPass, SampleCountFlags, ShaderModule, ShaderModuleCreateInfo,
-    ShaderStageFlags, StencilOp, StencilOpState, Viewport,
+    BlendFactor, BlendOp, ColorComponentFlags, CompareOp, ComputePipelineCreateInfo, CullModeFlags,
+    DescriptorSetLayout, DynamicState, Format, FrontFace, GraphicsPipelineCreateInfo, LogicOp,
+    Pipeline, PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
+    PipelineCreateFlags, PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo,
+    PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineLayoutCreateInfo,
+    PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo,
+    PipelineRenderingCreateInfo, PipelineShaderStageCreateInfo,
+    PipelineTessellationStateCreateInfo, PipelineVertexInputStateCreateInfo,
+    PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, PushConstantRange, RenderPass,
+    SampleCountFlags, ShaderModule, ShaderStageFlags, SpecializationInfo,
+    SpecializationInfoBuilder, SpecializationMapEntry, StencilOp, StencilOpState,
+    VertexInputAttributeDescription, VertexInputBindingDescription,
 };
 use ash::Device;
+use log::info;
+use tracing::info_span;
 
+use crate::config::DepthConvention;
+use crate::constants::DEFAULT_ENTRY_POINT;
 use crate::util::util::load_file_bytes;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::features::DeviceFeature;
+use crate::vulkan::reflect::{check_vertex_inputs, create_reflected_layouts, ShaderReflection};
+use crate::vulkan::render::RenderTarget;
+use crate::vulkan::render_pass_cache::RenderPassDesc;
+use crate::vulkan::shader_cache::ShaderHandle;
+#[cfg(feature = "shaderc")]
+use crate::vulkan::shader_compiler::compile_if_stale;
 
-pub fn create_graphics_pipeline(
-    device: &Device,
-    render_pass: RenderPass,
-    swapchain_extent: Extent2D,
-) -> Result<(Pipeline, PipelineLayout)> {
-    let mut vertex_shader_file =
-        Cursor::new(load_file_bytes(Path::new("shaders/build/vert-shader.spv")));
-    let mut fragment_shader_file =
-        Cursor::new(load_file_bytes(Path::new("shaders/build/frag-shader.spv")));
-
-    let vertex_shader_code = read_spv(&mut vertex_shader_file)?;
-    let fragment_shader_code = read_spv(&mut fragment_shader_file)?;
-
-    let vertex_shader_module = create_shader_module(device, vertex_shader_code)?;
-    let fragment_shader_module = create_shader_module(device, fragment_shader_code)?;
-
-    let main_function = CString::new("main").unwrap();
-
-    let shader_stages_create_info = [
-        create_pipeline_shader_stage_create_info(
-            &main_function,
-            vertex_shader_module,
-            ShaderStageFlags::VERTEX,
-        ),
-        create_pipeline_shader_stage_create_info(
-            &main_function,
-            fragment_shader_module,
-            ShaderStageFlags::FRAGMENT,
-        ),
-    ];
-
-    let viewports = [Viewport::builder()
-        .x(0.0)
-        .y(0.0)
-        .width(swapchain_extent.width as f32)
-        .height(swapchain_extent.height as f32)
-        .min_depth(0.0)
-        .max_depth(0.0)
-        .build()];
+/// The engine's own shaders, compiled into the binary so it still runs when the shader
+/// directory can't be found.
+const EMBEDDED_SHADERS: [(&str, &[u8]); 24] = [
+    (
+        "vert-shader.spv",
+        include_bytes!("../../shaders/build/vert-shader.spv"),
+    ),
+    (
+        "frag-shader.spv",
+        include_bytes!("../../shaders/build/frag-shader.spv"),
+    ),
+    (
+        "fullscreen-vert.spv",
+        include_bytes!("../../shaders/build/fullscreen-vert.spv"),
+    ),
+    (
+        "composite-frag.spv",
+        include_bytes!("../../shaders/build/composite-frag.spv"),
+    ),
+    (
+        "debug-vert.spv",
+        include_bytes!("../../shaders/build/debug-vert.spv"),
+    ),
+    (
+        "debug-frag.spv",
+        include_bytes!("../../shaders/build/debug-frag.spv"),
+    ),
+    (
+        "equirect-to-cubemap-comp.spv",
+        include_bytes!("../../shaders/build/equirect-to-cubemap-comp.spv"),
+    ),
+    (
+        "bloom-downsample-comp.spv",
+        include_bytes!("../../shaders/build/bloom-downsample-comp.spv"),
+    ),
+    (
+        "bloom-upsample-comp.spv",
+        include_bytes!("../../shaders/build/bloom-upsample-comp.spv"),
+    ),
+    (
+        "normals-geom.spv",
+        include_bytes!("../../shaders/build/normals-geom.spv"),
+    ),
+    (
+        "tess-vert.spv",
+        include_bytes!("../../shaders/build/tess-vert.spv"),
+    ),
+    (
+        "tess-tesc.spv",
+        include_bytes!("../../shaders/build/tess-tesc.spv"),
+    ),
+    (
+        "tess-tese.spv",
+        include_bytes!("../../shaders/build/tess-tese.spv"),
+    ),
+    (
+        "gradient-comp.spv",
+        include_bytes!("../../shaders/build/gradient-comp.spv"),
+    ),
+    (
+        "quads-comp.spv",
+        include_bytes!("../../shaders/build/quads-comp.spv"),
+    ),
+    (
+        "triangle-vert.spv",
+        include_bytes!("../../shaders/build/triangle-vert.spv"),
+    ),
+    (
+        "triangle-frag.spv",
+        include_bytes!("../../shaders/build/triangle-frag.spv"),
+    ),
+    (
+        "triangle-combined.spv",
+        include_bytes!("../../shaders/build/triangle-combined.spv"),
+    ),
+    (
+        "post-frag.spv",
+        include_bytes!("../../shaders/build/post-frag.spv"),
+    ),
+    (
+        "shadow-vert.spv",
+        include_bytes!("../../shaders/build/shadow-vert.spv"),
+    ),
+    (
+        "shadow-frag.spv",
+        include_bytes!("../../shaders/build/shadow-frag.spv"),
+    ),
+    (
+        "shadowed-vert.spv",
+        include_bytes!("../../shaders/build/shadowed-vert.spv"),
+    ),
+    (
+        "shadowed-frag.spv",
+        include_bytes!("../../shaders/build/shadowed-frag.spv"),
+    ),
+    (
+        "picking-frag.spv",
+        include_bytes!("../../shaders/build/picking-frag.spv"),
+    ),
+];
 
-    let scissors = [Rect2D::builder()
-        .offset(Offset2D::builder().x(0).y(0).build())
-        .extent(swapchain_extent)
-        .build()];
+/// Vertex buffer bindings and the attributes read from them.
+#[derive(Clone, Debug, Default)]
+pub struct VertexLayout {
+    pub bindings: Vec<VertexInputBindingDescription>,
+    pub attributes: Vec<VertexInputAttributeDescription>,
+}
 
-    let viewport_state_create_info = PipelineViewportStateCreateInfo::builder()
-        .scissors(&scissors)
-        .viewports(&viewports)
-        .build();
+/// How a pipeline's color output is combined with what is already in the attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    AlphaBlend,
+    Additive,
+}
+
+impl BlendMode {
+    fn color_blend_attachment_state(self) -> PipelineColorBlendAttachmentState {
+        let (blend_enable, src_color_blend_factor, dst_color_blend_factor) = match self {
+            BlendMode::Opaque => (false, BlendFactor::ONE, BlendFactor::ZERO),
+            BlendMode::AlphaBlend => (
+                true,
+                BlendFactor::SRC_ALPHA,
+                BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (true, BlendFactor::SRC_ALPHA, BlendFactor::ONE),
+        };
+        // Alpha is coverage, which a transparent window composites with. Blended geometry
+        // covers what's behind it, additive geometry only brightens it.
+        let (src_alpha_blend_factor, dst_alpha_blend_factor) = match self {
+            BlendMode::Opaque => (BlendFactor::ONE, BlendFactor::ZERO),
+            BlendMode::AlphaBlend => (BlendFactor::ONE, BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (BlendFactor::ZERO, BlendFactor::ONE),
+        };
+
+        PipelineColorBlendAttachmentState::builder()
+            .blend_enable(blend_enable)
+            .color_write_mask(ColorComponentFlags::RGBA)
+            .src_color_blend_factor(src_color_blend_factor)
+            .dst_color_blend_factor(dst_color_blend_factor)
+            .color_blend_op(BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha_blend_factor)
+            .dst_alpha_blend_factor(dst_alpha_blend_factor)
+            .alpha_blend_op(BlendOp::ADD)
+            .build()
+    }
+}
+
+/// Describes a graphics pipeline. The defaults draw filled, back-face culled triangles without
+/// vertex buffers, depth testing or blending, in subpass 0 with one sample per pixel.
+#[derive(Clone, Debug)]
+pub struct PipelineBuilder {
+    vertex_shader: Option<ShaderHandle>,
+    fragment_shader: Option<ShaderHandle>,
+    geometry_shader: Option<ShaderHandle>,
+    /// Control and evaluation shaders
+    tessellation_shaders: Option<(ShaderHandle, ShaderHandle)>,
+    patch_control_points: u32,
+    /// Entry points of the stages that don't run `main`
+    entry_points: Vec<(ShaderStageFlags, String)>,
+    vertex_constants: SpecializationConstants,
+    fragment_constants: SpecializationConstants,
+    vertex_layout: VertexLayout,
+    topology: PrimitiveTopology,
+    polygon_mode: PolygonMode,
+    cull_mode: CullModeFlags,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: CompareOp,
+    depth_bias: bool,
+    blend_mode: BlendMode,
+    samples: SampleCountFlags,
+    dynamic_states: Vec<DynamicState>,
+    render_target: RenderTarget,
+    subpass: u32,
+    /// The debug name, the shader names when not set
+    name: Option<String>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> PipelineBuilder {
+        PipelineBuilder {
+            vertex_shader: None,
+            fragment_shader: None,
+            geometry_shader: None,
+            tessellation_shaders: None,
+            patch_control_points: 0,
+            entry_points: vec![],
+            vertex_constants: SpecializationConstants::new(),
+            fragment_constants: SpecializationConstants::new(),
+            vertex_layout: VertexLayout::default(),
+            topology: PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: PolygonMode::FILL,
+            cull_mode: CullModeFlags::BACK,
+            depth_test: false,
+            depth_write: true,
+            depth_compare_op: CompareOp::LESS_OR_EQUAL,
+            depth_bias: false,
+            blend_mode: BlendMode::Opaque,
+            samples: SampleCountFlags::TYPE_1,
+            dynamic_states: vec![],
+            render_target: RenderTarget::RenderPass(RenderPass::null()),
+            subpass: 0,
+            name: None,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    pub fn shaders(
+        mut self,
+        vertex_shader: ShaderHandle,
+        fragment_shader: ShaderHandle,
+    ) -> PipelineBuilder {
+        self.vertex_shader = Some(vertex_shader);
+        self.fragment_shader = Some(fragment_shader);
+        self
+    }
+
+    /// Drops the fragment stage, so the pipeline only writes depth, as in a depth pre-pass.
+    pub fn depth_only(mut self) -> PipelineBuilder {
+        self.fragment_shader = None;
+        self.fragment_constants = SpecializationConstants::new();
+        self.entry_points
+            .retain(|(stage, _)| *stage != ShaderStageFlags::FRAGMENT);
+        self
+    }
+
+    /// Adds a geometry stage between the vertex and fragment shaders. Building fails on devices
+    /// without the `geometry_shader` feature.
+    pub fn geometry_shader(mut self, geometry_shader: ShaderHandle) -> PipelineBuilder {
+        self.geometry_shader = Some(geometry_shader);
+        self
+    }
+
+    /// Adds tessellation control and evaluation stages. The pipeline then draws patch lists of
+    /// `patch_control_points` vertices, and building fails on devices without the
+    /// `tessellation_shader` feature.
+    pub fn tessellation_shaders(
+        mut self,
+        control_shader: ShaderHandle,
+        evaluation_shader: ShaderHandle,
+        patch_control_points: u32,
+    ) -> PipelineBuilder {
+        self.tessellation_shaders = Some((control_shader, evaluation_shader));
+        self.patch_control_points = patch_control_points;
+        self
+    }
+
+    /// Runs `entry_point` instead of `main` in the shader of `stage`, such as `VSMain` in an HLSL
+    /// vertex shader or one of the stages of a module that holds several entry points.
+    pub fn entry_point(mut self, stage: ShaderStageFlags, entry_point: &str) -> PipelineBuilder {
+        self.entry_points
+            .retain(|(existing_stage, _)| *existing_stage != stage);
+        self.entry_points.push((stage, entry_point.to_string()));
+        self
+    }
+
+    pub fn vertex_constants(
+        mut self,
+        vertex_constants: SpecializationConstants,
+    ) -> PipelineBuilder {
+        self.vertex_constants = vertex_constants;
+        self
+    }
+
+    pub fn fragment_constants(
+        mut self,
+        fragment_constants: SpecializationConstants,
+    ) -> PipelineBuilder {
+        self.fragment_constants = fragment_constants;
+        self
+    }
+
+    pub fn vertex_layout(mut self, vertex_layout: VertexLayout) -> PipelineBuilder {
+        self.vertex_layout = vertex_layout;
+        self
+    }
+
+    pub fn topology(mut self, topology: PrimitiveTopology) -> PipelineBuilder {
+        self.topology = topology;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: PolygonMode) -> PipelineBuilder {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: CullModeFlags) -> PipelineBuilder {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Enables depth testing. Depth writes are only enabled for opaque pipelines, blended
+    /// geometry must not hide what is drawn behind it later.
+    pub fn depth_test(mut self, depth_test: bool) -> PipelineBuilder {
+        self.depth_test = depth_test;
+        self
+    }
+
+    /// Turns depth writes off for opaque pipelines too, such as for geometry whose depth was
+    /// already written by a pre-pass.
+    pub fn depth_write(mut self, depth_write: bool) -> PipelineBuilder {
+        self.depth_write = depth_write;
+        self
+    }
+
+    /// `LESS_OR_EQUAL` unless set, a pass after a depth pre-pass only shades what tests `EQUAL`.
+    /// The op is written for standard depth, reverse-Z pipelines get the mirrored one.
+    pub fn depth_compare_op(mut self, depth_compare_op: CompareOp) -> PipelineBuilder {
+        self.depth_compare_op = depth_compare_op;
+        self
+    }
+
+    /// Enables depth bias. The bias is dynamic state, set with `cmd_set_depth_bias` while
+    /// recording so it can be tuned without rebuilding the pipeline.
+    pub fn depth_bias(mut self, depth_bias: bool) -> PipelineBuilder {
+        self.depth_bias = depth_bias;
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> PipelineBuilder {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn samples(mut self, samples: SampleCountFlags) -> PipelineBuilder {
+        self.samples = samples;
+        self
+    }
+
+    /// Dynamic states on top of the viewport and scissor, which are always set while recording.
+    pub fn dynamic_states(mut self, dynamic_states: &[DynamicState]) -> PipelineBuilder {
+        self.dynamic_states = dynamic_states.to_vec();
+        self
+    }
+
+    pub fn render_pass(mut self, render_pass: RenderPass) -> PipelineBuilder {
+        self.render_target = RenderTarget::RenderPass(render_pass);
+        self
+    }
+
+    /// Builds the pipeline for a render pass, or for dynamic rendering with the target's
+    /// attachment formats.
+    pub fn render_target(mut self, render_target: &RenderTarget) -> PipelineBuilder {
+        self.render_target = render_target.clone();
+        self
+    }
+
+    pub fn subpass(mut self, subpass: u32) -> PipelineBuilder {
+        self.subpass = subpass;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> PipelineBuilder {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    fn debug_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .shader_stages()
+                .map(|stages| {
+                    stages
+                        .iter()
+                        .map(|(shader, _, _)| shader.name())
+                        .collect::<Vec<_>>()
+                        .join(" + ")
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn build(&self, context: &VulkanContext) -> Result<PistonPipeline> {
+        let _span = info_span!("create_pipeline", name = %self.debug_name()).entered();
+        let device = &context.device;
+        let reflections = self.reflect_shaders(context)?;
+        let shader_stages = self.shader_stages()?;
+        let state = GraphicsPipelineState::new(
+            self,
+            &shader_stages,
+            &reflections,
+            self.color_attachment_count(context)?,
+            self.device_samples(context),
+            context.depth_convention,
+        )?;
+        let (descriptor_set_layouts, pipeline_layout) =
+            create_reflected_layouts(device, &reflections)?;
+        let graphics_pipeline_create_infos =
+            [state.create_info(self, pipeline_layout, PipelineCreateFlags::empty(), -1)];
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                context.pipeline_cache,
+                &graphics_pipeline_create_infos,
+                None,
+            )
+        };
+        let shaders = shader_stages
+            .into_iter()
+            .map(|(shader, _, _)| shader.clone())
+            .collect();
+
+        let pipeline = finish_pipeline(
+            device,
+            pipelines,
+            pipeline_layout,
+            descriptor_set_layouts,
+            shaders,
+            self.render_pass_desc(context)?,
+        )?;
+        pipeline.set_name(context, &self.debug_name());
+
+        Ok(pipeline)
+    }
+
+    /// The shader stages and their entry points in pipeline order, the vertex shader always
+    /// comes first.
+    fn shader_stages(&self) -> Result<Vec<(&ShaderHandle, ShaderStageFlags, &str)>> {
+        let vertex_shader = self
+            .vertex_shader
+            .as_ref()
+            .ok_or_else(|| anyhow!("Graphics pipeline has no shaders"))?;
+        if self.fragment_shader.is_none() && !self.depth_test {
+            return Err(anyhow!(
+                "Graphics pipeline has no fragment shader and doesn't test depth, it has no output"
+            ));
+        }
+
+        let mut stages = vec![(vertex_shader, ShaderStageFlags::VERTEX)];
+        if let Some((control_shader, evaluation_shader)) = &self.tessellation_shaders {
+            stages.push((control_shader, ShaderStageFlags::TESSELLATION_CONTROL));
+            stages.push((evaluation_shader, ShaderStageFlags::TESSELLATION_EVALUATION));
+        }
+        if let Some(geometry_shader) = &self.geometry_shader {
+            stages.push((geometry_shader, ShaderStageFlags::GEOMETRY));
+        }
+        if let Some(fragment_shader) = &self.fragment_shader {
+            stages.push((fragment_shader, ShaderStageFlags::FRAGMENT));
+        }
+
+        Ok(stages
+            .into_iter()
+            .map(|(shader, stage)| (shader, stage, self.stage_entry_point(stage)))
+            .collect())
+    }
+
+    fn stage_entry_point(&self, stage: ShaderStageFlags) -> &str {
+        self.entry_points
+            .iter()
+            .find(|(entry_point_stage, _)| *entry_point_stage == stage)
+            .map_or(DEFAULT_ENTRY_POINT, |(_, entry_point)| entry_point)
+    }
+
+    fn specialization_constants(&self, stage: ShaderStageFlags) -> &SpecializationConstants {
+        match stage {
+            ShaderStageFlags::VERTEX => &self.vertex_constants,
+            ShaderStageFlags::FRAGMENT => &self.fragment_constants,
+            _ => &NO_SPECIALIZATION_CONSTANTS,
+        }
+    }
+
+    fn validate(&self, context: &VulkanContext) -> Result<()> {
+        match &self.render_target {
+            RenderTarget::RenderPass(render_pass) if *render_pass == RenderPass::null() => {
+                return Err(anyhow!("Graphics pipeline has no render pass"));
+            }
+            RenderTarget::Dynamic { .. } if context.dynamic_rendering.is_none() => {
+                return Err(anyhow!(
+                    "Pipeline uses dynamic rendering, but it is not enabled on this device"
+                ));
+            }
+            _ => {}
+        }
+        if let Some(render_pass_desc) = self.render_pass_desc(context)? {
+            self.check_render_pass_desc(&render_pass_desc, self.device_samples(context))?;
+        }
+
+        if self.geometry_shader.is_some()
+            && !context
+                .capabilities
+                .is_enabled(DeviceFeature::GeometryShader)
+        {
+            return Err(anyhow!(
+                "Pipeline uses a geometry shader, but geometry shaders are not supported by this device"
+            ));
+        }
+        if self.tessellation_shaders.is_some() {
+            if !context
+                .capabilities
+                .is_enabled(DeviceFeature::TessellationShader)
+            {
+                return Err(anyhow!(
+                    "Pipeline uses tessellation shaders, but tessellation is not supported by this device"
+                ));
+            }
+            let max_patch_size = context.device_info.limits.max_tessellation_patch_size;
+            if self.patch_control_points == 0 || self.patch_control_points > max_patch_size {
+                return Err(anyhow!(
+                    "Pipeline uses {} patch control points, the device supports 1 to {}",
+                    self.patch_control_points,
+                    max_patch_size
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The desc of the render pass the pipeline is built for, if the pass came from the render
+    /// pass cache.
+    fn render_pass_desc(&self, context: &VulkanContext) -> Result<Option<RenderPassDesc>> {
+        match self.render_target.render_pass() {
+            Some(render_pass) => context.render_pass_desc(render_pass),
+            None => Ok(None),
+        }
+    }
+
+    /// Render passes from outside the cache are assumed to have a single color attachment.
+    fn color_attachment_count(&self, context: &VulkanContext) -> Result<usize> {
+        match &self.render_target {
+            RenderTarget::Dynamic { color_formats, .. } => Ok(color_formats.len()),
+            RenderTarget::RenderPass(_) => Ok(self
+                .render_pass_desc(context)?
+                .map_or(1, |render_pass_desc| render_pass_desc.color_formats.len())),
+        }
+    }
+
+    /// The requested sample count, lowered to what the device supports the same way render
+    /// passes from the cache are.
+    fn device_samples(&self, context: &VulkanContext) -> SampleCountFlags {
+        context.device_info.clamp_sample_count(self.samples)
+    }
+
+    fn check_render_pass_desc(
+        &self,
+        render_pass_desc: &RenderPassDesc,
+        samples: SampleCountFlags,
+    ) -> Result<()> {
+        if samples != render_pass_desc.samples {
+            return Err(anyhow!(
+                "Pipeline uses {:?} samples, but its render pass was created for {:?}",
+                samples,
+                render_pass_desc
+            ));
+        }
+        if self.subpass != 0 {
+            return Err(anyhow!(
+                "Pipeline uses subpass {}, but its render pass {:?} has only one",
+                self.subpass,
+                render_pass_desc
+            ));
+        }
+        if self.depth_test && render_pass_desc.depth_format.is_none() {
+            return Err(anyhow!(
+                "Pipeline tests depth, but its render pass {:?} has no depth attachment",
+                render_pass_desc
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_vertex_layout(
+        &self,
+        vertex_shader: &ShaderHandle,
+        vertex_reflection: &ShaderReflection,
+    ) -> Result<()> {
+        check_vertex_inputs(vertex_reflection, &self.vertex_layout.attributes).with_context(|| {
+            format!(
+                "Vertex shader {} doesn't match the vertex layout",
+                vertex_shader.name()
+            )
+        })
+    }
+
+    fn reflect_shaders(&self, context: &VulkanContext) -> Result<Vec<ShaderReflection>> {
+        self.validate(context)?;
+        let shader_stages = self.shader_stages()?;
+        let reflections = shader_stages
+            .iter()
+            .map(|&(shader, stage, entry_point)| reflect_stage(context, shader, stage, entry_point))
+            .collect::<Result<Vec<_>>>()?;
+        self.check_vertex_layout(shader_stages[0].0, &reflections[0])?;
+
+        Ok(reflections)
+    }
+}
+
+/// Everything a graphics pipeline create info points at besides the builder itself. The
+/// pointers into the inline fields make it unmovable once a create info is made from it, so a
+/// batch collects all its states before building any create info.
+struct GraphicsPipelineState {
+    shader_stages: Vec<PipelineShaderStageCreateInfo>,
+    tessellation: bool,
+    viewport_state: PipelineViewportStateCreateInfo,
+    dynamic_state: PipelineDynamicStateCreateInfo,
+    vertex_input_state: PipelineVertexInputStateCreateInfo,
+    input_assembly_state: PipelineInputAssemblyStateCreateInfo,
+    tessellation_state: PipelineTessellationStateCreateInfo,
+    rasterization_state: PipelineRasterizationStateCreateInfo,
+    multisample_state: PipelineMultisampleStateCreateInfo,
+    depth_stencil_state: PipelineDepthStencilStateCreateInfo,
+    color_blend_state: PipelineColorBlendStateCreateInfo,
+    /// Chained to the create info for dynamic rendering
+    rendering: Option<PipelineRenderingCreateInfo>,
+    // Heap storage the create infos above point into, it stays put when the state is moved
+    _entry_points: Vec<CString>,
+    _specialization_infos: Vec<SpecializationInfo>,
+    _dynamic_states: Vec<DynamicState>,
+    _color_blend_attachment_states: Vec<PipelineColorBlendAttachmentState>,
+    _color_attachment_formats: Vec<Format>,
+}
+
+impl GraphicsPipelineState {
+    fn new(
+        builder: &PipelineBuilder,
+        stages: &[(&ShaderHandle, ShaderStageFlags, &str)],
+        reflections: &[ShaderReflection],
+        color_attachment_count: usize,
+        samples: SampleCountFlags,
+        depth_convention: DepthConvention,
+    ) -> Result<GraphicsPipelineState> {
+        let entry_points = reflections
+            .iter()
+            .map(entry_point_name)
+            .collect::<Result<Vec<_>>>()?;
+        let specialization_infos = stages
+            .iter()
+            .map(|&(_, stage, _)| {
+                builder
+                    .specialization_constants(stage)
+                    .specialization_info()
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let shader_stages = stages
+            .iter()
+            .zip(specialization_infos.iter())
+            .zip(entry_points.iter())
+            .map(
+                |((&(shader, stage, _), specialization_info), entry_point)| {
+                    create_pipeline_shader_stage_create_info(
+                        entry_point,
+                        shader.module(),
+                        stage,
+                        specialization_info,
+                    )
+                },
+            )
+            .collect::<Vec<_>>();
+
+        // Viewport and scissor are set while recording, so the pipeline survives extent changes
+        let viewport_state = PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1)
+            .build();
+        let mut dynamic_states = vec![DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        if builder.depth_bias {
+            dynamic_states.push(DynamicState::DEPTH_BIAS);
+        }
+        dynamic_states.extend_from_slice(&builder.dynamic_states);
+        let dynamic_state = PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states)
+            .build();
+
+        let vertex_input_state = PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&builder.vertex_layout.bindings)
+            .vertex_attribute_descriptions(&builder.vertex_layout.attributes)
+            .build();
+        // Tessellation shaders only consume patches, whatever topology was set
+        let tessellation = builder.tessellation_shaders.is_some();
+        let topology = match tessellation {
+            true => PrimitiveTopology::PATCH_LIST,
+            false => builder.topology,
+        };
+        let color_blend_attachment_states =
+            vec![builder.blend_mode.color_blend_attachment_state(); color_attachment_count];
+        let color_blend_state = PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(LogicOp::COPY)
+            .attachments(&color_blend_attachment_states)
+            .build();
+
+        let (rendering, color_attachment_formats) = match &builder.render_target {
+            RenderTarget::RenderPass(_) => (None, vec![]),
+            RenderTarget::Dynamic {
+                color_formats,
+                depth_format,
+            } => {
+                let color_attachment_formats = color_formats.clone();
+                let rendering = PipelineRenderingCreateInfo::builder()
+                    .color_attachment_formats(&color_attachment_formats)
+                    .depth_attachment_format(depth_format.unwrap_or(Format::UNDEFINED))
+                    .build();
+                (Some(rendering), color_attachment_formats)
+            }
+        };
+
+        Ok(GraphicsPipelineState {
+            shader_stages,
+            tessellation,
+            viewport_state,
+            dynamic_state,
+            vertex_input_state,
+            input_assembly_state: create_input_assembly_state_create_info(topology),
+            tessellation_state: PipelineTessellationStateCreateInfo::builder()
+                .patch_control_points(builder.patch_control_points)
+                .build(),
+            rasterization_state: create_rasterization_state_create_info(
+                builder.polygon_mode,
+                builder.cull_mode,
+                builder.depth_bias,
+            ),
+            multisample_state: create_multisample_state_create_info(samples),
+            depth_stencil_state: create_depth_stencil_state_create_info(
+                builder.depth_test,
+                builder.depth_test
+                    && builder.depth_write
+                    && builder.blend_mode == BlendMode::Opaque,
+                depth_convention.compare_op(builder.depth_compare_op),
+            ),
+            color_blend_state,
+            rendering,
+            _entry_points: entry_points,
+            _specialization_infos: specialization_infos,
+            _dynamic_states: dynamic_states,
+            _color_blend_attachment_states: color_blend_attachment_states,
+            _color_attachment_formats: color_attachment_formats,
+        })
+    }
+
+    fn create_info(
+        &self,
+        builder: &PipelineBuilder,
+        pipeline_layout: PipelineLayout,
+        flags: PipelineCreateFlags,
+        base_pipeline_index: i32,
+    ) -> GraphicsPipelineCreateInfo {
+        let mut graphics_pipeline_create_info = GraphicsPipelineCreateInfo::builder()
+            .flags(flags)
+            .stages(&self.shader_stages)
+            .vertex_input_state(&self.vertex_input_state)
+            .input_assembly_state(&self.input_assembly_state)
+            .viewport_state(&self.viewport_state)
+            .rasterization_state(&self.rasterization_state)
+            .multisample_state(&self.multisample_state)
+            .depth_stencil_state(&self.depth_stencil_state)
+            .color_blend_state(&self.color_blend_state)
+            .dynamic_state(&self.dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(builder.render_target.render_pass().unwrap_or_default())
+            .subpass(builder.subpass)
+            .base_pipeline_index(base_pipeline_index);
+        if self.tessellation {
+            graphics_pipeline_create_info =
+                graphics_pipeline_create_info.tessellation_state(&self.tessellation_state);
+        }
+
+        // `push_next` needs the struct mutably, the state is shared by every create info built
+        // from it
+        let mut graphics_pipeline_create_info = graphics_pipeline_create_info.build();
+        if let Some(rendering) = &self.rendering {
+            graphics_pipeline_create_info.p_next =
+                rendering as *const PipelineRenderingCreateInfo as *const c_void;
+        }
+
+        graphics_pipeline_create_info
+    }
+}
+
+/// A base pipeline and variants of it that differ only in fixed-function state, such as
+/// polygon, blend or depth modes. All members share the base's shaders and layouts. With
+/// `pipeline_derivatives` enabled the variants are created as derivatives of the base, which
+/// some drivers create faster.
+pub struct PipelineFamily<K> {
+    pipelines: Vec<(K, Pipeline)>,
+    pub pipeline_layout: PipelineLayout,
+    pub descriptor_set_layouts: Vec<DescriptorSetLayout>,
+    _shaders: Vec<ShaderHandle>,
+    /// See `PistonPipeline::render_pass_desc`
+    pub render_pass_desc: Option<RenderPassDesc>,
+}
+
+impl<K: Copy + PartialEq + Debug> PipelineFamily<K> {
+    /// Creates all members in one batch, the first one is the base.
+    pub fn new(
+        context: &VulkanContext,
+        members: &[(K, PipelineBuilder)],
+    ) -> Result<PipelineFamily<K>> {
+        let device = &context.device;
+        let (_, base_builder) = members
+            .first()
+            .ok_or_else(|| anyhow!("Pipeline family has no members"))?;
+        let reflections = base_builder.reflect_shaders(context)?;
+        let base_shader_stages = base_builder.shader_stages()?;
+        for (key, builder) in members.iter().skip(1) {
+            builder.validate(context)?;
+            if builder.shader_stages()? != base_shader_stages {
+                return Err(anyhow!(
+                    "Pipeline family member {:?} doesn't use the shaders of the base",
+                    key
+                ));
+            }
+            builder.check_vertex_layout(base_shader_stages[0].0, &reflections[0])?;
+        }
+
+        let render_pass_desc = base_builder.render_pass_desc(context)?;
+        let states = members
+            .iter()
+            .map(|(_, builder)| {
+                GraphicsPipelineState::new(
+                    builder,
+                    &base_shader_stages,
+                    &reflections,
+                    builder.color_attachment_count(context)?,
+                    builder.device_samples(context),
+                    context.depth_convention,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (descriptor_set_layouts, pipeline_layout) =
+            create_reflected_layouts(device, &reflections)?;
+
+        let derivatives = context.pipeline_derivatives;
+        // Derivatives refer to the base by its index, it is created in the same batch
+        let graphics_pipeline_create_infos = members
+            .iter()
+            .zip(states.iter())
+            .enumerate()
+            .map(|(index, ((_, builder), state))| {
+                let (flags, base_pipeline_index) = match (derivatives, index) {
+                    (false, _) => (PipelineCreateFlags::empty(), -1),
+                    (true, 0) => (PipelineCreateFlags::ALLOW_DERIVATIVES, -1),
+                    (true, _) => (PipelineCreateFlags::DERIVATIVE, 0),
+                };
+                state.create_info(builder, pipeline_layout, flags, base_pipeline_index)
+            })
+            .collect::<Vec<_>>();
+
+        let started = Instant::now();
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                context.pipeline_cache,
+                &graphics_pipeline_create_infos,
+                None,
+            )
+        };
+        let elapsed = started.elapsed();
+        let shaders = base_shader_stages
+            .into_iter()
+            .map(|(shader, _, _)| shader.clone())
+            .collect::<Vec<_>>();
+
+        match pipelines {
+            Ok(pipelines) => {
+                info!(
+                    "Created a family of {} pipelines {} derivatives in {:.2} ms",
+                    pipelines.len(),
+                    if derivatives { "with" } else { "without" },
+                    elapsed.as_secs_f64() * 1000.0
+                );
+
+                let name = base_builder.debug_name();
+                for (&(key, _), &pipeline) in members.iter().zip(pipelines.iter()) {
+                    context.set_object_name(pipeline, &format!("{} {:?}", name, key));
+                }
+                name_layouts(context, pipeline_layout, &descriptor_set_layouts, &name);
+
+                Ok(PipelineFamily {
+                    pipelines: members.iter().map(|&(key, _)| key).zip(pipelines).collect(),
+                    pipeline_layout,
+                    descriptor_set_layouts,
+                    _shaders: shaders,
+                    render_pass_desc,
+                })
+            }
+            Err((pipelines, error)) => {
+                PipelineFamily {
+                    pipelines: members.iter().map(|&(key, _)| key).zip(pipelines).collect(),
+                    pipeline_layout,
+                    descriptor_set_layouts,
+                    _shaders: shaders,
+                    render_pass_desc,
+                }
+                .destroy(device);
+                Err(error.into())
+            }
+        }
+    }
+
+    pub fn base(&self) -> Pipeline {
+        self.pipelines[0].1
+    }
+
+    pub fn pipeline(&self, key: K) -> Option<Pipeline> {
+        self.pipelines
+            .iter()
+            .find(|&&(member_key, _)| member_key == key)
+            .map(|&(_, pipeline)| pipeline)
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for &(_, pipeline) in self.pipelines.iter() {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            for &descriptor_set_layout in self.descriptor_set_layouts.iter() {
+                device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+            }
+        }
+    }
+}
+
+static NO_SPECIALIZATION_CONSTANTS: SpecializationConstants = SpecializationConstants {
+    map_entries: Vec::new(),
+    data: Vec::new(),
+};
+
+/// Collects `(constant_id, value)` pairs for one shader stage. The constants own the bytes the
+/// `SpecializationInfo` points at, so they must outlive pipeline creation.
+#[derive(Clone, Debug, Default)]
+pub struct SpecializationConstants {
+    map_entries: Vec<SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
+    pub fn with_u32(self, constant_id: u32, value: u32) -> SpecializationConstants {
+        self.with_bytes(constant_id, &value.to_ne_bytes())
+    }
+
+    pub fn with_i32(self, constant_id: u32, value: i32) -> SpecializationConstants {
+        self.with_bytes(constant_id, &value.to_ne_bytes())
+    }
+
+    pub fn with_f32(self, constant_id: u32, value: f32) -> SpecializationConstants {
+        self.with_bytes(constant_id, &value.to_ne_bytes())
+    }
+
+    /// Boolean constants are 32-bit `VkBool32` values.
+    pub fn with_bool(self, constant_id: u32, value: bool) -> SpecializationConstants {
+        self.with_u32(constant_id, value as u32)
+    }
+
+    fn with_bytes(mut self, constant_id: u32, bytes: &[u8]) -> SpecializationConstants {
+        self.map_entries.push(SpecializationMapEntry {
+            constant_id,
+            offset: self.data.len() as u32,
+            size: bytes.len(),
+        });
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn specialization_info(&self) -> SpecializationInfoBuilder<'_> {
+        SpecializationInfo::builder()
+            .map_entries(&self.map_entries)
+            .data(&self.data)
+    }
+}
+
+pub struct PistonPipeline {
+    pub pipeline: Pipeline,
+    pub pipeline_layout: PipelineLayout,
+    /// Built from the shaders' reflected bindings, indexed by set number
+    pub descriptor_set_layouts: Vec<DescriptorSetLayout>,
+    /// Keeps the shader modules alive in the context's shader cache
+    pub shaders: Vec<ShaderHandle>,
+    /// The cached render pass the pipeline was built for, `None` for compute pipelines, dynamic
+    /// rendering and render passes created outside the cache
+    pub render_pass_desc: Option<RenderPassDesc>,
+}
+
+impl PistonPipeline {
+    /// Fails if the pipeline can't be used in a render pass created for `render_pass_desc`.
+    pub fn check_render_pass(&self, render_pass_desc: &RenderPassDesc) -> Result<()> {
+        match &self.render_pass_desc {
+            Some(desc) if !desc.is_compatible_with(render_pass_desc) => Err(anyhow!(
+                "Pipeline was built for render pass {:?}, which is not compatible with {:?}",
+                desc,
+                render_pass_desc
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Names the pipeline and its layouts after `name`.
+    pub fn set_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.pipeline, name);
+        name_layouts(
+            context,
+            self.pipeline_layout,
+            &self.descriptor_set_layouts,
+            name,
+        );
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            for &descriptor_set_layout in self.descriptor_set_layouts.iter() {
+                device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+            }
+        }
+    }
+}
 
-    let vertex_input_state_create_info = create_vertex_input_state_create_info();
-    let input_assembly_state_create_info = create_input_assembly_state_create_info();
-    let rasterization_state_create_info = create_rasterization_state_create_info();
-    let multisample_state_create_info = create_multisample_state_create_info();
-    let depth_stencil_state_create_info = create_depth_stencil_state_create_info();
-    let color_blend_state_create_info = create_color_blend_state_create_info();
-    let pipeline_layout = create_pipeline_layout(device)?;
-    let graphics_pipeline_create_infos = [GraphicsPipelineCreateInfo::builder()
-        .stages(&shader_stages_create_info)
-        .vertex_input_state(&vertex_input_state_create_info)
-        .input_assembly_state(&input_assembly_state_create_info)
-        .viewport_state(&viewport_state_create_info)
-        .rasterization_state(&rasterization_state_create_info)
-        .multisample_state(&multisample_state_create_info)
-        .depth_stencil_state(&depth_stencil_state_create_info)
-        .color_blend_state(&color_blend_state_create_info)
+/// A compute pipeline and the workgroup size declared by its shader.
+pub struct ComputePipeline {
+    pub pipeline: PistonPipeline,
+    pub local_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    pub fn destroy(&self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+}
+
+pub fn create_compute_pipeline(
+    context: &VulkanContext,
+    shader: ShaderHandle,
+    constants: &SpecializationConstants,
+) -> Result<ComputePipeline> {
+    let _span = info_span!("create_compute_pipeline", name = shader.name()).entered();
+    let device = &context.device;
+    let reflection = reflect_stage(
+        context,
+        &shader,
+        ShaderStageFlags::COMPUTE,
+        DEFAULT_ENTRY_POINT,
+    )?;
+    let local_size = reflection.local_size;
+    let entry_point = entry_point_name(&reflection)?;
+    let (descriptor_set_layouts, pipeline_layout) =
+        create_reflected_layouts(device, &[reflection])?;
+    let specialization_info = constants.specialization_info();
+
+    let compute_pipeline_create_infos = [ComputePipelineCreateInfo::builder()
+        .stage(create_pipeline_shader_stage_create_info(
+            &entry_point,
+            shader.module(),
+            ShaderStageFlags::COMPUTE,
+            &specialization_info,
+        ))
         .layout(pipeline_layout)
-        .render_pass(render_pass)
-        .subpass(0)
         .build()];
 
     let pipelines = unsafe {
-        device.create_graphics_pipelines(
-            PipelineCache::null(),
-            &graphics_pipeline_create_infos,
+        device.create_compute_pipelines(
+            context.pipeline_cache,
+            &compute_pipeline_create_infos,
             None,
         )
+    };
+
+    let name = shader.name().to_string();
+    let pipeline = finish_pipeline(
+        device,
+        pipelines,
+        pipeline_layout,
+        descriptor_set_layouts,
+        vec![shader],
+        None,
+    )?;
+    pipeline.set_name(context, &name);
+
+    Ok(ComputePipeline {
+        pipeline,
+        local_size,
+    })
+}
+
+fn name_layouts(
+    context: &VulkanContext,
+    pipeline_layout: PipelineLayout,
+    descriptor_set_layouts: &[DescriptorSetLayout],
+    name: &str,
+) {
+    context.set_object_name(pipeline_layout, &format!("{} layout", name));
+    for (set, &descriptor_set_layout) in descriptor_set_layouts.iter().enumerate() {
+        context.set_object_name(descriptor_set_layout, &format!("{} set {}", name, set));
     }
-    .unwrap();
+}
 
-    unsafe {
-        device.destroy_shader_module(vertex_shader_module, None);
-        device.destroy_shader_module(fragment_shader_module, None);
+/// Reflects an entry point of a shader and checks that it was written for the stage it is
+/// bound to.
+fn reflect_stage(
+    context: &VulkanContext,
+    shader: &ShaderHandle,
+    stage: ShaderStageFlags,
+    entry_point: &str,
+) -> Result<ShaderReflection> {
+    let reflection = context
+        .reflect_shader(shader.code(), entry_point)
+        .with_context(|| {
+            format!(
+                "Failed to reflect the {:?} stage of {}",
+                stage,
+                shader.name()
+            )
+        })?;
+    if reflection.stage != stage {
+        return Err(anyhow!(
+            "Entry point {} of {} is a {:?} shader but was bound to the {:?} stage",
+            entry_point,
+            shader.name(),
+            reflection.stage,
+            stage
+        ));
     }
 
-    Ok((pipelines[0], pipeline_layout))
+    Ok(reflection)
 }
 
-fn create_shader_module(device: &Device, shader_code: Vec<u32>) -> Result<ShaderModule> {
-    let shader_module_create_info = ShaderModuleCreateInfo::builder().code(&shader_code).build();
+/// The entry point a reflection was made for, which reflecting proved to exist in the module.
+fn entry_point_name(reflection: &ShaderReflection) -> Result<CString> {
+    CString::new(reflection.entry_point.as_str()).with_context(|| {
+        format!(
+            "Entry point name {:?} contains a nul byte",
+            reflection.entry_point
+        )
+    })
+}
+
+fn finish_pipeline(
+    device: &Device,
+    pipelines: Result<Vec<Pipeline>, (Vec<Pipeline>, ash::vk::Result)>,
+    pipeline_layout: PipelineLayout,
+    descriptor_set_layouts: Vec<DescriptorSetLayout>,
+    shaders: Vec<ShaderHandle>,
+    render_pass_desc: Option<RenderPassDesc>,
+) -> Result<PistonPipeline> {
+    match pipelines {
+        Ok(pipelines) => Ok(PistonPipeline {
+            pipeline: pipelines[0],
+            pipeline_layout,
+            descriptor_set_layouts,
+            shaders,
+            render_pass_desc,
+        }),
+        Err((_, error)) => {
+            PistonPipeline {
+                pipeline: Pipeline::null(),
+                pipeline_layout,
+                descriptor_set_layouts,
+                shaders,
+                render_pass_desc,
+            }
+            .destroy(device);
+            Err(error.into())
+        }
+    }
+}
+
+pub fn load_shader_code(shader_path: &Path) -> Result<Vec<u32>> {
+    #[cfg(feature = "shaderc")]
+    compile_if_stale(shader_path)?;
 
-    Ok(unsafe { device.create_shader_module(&shader_module_create_info, None) }?)
+    let shader_code = if shader_path.exists() {
+        info!("Loading shader {:?} from disk", shader_path);
+        let shader_bytes = load_file_bytes(shader_path)?;
+        if shader_bytes.is_empty() {
+            return Err(anyhow!(
+                "Shader {:?} is empty, the shader compiler probably failed without reporting it",
+                shader_path
+            ));
+        }
+        read_spv(&mut Cursor::new(shader_bytes))
+            .with_context(|| format!("Shader {:?} is not SPIR-V", shader_path))?
+    } else {
+        let embedded_shader = find_embedded_shader(shader_path).ok_or_else(|| {
+            anyhow!(
+                "Shader {:?} does not exist and has no embedded copy",
+                shader_path
+            )
+        })?;
+        info!(
+            "Shader {:?} does not exist, using the embedded copy",
+            shader_path
+        );
+        read_spv(&mut Cursor::new(embedded_shader))?
+    };
+
+    Ok(shader_code)
+}
+
+fn find_embedded_shader(shader_path: &Path) -> Option<&'static [u8]> {
+    let file_name = shader_path.file_name()?;
+    EMBEDDED_SHADERS
+        .iter()
+        .find(|(embedded_file_name, _)| file_name == *embedded_file_name)
+        .map(|&(_, shader_bytes)| shader_bytes)
 }
 
 fn create_pipeline_shader_stage_create_info(
-    main_function_name: &CString,
+    entry_point: &CStr,
     shader_module: ShaderModule,
     stage: ShaderStageFlags,
+    specialization_info: &SpecializationInfo,
 ) -> PipelineShaderStageCreateInfo {
     PipelineShaderStageCreateInfo::builder()
         .module(shader_module)
-        .name(main_function_name)
+        .name(entry_point)
         .stage(stage)
+        .specialization_info(specialization_info)
         .build()
 }
 
-fn create_vertex_input_state_create_info() -> PipelineVertexInputStateCreateInfo {
-    PipelineVertexInputStateCreateInfo::default()
-}
-
-fn create_input_assembly_state_create_info() -> PipelineInputAssemblyStateCreateInfo {
+fn create_input_assembly_state_create_info(
+    topology: PrimitiveTopology,
+) -> PipelineInputAssemblyStateCreateInfo {
     PipelineInputAssemblyStateCreateInfo::builder()
         .primitive_restart_enable(false)
-        .topology(PrimitiveTopology::TRIANGLE_LIST)
+        .topology(topology)
         .build()
 }
 
-fn create_rasterization_state_create_info() -> PipelineRasterizationStateCreateInfo {
+fn create_rasterization_state_create_info(
+    polygon_mode: PolygonMode,
+    cull_mode: CullModeFlags,
+    depth_bias: bool,
+) -> PipelineRasterizationStateCreateInfo {
     PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
-        .cull_mode(CullModeFlags::BACK)
+        .cull_mode(cull_mode)
         .front_face(FrontFace::CLOCKWISE)
         .line_width(1.0)
-        .polygon_mode(PolygonMode::FILL)
+        .polygon_mode(polygon_mode)
         .rasterizer_discard_enable(false)
         .depth_bias_clamp(0.0)
         .depth_bias_constant_factor(0.0)
-        .depth_bias_enable(false)
+        .depth_bias_enable(depth_bias)
         .depth_bias_slope_factor(0.0)
         .build()
 }
 
-fn create_multisample_state_create_info() -> PipelineMultisampleStateCreateInfo {
+fn create_multisample_state_create_info(
+    samples: SampleCountFlags,
+) -> PipelineMultisampleStateCreateInfo {
     PipelineMultisampleStateCreateInfo::builder()
-        .rasterization_samples(SampleCountFlags::TYPE_1)
+        .rasterization_samples(samples)
         .sample_shading_enable(false)
         .min_sample_shading(0.0)
         .alpha_to_one_enable(false)
@@ -161,7 +1298,11 @@ fn create_multisample_state_create_info() -> PipelineMultisampleStateCreateInfo
         .build()
 }
 
-fn create_depth_stencil_state_create_info() -> PipelineDepthStencilStateCreateInfo {
+fn create_depth_stencil_state_create_info(
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: CompareOp,
+) -> PipelineDepthStencilStateCreateInfo {
     let stencil_state = StencilOpState::builder()
         .fail_op(StencilOp::KEEP)
         .pass_op(StencilOp::KEEP)
@@ -173,9 +1314,9 @@ fn create_depth_stencil_state_create_info() -> PipelineDepthStencilStateCreateIn
         .build();
 
     PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(false)
-        .depth_write_enable(false)
-        .depth_compare_op(CompareOp::LESS_OR_EQUAL)
+        .depth_test_enable(depth_test)
+        .depth_write_enable(depth_write)
+        .depth_compare_op(depth_compare_op)
         .depth_bounds_test_enable(false)
         .front(stencil_state)
         .back(stencil_state)
@@ -184,26 +1325,138 @@ fn create_depth_stencil_state_create_info() -> PipelineDepthStencilStateCreateIn
         .build()
 }
 
-fn create_color_blend_state_create_info() -> PipelineColorBlendStateCreateInfo {
-    let color_blend_attachment_states = [PipelineColorBlendAttachmentState::builder()
-        .blend_enable(false)
-        .color_write_mask(ColorComponentFlags::RGBA)
-        .src_color_blend_factor(BlendFactor::ONE)
-        .dst_color_blend_factor(BlendFactor::ZERO)
-        .color_blend_op(BlendOp::ADD)
-        .src_alpha_blend_factor(BlendFactor::ONE)
-        .dst_alpha_blend_factor(BlendFactor::ZERO)
-        .alpha_blend_op(BlendOp::ADD)
-        .build()];
-
-    PipelineColorBlendStateCreateInfo::builder()
-        .logic_op_enable(false)
-        .logic_op(LogicOp::COPY)
-        .attachments(&color_blend_attachment_states)
-        .build()
+pub fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layouts: &[DescriptorSetLayout],
+    push_constant_ranges: &[PushConstantRange],
+) -> Result<PipelineLayout> {
+    let pipeline_layout_create_info = PipelineLayoutCreateInfo::builder()
+        .set_layouts(descriptor_set_layouts)
+        .push_constant_ranges(push_constant_ranges)
+        .build();
+    Ok(unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }?)
 }
 
-fn create_pipeline_layout(device: &Device) -> Result<PipelineLayout> {
-    let pipeline_layout_create_info = PipelineLayoutCreateInfo::default();
-    Ok(unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }?)
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use ash::vk::{FALSE, TRUE};
+
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn shader_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piston-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_shader_without_embedded_copy_fails() {
+        let dir = shader_test_dir("missing-shader");
+        let error = load_shader_code(&dir.join("missing-frag.spv")).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("does not exist and has no embedded copy"));
+    }
+
+    #[test]
+    fn missing_shader_falls_back_to_embedded_copy() {
+        let dir = shader_test_dir("embedded-shader");
+        let shader_code = load_shader_code(&dir.join("gradient-comp.spv")).unwrap();
+        assert_eq!(shader_code[0], 0x0723_0203);
+    }
+
+    #[test]
+    fn empty_shader_fails() {
+        let dir = shader_test_dir("empty-shader");
+        let shader_path = dir.join("gradient-comp.spv");
+        fs::write(&shader_path, []).unwrap();
+        let error = load_shader_code(&shader_path).unwrap_err();
+        assert!(error.to_string().contains("is empty"));
+    }
+
+    /// The state of a pipeline without shader stages, which needs no device.
+    fn state(builder: &PipelineBuilder) -> GraphicsPipelineState {
+        GraphicsPipelineState::new(
+            builder,
+            &[],
+            &[],
+            1,
+            builder.samples,
+            DepthConvention::Standard,
+        )
+        .unwrap()
+    }
+
+    fn dynamic_states(state: &GraphicsPipelineState) -> &[DynamicState] {
+        let dynamic_state = &state.dynamic_state;
+        unsafe {
+            std::slice::from_raw_parts(
+                dynamic_state.p_dynamic_states,
+                dynamic_state.dynamic_state_count as usize,
+            )
+        }
+    }
+
+    #[test]
+    fn builder_defaults_match_the_original_pipeline() {
+        let state = state(&PipelineBuilder::new());
+        assert_eq!(
+            state.input_assembly_state.topology,
+            PrimitiveTopology::TRIANGLE_LIST
+        );
+        assert_eq!(state.rasterization_state.polygon_mode, PolygonMode::FILL);
+        assert_eq!(state.rasterization_state.cull_mode, CullModeFlags::BACK);
+        assert_eq!(state.rasterization_state.depth_bias_enable, FALSE);
+        assert_eq!(state.depth_stencil_state.depth_test_enable, FALSE);
+        assert_eq!(state.depth_stencil_state.depth_write_enable, FALSE);
+        assert_eq!(
+            state.multisample_state.rasterization_samples,
+            SampleCountFlags::TYPE_1
+        );
+        assert_eq!(state.color_blend_state.attachment_count, 1);
+        let attachment = unsafe { *state.color_blend_state.p_attachments };
+        assert_eq!(attachment.blend_enable, FALSE);
+        assert_eq!(
+            dynamic_states(&state),
+            [DynamicState::VIEWPORT, DynamicState::SCISSOR]
+        );
+        assert!(state.rendering.is_none());
+        assert!(!state.tessellation);
+    }
+
+    #[test]
+    fn builder_setters_reach_the_state() {
+        let builder = PipelineBuilder::new()
+            .topology(PrimitiveTopology::LINE_LIST)
+            .cull_mode(CullModeFlags::NONE)
+            .depth_test(true)
+            .depth_bias(true)
+            .blend_mode(BlendMode::AlphaBlend)
+            .dynamic_states(&[DynamicState::LINE_WIDTH]);
+        let state = state(&builder);
+        assert_eq!(
+            state.input_assembly_state.topology,
+            PrimitiveTopology::LINE_LIST
+        );
+        assert_eq!(state.rasterization_state.cull_mode, CullModeFlags::NONE);
+        assert_eq!(state.depth_stencil_state.depth_test_enable, TRUE);
+        // Blended geometry doesn't hide what's behind it
+        assert_eq!(state.depth_stencil_state.depth_write_enable, FALSE);
+        let attachment = unsafe { *state.color_blend_state.p_attachments };
+        assert_eq!(attachment.blend_enable, TRUE);
+        assert_eq!(
+            dynamic_states(&state),
+            [
+                DynamicState::VIEWPORT,
+                DynamicState::SCISSOR,
+                DynamicState::DEPTH_BIAS,
+                DynamicState::LINE_WIDTH
+            ]
+        );
+    }
 }
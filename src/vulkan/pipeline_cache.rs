@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use ash::vk::{PipelineCache, PipelineCacheCreateInfo, PipelineCacheHeaderVersion, UUID_SIZE};
+use ash::Device;
+use log::{info, warn};
+
+use crate::vulkan::device_info::DeviceInfo;
+
+const PIPELINE_CACHE_HEADER_SIZE: usize = 16 + UUID_SIZE;
+
+/// Creates the pipeline cache, seeded from `path` when the file exists and was written by the
+/// same driver and device. Unreadable or mismatched files are ignored and an empty cache is used.
+pub fn create_pipeline_cache(
+    device: &Device,
+    device_info: &DeviceInfo,
+    path: Option<&Path>,
+) -> Result<PipelineCache> {
+    let initial_data = initial_pipeline_cache_data(device_info, path);
+    let pipeline_cache_create_info = PipelineCacheCreateInfo::builder()
+        .initial_data(&initial_data)
+        .build();
+
+    Ok(unsafe { device.create_pipeline_cache(&pipeline_cache_create_info, None) }?)
+}
+
+/// The data of the file at `path` when it's usable, or else nothing.
+fn initial_pipeline_cache_data(device_info: &DeviceInfo, path: Option<&Path>) -> Vec<u8> {
+    match path {
+        Some(path) if path.exists() => match load_pipeline_cache_data(device_info, path) {
+            Ok(data) => {
+                info!(
+                    "Loaded {} bytes of pipeline cache data from {:?}",
+                    data.len(),
+                    path
+                );
+                data
+            }
+            Err(error) => {
+                warn!("Ignoring pipeline cache file {:?}: {}", path, error);
+                vec![]
+            }
+        },
+        Some(path) => {
+            info!("No pipeline cache file at {:?}, starting empty", path);
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
+pub fn save_pipeline_cache(
+    device: &Device,
+    pipeline_cache: PipelineCache,
+    path: &Path,
+) -> Result<()> {
+    let data = unsafe { device.get_pipeline_cache_data(pipeline_cache) }?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create pipeline cache directory {:?}", parent))?;
+    }
+    fs::write(path, &data)
+        .with_context(|| format!("Failed to write pipeline cache file {:?}", path))?;
+
+    info!(
+        "Saved {} bytes of pipeline cache data to {:?}",
+        data.len(),
+        path
+    );
+
+    Ok(())
+}
+
+fn load_pipeline_cache_data(device_info: &DeviceInfo, path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return Err(anyhow!("file is too small to hold a pipeline cache header"));
+    }
+
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    let header_size = read_u32(0) as usize;
+    let header_version = read_u32(4);
+    let vendor_id = read_u32(8);
+    let device_id = read_u32(12);
+    let uuid = &data[16..PIPELINE_CACHE_HEADER_SIZE];
+
+    if header_size < PIPELINE_CACHE_HEADER_SIZE || header_size > data.len() {
+        return Err(anyhow!("invalid header size {}", header_size));
+    }
+    if header_version != PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err(anyhow!("unsupported header version {}", header_version));
+    }
+    if vendor_id != device_info.vendor_id || device_id != device_info.device_id {
+        return Err(anyhow!(
+            "written for vendor {:#x} device {:#x}, current device is vendor {:#x} device {:#x}",
+            vendor_id,
+            device_id,
+            device_info.vendor_id,
+            device_info.device_id
+        ));
+    }
+    if uuid != device_info.pipeline_cache_uuid {
+        return Err(anyhow!(
+            "pipeline cache UUID does not match the current driver"
+        ));
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ash::vk::{PhysicalDeviceLimits, PhysicalDeviceMemoryProperties, PhysicalDeviceType};
+
+    use super::*;
+
+    const VENDOR_ID: u32 = 0x1002;
+    const DEVICE_ID: u32 = 0x73bf;
+    const UUID: [u8; UUID_SIZE] = [7; UUID_SIZE];
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            name: "Test device".to_string(),
+            device_type: PhysicalDeviceType::DISCRETE_GPU,
+            api_version: 0,
+            instance_api_version: 0,
+            driver_version: 0,
+            vendor_id: VENDOR_ID,
+            device_id: DEVICE_ID,
+            pipeline_cache_uuid: UUID,
+            limits: PhysicalDeviceLimits::default(),
+            memory_properties: PhysicalDeviceMemoryProperties::default(),
+            enabled_extensions: vec![],
+            enabled_features: vec![],
+        }
+    }
+
+    /// A header followed by a few bytes of cache data.
+    fn cache_file(vendor_id: u32, device_id: u32, uuid: [u8; UUID_SIZE]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(PIPELINE_CACHE_HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&(PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes());
+        data.extend_from_slice(&vendor_id.to_le_bytes());
+        data.extend_from_slice(&device_id.to_le_bytes());
+        data.extend_from_slice(&uuid);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data
+    }
+
+    /// Writes `data` to a file of its own for each test.
+    fn write_cache_file(name: &str, data: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("piston-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pipeline_cache.bin");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn matching_file_seeds_the_cache() {
+        let data = cache_file(VENDOR_ID, DEVICE_ID, UUID);
+        let path = write_cache_file("matching-cache", &data);
+        assert_eq!(
+            initial_pipeline_cache_data(&device_info(), Some(&path)),
+            data
+        );
+    }
+
+    #[test]
+    fn truncated_file_is_discarded() {
+        let data = cache_file(VENDOR_ID, DEVICE_ID, UUID);
+        let path = write_cache_file("truncated-cache", &data[..PIPELINE_CACHE_HEADER_SIZE - 1]);
+        assert!(initial_pipeline_cache_data(&device_info(), Some(&path)).is_empty());
+    }
+
+    #[test]
+    fn file_for_another_device_is_discarded() {
+        let cases = [
+            ("other-vendor-cache", cache_file(0x10de, DEVICE_ID, UUID)),
+            ("other-device-cache", cache_file(VENDOR_ID, 0x1234, UUID)),
+            (
+                "other-uuid-cache",
+                cache_file(VENDOR_ID, DEVICE_ID, [8; UUID_SIZE]),
+            ),
+        ];
+        for (name, data) in cases {
+            let path = write_cache_file(name, &data);
+            assert!(
+                initial_pipeline_cache_data(&device_info(), Some(&path)).is_empty(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn missing_file_starts_empty() {
+        let path = std::env::temp_dir().join("piston-no-such-dir/pipeline_cache.bin");
+        assert!(initial_pipeline_cache_data(&device_info(), Some(&path)).is_empty());
+        assert!(initial_pipeline_cache_data(&device_info(), None).is_empty());
+    }
+}
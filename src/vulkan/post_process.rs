@@ -0,0 +1,121 @@
+use anyhow::Result;
+use ash::vk::{
+    CommandBuffer, DescriptorImageInfo, DescriptorPool, DescriptorPoolSize, DescriptorSet,
+    DescriptorType, Extent2D, Format, ImageLayout, PipelineBindPoint, RenderPass, ShaderStageFlags,
+    SubpassContents,
+};
+use ash::Device;
+
+use crate::config::PostEffect;
+use crate::constants::POST_PROCESS_INPUT_BINDING;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::descriptor::{
+    allocate_descriptor_set, create_descriptor_pool, write_input_attachment,
+};
+use crate::vulkan::image::{ImageDesc, PistonImage};
+use crate::vulkan::pipeline::{PipelineBuilder, PistonPipeline};
+use crate::vulkan::render::{create_post_process_render_pass, RenderTarget};
+
+/// Subpass 1 of the swapchain pass. Subpass 0 renders into `intermediate`, which this subpass
+/// reads as an input attachment to draw a fullscreen effect onto the swapchain image. The
+/// two-subpass render pass is owned here rather than by the render pass cache.
+pub struct PostProcessSubpass {
+    pub render_pass: RenderPass,
+    pub intermediate: PistonImage,
+    pub pipeline: PistonPipeline,
+    descriptor_pool: DescriptorPool,
+    descriptor_set: DescriptorSet,
+}
+
+impl PostProcessSubpass {
+    /// The intermediate attachment uses the swapchain's `format`.
+    pub fn new(
+        context: &VulkanContext,
+        extent: Extent2D,
+        format: Format,
+    ) -> Result<PostProcessSubpass> {
+        let device = &context.device;
+        let render_pass = create_post_process_render_pass(device, format, format)?;
+        let intermediate = PistonImage::new(
+            context,
+            &ImageDesc::input_attachment(extent, format),
+            "post process intermediate",
+        )?;
+        let pipeline = PipelineBuilder::new()
+            .shaders(
+                context.load_shader("fullscreen-vert.spv")?,
+                context.load_shader("post-frag.spv")?,
+            )
+            .render_pass(render_pass)
+            .subpass(1)
+            .build(context)?;
+
+        let descriptor_pool = create_descriptor_pool(
+            device,
+            &[DescriptorPoolSize {
+                ty: DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: 1,
+            }],
+            1,
+        )?;
+        let descriptor_set =
+            allocate_descriptor_set(device, descriptor_pool, pipeline.descriptor_set_layouts[0])?;
+        write_input_attachment(
+            device,
+            descriptor_set,
+            POST_PROCESS_INPUT_BINDING,
+            DescriptorImageInfo::builder()
+                .image_view(intermediate.view)
+                .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        );
+
+        Ok(PostProcessSubpass {
+            render_pass,
+            intermediate,
+            pipeline,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    /// The swapchain pass, pipelines drawn in subpass 0 are built for it.
+    pub fn render_target(&self) -> RenderTarget {
+        RenderTarget::RenderPass(self.render_pass)
+    }
+
+    /// Moves on to subpass 1 and draws `effect` over the whole swapchain image.
+    pub fn record(&self, device: &Device, command_buffer: CommandBuffer, effect: PostEffect) {
+        unsafe {
+            device.cmd_next_subpass(command_buffer, SubpassContents::INLINE);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.pipeline_layout,
+                ShaderStageFlags::FRAGMENT,
+                0,
+                &(effect as u32).to_ne_bytes(),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.pipeline.destroy(device);
+        unsafe { device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.intermediate.destroy(device);
+        unsafe { device.destroy_render_pass(self.render_pass, None) };
+    }
+}
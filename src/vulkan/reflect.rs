@@ -0,0 +1,607 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, PipelineLayout,
+    PushConstantRange, ShaderStageFlags, VertexInputAttributeDescription,
+};
+use ash::Device;
+use log::debug;
+use rspirv::dr::{load_words, Instruction, Module, Operand};
+use rspirv::spirv::{Decoration, Dim, ExecutionMode, ExecutionModel, Op, StorageClass, Word};
+
+use crate::vulkan::descriptor::create_descriptor_set_layout;
+use crate::vulkan::pipeline::create_pipeline_layout;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    pub descriptor_count: u32,
+    pub stage_flags: ShaderStageFlags,
+}
+
+/// The resource interface of one entry point of a shader module.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    pub stage: ShaderStageFlags,
+    pub entry_point: String,
+    pub bindings: Vec<ReflectedBinding>,
+    pub push_constant_range: Option<PushConstantRange>,
+    pub input_locations: Vec<u32>,
+    /// Workgroup size of a compute shader, `[1, 1, 1]` for the other stages.
+    pub local_size: [u32; 3],
+}
+
+/// Reflections keyed by a hash of the SPIR-V and the entry point name.
+pub struct ReflectionCache {
+    reflections: HashMap<(u64, String), ShaderReflection>,
+}
+
+impl ReflectionCache {
+    pub fn new() -> ReflectionCache {
+        ReflectionCache {
+            reflections: HashMap::new(),
+        }
+    }
+
+    pub fn get_or_reflect(
+        &mut self,
+        shader_code: &[u32],
+        entry_point: &str,
+    ) -> Result<ShaderReflection> {
+        let key = (code_hash(shader_code), entry_point.to_string());
+
+        if let Some(reflection) = self.reflections.get(&key) {
+            return Ok(reflection.clone());
+        }
+
+        let reflection = reflect_shader(shader_code, entry_point)?;
+        debug!(
+            "Reflected {:?} shader {:#018x} entry point {}: {:?}",
+            reflection.stage, key.0, entry_point, reflection
+        );
+        self.reflections.insert(key, reflection.clone());
+
+        Ok(reflection)
+    }
+
+    /// Records a reflection made from the shader's source, such as naga's reflection of a
+    /// translated WGSL shader, in place of reflecting the SPIR-V.
+    pub fn insert(&mut self, shader_code: &[u32], reflection: ShaderReflection) {
+        let key = (code_hash(shader_code), reflection.entry_point.clone());
+        self.reflections.insert(key, reflection);
+    }
+}
+
+impl Default for ReflectionCache {
+    fn default() -> ReflectionCache {
+        ReflectionCache::new()
+    }
+}
+
+fn code_hash(shader_code: &[u32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    shader_code.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Descriptor set layouts and pipeline layout built from the reflected shaders of a pipeline.
+/// Set numbers the shaders skip get an empty layout so the indices line up with the sets.
+pub fn create_reflected_layouts(
+    device: &Device,
+    reflections: &[ShaderReflection],
+) -> Result<(Vec<DescriptorSetLayout>, PipelineLayout)> {
+    let mut sets: BTreeMap<u32, Vec<ReflectedBinding>> = BTreeMap::new();
+    for binding in reflections
+        .iter()
+        .flat_map(|reflection| &reflection.bindings)
+    {
+        let set_bindings = sets.entry(binding.set).or_default();
+        match set_bindings
+            .iter_mut()
+            .find(|existing| existing.binding == binding.binding)
+        {
+            Some(existing)
+                if existing.descriptor_type == binding.descriptor_type
+                    && existing.descriptor_count == binding.descriptor_count =>
+            {
+                existing.stage_flags |= binding.stage_flags
+            }
+            Some(existing) => {
+                return Err(anyhow!(
+                    "Set {} binding {} is {} x {:?} in {:?} but {} x {:?} in {:?}",
+                    binding.set,
+                    binding.binding,
+                    existing.descriptor_count,
+                    existing.descriptor_type,
+                    existing.stage_flags,
+                    binding.descriptor_count,
+                    binding.descriptor_type,
+                    binding.stage_flags
+                ))
+            }
+            None => set_bindings.push(*binding),
+        }
+    }
+
+    let set_count = sets.keys().last().map_or(0, |&set| set + 1);
+    let mut descriptor_set_layouts = vec![];
+    for set in 0..set_count {
+        let layout_bindings: Vec<DescriptorSetLayoutBinding> = sets
+            .get(&set)
+            .map(|bindings| {
+                bindings
+                    .iter()
+                    .map(|binding| {
+                        DescriptorSetLayoutBinding::builder()
+                            .binding(binding.binding)
+                            .descriptor_type(binding.descriptor_type)
+                            .descriptor_count(binding.descriptor_count)
+                            .stage_flags(binding.stage_flags)
+                            .build()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        match create_descriptor_set_layout(device, &layout_bindings) {
+            Ok(descriptor_set_layout) => descriptor_set_layouts.push(descriptor_set_layout),
+            Err(error) => {
+                destroy_descriptor_set_layouts(device, &descriptor_set_layouts);
+                return Err(error);
+            }
+        }
+    }
+
+    let push_constant_ranges: Vec<PushConstantRange> = reflections
+        .iter()
+        .filter_map(|reflection| reflection.push_constant_range)
+        .collect();
+    match create_pipeline_layout(device, &descriptor_set_layouts, &push_constant_ranges) {
+        Ok(pipeline_layout) => Ok((descriptor_set_layouts, pipeline_layout)),
+        Err(error) => {
+            destroy_descriptor_set_layouts(device, &descriptor_set_layouts);
+            Err(error)
+        }
+    }
+}
+
+pub fn check_vertex_inputs(
+    reflection: &ShaderReflection,
+    attribute_descriptions: &[VertexInputAttributeDescription],
+) -> Result<()> {
+    let missing_locations: Vec<u32> = reflection
+        .input_locations
+        .iter()
+        .copied()
+        .filter(|&location| {
+            !attribute_descriptions
+                .iter()
+                .any(|attribute| attribute.location == location)
+        })
+        .collect();
+    if !missing_locations.is_empty() {
+        return Err(anyhow!(
+            "Vertex shader reads input locations {:?} but the vertex attributes only provide {:?}",
+            missing_locations,
+            attribute_descriptions
+                .iter()
+                .map(|attribute| attribute.location)
+                .collect::<Vec<u32>>()
+        ));
+    }
+
+    Ok(())
+}
+
+fn destroy_descriptor_set_layouts(device: &Device, descriptor_set_layouts: &[DescriptorSetLayout]) {
+    for &descriptor_set_layout in descriptor_set_layouts {
+        unsafe { device.destroy_descriptor_set_layout(descriptor_set_layout, None) };
+    }
+}
+
+/// Reflects the named entry point. In a module with several entry points only the variables
+/// the entry point's interface and call tree refer to are part of its reflection.
+pub fn reflect_shader(shader_code: &[u32], entry_point: &str) -> Result<ShaderReflection> {
+    let module =
+        load_words(shader_code).map_err(|error| anyhow!("Failed to parse SPIR-V: {}", error))?;
+    let reflector = Reflector::new(&module);
+    let entry_point_instruction = reflector.entry_point(entry_point)?;
+    let stage = Reflector::stage(entry_point_instruction)?;
+    let used_ids = match module.entry_points.len() {
+        1 => None,
+        _ => Some(reflector.used_ids(entry_point_instruction)?),
+    };
+
+    let mut bindings = vec![];
+    let mut push_constant_range = None;
+    let mut input_locations = vec![];
+    for variable in module
+        .types_global_values
+        .iter()
+        .filter(|instruction| instruction.class.opcode == Op::Variable)
+    {
+        let variable_id = variable.result_id.unwrap_or_default();
+        if used_ids
+            .as_ref()
+            .is_some_and(|used_ids| !used_ids.contains(&variable_id))
+        {
+            continue;
+        }
+        let (storage_class, type_id) =
+            reflector.pointee(variable.result_type.unwrap_or_default())?;
+        match storage_class {
+            StorageClass::UniformConstant | StorageClass::Uniform | StorageClass::StorageBuffer => {
+                let (descriptor_type, descriptor_count) =
+                    reflector.descriptor_type(storage_class, type_id)?;
+                bindings.push(ReflectedBinding {
+                    set: reflector
+                        .decoration(variable_id, Decoration::DescriptorSet)
+                        .unwrap_or(0),
+                    binding: reflector
+                        .decoration(variable_id, Decoration::Binding)
+                        .ok_or_else(|| {
+                            anyhow!("Resource variable {} has no binding", variable_id)
+                        })?,
+                    descriptor_type,
+                    descriptor_count,
+                    stage_flags: stage,
+                });
+            }
+            StorageClass::PushConstant => {
+                let (offset, size) = reflector.struct_extent(type_id)?;
+                push_constant_range = Some(
+                    PushConstantRange::builder()
+                        .stage_flags(stage)
+                        .offset(offset)
+                        .size(size.next_multiple_of(4))
+                        .build(),
+                );
+            }
+            StorageClass::Input => {
+                if let Some(location) = reflector.decoration(variable_id, Decoration::Location) {
+                    input_locations.push(location);
+                }
+            }
+            _ => {}
+        }
+    }
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    input_locations.sort_unstable();
+
+    Ok(ShaderReflection {
+        stage,
+        entry_point: entry_point.to_string(),
+        bindings,
+        push_constant_range,
+        input_locations,
+        local_size: reflector.local_size(entry_point_instruction),
+    })
+}
+
+struct Reflector<'module> {
+    module: &'module Module,
+    definitions: HashMap<Word, &'module Instruction>,
+}
+
+impl<'module> Reflector<'module> {
+    fn new(module: &'module Module) -> Reflector<'module> {
+        let definitions = module
+            .types_global_values
+            .iter()
+            .filter_map(|instruction| Some((instruction.result_id?, instruction)))
+            .collect();
+
+        Reflector {
+            module,
+            definitions,
+        }
+    }
+
+    fn entry_point(&self, name: &str) -> Result<&'module Instruction> {
+        self.module
+            .entry_points
+            .iter()
+            .find(|entry_point| {
+                matches!(entry_point.operands.get(2), Some(Operand::LiteralString(entry_point_name)) if entry_point_name == name)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Shader module has no entry point {}, it contains {}",
+                    name,
+                    self.describe_entry_points()
+                )
+            })
+    }
+
+    fn describe_entry_points(&self) -> String {
+        let entry_points = self
+            .module
+            .entry_points
+            .iter()
+            .filter_map(|entry_point| match entry_point.operands.as_slice() {
+                [Operand::ExecutionModel(execution_model), _, Operand::LiteralString(name), ..] => {
+                    Some(format!("{} ({:?})", name, execution_model))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        match entry_points.is_empty() {
+            true => "none".to_string(),
+            false => entry_points.join(", "),
+        }
+    }
+
+    /// The ids an entry point's interface and the functions it calls refer to.
+    fn used_ids(&self, entry_point: &Instruction) -> Result<HashSet<Word>> {
+        let mut used_ids = HashSet::new();
+        for operand in entry_point.operands.iter().skip(3) {
+            if let Operand::IdRef(id) = operand {
+                used_ids.insert(*id);
+            }
+        }
+
+        let mut pending_functions = vec![Reflector::id_operand(entry_point, 1)?];
+        let mut visited_functions = HashSet::new();
+        while let Some(function_id) = pending_functions.pop() {
+            if !visited_functions.insert(function_id) {
+                continue;
+            }
+            let function = self
+                .module
+                .functions
+                .iter()
+                .find(|function| function.def_id() == Some(function_id))
+                .ok_or_else(|| anyhow!("SPIR-V function {} is not defined", function_id))?;
+            for instruction in function.all_inst_iter() {
+                if instruction.class.opcode == Op::FunctionCall {
+                    pending_functions.push(Reflector::id_operand(instruction, 0)?);
+                }
+                for operand in &instruction.operands {
+                    if let Operand::IdRef(id) = operand {
+                        used_ids.insert(*id);
+                    }
+                }
+            }
+        }
+
+        Ok(used_ids)
+    }
+
+    fn stage(entry_point: &Instruction) -> Result<ShaderStageFlags> {
+        match entry_point.operands.first() {
+            Some(Operand::ExecutionModel(ExecutionModel::Vertex)) => Ok(ShaderStageFlags::VERTEX),
+            Some(Operand::ExecutionModel(ExecutionModel::Fragment)) => {
+                Ok(ShaderStageFlags::FRAGMENT)
+            }
+            Some(Operand::ExecutionModel(ExecutionModel::GLCompute)) => {
+                Ok(ShaderStageFlags::COMPUTE)
+            }
+            Some(Operand::ExecutionModel(ExecutionModel::Geometry)) => {
+                Ok(ShaderStageFlags::GEOMETRY)
+            }
+            Some(Operand::ExecutionModel(ExecutionModel::TessellationControl)) => {
+                Ok(ShaderStageFlags::TESSELLATION_CONTROL)
+            }
+            Some(Operand::ExecutionModel(ExecutionModel::TessellationEvaluation)) => {
+                Ok(ShaderStageFlags::TESSELLATION_EVALUATION)
+            }
+            other => Err(anyhow!("Unsupported shader execution model {:?}", other)),
+        }
+    }
+
+    fn local_size(&self, entry_point: &Instruction) -> [u32; 3] {
+        let function = entry_point.operands.get(1);
+        self.module
+            .execution_modes
+            .iter()
+            .find(|instruction| {
+                instruction.operands.first() == function
+                    && instruction.operands.get(1)
+                        == Some(&Operand::ExecutionMode(ExecutionMode::LocalSize))
+            })
+            .map(|instruction| {
+                let mut local_size = [1; 3];
+                for (size, operand) in local_size.iter_mut().zip(&instruction.operands[2..]) {
+                    if let Operand::LiteralInt32(value) = operand {
+                        *size = *value;
+                    }
+                }
+                local_size
+            })
+            .unwrap_or([1, 1, 1])
+    }
+
+    fn definition(&self, id: Word) -> Result<&'module Instruction> {
+        self.definitions
+            .get(&id)
+            .copied()
+            .ok_or_else(|| anyhow!("SPIR-V id {} is not defined", id))
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<u32> {
+        let operands = self.decoration_operands(Op::Decorate, &[Operand::IdRef(id)], decoration)?;
+        match operands.first() {
+            Some(Operand::LiteralInt32(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn has_decoration(&self, id: Word, decoration: Decoration) -> bool {
+        self.decoration_operands(Op::Decorate, &[Operand::IdRef(id)], decoration)
+            .is_some()
+    }
+
+    fn member_decoration(&self, id: Word, member: u32, decoration: Decoration) -> Option<u32> {
+        let target = [Operand::IdRef(id), Operand::LiteralInt32(member)];
+        let operands = self.decoration_operands(Op::MemberDecorate, &target, decoration)?;
+        match operands.first() {
+            Some(Operand::LiteralInt32(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The operands following `decoration` in the first matching decorate instruction.
+    fn decoration_operands(
+        &self,
+        opcode: Op,
+        target: &[Operand],
+        decoration: Decoration,
+    ) -> Option<&'module [Operand]> {
+        self.module
+            .annotations
+            .iter()
+            .filter(|annotation| annotation.class.opcode == opcode)
+            .find_map(|annotation| {
+                match annotation.operands.strip_prefix(target)?.split_first()? {
+                    (Operand::Decoration(found), operands) if *found == decoration => {
+                        Some(operands)
+                    }
+                    _ => None,
+                }
+            })
+    }
+
+    fn pointee(&self, pointer_type_id: Word) -> Result<(StorageClass, Word)> {
+        let pointer_type = self.definition(pointer_type_id)?;
+        match pointer_type.operands.as_slice() {
+            [Operand::StorageClass(storage_class), Operand::IdRef(type_id)] => {
+                Ok((*storage_class, *type_id))
+            }
+            _ => Err(anyhow!(
+                "SPIR-V id {} is not a pointer type",
+                pointer_type_id
+            )),
+        }
+    }
+
+    fn id_operand(instruction: &Instruction, index: usize) -> Result<Word> {
+        match instruction.operands.get(index) {
+            Some(Operand::IdRef(id)) => Ok(*id),
+            other => Err(anyhow!(
+                "Expected an id as operand {} of {:?}, found {:?}",
+                index,
+                instruction.class.opcode,
+                other
+            )),
+        }
+    }
+
+    fn literal_operand(instruction: &Instruction, index: usize) -> Result<u32> {
+        match instruction.operands.get(index) {
+            Some(Operand::LiteralInt32(value)) => Ok(*value),
+            other => Err(anyhow!(
+                "Expected a literal as operand {} of {:?}, found {:?}",
+                index,
+                instruction.class.opcode,
+                other
+            )),
+        }
+    }
+
+    fn constant_value(&self, constant_id: Word) -> Result<u32> {
+        Reflector::literal_operand(self.definition(constant_id)?, 0)
+    }
+
+    fn descriptor_type(
+        &self,
+        storage_class: StorageClass,
+        type_id: Word,
+    ) -> Result<(DescriptorType, u32)> {
+        let definition = self.definition(type_id)?;
+        match definition.class.opcode {
+            Op::TypeArray => {
+                let element_type_id = Reflector::id_operand(definition, 0)?;
+                let length = self.constant_value(Reflector::id_operand(definition, 1)?)?;
+                let (descriptor_type, _) = self.descriptor_type(storage_class, element_type_id)?;
+                Ok((descriptor_type, length))
+            }
+            Op::TypeRuntimeArray => {
+                let element_type_id = Reflector::id_operand(definition, 0)?;
+                let (descriptor_type, _) = self.descriptor_type(storage_class, element_type_id)?;
+                Ok((descriptor_type, 1))
+            }
+            Op::TypeSampledImage => Ok((DescriptorType::COMBINED_IMAGE_SAMPLER, 1)),
+            Op::TypeSampler => Ok((DescriptorType::SAMPLER, 1)),
+            Op::TypeImage => {
+                let dim = match definition.operands.get(1) {
+                    Some(Operand::Dim(dim)) => *dim,
+                    other => return Err(anyhow!("Image type without a dimension: {:?}", other)),
+                };
+                let sampled = Reflector::literal_operand(definition, 5)?;
+                let descriptor_type = match (dim, sampled) {
+                    (Dim::DimSubpassData, _) => DescriptorType::INPUT_ATTACHMENT,
+                    (Dim::DimBuffer, 2) => DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (Dim::DimBuffer, _) => DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (_, 2) => DescriptorType::STORAGE_IMAGE,
+                    _ => DescriptorType::SAMPLED_IMAGE,
+                };
+                Ok((descriptor_type, 1))
+            }
+            Op::TypeStruct
+                if storage_class == StorageClass::StorageBuffer
+                    || self.has_decoration(type_id, Decoration::BufferBlock) =>
+            {
+                Ok((DescriptorType::STORAGE_BUFFER, 1))
+            }
+            Op::TypeStruct => Ok((DescriptorType::UNIFORM_BUFFER, 1)),
+            other => Err(anyhow!("Unsupported resource type {:?}", other)),
+        }
+    }
+
+    /// Offset of the first member and size up to the end of the last member of a block.
+    fn struct_extent(&self, struct_type_id: Word) -> Result<(u32, u32)> {
+        let definition = self.definition(struct_type_id)?;
+        let mut start = u32::MAX;
+        let mut end = 0;
+        for (member, _) in definition.operands.iter().enumerate() {
+            let member_type_id = Reflector::id_operand(definition, member)?;
+            let offset = self
+                .member_decoration(struct_type_id, member as u32, Decoration::Offset)
+                .unwrap_or(0);
+            let size = match self.member_decoration(
+                struct_type_id,
+                member as u32,
+                Decoration::MatrixStride,
+            ) {
+                Some(matrix_stride) => {
+                    matrix_stride * Reflector::literal_operand(self.definition(member_type_id)?, 1)?
+                }
+                None => self.type_size(member_type_id)?,
+            };
+            start = start.min(offset);
+            end = end.max(offset + size);
+        }
+
+        Ok((start.min(end), end - start.min(end)))
+    }
+
+    fn type_size(&self, type_id: Word) -> Result<u32> {
+        let definition = self.definition(type_id)?;
+        match definition.class.opcode {
+            Op::TypeBool => Ok(4),
+            Op::TypeInt | Op::TypeFloat => Ok(Reflector::literal_operand(definition, 0)? / 8),
+            Op::TypeVector | Op::TypeMatrix => {
+                let component_size = self.type_size(Reflector::id_operand(definition, 0)?)?;
+                Ok(component_size * Reflector::literal_operand(definition, 1)?)
+            }
+            Op::TypeArray => {
+                let length = self.constant_value(Reflector::id_operand(definition, 1)?)?;
+                let stride = match self.decoration(type_id, Decoration::ArrayStride) {
+                    Some(stride) => stride,
+                    None => self.type_size(Reflector::id_operand(definition, 0)?)?,
+                };
+                Ok(stride * length)
+            }
+            Op::TypeStruct => {
+                let (offset, size) = self.struct_extent(type_id)?;
+                Ok(offset + size)
+            }
+            other => Err(anyhow!("Can't determine the size of {:?}", other)),
+        }
+    }
+}
@@ -1,40 +1,315 @@
 use anyhow::Result;
+use ash::extensions::khr::DynamicRendering;
 use ash::vk::{
-    AttachmentDescription, AttachmentDescriptionFlags, AttachmentLoadOp, AttachmentReference,
-    AttachmentStoreOp, Format, ImageLayout, PipelineBindPoint, RenderPass, RenderPassCreateFlags,
-    RenderPassCreateInfo, SampleCountFlags, SubpassDescription, SubpassDescriptionFlags,
+    AccessFlags, AttachmentDescription, AttachmentDescriptionFlags, AttachmentLoadOp,
+    AttachmentReference, AttachmentStoreOp, ClearValue, CommandBuffer, DependencyFlags, Extent2D,
+    Format, Framebuffer, ImageLayout, ImageView, Offset2D, PipelineBindPoint, PipelineStageFlags,
+    Rect2D, RenderPass, RenderPassBeginInfo, RenderPassCreateFlags, RenderPassCreateInfo,
+    RenderingAttachmentInfo, RenderingInfo, SampleCountFlags, SubpassContents, SubpassDependency,
+    SubpassDescription, SubpassDescriptionFlags, Viewport, SUBPASS_EXTERNAL,
 };
 use ash::Device;
 
-pub fn create_render_pass(device: &Device, surface_format: Format) -> Result<RenderPass> {
-    let color_attachment = AttachmentDescription::builder()
-        .flags(AttachmentDescriptionFlags::empty())
-        .format(surface_format)
-        .samples(SampleCountFlags::TYPE_1)
-        .load_op(AttachmentLoadOp::CLEAR)
-        .store_op(AttachmentStoreOp::STORE)
-        .stencil_load_op(AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(AttachmentStoreOp::DONT_CARE)
-        .initial_layout(ImageLayout::UNDEFINED)
-        .final_layout(ImageLayout::PRESENT_SRC_KHR)
-        .build();
+/// What a graphics pipeline renders into. With dynamic rendering there is no render pass, the
+/// pipeline only names the formats of the attachments bound when recording. The target doesn't
+/// own its render pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenderTarget {
+    RenderPass(RenderPass),
+    Dynamic {
+        color_formats: Vec<Format>,
+        depth_format: Option<Format>,
+    },
+}
+
+impl RenderTarget {
+    pub fn render_pass(&self) -> Option<RenderPass> {
+        match self {
+            RenderTarget::RenderPass(render_pass) => Some(*render_pass),
+            RenderTarget::Dynamic { .. } => None,
+        }
+    }
+}
+
+/// The dependencies of the post-process swapchain pass. The image-available semaphore is waited
+/// on in the color attachment output stage, so the layout transition and the clear of the
+/// swapchain image in subpass 1 have to wait for that stage too. Subpass 1 also waits for
+/// subpass 0 to write its input attachment.
+fn post_process_subpass_dependencies() -> [SubpassDependency; 3] {
+    [
+        SubpassDependency::builder()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(1)
+            .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(AccessFlags::empty())
+            .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .build(),
+        // The input attachment is shared by the frames in flight, the previous frame's reads
+        // must finish before it is cleared
+        SubpassDependency::builder()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(AccessFlags::empty())
+            .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .build(),
+        SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(1)
+            .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(AccessFlags::INPUT_ATTACHMENT_READ)
+            .dependency_flags(DependencyFlags::BY_REGION)
+            .build(),
+    ]
+}
+
+/// The swapchain pass with a post-process subpass. Subpass 0 renders into an intermediate
+/// attachment of `intermediate_format`, and subpass 1 reads it as an input attachment to write
+/// the swapchain image. The intermediate is attachment 1 of the framebuffers. Passes with a
+/// single subpass come from the context's render pass cache instead.
+pub fn create_post_process_render_pass(
+    device: &Device,
+    surface_format: Format,
+    intermediate_format: Format,
+) -> Result<RenderPass> {
+    with_post_process_render_pass_info(surface_format, intermediate_format, |create_info| {
+        Ok(unsafe { device.create_render_pass(create_info, None) }?)
+    })
+}
 
-    let color_attachment_ref = AttachmentReference::builder()
+/// Calls `create` with the create info of the post-process pass, which is only valid while the
+/// arrays it points at, locals of this function, are alive.
+fn with_post_process_render_pass_info<R>(
+    surface_format: Format,
+    intermediate_format: Format,
+    create: impl FnOnce(&RenderPassCreateInfo) -> R,
+) -> R {
+    let attachments = [
+        AttachmentDescription::builder()
+            .flags(AttachmentDescriptionFlags::empty())
+            .format(surface_format)
+            .samples(SampleCountFlags::TYPE_1)
+            .load_op(AttachmentLoadOp::CLEAR)
+            .store_op(AttachmentStoreOp::STORE)
+            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .final_layout(ImageLayout::PRESENT_SRC_KHR)
+            .build(),
+        AttachmentDescription::builder()
+            .format(intermediate_format)
+            .samples(SampleCountFlags::TYPE_1)
+            .load_op(AttachmentLoadOp::CLEAR)
+            .store_op(AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .final_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build(),
+    ];
+
+    let color_attachment_refs = [AttachmentReference::builder()
         .attachment(0)
         .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-        .build();
+        .build()];
+    let intermediate_attachment_refs = [AttachmentReference::builder()
+        .attachment(1)
+        .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build()];
+    let input_attachment_refs = [AttachmentReference::builder()
+        .attachment(1)
+        .layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .build()];
 
-    let subpass = SubpassDescription::builder()
-        .flags(SubpassDescriptionFlags::empty())
-        .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
-        .color_attachments(&[color_attachment_ref])
-        .build();
+    let subpasses = [
+        SubpassDescription::builder()
+            .flags(SubpassDescriptionFlags::empty())
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(&intermediate_attachment_refs)
+            .build(),
+        SubpassDescription::builder()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .input_attachments(&input_attachment_refs)
+            .color_attachments(&color_attachment_refs)
+            .build(),
+    ];
+    let dependencies = post_process_subpass_dependencies();
 
     let render_pass_create_info = RenderPassCreateInfo::builder()
         .flags(RenderPassCreateFlags::empty())
-        .attachments(&[color_attachment])
-        .subpasses(&[subpass])
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies)
+        .build();
+
+    create(&render_pass_create_info)
+}
+
+pub fn record_render_pass<F>(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    render_pass: RenderPass,
+    framebuffer: Framebuffer,
+    extent: Extent2D,
+    clear_values: &[ClearValue],
+    record: F,
+) where
+    F: FnOnce(&Device, CommandBuffer),
+{
+    let render_pass_begin_info = RenderPassBeginInfo::builder()
+        .render_pass(render_pass)
+        .framebuffer(framebuffer)
+        .render_area(Rect2D {
+            offset: Offset2D { x: 0, y: 0 },
+            extent,
+        })
+        .clear_values(clear_values)
         .build();
 
-    Ok(unsafe { device.create_render_pass(&render_pass_create_info, None) }?)
+    unsafe {
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            SubpassContents::INLINE,
+        )
+    };
+    record_viewport_and_scissor(device, command_buffer, extent);
+    record(device, command_buffer);
+    unsafe { device.cmd_end_render_pass(command_buffer) };
+}
+
+/// An attachment of a dynamic rendering pass that is cleared when rendering begins.
+pub fn rendering_attachment_info(
+    image_view: ImageView,
+    image_layout: ImageLayout,
+    store_op: AttachmentStoreOp,
+    clear_value: ClearValue,
+) -> RenderingAttachmentInfo {
+    RenderingAttachmentInfo::builder()
+        .image_view(image_view)
+        .image_layout(image_layout)
+        .load_op(AttachmentLoadOp::CLEAR)
+        .store_op(store_op)
+        .clear_value(clear_value)
+        .build()
+}
+
+/// The dynamic rendering counterpart of `record_render_pass`. Nothing transitions the
+/// attachments here, they must already be in the layouts their infos name.
+pub fn record_rendering<F>(
+    device: &Device,
+    dynamic_rendering: &DynamicRendering,
+    command_buffer: CommandBuffer,
+    color_attachments: &[RenderingAttachmentInfo],
+    depth_attachment: Option<&RenderingAttachmentInfo>,
+    extent: Extent2D,
+    record: F,
+) where
+    F: FnOnce(&Device, CommandBuffer),
+{
+    let mut rendering_info_builder = RenderingInfo::builder()
+        .render_area(Rect2D {
+            offset: Offset2D { x: 0, y: 0 },
+            extent,
+        })
+        .layer_count(1)
+        .color_attachments(color_attachments);
+    if let Some(depth_attachment) = depth_attachment {
+        rendering_info_builder = rendering_info_builder.depth_attachment(depth_attachment);
+    }
+    let rendering_info = rendering_info_builder.build();
+
+    unsafe { dynamic_rendering.cmd_begin_rendering(command_buffer, &rendering_info) };
+    record_viewport_and_scissor(device, command_buffer, extent);
+    record(device, command_buffer);
+    unsafe { dynamic_rendering.cmd_end_rendering(command_buffer) };
+}
+
+pub fn record_viewport_and_scissor(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    extent: Extent2D,
+) {
+    let viewport = Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)
+        .build();
+    let scissor = Rect2D {
+        offset: Offset2D { x: 0, y: 0 },
+        extent,
+    };
+
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice::from_raw_parts;
+
+    use super::*;
+
+    /// Reads a pointer and count pair of a create info, an empty slice for a null pointer.
+    unsafe fn read<'a, T>(pointer: *const T, count: u32) -> &'a [T] {
+        match pointer.is_null() {
+            true => &[],
+            false => from_raw_parts(pointer, count as usize),
+        }
+    }
+
+    #[test]
+    fn post_process_render_pass_info_points_at_live_arrays() {
+        with_post_process_render_pass_info(
+            Format::B8G8R8A8_SRGB,
+            Format::R16G16B16A16_SFLOAT,
+            |create_info| unsafe {
+                let attachments = read(create_info.p_attachments, create_info.attachment_count);
+                let formats = attachments
+                    .iter()
+                    .map(|attachment| attachment.format)
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    formats,
+                    [Format::B8G8R8A8_SRGB, Format::R16G16B16A16_SFLOAT]
+                );
+
+                let subpasses = read(create_info.p_subpasses, create_info.subpass_count);
+                assert_eq!(subpasses.len(), 2);
+                let color_attachments = subpasses
+                    .iter()
+                    .map(|subpass| {
+                        read(subpass.p_color_attachments, subpass.color_attachment_count)[0]
+                            .attachment
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(color_attachments, [1, 0]);
+                let input_attachment = read(
+                    subpasses[1].p_input_attachments,
+                    subpasses[1].input_attachment_count,
+                )[0];
+                assert_eq!(input_attachment.attachment, 1);
+                assert_eq!(
+                    input_attachment.layout,
+                    ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                );
+
+                let dependencies = read(create_info.p_dependencies, create_info.dependency_count);
+                assert_eq!(dependencies.len(), 3);
+                assert_eq!(dependencies[2].src_subpass, 0);
+                assert_eq!(dependencies[2].dst_subpass, 1);
+            },
+        );
+    }
 }
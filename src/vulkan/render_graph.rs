@@ -0,0 +1,892 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, CommandBuffer, DependencyFlags, Extent2D, Format, Image, ImageAspectFlags,
+    ImageLayout, ImageMemoryBarrier, ImageSubresourceRange, ImageUsageFlags, ImageView,
+    PipelineStageFlags, QUEUE_FAMILY_IGNORED,
+};
+use ash::Device;
+use log::{debug, info};
+
+use crate::constants::RENDER_GRAPH_LABEL_COLOR;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::image::{ImageDesc, PistonImage};
+
+/// Only writes have to be made available to later accesses.
+const WRITE_ACCESS_MASK: AccessFlags = AccessFlags::from_raw(
+    AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | AccessFlags::SHADER_WRITE.as_raw()
+        | AccessFlags::TRANSFER_WRITE.as_raw(),
+);
+
+/// How a pass uses an image. Each access has one layout, so a pass declares an image once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthAttachment,
+    FragmentSampled,
+    ComputeSampled,
+    /// Read and written by compute shaders
+    ComputeStorage,
+    TransferSrc,
+    TransferDst,
+    Present,
+}
+
+impl ImageAccess {
+    pub fn layout(self) -> ImageLayout {
+        match self {
+            ImageAccess::ColorAttachment => ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageAccess::DepthAttachment => ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageAccess::FragmentSampled | ImageAccess::ComputeSampled => {
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+            ImageAccess::ComputeStorage => ImageLayout::GENERAL,
+            ImageAccess::TransferSrc => ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageAccess::TransferDst => ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageAccess::Present => ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    pub fn stage(self) -> PipelineStageFlags {
+        match self {
+            ImageAccess::ColorAttachment => PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ImageAccess::DepthAttachment => {
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            ImageAccess::FragmentSampled => PipelineStageFlags::FRAGMENT_SHADER,
+            ImageAccess::ComputeSampled | ImageAccess::ComputeStorage => {
+                PipelineStageFlags::COMPUTE_SHADER
+            }
+            ImageAccess::TransferSrc | ImageAccess::TransferDst => PipelineStageFlags::TRANSFER,
+            ImageAccess::Present => PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    pub fn access_mask(self) -> AccessFlags {
+        match self {
+            ImageAccess::ColorAttachment => {
+                AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            ImageAccess::DepthAttachment => {
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            ImageAccess::FragmentSampled | ImageAccess::ComputeSampled => AccessFlags::SHADER_READ,
+            ImageAccess::ComputeStorage => AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+            ImageAccess::TransferSrc => AccessFlags::TRANSFER_READ,
+            ImageAccess::TransferDst => AccessFlags::TRANSFER_WRITE,
+            ImageAccess::Present => AccessFlags::empty(),
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            ImageAccess::ColorAttachment
+                | ImageAccess::DepthAttachment
+                | ImageAccess::ComputeStorage
+                | ImageAccess::TransferDst
+        )
+    }
+}
+
+/// The handles a pass records with, for imported and transient images alike.
+#[derive(Clone, Copy, Debug)]
+pub struct GraphImage {
+    pub image: Image,
+    pub view: ImageView,
+    pub format: Format,
+    pub extent: Extent2D,
+    pub subresource_range: ImageSubresourceRange,
+}
+
+impl From<&PistonImage> for GraphImage {
+    fn from(image: &PistonImage) -> GraphImage {
+        GraphImage {
+            image: image.image,
+            view: image.view,
+            format: image.format,
+            extent: image.extent,
+            subresource_range: image.subresource_range,
+        }
+    }
+}
+
+/// An image the graph takes from the transient image pool instead of the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientImageDesc {
+    pub extent: Extent2D,
+    pub format: Format,
+    pub usage: ImageUsageFlags,
+    pub aspect_mask: ImageAspectFlags,
+}
+
+impl TransientImageDesc {
+    pub fn color_attachment(extent: Extent2D, format: Format) -> TransientImageDesc {
+        TransientImageDesc {
+            extent,
+            format,
+            usage: ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+            aspect_mask: ImageAspectFlags::COLOR,
+        }
+    }
+
+    pub fn depth_attachment(extent: Extent2D, format: Format) -> TransientImageDesc {
+        TransientImageDesc {
+            extent,
+            format,
+            usage: ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | ImageUsageFlags::SAMPLED,
+            aspect_mask: ImageAspectFlags::DEPTH,
+        }
+    }
+}
+
+/// One layout transition or memory dependency recorded before a pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageTransition {
+    pub image: String,
+    pub old_layout: ImageLayout,
+    pub new_layout: ImageLayout,
+    pub src_stage: PipelineStageFlags,
+    pub dst_stage: PipelineStageFlags,
+    pub src_access_mask: AccessFlags,
+    pub dst_access_mask: AccessFlags,
+}
+
+/// What `RenderGraph::compile` derives from the declarations, without touching the device.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderGraphPlan {
+    /// Pass names in recording order, each with the transitions recorded before it
+    pub passes: Vec<(String, Vec<ImageTransition>)>,
+    /// Transitions of imported images to their final layouts, after the last pass
+    pub final_transitions: Vec<ImageTransition>,
+}
+
+/// The images of a graph, by name, while its passes record.
+pub struct GraphResources {
+    images: HashMap<String, GraphImage>,
+}
+
+impl GraphResources {
+    pub fn image(&self, name: &str) -> Result<GraphImage> {
+        self.images
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("Render graph has no image named {}", name))
+    }
+}
+
+type RecordPass<'a> = Box<dyn FnOnce(&Device, CommandBuffer, &GraphResources) -> Result<()> + 'a>;
+
+enum ImageSource {
+    Imported {
+        image: GraphImage,
+        initial_layout: ImageLayout,
+        final_layout: Option<ImageLayout>,
+    },
+    Transient(TransientImageDesc),
+}
+
+struct GraphPass<'a> {
+    name: String,
+    accesses: Vec<(String, ImageAccess)>,
+    record: RecordPass<'a>,
+}
+
+/// Where an image was left by the passes compiled so far.
+struct ImageState {
+    layout: ImageLayout,
+    /// The last write, until a later write replaces it
+    write: Option<(PipelineStageFlags, AccessFlags)>,
+    /// Stages and accesses the last write was made visible to
+    visible_stages: PipelineStageFlags,
+    visible_access_mask: AccessFlags,
+    /// Stages that read the image since the last write
+    read_stages: PipelineStageFlags,
+}
+
+/// Passes declare the images they read and write and a closure that records them. The graph
+/// orders the passes so that every writer of an image runs before its readers, writers of the
+/// same image keep the order they were added in. Before each pass it records the layout
+/// transitions and barriers its accesses need, so passes begin rendering with their attachments
+/// already in the declared layouts. Passes that use render pass objects must not change the
+/// layouts themselves.
+///
+/// Images are either imported, such as the swapchain image, or transient and taken from the
+/// context's pool, which keeps them for the next frame. Everything is recorded into one command
+/// buffer on one queue.
+pub struct RenderGraph<'a> {
+    images: Vec<(String, ImageSource)>,
+    passes: Vec<GraphPass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> RenderGraph<'a> {
+        RenderGraph {
+            images: vec![],
+            passes: vec![],
+        }
+    }
+
+    /// `final_layout` is the layout the image is left in after the last pass, `None` leaves it
+    /// in the layout of its last access.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        image: GraphImage,
+        initial_layout: ImageLayout,
+        final_layout: Option<ImageLayout>,
+    ) {
+        self.images.push((
+            name.to_string(),
+            ImageSource::Imported {
+                image,
+                initial_layout,
+                final_layout,
+            },
+        ));
+    }
+
+    /// The contents of transient images don't outlive the frame.
+    pub fn create_image(&mut self, name: &str, desc: TransientImageDesc) {
+        self.images
+            .push((name.to_string(), ImageSource::Transient(desc)));
+    }
+
+    pub fn add_pass<F>(&mut self, name: &str, accesses: &[(&str, ImageAccess)], record: F)
+    where
+        F: FnOnce(&Device, CommandBuffer, &GraphResources) -> Result<()> + 'a,
+    {
+        self.passes.push(GraphPass {
+            name: name.to_string(),
+            accesses: accesses
+                .iter()
+                .map(|&(image, access)| (image.to_string(), access))
+                .collect(),
+            record: Box::new(record),
+        });
+    }
+
+    pub fn compile(&self) -> Result<RenderGraphPlan> {
+        self.validate()?;
+        let order = self.pass_order()?;
+
+        let mut states = self
+            .images
+            .iter()
+            .map(|(name, source)| {
+                let layout = match source {
+                    ImageSource::Imported { initial_layout, .. } => *initial_layout,
+                    ImageSource::Transient(_) => ImageLayout::UNDEFINED,
+                };
+                // The previous frame, or whoever handed the image over, may still use it
+                let state = ImageState {
+                    layout,
+                    write: None,
+                    visible_stages: PipelineStageFlags::empty(),
+                    visible_access_mask: AccessFlags::empty(),
+                    read_stages: PipelineStageFlags::ALL_COMMANDS,
+                };
+                (name.as_str(), state)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut passes = vec![];
+        for index in order {
+            let pass = &self.passes[index];
+            let mut transitions = vec![];
+            for (image, access) in pass.accesses.iter() {
+                let state = states
+                    .get_mut(image.as_str())
+                    .ok_or_else(|| anyhow!("Render graph has no image named {}", image))?;
+                if let Some(transition) = transition_for_access(image, state, *access) {
+                    transitions.push(transition);
+                }
+            }
+            passes.push((pass.name.clone(), transitions));
+        }
+
+        let mut final_transitions = vec![];
+        for (name, source) in self.images.iter() {
+            if let ImageSource::Imported {
+                final_layout: Some(final_layout),
+                ..
+            } = source
+            {
+                let state = &states[name.as_str()];
+                if state.layout != *final_layout {
+                    final_transitions.push(ImageTransition {
+                        image: name.clone(),
+                        old_layout: state.layout,
+                        new_layout: *final_layout,
+                        src_stage: source_stage(state),
+                        dst_stage: PipelineStageFlags::BOTTOM_OF_PIPE,
+                        src_access_mask: state
+                            .write
+                            .map_or(AccessFlags::empty(), |(_, access_mask)| access_mask),
+                        dst_access_mask: AccessFlags::empty(),
+                    });
+                }
+            }
+        }
+
+        Ok(RenderGraphPlan {
+            passes,
+            final_transitions,
+        })
+    }
+
+    /// Compiles the graph, takes its transient images from the context's pool and records every
+    /// pass into `command_buffer`.
+    pub fn execute(self, context: &VulkanContext, command_buffer: CommandBuffer) -> Result<()> {
+        let plan = self.compile()?;
+        let device = &context.device;
+
+        let transient_descs = self
+            .images
+            .iter()
+            .filter_map(|(_, source)| match source {
+                ImageSource::Transient(desc) => Some(*desc),
+                ImageSource::Imported { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        let mut transient_images = context
+            .acquire_transient_images(&transient_descs)?
+            .into_iter();
+        let mut images = HashMap::new();
+        for (name, source) in self.images.iter() {
+            let image = match source {
+                ImageSource::Imported { image, .. } => *image,
+                ImageSource::Transient(_) => transient_images
+                    .next()
+                    .ok_or_else(|| anyhow!("Transient image pool returned too few images"))?,
+            };
+            images.insert(name.clone(), image);
+        }
+        let resources = GraphResources { images };
+
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+        for (name, transitions) in plan.passes.iter() {
+            record_transitions(device, command_buffer, &resources, transitions)?;
+            let pass = passes
+                .iter_mut()
+                .find(|pass| pass.as_ref().is_some_and(|pass| pass.name == *name))
+                .and_then(Option::take)
+                .ok_or_else(|| anyhow!("Render graph pass {} was recorded twice", name))?;
+            let _label = context.begin_label(command_buffer, name, RENDER_GRAPH_LABEL_COLOR);
+            (pass.record)(device, command_buffer, &resources)?;
+        }
+        record_transitions(device, command_buffer, &resources, &plan.final_transitions)?;
+        debug!(
+            "Recorded {} render graph passes with {} transitions",
+            plan.passes.len(),
+            plan.passes
+                .iter()
+                .map(|(_, transitions)| transitions.len())
+                .sum::<usize>()
+                + plan.final_transitions.len()
+        );
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (index, (name, _)) in self.images.iter().enumerate() {
+            if self.images[..index].iter().any(|(other, _)| other == name) {
+                return Err(anyhow!("Render graph image {} is declared twice", name));
+            }
+        }
+        for (index, pass) in self.passes.iter().enumerate() {
+            if self.passes[..index]
+                .iter()
+                .any(|other| other.name == pass.name)
+            {
+                return Err(anyhow!("Render graph pass {} is added twice", pass.name));
+            }
+            for (access_index, (image, _)) in pass.accesses.iter().enumerate() {
+                if !self.images.iter().any(|(name, _)| name == image) {
+                    return Err(anyhow!(
+                        "Pass {} uses image {}, which the render graph doesn't have",
+                        pass.name,
+                        image
+                    ));
+                }
+                if pass.accesses[..access_index]
+                    .iter()
+                    .any(|(other, _)| other == image)
+                {
+                    return Err(anyhow!(
+                        "Pass {} declares image {} more than once",
+                        pass.name,
+                        image
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A topological order of the passes, ties go to the pass added first.
+    fn pass_order(&self) -> Result<Vec<usize>> {
+        let mut dependencies = vec![BTreeSet::new(); self.passes.len()];
+        for (image, _) in self.images.iter() {
+            let accesses = self
+                .passes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, pass)| {
+                    pass.accesses
+                        .iter()
+                        .find(|(name, _)| name == image)
+                        .map(|&(_, access)| (index, access))
+                })
+                .collect::<Vec<_>>();
+            let writers = accesses
+                .iter()
+                .filter(|(_, access)| access.is_write())
+                .map(|&(index, _)| index)
+                .collect::<Vec<_>>();
+            for pair in writers.windows(2) {
+                dependencies[pair[1]].insert(pair[0]);
+            }
+            for &(index, access) in accesses.iter() {
+                if !access.is_write() {
+                    dependencies[index].extend(writers.iter().copied());
+                }
+            }
+        }
+
+        let mut order = vec![];
+        let mut remaining = (0..self.passes.len()).collect::<BTreeSet<_>>();
+        while let Some(&next) = remaining.iter().find(|&&index| {
+            dependencies[index]
+                .iter()
+                .all(|dependency| !remaining.contains(dependency))
+        }) {
+            remaining.remove(&next);
+            order.push(next);
+        }
+        if !remaining.is_empty() {
+            let names = remaining
+                .iter()
+                .map(|&index| self.passes[index].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(anyhow!(
+                "Render graph passes {} depend on each other",
+                names
+            ));
+        }
+
+        Ok(order)
+    }
+}
+
+impl<'a> Default for RenderGraph<'a> {
+    fn default() -> RenderGraph<'a> {
+        RenderGraph::new()
+    }
+}
+
+/// The transition `access` needs, if any, and the state it leaves the image in. Reads in the
+/// same layout as an earlier read don't need one once the last write is visible to them.
+fn transition_for_access(
+    image: &str,
+    state: &mut ImageState,
+    access: ImageAccess,
+) -> Option<ImageTransition> {
+    let layout = access.layout();
+    let stage = access.stage();
+    let access_mask = access.access_mask();
+    let visible = state.write.is_none()
+        || (state.visible_stages.contains(stage)
+            && state.visible_access_mask.contains(access_mask));
+    if layout == state.layout && !access.is_write() && visible {
+        state.read_stages |= stage;
+        return None;
+    }
+
+    let transition = ImageTransition {
+        image: image.to_string(),
+        old_layout: state.layout,
+        new_layout: layout,
+        src_stage: source_stage(state),
+        dst_stage: stage,
+        src_access_mask: state
+            .write
+            .map_or(AccessFlags::empty(), |(_, access_mask)| access_mask),
+        dst_access_mask: access_mask,
+    };
+    state.layout = layout;
+    if access.is_write() {
+        state.write = Some((stage, access_mask & WRITE_ACCESS_MASK));
+        state.visible_stages = PipelineStageFlags::empty();
+        state.visible_access_mask = AccessFlags::empty();
+        state.read_stages = PipelineStageFlags::empty();
+    } else {
+        state.visible_stages |= stage;
+        state.visible_access_mask |= access_mask;
+        state.read_stages |= stage;
+    }
+
+    Some(transition)
+}
+
+fn source_stage(state: &ImageState) -> PipelineStageFlags {
+    let stage = state
+        .write
+        .map_or(PipelineStageFlags::empty(), |(stage, _)| stage)
+        | state.read_stages;
+    match stage.is_empty() {
+        true => PipelineStageFlags::TOP_OF_PIPE,
+        false => stage,
+    }
+}
+
+fn record_transitions(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    resources: &GraphResources,
+    transitions: &[ImageTransition],
+) -> Result<()> {
+    if transitions.is_empty() {
+        return Ok(());
+    }
+
+    let mut src_stage = PipelineStageFlags::empty();
+    let mut dst_stage = PipelineStageFlags::empty();
+    let mut image_memory_barriers = vec![];
+    for transition in transitions {
+        let image = resources.image(&transition.image)?;
+        src_stage |= transition.src_stage;
+        dst_stage |= transition.dst_stage;
+        image_memory_barriers.push(
+            ImageMemoryBarrier::builder()
+                .old_layout(transition.old_layout)
+                .new_layout(transition.new_layout)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(image.image)
+                .subresource_range(image.subresource_range)
+                .src_access_mask(transition.src_access_mask)
+                .dst_access_mask(transition.dst_access_mask)
+                .build(),
+        );
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            DependencyFlags::empty(),
+            &[],
+            &[],
+            &image_memory_barriers,
+        )
+    };
+
+    Ok(())
+}
+
+/// Images for the transient resources of render graphs, kept between frames. Every graph that
+/// executes takes the images from the start of the pool, so a graph asking for two images of
+/// the same desc gets two different ones, and the same ones each frame.
+pub struct TransientImagePool {
+    images: HashMap<TransientImageDesc, Vec<PistonImage>>,
+}
+
+impl TransientImagePool {
+    pub fn new() -> TransientImagePool {
+        TransientImagePool {
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn acquire(
+        &mut self,
+        context: &VulkanContext,
+        descs: &[TransientImageDesc],
+    ) -> Result<Vec<GraphImage>> {
+        let mut taken = HashMap::<TransientImageDesc, usize>::new();
+        let mut acquired = vec![];
+        for desc in descs {
+            let index = taken.entry(*desc).or_default();
+            let images = self.images.entry(*desc).or_default();
+            if *index == images.len() {
+                images.push(PistonImage::new(
+                    context,
+                    &ImageDesc {
+                        usage: desc.usage,
+                        aspect_mask: desc.aspect_mask,
+                        ..ImageDesc::texture_2d(desc.extent, desc.format)
+                    },
+                    &format!("transient {:?} image {}", desc.format, images.len()),
+                )?);
+                info!(
+                    "Created transient {}x{} {:?} image {} for render graphs",
+                    desc.extent.width,
+                    desc.extent.height,
+                    desc.format,
+                    images.len()
+                );
+            }
+            acquired.push(GraphImage::from(&images[*index]));
+            *index += 1;
+        }
+
+        Ok(acquired)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn destroy_all(&mut self, device: &Device) {
+        info!("Destroying {} transient images", self.len());
+        for (_, images) in self.images.drain() {
+            for image in images {
+                image.destroy(device);
+            }
+        }
+    }
+}
+
+impl Default for TransientImagePool {
+    fn default() -> TransientImagePool {
+        TransientImagePool::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: Extent2D = Extent2D {
+        width: 64,
+        height: 64,
+    };
+
+    fn swapchain_image() -> GraphImage {
+        GraphImage {
+            image: Image::null(),
+            view: ImageView::null(),
+            format: Format::B8G8R8A8_SRGB,
+            extent: EXTENT,
+            subresource_range: ImageSubresourceRange::default(),
+        }
+    }
+
+    fn add_empty_pass(graph: &mut RenderGraph, name: &str, accesses: &[(&str, ImageAccess)]) {
+        graph.add_pass(name, accesses, |_, _, _| Ok(()));
+    }
+
+    /// Shadow, scene and post passes, added in reverse to check that the graph orders them.
+    fn shadow_scene_post_graph() -> RenderGraph<'static> {
+        let mut graph = RenderGraph::new();
+        graph.import_image(
+            "swapchain",
+            swapchain_image(),
+            ImageLayout::UNDEFINED,
+            Some(ImageLayout::PRESENT_SRC_KHR),
+        );
+        graph.create_image(
+            "shadow map",
+            TransientImageDesc::depth_attachment(EXTENT, Format::D32_SFLOAT),
+        );
+        graph.create_image(
+            "scene color",
+            TransientImageDesc::color_attachment(EXTENT, Format::R16G16B16A16_SFLOAT),
+        );
+        graph.create_image(
+            "scene depth",
+            TransientImageDesc::depth_attachment(EXTENT, Format::D32_SFLOAT),
+        );
+        add_empty_pass(
+            &mut graph,
+            "post",
+            &[
+                ("scene color", ImageAccess::FragmentSampled),
+                ("swapchain", ImageAccess::ColorAttachment),
+            ],
+        );
+        add_empty_pass(
+            &mut graph,
+            "scene",
+            &[
+                ("shadow map", ImageAccess::FragmentSampled),
+                ("scene color", ImageAccess::ColorAttachment),
+                ("scene depth", ImageAccess::DepthAttachment),
+            ],
+        );
+        add_empty_pass(
+            &mut graph,
+            "shadow",
+            &[("shadow map", ImageAccess::DepthAttachment)],
+        );
+        graph
+    }
+
+    fn pass_names(plan: &RenderGraphPlan) -> Vec<&str> {
+        plan.passes.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    fn transition<'a>(plan: &'a RenderGraphPlan, pass: &str, image: &str) -> &'a ImageTransition {
+        plan.passes
+            .iter()
+            .find(|(name, _)| name == pass)
+            .and_then(|(_, transitions)| {
+                transitions
+                    .iter()
+                    .find(|transition| transition.image == image)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let plan = shadow_scene_post_graph().compile().unwrap();
+        assert_eq!(pass_names(&plan), ["shadow", "scene", "post"]);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let mut graph = RenderGraph::new();
+        graph.create_image(
+            "a",
+            TransientImageDesc::color_attachment(EXTENT, Format::R8G8B8A8_UNORM),
+        );
+        graph.create_image(
+            "b",
+            TransientImageDesc::color_attachment(EXTENT, Format::R8G8B8A8_UNORM),
+        );
+        add_empty_pass(&mut graph, "second", &[("b", ImageAccess::ColorAttachment)]);
+        add_empty_pass(&mut graph, "first", &[("a", ImageAccess::ColorAttachment)]);
+        let plan = graph.compile().unwrap();
+        assert_eq!(pass_names(&plan), ["second", "first"]);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut graph = RenderGraph::new();
+        graph.create_image(
+            "a",
+            TransientImageDesc::color_attachment(EXTENT, Format::R8G8B8A8_UNORM),
+        );
+        graph.create_image(
+            "b",
+            TransientImageDesc::color_attachment(EXTENT, Format::R8G8B8A8_UNORM),
+        );
+        add_empty_pass(
+            &mut graph,
+            "x",
+            &[
+                ("a", ImageAccess::ColorAttachment),
+                ("b", ImageAccess::FragmentSampled),
+            ],
+        );
+        add_empty_pass(
+            &mut graph,
+            "y",
+            &[
+                ("b", ImageAccess::ColorAttachment),
+                ("a", ImageAccess::FragmentSampled),
+            ],
+        );
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn shadow_map_moves_from_attachment_to_sampled() {
+        let plan = shadow_scene_post_graph().compile().unwrap();
+
+        let shadow_write = transition(&plan, "shadow", "shadow map");
+        assert_eq!(shadow_write.old_layout, ImageLayout::UNDEFINED);
+        assert_eq!(
+            shadow_write.new_layout,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        );
+
+        let shadow_read = transition(&plan, "scene", "shadow map");
+        assert_eq!(
+            shadow_read.old_layout,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            shadow_read.new_layout,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(
+            shadow_read.src_stage,
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS
+        );
+        assert_eq!(
+            shadow_read.src_access_mask,
+            AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        );
+        assert_eq!(shadow_read.dst_stage, PipelineStageFlags::FRAGMENT_SHADER);
+        assert_eq!(shadow_read.dst_access_mask, AccessFlags::SHADER_READ);
+    }
+
+    #[test]
+    fn scene_color_is_sampled_by_post() {
+        let plan = shadow_scene_post_graph().compile().unwrap();
+        let scene_read = transition(&plan, "post", "scene color");
+        assert_eq!(scene_read.old_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(scene_read.new_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(
+            scene_read.src_stage,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            scene_read.src_access_mask,
+            AccessFlags::COLOR_ATTACHMENT_WRITE
+        );
+    }
+
+    #[test]
+    fn imported_image_ends_in_its_final_layout() {
+        let plan = shadow_scene_post_graph().compile().unwrap();
+        assert_eq!(plan.final_transitions.len(), 1);
+        let present = &plan.final_transitions[0];
+        assert_eq!(present.image, "swapchain");
+        assert_eq!(present.old_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(present.new_layout, ImageLayout::PRESENT_SRC_KHR);
+        assert_eq!(present.dst_stage, PipelineStageFlags::BOTTOM_OF_PIPE);
+    }
+
+    #[test]
+    fn second_read_in_the_same_layout_needs_no_transition() {
+        let mut graph = RenderGraph::new();
+        graph.create_image(
+            "color",
+            TransientImageDesc::color_attachment(EXTENT, Format::R8G8B8A8_UNORM),
+        );
+        add_empty_pass(
+            &mut graph,
+            "write",
+            &[("color", ImageAccess::ColorAttachment)],
+        );
+        add_empty_pass(
+            &mut graph,
+            "first read",
+            &[("color", ImageAccess::FragmentSampled)],
+        );
+        add_empty_pass(
+            &mut graph,
+            "second read",
+            &[("color", ImageAccess::FragmentSampled)],
+        );
+        let plan = graph.compile().unwrap();
+        assert_eq!(plan.passes[1].1.len(), 1);
+        assert!(plan.passes[2].1.is_empty());
+    }
+}
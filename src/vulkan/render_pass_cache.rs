@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ash::extensions::ext::DebugUtils;
+use ash::vk::{
+    AccessFlags, AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp,
+    Format, ImageLayout, PipelineBindPoint, PipelineStageFlags, RenderPass, RenderPassCreateInfo,
+    SampleCountFlags, SubpassDependency, SubpassDescription, SUBPASS_EXTERNAL,
+};
+use ash::Device;
+use log::{debug, info};
+
+use crate::util::debug::set_object_name;
+
+/// A render pass with one subpass that writes every color attachment and the optional depth
+/// attachment.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenderPassDesc {
+    pub color_formats: Vec<Format>,
+    pub depth_format: Option<Format>,
+    pub samples: SampleCountFlags,
+    /// One per attachment, the color attachments come first
+    pub load_ops: Vec<AttachmentLoadOp>,
+    /// One per attachment, the color attachments come first
+    pub final_layouts: Vec<ImageLayout>,
+}
+
+impl RenderPassDesc {
+    /// A pass whose color attachment is sampled by a later pass.
+    pub fn offscreen(color_format: Format, depth_format: Option<Format>) -> RenderPassDesc {
+        let mut load_ops = vec![AttachmentLoadOp::CLEAR];
+        let mut final_layouts = vec![ImageLayout::SHADER_READ_ONLY_OPTIMAL];
+        if depth_format.is_some() {
+            load_ops.push(AttachmentLoadOp::CLEAR);
+            final_layouts.push(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        }
+
+        RenderPassDesc {
+            color_formats: vec![color_format],
+            depth_format,
+            samples: SampleCountFlags::TYPE_1,
+            load_ops,
+            final_layouts,
+        }
+    }
+
+    /// The offscreen pass after a depth pre-pass, it keeps the pre-pass depth instead of
+    /// clearing it.
+    pub fn offscreen_after_depth_prepass(
+        color_format: Format,
+        depth_format: Format,
+    ) -> RenderPassDesc {
+        RenderPassDesc {
+            color_formats: vec![color_format],
+            depth_format: Some(depth_format),
+            samples: SampleCountFlags::TYPE_1,
+            load_ops: vec![AttachmentLoadOp::CLEAR, AttachmentLoadOp::LOAD],
+            final_layouts: vec![
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ],
+        }
+    }
+
+    /// A depth-only pass whose depth is loaded by the offscreen pass after it.
+    pub fn depth_prepass(depth_format: Format) -> RenderPassDesc {
+        RenderPassDesc {
+            color_formats: vec![],
+            depth_format: Some(depth_format),
+            samples: SampleCountFlags::TYPE_1,
+            load_ops: vec![AttachmentLoadOp::CLEAR],
+            final_layouts: vec![ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL],
+        }
+    }
+
+    /// A depth-only pass whose depth is sampled by a later pass.
+    pub fn shadow_map(depth_format: Format) -> RenderPassDesc {
+        RenderPassDesc {
+            color_formats: vec![],
+            depth_format: Some(depth_format),
+            samples: SampleCountFlags::TYPE_1,
+            load_ops: vec![AttachmentLoadOp::CLEAR],
+            final_layouts: vec![ImageLayout::SHADER_READ_ONLY_OPTIMAL],
+        }
+    }
+
+    /// A pass whose color attachment is copied out afterwards, the depth is only used within it.
+    pub fn picking(color_format: Format, depth_format: Format) -> RenderPassDesc {
+        RenderPassDesc {
+            color_formats: vec![color_format],
+            depth_format: Some(depth_format),
+            samples: SampleCountFlags::TYPE_1,
+            load_ops: vec![AttachmentLoadOp::CLEAR; 2],
+            final_layouts: vec![
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ],
+        }
+    }
+
+    pub fn swapchain(surface_format: Format) -> RenderPassDesc {
+        RenderPassDesc {
+            color_formats: vec![surface_format],
+            depth_format: None,
+            samples: SampleCountFlags::TYPE_1,
+            load_ops: vec![AttachmentLoadOp::CLEAR],
+            final_layouts: vec![ImageLayout::PRESENT_SRC_KHR],
+        }
+    }
+
+    /// Pipelines built for one pass can be used in another if this holds, load ops and layouts
+    /// don't affect render pass compatibility.
+    pub fn is_compatible_with(&self, other: &RenderPassDesc) -> bool {
+        self.color_formats == other.color_formats
+            && self.depth_format == other.depth_format
+            && self.samples == other.samples
+    }
+
+    fn attachment_count(&self) -> usize {
+        self.color_formats.len() + self.depth_format.iter().len()
+    }
+}
+
+pub struct RenderPassCache {
+    render_passes: HashMap<RenderPassDesc, RenderPass>,
+    hits: usize,
+}
+
+impl RenderPassCache {
+    pub fn new() -> RenderPassCache {
+        RenderPassCache {
+            render_passes: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        debug_utils: Option<&DebugUtils>,
+        desc: &RenderPassDesc,
+    ) -> Result<RenderPass> {
+        if let Some(&render_pass) = self.render_passes.get(desc) {
+            self.hits += 1;
+            debug!("Render pass cache hit for {:?} ({} hits)", desc, self.hits);
+            return Ok(render_pass);
+        }
+
+        let render_pass = create_render_pass(device, desc)?;
+        self.render_passes.insert(desc.clone(), render_pass);
+        info!(
+            "Created render pass {} for {:?}",
+            self.render_passes.len(),
+            desc
+        );
+        set_object_name(
+            debug_utils,
+            device,
+            render_pass,
+            &format!("render pass {}", self.render_passes.len()),
+        );
+
+        Ok(render_pass)
+    }
+
+    /// The desc a cached render pass was created for, `None` for passes created elsewhere.
+    pub fn desc(&self, render_pass: RenderPass) -> Option<&RenderPassDesc> {
+        self.render_passes
+            .iter()
+            .find(|&(_, &cached_render_pass)| cached_render_pass == render_pass)
+            .map(|(desc, _)| desc)
+    }
+
+    pub fn len(&self) -> usize {
+        self.render_passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.render_passes.is_empty()
+    }
+
+    pub fn destroy_all(&mut self, device: &Device) {
+        info!(
+            "Destroying {} unique render passes, the render pass cache had {} hits",
+            self.render_passes.len(),
+            self.hits
+        );
+        for (_, render_pass) in self.render_passes.drain() {
+            unsafe { device.destroy_render_pass(render_pass, None) };
+        }
+    }
+}
+
+impl Default for RenderPassCache {
+    fn default() -> RenderPassCache {
+        RenderPassCache::new()
+    }
+}
+
+fn create_render_pass(device: &Device, desc: &RenderPassDesc) -> Result<RenderPass> {
+    with_render_pass_info(desc, |create_info| {
+        Ok(unsafe { device.create_render_pass(create_info, None) }?)
+    })?
+}
+
+/// Calls `create` with the create info of `desc`, while the arrays it points at are alive.
+/// Fails when `desc` lacks a load op or final layout for an attachment.
+fn with_render_pass_info<R>(
+    desc: &RenderPassDesc,
+    create: impl FnOnce(&RenderPassCreateInfo) -> R,
+) -> Result<R> {
+    if desc.load_ops.len() != desc.attachment_count()
+        || desc.final_layouts.len() != desc.attachment_count()
+    {
+        return Err(anyhow!(
+            "Render pass {:?} needs a load op and a final layout for each of its {} attachments",
+            desc,
+            desc.attachment_count()
+        ));
+    }
+
+    let formats = desc.color_formats.iter().chain(desc.depth_format.iter());
+    let attachments = formats
+        .zip(desc.load_ops.iter().zip(desc.final_layouts.iter()))
+        .enumerate()
+        .map(|(index, (&format, (&load_op, &final_layout)))| {
+            // Depth is only kept when a later pass reads it
+            let store_op = match (index < desc.color_formats.len(), final_layout) {
+                (true, _)
+                | (_, ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                | (_, ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL) => AttachmentStoreOp::STORE,
+                _ => AttachmentStoreOp::DONT_CARE,
+            };
+            // Loaded attachments are expected in the layout an earlier pass of this kind left
+            let initial_layout = match load_op {
+                AttachmentLoadOp::LOAD => final_layout,
+                _ => ImageLayout::UNDEFINED,
+            };
+            AttachmentDescription::builder()
+                .format(format)
+                .samples(desc.samples)
+                .load_op(load_op)
+                .store_op(store_op)
+                .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                .initial_layout(initial_layout)
+                .final_layout(final_layout)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let color_attachment_refs = (0..desc.color_formats.len())
+        .map(|index| {
+            AttachmentReference::builder()
+                .attachment(index as u32)
+                .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let depth_attachment_ref = AttachmentReference::builder()
+        .attachment(desc.color_formats.len() as u32)
+        .layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let mut subpass_builder = SubpassDescription::builder()
+        .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs);
+    if desc.depth_format.is_some() {
+        subpass_builder = subpass_builder.depth_stencil_attachment(&depth_attachment_ref);
+    }
+    let subpasses = [subpass_builder.build()];
+    let dependencies = subpass_dependencies(desc);
+
+    let render_pass_create_info = RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies)
+        .build();
+
+    Ok(create(&render_pass_create_info))
+}
+
+/// The attachments may still be in use by the previous frame, which either read them in a
+/// fragment shader, copied them or wrote them as attachments. The swapchain's image-available
+/// semaphore is waited on in the color attachment output stage, so that stage is always part of
+/// the source.
+/// Attachments that end up sampled or copied are made visible to fragment shaders or transfers.
+fn subpass_dependencies(desc: &RenderPassDesc) -> Vec<SubpassDependency> {
+    let mut src_stage_mask =
+        PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::FRAGMENT_SHADER;
+    if desc
+        .final_layouts
+        .contains(&ImageLayout::TRANSFER_SRC_OPTIMAL)
+    {
+        src_stage_mask |= PipelineStageFlags::TRANSFER;
+    }
+    let mut dst_stage_mask = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+    let mut src_access_mask = AccessFlags::empty();
+    let mut dst_access_mask = AccessFlags::COLOR_ATTACHMENT_WRITE;
+    if desc.depth_format.is_some() {
+        src_stage_mask |= PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        dst_stage_mask |=
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        src_access_mask |= AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        dst_access_mask |= AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    let mut dependencies = vec![SubpassDependency::builder()
+        .src_subpass(SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(src_stage_mask)
+        .dst_stage_mask(dst_stage_mask)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build()];
+
+    let (color_final_layouts, depth_final_layouts) =
+        desc.final_layouts.split_at(desc.color_formats.len());
+    if color_final_layouts.contains(&ImageLayout::SHADER_READ_ONLY_OPTIMAL) {
+        dependencies.push(
+            SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::SHADER_READ)
+                .build(),
+        );
+    }
+    if color_final_layouts.contains(&ImageLayout::TRANSFER_SRC_OPTIMAL) {
+        dependencies.push(
+            SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(PipelineStageFlags::TRANSFER)
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::TRANSFER_READ)
+                .build(),
+        );
+    }
+    if depth_final_layouts.contains(&ImageLayout::SHADER_READ_ONLY_OPTIMAL) {
+        dependencies.push(
+            SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::SHADER_READ)
+                .build(),
+        );
+    }
+
+    // Loaded by the next pass, such as the offscreen pass after a depth pre-pass
+    if depth_final_layouts.contains(&ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL) {
+        dependencies.push(
+            SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                .dst_stage_mask(
+                    PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ)
+                .build(),
+        );
+    }
+
+    dependencies
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice::from_raw_parts;
+
+    use super::*;
+
+    #[test]
+    fn render_pass_info_points_at_live_arrays() {
+        let desc = RenderPassDesc::offscreen(Format::R16G16B16A16_SFLOAT, Some(Format::D32_SFLOAT));
+        with_render_pass_info(&desc, |create_info| unsafe {
+            let attachments = from_raw_parts(
+                create_info.p_attachments,
+                create_info.attachment_count as usize,
+            );
+            let formats = attachments
+                .iter()
+                .map(|attachment| attachment.format)
+                .collect::<Vec<_>>();
+            assert_eq!(formats, [Format::R16G16B16A16_SFLOAT, Format::D32_SFLOAT]);
+
+            assert_eq!(create_info.subpass_count, 1);
+            let subpass = &*create_info.p_subpasses;
+            let color_attachment = &*subpass.p_color_attachments;
+            assert_eq!(subpass.color_attachment_count, 1);
+            assert_eq!(color_attachment.attachment, 0);
+            let depth_attachment = &*subpass.p_depth_stencil_attachment;
+            assert_eq!(depth_attachment.attachment, 1);
+            assert_eq!(
+                depth_attachment.layout,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            );
+
+            let dependencies = from_raw_parts(
+                create_info.p_dependencies,
+                create_info.dependency_count as usize,
+            );
+            let masks = |dependency: &SubpassDependency| {
+                (
+                    dependency.dst_subpass,
+                    dependency.dst_stage_mask,
+                    dependency.dst_access_mask,
+                )
+            };
+            assert_eq!(
+                dependencies.iter().map(masks).collect::<Vec<_>>(),
+                subpass_dependencies(&desc)
+                    .iter()
+                    .map(masks)
+                    .collect::<Vec<_>>()
+            );
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn render_pass_info_without_depth_has_no_depth_reference() {
+        let desc = RenderPassDesc::swapchain(Format::B8G8R8A8_SRGB);
+        let has_depth = with_render_pass_info(&desc, |create_info| unsafe {
+            !(*create_info.p_subpasses)
+                .p_depth_stencil_attachment
+                .is_null()
+        })
+        .unwrap();
+        assert!(!has_depth);
+    }
+
+    #[test]
+    fn render_pass_info_needs_an_op_per_attachment() {
+        let mut desc = RenderPassDesc::swapchain(Format::B8G8R8A8_SRGB);
+        desc.load_ops.clear();
+        assert!(with_render_pass_info(&desc, |_| ()).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use anyhow::Result;
+use ash::extensions::ext::DebugUtils;
+use ash::vk::{
+    BorderColor, CompareOp, Filter, Sampler, SamplerAddressMode, SamplerCreateInfo,
+    SamplerMipmapMode, LOD_CLAMP_NONE,
+};
+use ash::Device;
+use log::{debug, info};
+
+use crate::util::debug::set_object_name;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub filter: Filter,
+    pub address_mode: SamplerAddressMode,
+    pub max_anisotropy: Option<f32>,
+    pub mipmap_mode: SamplerMipmapMode,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub compare_op: Option<CompareOp>,
+}
+
+impl SamplerDesc {
+    pub fn linear(address_mode: SamplerAddressMode) -> SamplerDesc {
+        SamplerDesc {
+            filter: Filter::LINEAR,
+            address_mode,
+            max_anisotropy: Some(16.0),
+            mipmap_mode: SamplerMipmapMode::LINEAR,
+            min_lod: 0.0,
+            max_lod: LOD_CLAMP_NONE,
+            compare_op: None,
+        }
+    }
+
+    pub fn nearest(address_mode: SamplerAddressMode) -> SamplerDesc {
+        SamplerDesc {
+            filter: Filter::NEAREST,
+            max_anisotropy: None,
+            mipmap_mode: SamplerMipmapMode::NEAREST,
+            ..SamplerDesc::linear(address_mode)
+        }
+    }
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc::linear(SamplerAddressMode::REPEAT)
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &SamplerDesc) -> bool {
+        self.filter == other.filter
+            && self.address_mode == other.address_mode
+            && self.max_anisotropy.map(f32::to_bits) == other.max_anisotropy.map(f32::to_bits)
+            && self.mipmap_mode == other.mipmap_mode
+            && self.min_lod.to_bits() == other.min_lod.to_bits()
+            && self.max_lod.to_bits() == other.max_lod.to_bits()
+            && self.compare_op == other.compare_op
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.filter.hash(state);
+        self.address_mode.hash(state);
+        self.max_anisotropy.map(f32::to_bits).hash(state);
+        self.mipmap_mode.hash(state);
+        self.min_lod.to_bits().hash(state);
+        self.max_lod.to_bits().hash(state);
+        self.compare_op.hash(state);
+    }
+}
+
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, Sampler>,
+    /// `None` without the `samplerAnisotropy` feature, descs then fall back to plain filtering
+    max_supported_anisotropy: Option<f32>,
+    hits: usize,
+}
+
+impl SamplerCache {
+    pub fn new(max_supported_anisotropy: Option<f32>) -> SamplerCache {
+        SamplerCache {
+            samplers: HashMap::new(),
+            max_supported_anisotropy,
+            hits: 0,
+        }
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        debug_utils: Option<&DebugUtils>,
+        desc: &SamplerDesc,
+    ) -> Result<Sampler> {
+        if let Some(&sampler) = self.samplers.get(desc) {
+            self.hits += 1;
+            debug!("Sampler cache hit for {:?} ({} hits)", desc, self.hits);
+            return Ok(sampler);
+        }
+
+        let sampler = create_sampler(device, desc, self.max_supported_anisotropy)?;
+        self.samplers.insert(*desc, sampler);
+        info!("Created sampler {} for {:?}", self.samplers.len(), desc);
+        set_object_name(
+            debug_utils,
+            device,
+            sampler,
+            &format!("sampler {}", self.samplers.len()),
+        );
+
+        Ok(sampler)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    pub fn destroy_all(&mut self, device: &Device) {
+        info!(
+            "Destroying {} unique samplers, the sampler cache had {} hits",
+            self.samplers.len(),
+            self.hits
+        );
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { device.destroy_sampler(sampler, None) };
+        }
+    }
+}
+
+fn create_sampler(
+    device: &Device,
+    desc: &SamplerDesc,
+    max_supported_anisotropy: Option<f32>,
+) -> Result<Sampler> {
+    let max_anisotropy = desc.max_anisotropy.zip(max_supported_anisotropy).map(
+        |(max_anisotropy, max_supported_anisotropy)| max_anisotropy.min(max_supported_anisotropy),
+    );
+    let sampler_create_info = SamplerCreateInfo::builder()
+        .mag_filter(desc.filter)
+        .min_filter(desc.filter)
+        .mipmap_mode(desc.mipmap_mode)
+        .address_mode_u(desc.address_mode)
+        .address_mode_v(desc.address_mode)
+        .address_mode_w(desc.address_mode)
+        .anisotropy_enable(max_anisotropy.is_some())
+        .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+        .border_color(BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(desc.compare_op.is_some())
+        .compare_op(desc.compare_op.unwrap_or(CompareOp::ALWAYS))
+        .min_lod(desc.min_lod)
+        .max_lod(desc.max_lod)
+        .mip_lod_bias(0.0)
+        .build();
+
+    Ok(unsafe { device.create_sampler(&sampler_create_info, None) }?)
+}
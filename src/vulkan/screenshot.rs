@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::slice::from_raw_parts;
+
+use anyhow::{anyhow, Context, Result};
+use ash::vk::{
+    BufferImageCopy, BufferUsageFlags, CommandBuffer, DeviceSize, Extent2D, Extent3D, Format,
+    Image, ImageAspectFlags, ImageLayout, ImageSubresourceLayers, ImageUsageFlags, MemoryMapFlags,
+    MemoryPropertyFlags, Offset3D,
+};
+use ash::Device;
+use image::ColorType;
+use log::info;
+
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::format::texel_size;
+
+pub struct ScreenshotReadback {
+    buffer: PistonBuffer,
+    format: Format,
+    extent: Extent2D,
+    row_pitch: DeviceSize,
+}
+
+impl ScreenshotReadback {
+    /// What the swapchain images need for screenshots, on top of being color attachments.
+    pub const SWAPCHAIN_USAGE: ImageUsageFlags = ImageUsageFlags::TRANSFER_SRC;
+
+    pub fn new(
+        context: &VulkanContext,
+        format: Format,
+        extent: Extent2D,
+    ) -> Result<ScreenshotReadback> {
+        if texel_size(format) != Some(4) {
+            return Err(anyhow!(
+                "Screenshots are not supported for swapchain format {:?}",
+                format
+            ));
+        }
+
+        let row_alignment = context
+            .device_info
+            .limits
+            .optimal_buffer_copy_row_pitch_alignment
+            .max(4);
+        let row_pitch = (extent.width as DeviceSize * 4).div_ceil(row_alignment) * row_alignment;
+        let buffer = PistonBuffer::new(
+            context,
+            row_pitch * extent.height as DeviceSize,
+            BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            "screenshot readback",
+        )?;
+
+        Ok(ScreenshotReadback {
+            buffer,
+            format,
+            extent,
+            row_pitch,
+        })
+    }
+
+    /// The image must be in the transfer source layout, the frame's render graph moves it there
+    /// and back.
+    pub fn record_copy(&self, device: &Device, command_buffer: CommandBuffer, image: Image) {
+        let buffer_image_copy = BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length((self.row_pitch / 4) as u32)
+            .buffer_image_height(0)
+            .image_subresource(
+                ImageSubresourceLayers::builder()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .build();
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffer.buffer,
+                &[buffer_image_copy],
+            )
+        };
+    }
+
+    /// Writes the copied image to a PNG. The copy recorded by `record_copy` must have completed.
+    pub fn save_png(&self, device: &Device, path: &Path) -> Result<()> {
+        let row_size = self.extent.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_size * self.extent.height as usize);
+        unsafe {
+            let mapped_memory = device.map_memory(
+                self.buffer.memory,
+                0,
+                self.buffer.size,
+                MemoryMapFlags::empty(),
+            )?;
+            let data = from_raw_parts(mapped_memory as *const u8, self.buffer.size as usize);
+            for row in data.chunks(self.row_pitch as usize) {
+                pixels.extend_from_slice(&row[..row_size]);
+            }
+            device.unmap_memory(self.buffer.memory);
+        }
+
+        if matches!(self.format, Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::save_buffer(
+            path,
+            &pixels,
+            self.extent.width,
+            self.extent.height,
+            ColorType::Rgba8,
+        )
+        .with_context(|| format!("Failed to write screenshot to {:?}", path))?;
+        info!("Saved screenshot to {:?}", path);
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.buffer.destroy(device);
+    }
+}
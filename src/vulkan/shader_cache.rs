@@ -0,0 +1,199 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::extensions::ext::DebugUtils;
+use ash::vk::{ShaderModule, ShaderModuleCreateInfo};
+use ash::Device;
+use log::{debug, info};
+
+use crate::util::debug::set_object_name;
+use crate::vulkan::shader_validation::validate_shader_code;
+
+struct CachedShader {
+    name: String,
+    code_hash: u64,
+    code: Vec<u32>,
+    module: ShaderModule,
+}
+
+/// A shader module owned by the `ShaderCache`. Pipelines hold on to the handles of their
+/// shaders, the cache only retires a module once the last handle outside of it is dropped.
+#[derive(Clone)]
+pub struct ShaderHandle(Arc<CachedShader>);
+
+impl ShaderHandle {
+    /// The file the code was first loaded from, for log and error messages.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn code(&self) -> &[u32] {
+        &self.0.code
+    }
+
+    pub fn module(&self) -> ShaderModule {
+        self.0.module
+    }
+}
+
+impl PartialEq for ShaderHandle {
+    fn eq(&self, other: &ShaderHandle) -> bool {
+        self.0.code_hash == other.0.code_hash && self.0.code == other.0.code
+    }
+}
+
+impl Eq for ShaderHandle {}
+
+impl fmt::Debug for ShaderHandle {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} ({:#018x})", self.0.name, self.0.code_hash)
+    }
+}
+
+/// Shader modules keyed by a hash of their SPIR-V, so pipelines built from the same code share
+/// one module. Code whose hash collides with another's gets a module of its own.
+pub struct ShaderCache {
+    shaders: HashMap<u64, Vec<ShaderHandle>>,
+    validate_shaders: bool,
+    hits: usize,
+    misses: usize,
+}
+
+impl ShaderCache {
+    /// With `validate_shaders` set, new code is checked by naga before a module is created.
+    pub fn new(validate_shaders: bool) -> ShaderCache {
+        ShaderCache {
+            shaders: HashMap::new(),
+            validate_shaders,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        debug_utils: Option<&DebugUtils>,
+        name: &str,
+        shader_code: &[u32],
+    ) -> Result<ShaderHandle> {
+        let mut hasher = DefaultHasher::new();
+        shader_code.hash(&mut hasher);
+        let code_hash = hasher.finish();
+
+        if let Some(shader) = self.find(code_hash, shader_code) {
+            let shader = shader.clone();
+            self.hits += 1;
+            debug!("Shader cache hit for {:?} ({} hits)", shader, self.hits);
+            return Ok(shader);
+        }
+
+        if self.validate_shaders {
+            validate_shader_code(name, shader_code)?;
+        }
+        let shader_module_create_info = ShaderModuleCreateInfo::builder().code(shader_code).build();
+        let module = unsafe { device.create_shader_module(&shader_module_create_info, None) }?;
+        set_object_name(debug_utils, device, module, name);
+        let shader = ShaderHandle(Arc::new(CachedShader {
+            name: name.to_string(),
+            code_hash,
+            code: shader_code.to_vec(),
+            module,
+        }));
+        self.misses += 1;
+        debug!(
+            "Shader cache miss for {:?} ({} misses)",
+            shader, self.misses
+        );
+        self.shaders
+            .entry(code_hash)
+            .or_default()
+            .push(shader.clone());
+
+        Ok(shader)
+    }
+
+    /// The cached shader with exactly `shader_code`, a matching hash alone isn't enough.
+    fn find(&self, code_hash: u64, shader_code: &[u32]) -> Option<&ShaderHandle> {
+        self.shaders
+            .get(&code_hash)?
+            .iter()
+            .find(|shader| shader.code() == shader_code)
+    }
+
+    /// Destroys the modules no pipeline holds a handle to any more, such as the ones replaced
+    /// by a hot reload.
+    pub fn retire_unused(&mut self, device: &Device) {
+        self.shaders.retain(|_, shaders| {
+            shaders.retain(|shader| {
+                let in_use = Arc::strong_count(&shader.0) > 1;
+                if !in_use {
+                    debug!("Retiring unused shader module {:?}", shader);
+                    unsafe { device.destroy_shader_module(shader.module(), None) };
+                }
+                in_use
+            });
+            !shaders.is_empty()
+        });
+    }
+
+    pub fn log_stats(&self) {
+        info!(
+            "Shader cache holds {} modules after {} hits and {} misses",
+            self.shaders.values().map(Vec::len).sum::<usize>(),
+            self.hits,
+            self.misses
+        );
+    }
+
+    pub fn destroy_all(&mut self, device: &Device) {
+        self.log_stats();
+        for shader in self.shaders.drain().flat_map(|(_, shaders)| shaders) {
+            unsafe { device.destroy_shader_module(shader.module(), None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn cached_shader(code_hash: u64, code: &[u32], module: u64) -> ShaderHandle {
+        ShaderHandle(Arc::new(CachedShader {
+            name: "test.spv".to_string(),
+            code_hash,
+            code: code.to_vec(),
+            module: ShaderModule::from_raw(module),
+        }))
+    }
+
+    #[test]
+    fn colliding_hashes_only_match_identical_code() {
+        let mut cache = ShaderCache::new(false);
+        cache.shaders.insert(
+            7,
+            vec![cached_shader(7, &[1, 2], 1), cached_shader(7, &[3, 4], 2)],
+        );
+        assert_eq!(
+            cache.find(7, &[3, 4]).map(ShaderHandle::module),
+            Some(ShaderModule::from_raw(2))
+        );
+        assert_eq!(
+            cache.find(7, &[1, 2]).map(ShaderHandle::module),
+            Some(ShaderModule::from_raw(1))
+        );
+        assert!(cache.find(7, &[5, 6]).is_none());
+        assert!(cache.find(8, &[1, 2]).is_none());
+    }
+
+    #[test]
+    fn handles_with_the_same_hash_but_different_code_differ() {
+        assert_ne!(cached_shader(7, &[1, 2], 1), cached_shader(7, &[3, 4], 2));
+        assert_eq!(cached_shader(7, &[1, 2], 1), cached_shader(7, &[1, 2], 1));
+    }
+}
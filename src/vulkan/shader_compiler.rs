@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use shaderc::{CompileOptions, Compiler, ShaderKind, SourceLanguage};
+
+/// File extension, shader kind and HLSL entry point of each stage. GLSL entry points are
+/// always `main`.
+const SHADER_STAGES: [(&str, ShaderKind, &str); 6] = [
+    ("vert", ShaderKind::Vertex, "VSMain"),
+    ("frag", ShaderKind::Fragment, "PSMain"),
+    ("comp", ShaderKind::Compute, "CSMain"),
+    ("geom", ShaderKind::Geometry, "GSMain"),
+    ("tesc", ShaderKind::TessControl, "HSMain"),
+    ("tese", ShaderKind::TessEvaluation, "DSMain"),
+];
+
+struct ShaderSource {
+    path: PathBuf,
+    kind: ShaderKind,
+    language: SourceLanguage,
+    entry_point: &'static str,
+}
+
+/// Compiles the GLSL or HLSL source belonging to `spirv_path` when the SPIR-V file is missing
+/// or older than its source, and writes the result to `spirv_path`. Sources live in the `src`
+/// directory next to the build directory, `build/composite-frag.spv` and
+/// `build/frag-composite.spv` both map to `src/composite.frag`, or to
+/// `src/hlsl/composite.frag.hlsl` when there is no GLSL source. SPIR-V files without a matching
+/// source are left alone.
+pub fn compile_if_stale(spirv_path: &Path) -> Result<()> {
+    let source = match find_source(spirv_path) {
+        Some(source) => source,
+        None => {
+            debug!(
+                "No shader source found for {:?}, using it as is",
+                spirv_path
+            );
+            return Ok(());
+        }
+    };
+    let source_path = &source.path;
+
+    if !is_stale(spirv_path, source_path)? {
+        return Ok(());
+    }
+
+    let source_text = fs::read_to_string(source_path)
+        .with_context(|| format!("Failed to read shader source {:?}", source_path))?;
+    let mut compiler =
+        Compiler::new().ok_or_else(|| anyhow!("Failed to initialize the shaderc compiler"))?;
+    let mut options = CompileOptions::new()
+        .ok_or_else(|| anyhow!("Failed to initialize the shaderc compile options"))?;
+    options.set_source_language(source.language);
+    let artifact = compiler
+        .compile_into_spirv(
+            &source_text,
+            source.kind,
+            &source_path.to_string_lossy(),
+            source.entry_point,
+            Some(&options),
+        )
+        .map_err(|error| anyhow!("Failed to compile {:?}:\n{}", source_path, error))?;
+
+    if let Some(build_dir) = spirv_path.parent() {
+        fs::create_dir_all(build_dir)
+            .with_context(|| format!("Failed to create shader build directory {:?}", build_dir))?;
+    }
+    fs::write(spirv_path, artifact.as_binary_u8())
+        .with_context(|| format!("Failed to write compiled shader {:?}", spirv_path))?;
+
+    info!("Compiled {:?} to {:?}", source_path, spirv_path);
+
+    Ok(())
+}
+
+fn find_source(spirv_path: &Path) -> Option<ShaderSource> {
+    let stem = spirv_path.file_stem()?.to_str()?;
+    let source_dir = spirv_path.parent()?.parent()?.join("src");
+
+    SHADER_STAGES
+        .iter()
+        .find_map(|&(stage, kind, hlsl_entry_point)| {
+            let name = stem
+                .strip_suffix(&format!("-{}", stage))
+                .or_else(|| stem.strip_prefix(&format!("{}-", stage)))?;
+            let glsl_path = source_dir.join(format!("{}.{}", name, stage));
+            let hlsl_path = source_dir
+                .join("hlsl")
+                .join(format!("{}.{}.hlsl", name, stage));
+
+            if glsl_path.exists() {
+                Some(ShaderSource {
+                    path: glsl_path,
+                    kind,
+                    language: SourceLanguage::GLSL,
+                    entry_point: "main",
+                })
+            } else if hlsl_path.exists() {
+                Some(ShaderSource {
+                    path: hlsl_path,
+                    kind,
+                    language: SourceLanguage::HLSL,
+                    entry_point: hlsl_entry_point,
+                })
+            } else {
+                None
+            }
+        })
+}
+
+fn is_stale(spirv_path: &Path, source_path: &Path) -> Result<bool> {
+    if !spirv_path.exists() {
+        return Ok(true);
+    }
+
+    Ok(modified_time(spirv_path)? < modified_time(source_path)?)
+}
+
+fn modified_time(path: &Path) -> Result<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Failed to read the modification time of {:?}", path))
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use naga::front::spv::{Frontend, Options};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use rspirv::dr::{load_words, Operand};
+use rspirv::spirv::ExecutionModel;
+
+/// Runs SPIR-V through naga's parser and validator, so a broken file fails with its name and
+/// entry points instead of deep inside the driver. naga only understands vertex, fragment and
+/// compute shaders and doesn't read every valid module, such as some combined image sampler
+/// patterns, so the shaders it can't parse are passed through with a warning.
+pub fn validate_shader_code(name: &str, shader_code: &[u32]) -> Result<()> {
+    let entry_points = entry_points(shader_code)
+        .map_err(|error| anyhow!("Shader {} is not readable SPIR-V: {}", name, error))?;
+    if entry_points.is_empty() {
+        return Err(anyhow!("Shader {} has no entry points", name));
+    }
+    if entry_points.iter().any(|(_, execution_model)| {
+        !matches!(
+            execution_model,
+            ExecutionModel::Vertex | ExecutionModel::Fragment | ExecutionModel::GLCompute
+        )
+    }) {
+        debug!(
+            "Skipping validation of {}, naga can't read its {} entry points",
+            name,
+            describe_entry_points(&entry_points)
+        );
+        return Ok(());
+    }
+
+    let options = Options {
+        adjust_coordinate_space: false,
+        ..Options::default()
+    };
+    let module = match Frontend::new(shader_code.iter().cloned(), &options).parse() {
+        Ok(module) => module,
+        Err(error) => {
+            warn!(
+                "Skipping validation of {}, naga can't parse it: {}",
+                name, error
+            );
+            return Ok(());
+        }
+    };
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|error| {
+            anyhow!(
+                "Shader {} with {} entry points failed validation: {:?}",
+                name,
+                describe_entry_points(&entry_points),
+                error.into_inner()
+            )
+        })?;
+    debug!(
+        "Validated shader {} with {} entry points",
+        name,
+        describe_entry_points(&entry_points)
+    );
+
+    Ok(())
+}
+
+fn entry_points(shader_code: &[u32]) -> Result<Vec<(String, ExecutionModel)>> {
+    let module = load_words(shader_code).map_err(|error| anyhow!("{}", error))?;
+
+    Ok(module
+        .entry_points
+        .iter()
+        .filter_map(|entry_point| match entry_point.operands.as_slice() {
+            [Operand::ExecutionModel(execution_model), _, Operand::LiteralString(name), ..] => {
+                Some((name.clone(), *execution_model))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+fn describe_entry_points(entry_points: &[(String, ExecutionModel)]) -> String {
+    entry_points
+        .iter()
+        .map(|(name, execution_model)| format!("{} ({:?})", name, execution_model))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AttachmentStoreOp, BufferUsageFlags, ClearDepthStencilValue, ClearValue, CommandBuffer,
+    CompareOp, DescriptorBufferInfo, DescriptorImageInfo, DeviceSize, Extent2D, Format,
+    FormatFeatureFlags, ImageLayout, ImageUsageFlags, MemoryPropertyFlags, PhysicalDeviceLimits,
+    Sampler, SamplerAddressMode, WHOLE_SIZE,
+};
+use ash::Device;
+use log::info;
+
+use crate::config::DepthConvention;
+use crate::constants::{SHADOW_SCENE_CENTER, SHADOW_SCENE_RADIUS};
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::image::{record_image_layout_transition, ImageDesc, PistonImage};
+use crate::vulkan::render::{
+    record_render_pass, record_rendering, rendering_attachment_info, RenderTarget,
+};
+use crate::vulkan::render_pass_cache::RenderPassDesc;
+use crate::vulkan::sampler::SamplerDesc;
+use crate::vulkan::texture::check_format_features;
+
+const SHADOW_MAP_FORMAT: Format = Format::D32_SFLOAT;
+
+const SHADOW_MAP_FORMAT_FEATURES: FormatFeatureFlags = FormatFeatureFlags::from_raw(
+    FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT.as_raw()
+        | FormatFeatureFlags::SAMPLED_IMAGE.as_raw()
+        | FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR.as_raw(),
+);
+
+/// The light's view-projection matrix, read by the shadow pass and the passes that sample the
+/// map.
+const LIGHT_BUFFER_SIZE: DeviceSize = 64;
+
+/// The map is a depth attachment as well as a sampled image, so both sets of limits apply.
+fn check_shadow_map_size(size: u32, limits: &PhysicalDeviceLimits) -> Result<()> {
+    let max_size = limits
+        .max_image_dimension2_d
+        .min(limits.max_framebuffer_width)
+        .min(limits.max_framebuffer_height);
+    if size == 0 || size > max_size {
+        return Err(anyhow!(
+            "A {}x{} shadow map is not supported, the device allows up to {}x{}",
+            size,
+            size,
+            max_size,
+            max_size
+        ));
+    }
+
+    Ok(())
+}
+
+/// Depth of the scene as seen from a directional light. The shadow pass renders the casters
+/// into it, after which it is sampled with a comparison sampler, so a lookup returns how lit a
+/// position is instead of the stored depth.
+pub struct ShadowMap {
+    pub depth: PistonImage,
+    pub render_target: RenderTarget,
+    pub light_buffer: PistonBuffer,
+    pub sampler: Sampler,
+    pub extent: Extent2D,
+    depth_convention: DepthConvention,
+}
+
+impl ShadowMap {
+    pub fn new(context: &VulkanContext, size: u32, light_direction: [f32; 3]) -> Result<ShadowMap> {
+        check_shadow_map_size(size, &context.device_info.limits)?;
+        check_format_features(context, SHADOW_MAP_FORMAT, SHADOW_MAP_FORMAT_FEATURES)?;
+        let extent = Extent2D {
+            width: size,
+            height: size,
+        };
+        let depth = PistonImage::new(
+            context,
+            &ImageDesc {
+                usage: ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | ImageUsageFlags::SAMPLED,
+                ..ImageDesc::depth_attachment(extent, SHADOW_MAP_FORMAT)
+            },
+            "shadow map",
+        )?;
+        let render_target = match context.dynamic_rendering {
+            Some(_) => RenderTarget::Dynamic {
+                color_formats: vec![],
+                depth_format: Some(SHADOW_MAP_FORMAT),
+            },
+            None => RenderTarget::RenderPass(
+                context
+                    .get_or_create_render_pass(&RenderPassDesc::shadow_map(SHADOW_MAP_FORMAT))?,
+            ),
+        };
+        // Outside the map everything is lit, see `shadowed.frag`
+        let sampler = context.get_or_create_sampler(&SamplerDesc {
+            compare_op: Some(
+                context
+                    .depth_convention
+                    .compare_op(CompareOp::LESS_OR_EQUAL),
+            ),
+            ..SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE)
+        })?;
+        let light_buffer = PistonBuffer::new(
+            context,
+            LIGHT_BUFFER_SIZE,
+            BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            "shadow light buffer",
+        )?;
+
+        let shadow_map = ShadowMap {
+            depth,
+            render_target,
+            light_buffer,
+            sampler,
+            extent,
+            depth_convention: context.depth_convention,
+        };
+        shadow_map.set_light_direction(&context.device, light_direction)?;
+        info!("Created {}x{} shadow map", size, size);
+
+        Ok(shadow_map)
+    }
+
+    /// The buffer is read by frames in flight, so the device must be idle.
+    pub fn set_light_direction(&self, device: &Device, light_direction: [f32; 3]) -> Result<()> {
+        if light_direction == [0.0; 3] {
+            return Err(anyhow!("A directional light needs a non-zero direction"));
+        }
+        let light_view_projection = self
+            .depth_convention
+            .projection(light_view_projection(light_direction));
+        let bytes = light_view_projection
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect::<Vec<_>>();
+        self.light_buffer.write(device, &bytes)
+    }
+
+    /// Records the shadow pass with `depth_bias` as constant and slope factors, which push depth
+    /// away from the light in either depth convention. The map is cleared to the far plane, so
+    /// without casters everything is lit. Afterwards the depth is
+    /// ready to be sampled by fragment shaders, either way the pass is recorded.
+    pub fn record_pass<F>(
+        &self,
+        context: &VulkanContext,
+        command_buffer: CommandBuffer,
+        depth_bias: (f32, f32),
+        record: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&Device, CommandBuffer),
+    {
+        let device = &context.device;
+        let clear_value = ClearValue {
+            depth_stencil: ClearDepthStencilValue {
+                depth: self.depth_convention.clear_depth(),
+                stencil: 0,
+            },
+        };
+        let (constant_factor, slope_factor) = self.depth_convention.depth_bias(depth_bias);
+        let record = |device: &Device, command_buffer: CommandBuffer| {
+            unsafe {
+                device.cmd_set_depth_bias(command_buffer, constant_factor, 0.0, slope_factor)
+            };
+            record(device, command_buffer);
+        };
+        if let Some(render_pass) = self.render_target.render_pass() {
+            let framebuffer =
+                context.get_or_create_framebuffer(render_pass, &[self.depth.view], self.extent)?;
+            record_render_pass(
+                device,
+                command_buffer,
+                render_pass,
+                framebuffer,
+                self.extent,
+                &[clear_value],
+                record,
+            );
+            return Ok(());
+        }
+
+        let dynamic_rendering = context
+            .dynamic_rendering
+            .as_ref()
+            .ok_or_else(|| anyhow!("Shadow map has no render pass or dynamic rendering"))?;
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            self.depth.image,
+            self.depth.subresource_range,
+            ImageLayout::UNDEFINED,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        )?;
+        let depth_attachment = rendering_attachment_info(
+            self.depth.view,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            AttachmentStoreOp::STORE,
+            clear_value,
+        );
+        record_rendering(
+            device,
+            dynamic_rendering,
+            command_buffer,
+            &[],
+            Some(&depth_attachment),
+            self.extent,
+            record,
+        );
+
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            self.depth.image,
+            self.depth.subresource_range,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+
+    pub fn light_buffer_info(&self) -> DescriptorBufferInfo {
+        DescriptorBufferInfo::builder()
+            .buffer(self.light_buffer.buffer)
+            .offset(0)
+            .range(WHOLE_SIZE)
+            .build()
+    }
+
+    pub fn descriptor_image_info(&self) -> DescriptorImageInfo {
+        DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .image_view(self.depth.view)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.light_buffer.destroy(device);
+        self.depth.destroy(device);
+    }
+}
+
+/// An orthographic projection looking along `direction` that fits a sphere of
+/// `SHADOW_SCENE_RADIUS` around `SHADOW_SCENE_CENTER`, with standard depth from 0 to 1.
+/// Column-major, as GLSL reads it.
+pub fn light_view_projection(direction: [f32; 3]) -> [[f32; 4]; 4] {
+    let forward = normalize(direction);
+    // Any up that isn't parallel to the direction gives a valid basis
+    let up = match forward[1].abs() > 0.99 {
+        true => [1.0, 0.0, 0.0],
+        false => [0.0, 1.0, 0.0],
+    };
+    let right = normalize(cross(up, forward));
+    let up = cross(forward, right);
+
+    let radius = SHADOW_SCENE_RADIUS;
+    let center = SHADOW_SCENE_CENTER;
+    let rows = [
+        scaled_row(right, center, 1.0 / radius, 0.0),
+        scaled_row(up, center, 1.0 / radius, 0.0),
+        scaled_row(forward, center, 0.5 / radius, 0.5),
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+
+    [0, 1, 2, 3].map(|column| rows.map(|row| row[column]))
+}
+
+/// A row that maps a position to `scale * dot(axis, position - center) + offset`.
+fn scaled_row(axis: [f32; 3], center: [f32; 3], scale: f32, offset: f32) -> [f32; 4] {
+    [
+        axis[0] * scale,
+        axis[1] * scale,
+        axis[2] * scale,
+        offset - dot(axis, center) * scale,
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = dot(vector, vector).sqrt();
+    vector.map(|component| component / length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_image_dimension: u32, max_framebuffer_size: u32) -> PhysicalDeviceLimits {
+        PhysicalDeviceLimits {
+            max_image_dimension2_d: max_image_dimension,
+            max_framebuffer_width: max_framebuffer_size,
+            max_framebuffer_height: max_framebuffer_size,
+            ..PhysicalDeviceLimits::default()
+        }
+    }
+
+    #[test]
+    fn shadow_map_size_within_the_limits_is_accepted() {
+        assert!(check_shadow_map_size(2048, &limits(4096, 4096)).is_ok());
+        assert!(check_shadow_map_size(4096, &limits(4096, 4096)).is_ok());
+    }
+
+    #[test]
+    fn shadow_map_size_beyond_the_limits_is_rejected() {
+        assert!(check_shadow_map_size(0, &limits(4096, 4096)).is_err());
+        assert!(check_shadow_map_size(8192, &limits(4096, 4096)).is_err());
+        assert!(check_shadow_map_size(4096, &limits(16384, 2048)).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Context, Result};
+use ash::vk::{CommandBuffer, Fence, PipelineStageFlags, Queue, Semaphore, SubmitInfo};
+
+use crate::constants::QUEUE_LABEL_COLOR;
+use crate::util::debug::{queue_begin_label, queue_end_label};
+use crate::vulkan::context::VulkanContext;
+
+/// One queue submission, built up from its command buffers, semaphores and fence. The label
+/// names the submission in errors and, with debug utils, in validation messages and captures.
+pub struct SubmitBuilder<'a> {
+    context: &'a VulkanContext,
+    queue: Queue,
+    label: String,
+    command_buffers: Vec<CommandBuffer>,
+    wait_semaphores: Vec<Semaphore>,
+    wait_stages: Vec<PipelineStageFlags>,
+    signal_semaphores: Vec<Semaphore>,
+    fence: Fence,
+}
+
+impl<'a> SubmitBuilder<'a> {
+    pub fn new(context: &'a VulkanContext, queue: Queue, label: &str) -> SubmitBuilder<'a> {
+        SubmitBuilder {
+            context,
+            queue,
+            label: label.to_string(),
+            command_buffers: vec![],
+            wait_semaphores: vec![],
+            wait_stages: vec![],
+            signal_semaphores: vec![],
+            fence: Fence::null(),
+        }
+    }
+
+    pub fn command_buffer(mut self, command_buffer: CommandBuffer) -> SubmitBuilder<'a> {
+        self.command_buffers.push(command_buffer);
+        self
+    }
+
+    pub fn wait(mut self, semaphore: Semaphore, stage: PipelineStageFlags) -> SubmitBuilder<'a> {
+        self.wait_semaphores.push(semaphore);
+        self.wait_stages.push(stage);
+        self
+    }
+
+    /// The semaphores and their stages as separate lists, the way `SubmitInfo` takes them. The
+    /// lists must be as long as each other.
+    pub fn waits(
+        mut self,
+        semaphores: &[Semaphore],
+        stages: &[PipelineStageFlags],
+    ) -> SubmitBuilder<'a> {
+        self.wait_semaphores.extend_from_slice(semaphores);
+        self.wait_stages.extend_from_slice(stages);
+        self
+    }
+
+    pub fn signal(mut self, semaphore: Semaphore) -> SubmitBuilder<'a> {
+        self.signal_semaphores.push(semaphore);
+        self
+    }
+
+    pub fn fence(mut self, fence: Fence) -> SubmitBuilder<'a> {
+        self.fence = fence;
+        self
+    }
+
+    pub fn submit(self) -> Result<()> {
+        // `SubmitInfo` takes a single count for both, so a mismatch would read past a list
+        if self.wait_semaphores.len() != self.wait_stages.len() {
+            return Err(anyhow!(
+                "Submission {:?} waits on {} semaphores, but has {} wait stages",
+                self.label,
+                self.wait_semaphores.len(),
+                self.wait_stages.len()
+            ));
+        }
+
+        let submit_info = SubmitInfo::builder()
+            .wait_semaphores(&self.wait_semaphores)
+            .wait_dst_stage_mask(&self.wait_stages)
+            .command_buffers(&self.command_buffers)
+            .signal_semaphores(&self.signal_semaphores)
+            .build();
+        let debug_utils = self.context.debug_utils.as_ref();
+        queue_begin_label(debug_utils, self.queue, &self.label, QUEUE_LABEL_COLOR);
+        let result = unsafe {
+            self.context
+                .device
+                .queue_submit(self.queue, &[submit_info], self.fence)
+        };
+        queue_end_label(debug_utils, self.queue);
+
+        result.with_context(|| format!("Failed to submit {:?}", self.label))
+    }
+}
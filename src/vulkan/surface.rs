@@ -1,14 +1,41 @@
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use std::mem::transmute;
 
 use anyhow::Result;
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use ash::extensions::ext::MetalSurface;
 use ash::extensions::khr::Surface;
-use ash::vk::{MetalSurfaceCreateInfoEXT, SurfaceKHR};
+#[cfg(target_os = "windows")]
+use ash::extensions::khr::Win32Surface;
+#[cfg(target_os = "linux")]
+use ash::extensions::khr::{WaylandSurface, XcbSurface, XlibSurface};
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
+use ash::vk::MetalSurfaceCreateInfoEXT;
+#[cfg(target_os = "windows")]
+use ash::vk::Win32SurfaceCreateInfoKHR;
+use ash::vk::{PhysicalDevice, SurfaceKHR};
+#[cfg(target_os = "linux")]
+use ash::vk::{WaylandSurfaceCreateInfoKHR, XcbSurfaceCreateInfoKHR, XlibSurfaceCreateInfoKHR};
 use ash::{Entry, Instance};
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use cocoa::appkit::{NSView, NSWindow};
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use cocoa::base::id;
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use metal::foreign_types::ForeignTypeRef;
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
 use metal::MetalLayer;
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+use raw_window_handle::RawWindowHandle;
+#[cfg(target_os = "windows")]
+use raw_window_handle::Win32WindowHandle;
+#[cfg(not(all(target_os = "macos", feature = "metal-layer")))]
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+#[cfg(target_os = "linux")]
+use raw_window_handle::{
+    RawDisplayHandle, WaylandDisplayHandle, WaylandWindowHandle, XcbDisplayHandle, XcbWindowHandle,
+    XlibDisplayHandle, XlibWindowHandle,
+};
 use winit::window::Window;
 
 pub struct SurfaceEntities {
@@ -16,13 +43,32 @@ pub struct SurfaceEntities {
     pub surface: SurfaceKHR,
 }
 
+impl SurfaceEntities {
+    /// Whether the queue family at `queue_index` of `physical_device` can present to the surface.
+    pub fn query_support(&self, physical_device: PhysicalDevice, queue_index: u32) -> Result<bool> {
+        Ok(unsafe {
+            self.surface_loader.get_physical_device_surface_support(
+                physical_device,
+                queue_index,
+                self.surface,
+            )
+        }?)
+    }
+
+    /// Call after the swapchains created for the surface are destroyed, before the instance.
+    pub fn destroy(&self) {
+        unsafe { self.surface_loader.destroy_surface(self.surface, None) };
+    }
+}
+
+/// The instance must have been created with the extensions of the window's display.
 pub fn create_surface(
     entry: &Entry,
     instance: &Instance,
     window: &Window,
 ) -> Result<SurfaceEntities> {
     let surface_loader = Surface::new(entry, instance);
-    let surface = unsafe { create_macos_surface(entry, instance, window) }?;
+    let surface = unsafe { create_window_surface(entry, instance, window) }?;
 
     Ok(SurfaceEntities {
         surface_loader,
@@ -30,7 +76,60 @@ pub fn create_surface(
     })
 }
 
-unsafe fn create_macos_surface(
+/// Creates the surface natively where there is a path for the window system, and through
+/// ash-window otherwise. The handles winit provides decide, so an X11 window under XWayland gets
+/// an X11 surface.
+#[cfg(not(all(target_os = "macos", feature = "metal-layer")))]
+unsafe fn create_window_surface(
+    entry: &Entry,
+    instance: &Instance,
+    window: &Window,
+) -> Result<SurfaceKHR> {
+    match (window.raw_display_handle(), window.raw_window_handle()) {
+        #[cfg(target_os = "windows")]
+        (_, RawWindowHandle::Win32(window_handle)) => {
+            create_win32_surface(entry, instance, window_handle)
+        }
+        #[cfg(target_os = "linux")]
+        (RawDisplayHandle::Xlib(display_handle), RawWindowHandle::Xlib(window_handle)) => {
+            create_xlib_surface(entry, instance, display_handle, window_handle)
+        }
+        #[cfg(target_os = "linux")]
+        (RawDisplayHandle::Xcb(display_handle), RawWindowHandle::Xcb(window_handle)) => {
+            create_xcb_surface(entry, instance, display_handle, window_handle)
+        }
+        #[cfg(target_os = "linux")]
+        (RawDisplayHandle::Wayland(display_handle), RawWindowHandle::Wayland(window_handle)) => {
+            create_wayland_surface(entry, instance, display_handle, window_handle)
+        }
+        (display_handle, window_handle) => Ok(ash_window::create_surface(
+            entry,
+            instance,
+            display_handle,
+            window_handle,
+            None,
+        )?),
+    }
+}
+
+/// Needs `VK_KHR_win32_surface`, which the instance enables for a Windows display.
+#[cfg(target_os = "windows")]
+unsafe fn create_win32_surface(
+    entry: &Entry,
+    instance: &Instance,
+    window_handle: Win32WindowHandle,
+) -> Result<SurfaceKHR> {
+    let win32_surface_create_info = Win32SurfaceCreateInfoKHR::builder()
+        .hinstance(window_handle.hinstance)
+        .hwnd(window_handle.hwnd)
+        .build();
+
+    let win32_surface_loader = Win32Surface::new(entry, instance);
+    Ok(win32_surface_loader.create_win32_surface(&win32_surface_create_info, None)?)
+}
+
+#[cfg(all(target_os = "macos", feature = "metal-layer"))]
+unsafe fn create_window_surface(
     entry: &Entry,
     instance: &Instance,
     window: &Window,
@@ -56,3 +155,55 @@ unsafe fn create_macos_surface(
         .create_metal_surface(&metal_surface_create_info, None)
         .expect("Failed to create Metal surface"))
 }
+
+/// Needs `VK_KHR_xlib_surface`, which the instance enables for an Xlib display.
+#[cfg(target_os = "linux")]
+unsafe fn create_xlib_surface(
+    entry: &Entry,
+    instance: &Instance,
+    display_handle: XlibDisplayHandle,
+    window_handle: XlibWindowHandle,
+) -> Result<SurfaceKHR> {
+    let xlib_surface_create_info = XlibSurfaceCreateInfoKHR::builder()
+        .dpy(display_handle.display.cast())
+        .window(window_handle.window)
+        .build();
+
+    let xlib_surface_loader = XlibSurface::new(entry, instance);
+    Ok(xlib_surface_loader.create_xlib_surface(&xlib_surface_create_info, None)?)
+}
+
+/// Needs `VK_KHR_xcb_surface`, which the instance enables for an XCB display.
+#[cfg(target_os = "linux")]
+unsafe fn create_xcb_surface(
+    entry: &Entry,
+    instance: &Instance,
+    display_handle: XcbDisplayHandle,
+    window_handle: XcbWindowHandle,
+) -> Result<SurfaceKHR> {
+    let xcb_surface_create_info = XcbSurfaceCreateInfoKHR::builder()
+        .connection(display_handle.connection)
+        .window(window_handle.window)
+        .build();
+
+    let xcb_surface_loader = XcbSurface::new(entry, instance);
+    Ok(xcb_surface_loader.create_xcb_surface(&xcb_surface_create_info, None)?)
+}
+
+/// Needs `VK_KHR_wayland_surface`, which the instance enables for a Wayland display. The surface
+/// has no current extent, the swapchain takes the window size instead.
+#[cfg(target_os = "linux")]
+unsafe fn create_wayland_surface(
+    entry: &Entry,
+    instance: &Instance,
+    display_handle: WaylandDisplayHandle,
+    window_handle: WaylandWindowHandle,
+) -> Result<SurfaceKHR> {
+    let wayland_surface_create_info = WaylandSurfaceCreateInfoKHR::builder()
+        .display(display_handle.display)
+        .surface(window_handle.surface)
+        .build();
+
+    let wayland_surface_loader = WaylandSurface::new(entry, instance);
+    Ok(wayland_surface_loader.create_wayland_surface(&wayland_surface_create_info, None)?)
+}
@@ -1,24 +1,277 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
 use ash::extensions::khr::Swapchain;
 use ash::vk::{
     ColorSpaceKHR, ComponentMapping, ComponentSwizzle, CompositeAlphaFlagsKHR, Extent2D, Format,
-    Image, ImageAspectFlags, ImageSubresourceRange, ImageUsageFlags, ImageView,
-    ImageViewCreateFlags, ImageViewCreateInfo, ImageViewType, PhysicalDevice, PresentModeKHR,
-    SharingMode, SurfaceCapabilitiesKHR, SurfaceFormatKHR, SwapchainCreateFlagsKHR,
-    SwapchainCreateInfoKHR, SwapchainKHR,
+    FullScreenExclusiveEXT, Image, ImageAspectFlags, ImageSubresourceRange, ImageUsageFlags,
+    ImageView, ImageViewCreateFlags, ImageViewCreateInfo, ImageViewType, PhysicalDevice,
+    PresentModeKHR, SharingMode, SurfaceCapabilitiesKHR, SurfaceFormatKHR,
+    SurfaceFullScreenExclusiveInfoEXT, SurfaceKHR, SurfaceTransformFlagsKHR,
+    SwapchainCreateFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
 };
 use ash::{Device, Instance};
-use log::info;
+use log::{info, warn};
 use num_traits::clamp;
+use tracing::info_span;
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
-use crate::vulkan::device::QueueFamilyIndices;
+use crate::config::{EngineConfig, OutputColorSpace, PresentPreference};
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::device::{QueueFamilyIndices, SelectedDevice};
+use crate::vulkan::format::ColorSpaceIntent;
 use crate::vulkan::surface::SurfaceEntities;
 
+#[derive(Clone)]
 pub struct SwapchainSupportDetails {
-    capabilities: SurfaceCapabilitiesKHR,
-    pub(crate) formats: Vec<SurfaceFormatKHR>,
-    pub(crate) present_modes: Vec<PresentModeKHR>,
+    pub capabilities: SurfaceCapabilitiesKHR,
+    pub formats: Vec<SurfaceFormatKHR>,
+    pub present_modes: Vec<PresentModeKHR>,
+}
+
+impl SwapchainSupportDetails {
+    /// Whether the images can be created with every bit of `usage`.
+    pub fn supports_usage(&self, usage: ImageUsageFlags) -> bool {
+        self.capabilities.supported_usage_flags.contains(usage)
+    }
+
+    pub fn min_extent(&self) -> Extent2D {
+        self.capabilities.min_image_extent
+    }
+
+    pub fn max_extent(&self) -> Extent2D {
+        self.capabilities.max_image_extent
+    }
+
+    /// The minimum and maximum image count, `None` when there is no maximum.
+    pub fn image_count_range(&self) -> (u32, Option<u32>) {
+        let max_image_count = self.capabilities.max_image_count;
+        (
+            self.capabilities.min_image_count,
+            (max_image_count != 0).then_some(max_image_count),
+        )
+    }
+
+    pub fn supports_present_mode(&self, present_mode: PresentModeKHR) -> bool {
+        self.present_modes.contains(&present_mode)
+    }
+
+    /// The first of `preferences` the surface supports in the sRGB nonlinear color space, or else
+    /// the first available format. A single `UNDEFINED` format means the surface takes any
+    /// format, the first preference is then taken as is. `None` when the surface supports no
+    /// formats.
+    pub fn preferred_format(&self, preferences: &[Format]) -> Option<SurfaceFormatKHR> {
+        if let ([only_format], Some(&format)) = (self.formats.as_slice(), preferences.first()) {
+            if only_format.format == Format::UNDEFINED {
+                return Some(SurfaceFormatKHR {
+                    format,
+                    color_space: ColorSpaceKHR::SRGB_NONLINEAR,
+                });
+            }
+        }
+
+        preferences
+            .iter()
+            .find_map(|&format| {
+                self.formats.iter().find(|available_format| {
+                    available_format.format == format
+                        && available_format.color_space == ColorSpaceKHR::SRGB_NONLINEAR
+                })
+            })
+            .or_else(|| self.formats.first())
+            .copied()
+    }
+
+    /// `preferred_format` with the preferences matching `intent` tried first.
+    pub fn select_surface_format(
+        &self,
+        preferences: &[Format],
+        intent: ColorSpaceIntent,
+    ) -> Result<SurfaceFormatKHR> {
+        let mut ranked_formats = preferences.to_vec();
+        // Stable, so the order within each group is kept
+        ranked_formats.sort_by_key(|&format| ColorSpaceIntent::of_format(format) != Some(intent));
+        if ranked_formats.is_empty() {
+            ranked_formats.push(intent.apply_to(Format::B8G8R8A8_SRGB));
+        }
+
+        self.preferred_format(&ranked_formats)
+            .ok_or_else(|| anyhow!("The surface supports no formats"))
+    }
+
+    /// The best format of an HDR `output_color_space` the surface supports in it, `None` for
+    /// `SdrSrgb` or when it's unsupported.
+    pub fn select_hdr_format(
+        &self,
+        output_color_space: OutputColorSpace,
+    ) -> Option<SurfaceFormatKHR> {
+        output_color_space.hdr_formats().iter().find_map(|&format| {
+            self.formats.iter().copied().find(|available_format| {
+                available_format.format == format
+                    && available_format.color_space == output_color_space.color_space()
+            })
+        })
+    }
+
+    /// The composite alpha of a transparent window, opaque otherwise.
+    pub fn select_composite_alpha(&self, window_transparency: bool) -> CompositeAlphaFlagsKHR {
+        match window_transparency {
+            true => select_composite_alpha(self.capabilities.supported_composite_alpha),
+            false => CompositeAlphaFlagsKHR::OPAQUE,
+        }
+    }
+
+    /// The current transform when it's a rotation the application can apply, identity when the
+    /// surface supports it and the current transform is mirrored.
+    pub fn select_pre_transform(&self) -> SurfaceTransformFlagsKHR {
+        let current_transform = self.capabilities.current_transform;
+        match current_transform {
+            SurfaceTransformFlagsKHR::IDENTITY
+            | SurfaceTransformFlagsKHR::ROTATE_90
+            | SurfaceTransformFlagsKHR::ROTATE_180
+            | SurfaceTransformFlagsKHR::ROTATE_270 => current_transform,
+            _ if self
+                .capabilities
+                .supported_transforms
+                .contains(SurfaceTransformFlagsKHR::IDENTITY) =>
+            {
+                SurfaceTransformFlagsKHR::IDENTITY
+            }
+            _ => current_transform,
+        }
+    }
+
+    /// The first mode of `preference` the surface supports, FIFO is always supported. Fails when
+    /// the surface reports no modes at all.
+    pub fn select_present_mode(&self, preference: PresentPreference) -> Result<PresentModeKHR> {
+        if self.present_modes.is_empty() {
+            return Err(anyhow!("The surface supports no present modes"));
+        }
+
+        Ok(preference
+            .present_modes()
+            .iter()
+            .copied()
+            .find(|&present_mode| self.supports_present_mode(present_mode))
+            .unwrap_or(PresentModeKHR::FIFO))
+    }
+
+    /// `desired_image_count`, or one more than the minimum, within the surface limits.
+    pub fn select_image_count(&self, desired_image_count: Option<u32>) -> u32 {
+        let (min_image_count, max_image_count) = self.image_count_range();
+        let image_count = desired_image_count
+            .unwrap_or(min_image_count + 1)
+            .max(min_image_count);
+        match max_image_count {
+            Some(max_image_count) => image_count.min(max_image_count),
+            None => image_count,
+        }
+    }
+
+    /// The surface reports an extent the window no longer has, so the details were queried
+    /// before a resize.
+    pub fn has_stale_extent(&self, window_size: PhysicalSize<u32>) -> bool {
+        let current_extent = self.capabilities.current_extent;
+        current_extent.width != u32::MAX
+            && (current_extent.width, current_extent.height)
+                != (window_size.width, window_size.height)
+    }
+
+    /// The surface's current extent, or the window size within the surface limits when the
+    /// surface leaves it to the swapchain, as Wayland does. A Wayland window may not have been
+    /// configured yet, so the extent is kept at least a pixel wide and high.
+    pub fn select_extent(&self, window_size: PhysicalSize<u32>) -> Extent2D {
+        let current_extent = self.capabilities.current_extent;
+        if current_extent.width != u32::MAX {
+            return current_extent;
+        }
+
+        info!(
+            "Inner window size: ({}, {})",
+            window_size.width, window_size.height
+        );
+        let (min_extent, max_extent) = (self.min_extent(), self.max_extent());
+        Extent2D {
+            width: clamp(window_size.width, min_extent.width.max(1), max_extent.width),
+            height: clamp(
+                window_size.height,
+                min_extent.height.max(1),
+                max_extent.height,
+            ),
+        }
+    }
+}
+
+/// The support details per surface, queried once and kept until the surface changes. The
+/// capabilities follow the window, so a resize, an out of date or suboptimal swapchain and a
+/// surface recreation must invalidate them.
+pub struct SurfaceSupportCache {
+    details: HashMap<SurfaceKHR, SwapchainSupportDetails>,
+}
+
+impl SurfaceSupportCache {
+    pub fn new() -> SurfaceSupportCache {
+        SurfaceSupportCache {
+            details: HashMap::new(),
+        }
+    }
+
+    /// The cached details, queried again when there are none or their extent is stale for
+    /// `window_size`.
+    pub fn get_or_query(
+        &mut self,
+        physical_device: PhysicalDevice,
+        surface_entities: &SurfaceEntities,
+        window_size: PhysicalSize<u32>,
+    ) -> Result<SwapchainSupportDetails> {
+        self.get_or_query_with(surface_entities.surface, window_size, || {
+            get_swapchain_support_details(physical_device, surface_entities)
+        })
+    }
+
+    pub fn refresh(
+        &mut self,
+        physical_device: PhysicalDevice,
+        surface_entities: &SurfaceEntities,
+    ) -> Result<SwapchainSupportDetails> {
+        self.refresh_with(surface_entities.surface, || {
+            get_swapchain_support_details(physical_device, surface_entities)
+        })
+    }
+
+    fn get_or_query_with(
+        &mut self,
+        surface: SurfaceKHR,
+        window_size: PhysicalSize<u32>,
+        query: impl FnOnce() -> Result<SwapchainSupportDetails>,
+    ) -> Result<SwapchainSupportDetails> {
+        match self.details.get(&surface) {
+            Some(details) if !details.has_stale_extent(window_size) => Ok(details.clone()),
+            _ => self.refresh_with(surface, query),
+        }
+    }
+
+    fn refresh_with(
+        &mut self,
+        surface: SurfaceKHR,
+        query: impl FnOnce() -> Result<SwapchainSupportDetails>,
+    ) -> Result<SwapchainSupportDetails> {
+        let details = query()?;
+        self.details.insert(surface, details.clone());
+
+        Ok(details)
+    }
+
+    pub fn invalidate(&mut self, surface: SurfaceKHR) {
+        self.details.remove(&surface);
+    }
+}
+
+impl Default for SurfaceSupportCache {
+    fn default() -> SurfaceSupportCache {
+        SurfaceSupportCache::new()
+    }
 }
 
 pub struct SwapchainEntities {
@@ -27,6 +280,26 @@ pub struct SwapchainEntities {
     pub swapchain_images: Vec<Image>,
     pub swapchain_format: Format,
     pub swapchain_extent: Extent2D,
+    pub swapchain_image_usage: ImageUsageFlags,
+    pub present_mode: PresentModeKHR,
+    /// `SdrSrgb` when the configured HDR space isn't supported
+    pub output_color_space: OutputColorSpace,
+    /// The rotation of the display, which the application applies instead of the compositor
+    pub pre_transform: SurfaceTransformFlagsKHR,
+    /// Created for application controlled exclusive fullscreen, which still has to be acquired
+    pub full_screen_exclusive: bool,
+}
+
+impl SwapchainEntities {
+    /// The extent in the orientation of the window, which the scene is rendered at. The
+    /// swapchain images are turned a quarter for 90 and 270 degree rotations.
+    pub fn render_extent(&self) -> Extent2D {
+        pre_rotated_extent(self.swapchain_extent, self.pre_transform)
+    }
+
+    pub fn pre_rotation_matrix(&self) -> [[f32; 4]; 4] {
+        pre_rotation_matrix(self.pre_transform)
+    }
 }
 
 pub fn get_swapchain_support_details(
@@ -58,24 +331,78 @@ pub fn get_swapchain_support_details(
     })
 }
 
+/// Uses the support details selection queried for `selected_device`.
 pub fn create_swapchain(
     instance: &Instance,
-    device: &Device,
-    physical_device: PhysicalDevice,
+    context: &VulkanContext,
+    selected_device: &SelectedDevice,
     surface_entities: &SurfaceEntities,
-    queue_family_indices: &QueueFamilyIndices,
     window: &Window,
+    config: &EngineConfig,
 ) -> Result<(SwapchainEntities, Vec<ImageView>)> {
+    let window_size = window.inner_size();
+    let _span = info_span!(
+        "create_swapchain",
+        width = window_size.width,
+        height = window_size.height
+    )
+    .entered();
+    let swapchain_support_details = selected_device
+        .swapchain_support
+        .as_ref()
+        .ok_or_else(|| anyhow!("The device was selected without a surface"))?;
     let swapchain_entities = create_swapchain_entities(
-        instance,
-        device,
-        physical_device,
+        Swapchain::new(instance, &context.device),
+        swapchain_support_details,
         surface_entities,
-        queue_family_indices,
-        window,
+        &selected_device.queue_family_indices,
+        window.inner_size(),
+        config,
+        (SwapchainKHR::null(), false),
     )?;
     let swapchain_image_views = create_swapchain_image_views(
-        device,
+        context,
+        swapchain_entities.swapchain_format,
+        &swapchain_entities.swapchain_images,
+    )?;
+
+    Ok((swapchain_entities, swapchain_image_views))
+}
+
+/// Creates a swapchain that takes over from `old_swapchain`, from the cached support details. They
+/// are queried again when the cache was invalidated or they no longer match `window_size`. The
+/// caller destroys the old swapchain and its image views once they're no longer in use.
+/// `full_screen_exclusive` is ignored without `VK_EXT_full_screen_exclusive`.
+pub fn recreate_swapchain(
+    context: &VulkanContext,
+    swapchain_loader: &Swapchain,
+    surface_entities: &SurfaceEntities,
+    window_size: PhysicalSize<u32>,
+    config: &EngineConfig,
+    old_swapchain: SwapchainKHR,
+    full_screen_exclusive: bool,
+) -> Result<(SwapchainEntities, Vec<ImageView>)> {
+    let _span = info_span!(
+        "recreate_swapchain",
+        width = window_size.width,
+        height = window_size.height
+    )
+    .entered();
+    let swapchain_support_details = context.surface_capabilities(surface_entities, window_size)?;
+    let swapchain_entities = create_swapchain_entities(
+        swapchain_loader.clone(),
+        &swapchain_support_details,
+        surface_entities,
+        &context.queue_family_indices,
+        window_size,
+        config,
+        (
+            old_swapchain,
+            full_screen_exclusive && context.full_screen_exclusive.is_some(),
+        ),
+    )?;
+    let swapchain_image_views = create_swapchain_image_views(
+        context,
         swapchain_entities.swapchain_format,
         &swapchain_entities.swapchain_images,
     )?;
@@ -84,13 +411,16 @@ pub fn create_swapchain(
 }
 
 fn create_swapchain_image_views(
-    device: &Device,
+    context: &VulkanContext,
     surface_format: Format,
-    images: &Vec<Image>,
+    images: &[Image],
 ) -> Result<Vec<ImageView>> {
     let mut image_views = vec![];
-    for image in images {
-        image_views.push(create_image_view(device, surface_format, *image)?);
+    for (index, &image) in images.iter().enumerate() {
+        let image_view = create_image_view(&context.device, surface_format, image)?;
+        context.set_object_name(image, &format!("swapchain image {}", index));
+        context.set_object_name(image_view, &format!("swapchain image {} view", index));
+        image_views.push(image_view);
     }
 
     Ok(image_views)
@@ -124,61 +454,124 @@ fn create_image_view(device: &Device, surface_format: Format, image: Image) -> R
 }
 
 fn create_swapchain_entities(
-    instance: &Instance,
-    device: &Device,
-    physical_device: PhysicalDevice,
+    swapchain_loader: Swapchain,
+    swapchain_support_details: &SwapchainSupportDetails,
     surface_entities: &SurfaceEntities,
     queue_family_indices: &QueueFamilyIndices,
-    window: &Window,
+    window_size: PhysicalSize<u32>,
+    config: &EngineConfig,
+    (old_swapchain, full_screen_exclusive): (SwapchainKHR, bool),
 ) -> Result<SwapchainEntities> {
-    let swapchain_support_details =
-        get_swapchain_support_details(physical_device, surface_entities)?;
-    let surface_format = select_surface_format(&swapchain_support_details.formats);
-    let present_mode = select_present_mode(&swapchain_support_details.present_modes);
-    let extent = select_swapchain_extent(&swapchain_support_details.capabilities, window);
-
-    let image_count = swapchain_support_details.capabilities.min_image_count + 1;
-    let image_count = if swapchain_support_details.capabilities.max_image_count > 1 {
-        image_count.min(swapchain_support_details.capabilities.max_image_count)
-    } else {
-        image_count
+    info!(
+        "Available swapchain formats: {:?}",
+        swapchain_support_details.formats
+    );
+    let (surface_format, output_color_space) = match swapchain_support_details
+        .select_hdr_format(config.output_color_space)
+    {
+        Some(surface_format) => (surface_format, config.output_color_space),
+        None => {
+            if config.output_color_space != OutputColorSpace::SdrSrgb {
+                warn!(
+                    "The surface does not support {:?} output, falling back to SDR",
+                    config.output_color_space
+                );
+            }
+            let surface_format = swapchain_support_details
+                .select_surface_format(&config.swapchain_formats, config.swapchain_color_space)?;
+            if ColorSpaceIntent::of_format(surface_format.format)
+                != Some(config.swapchain_color_space)
+            {
+                warn!(
+                    "Swapchain format {:?} does not match the {:?} intent",
+                    surface_format.format, config.swapchain_color_space
+                );
+            }
+            (surface_format, OutputColorSpace::SdrSrgb)
+        }
     };
+    info!(
+        "Selected swapchain format {:?}, output color space {:?}",
+        surface_format, output_color_space
+    );
+    let present_mode = swapchain_support_details.select_present_mode(config.present_preference)?;
+    info!(
+        "Selected present mode {:?} for {:?}, available: {:?}",
+        present_mode, config.present_preference, swapchain_support_details.present_modes
+    );
+    let pre_transform = swapchain_support_details.select_pre_transform();
+    let extent = pre_rotated_extent(
+        nonzero_extent(swapchain_support_details.select_extent(window_size))?,
+        pre_transform,
+    );
+    if pre_transform != SurfaceTransformFlagsKHR::IDENTITY {
+        info!("The surface is pre-transformed with {:?}", pre_transform);
+    }
+    let image_count = swapchain_support_details.select_image_count(config.desired_image_count);
+    let composite_alpha =
+        swapchain_support_details.select_composite_alpha(config.window_transparency);
+    if config.window_transparency && composite_alpha == CompositeAlphaFlagsKHR::OPAQUE {
+        warn!("The surface only supports opaque composite alpha, the window stays opaque");
+    }
 
-    let (image_sharing_mode, queue_family_indices) = if queue_family_indices.graphics_family_index
-        != queue_family_indices.present_family_index
-    {
-        (
-            SharingMode::CONCURRENT,
-            vec![
-                queue_family_indices.graphics_family_index.unwrap(),
-                queue_family_indices.present_family_index.unwrap(),
-            ],
-        )
-    } else {
-        (SharingMode::EXCLUSIVE, vec![])
+    let (image_usage, usage_warnings) =
+        resolve_swapchain_usage(config.extra_swapchain_usage, swapchain_support_details);
+    for usage_warning in usage_warnings {
+        warn!("{}", usage_warning);
+    }
+
+    let (image_sharing_mode, queue_family_indices) = match (
+        queue_family_indices.graphics_family_index,
+        queue_family_indices.present_family_index,
+    ) {
+        (Some(graphics_family_index), Some(present_family_index))
+            if graphics_family_index != present_family_index =>
+        {
+            (
+                SharingMode::CONCURRENT,
+                vec![graphics_family_index, present_family_index],
+            )
+        }
+        (Some(_), Some(_)) => (SharingMode::EXCLUSIVE, vec![]),
+        _ => {
+            return Err(anyhow!(
+                "A swapchain needs a graphics and a present queue family"
+            ))
+        }
     };
 
-    let swapchain_create_info = SwapchainCreateInfoKHR::builder()
+    let mut full_screen_exclusive_info = SurfaceFullScreenExclusiveInfoEXT::builder()
+        .full_screen_exclusive(FullScreenExclusiveEXT::APPLICATION_CONTROLLED)
+        .build();
+    let mut swapchain_create_info_builder = SwapchainCreateInfoKHR::builder()
         .flags(SwapchainCreateFlagsKHR::empty())
         .surface(surface_entities.surface)
         .min_image_count(image_count)
         .image_color_space(surface_format.color_space)
         .image_format(surface_format.format)
         .image_extent(extent)
-        .image_usage(ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(image_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
-        .pre_transform(swapchain_support_details.capabilities.current_transform)
-        .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
+        .pre_transform(pre_transform)
+        .composite_alpha(composite_alpha)
         .present_mode(present_mode)
         .clipped(true)
-        .old_swapchain(SwapchainKHR::null())
-        .image_array_layers(1)
-        .build();
+        .old_swapchain(old_swapchain)
+        .image_array_layers(1);
+    if full_screen_exclusive {
+        swapchain_create_info_builder =
+            swapchain_create_info_builder.push_next(&mut full_screen_exclusive_info);
+    }
+    let swapchain_create_info = swapchain_create_info_builder.build();
 
-    let swapchain_loader = Swapchain::new(instance, device);
     let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }?;
     let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }?;
+    info!(
+        "Requested {} swapchain images, got {}",
+        image_count,
+        swapchain_images.len()
+    );
 
     Ok(SwapchainEntities {
         swapchain_loader,
@@ -186,51 +579,581 @@ fn create_swapchain_entities(
         swapchain_images,
         swapchain_format: surface_format.format,
         swapchain_extent: extent,
+        swapchain_image_usage: image_usage,
+        present_mode,
+        output_color_space,
+        pre_transform,
+        full_screen_exclusive,
     })
 }
 
-fn select_surface_format(available_formats: &Vec<SurfaceFormatKHR>) -> SurfaceFormatKHR {
-    for available_format in available_formats {
-        if available_format.format == Format::B8G8R8_SRGB
-            && available_format.color_space == ColorSpaceKHR::SRGB_NONLINEAR
-        {
-            return available_format.clone();
-        }
+/// A surface that reports a zero current extent, as a minimized window does, can't have a
+/// swapchain until it's shown again.
+fn nonzero_extent(extent: Extent2D) -> Result<Extent2D> {
+    match extent.width == 0 || extent.height == 0 {
+        true => Err(anyhow!(
+            "The surface extent is {}x{}, there is nothing to present to",
+            extent.width,
+            extent.height
+        )),
+        false => Ok(extent),
     }
+}
 
-    available_formats.first().unwrap().clone()
+/// Premultiplied alpha is what the scene holds, the others leave the alpha to the compositor or
+/// to the window system. Opaque when the surface supports nothing else.
+fn select_composite_alpha(supported: CompositeAlphaFlagsKHR) -> CompositeAlphaFlagsKHR {
+    [
+        CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        CompositeAlphaFlagsKHR::INHERIT,
+    ]
+    .into_iter()
+    .find(|&composite_alpha| supported.contains(composite_alpha))
+    .unwrap_or(CompositeAlphaFlagsKHR::OPAQUE)
 }
 
-fn select_present_mode(present_modes: &Vec<PresentModeKHR>) -> PresentModeKHR {
-    for mode in present_modes {
-        if *mode == PresentModeKHR::MAILBOX {
-            return *mode;
-        }
+/// Color attachment usage with the `requested` usage, without the bits the surface doesn't
+/// support. Each left out bit gets a warning.
+pub fn resolve_swapchain_usage(
+    requested: ImageUsageFlags,
+    swapchain_support_details: &SwapchainSupportDetails,
+) -> (ImageUsageFlags, Vec<String>) {
+    let requested = requested | ImageUsageFlags::COLOR_ATTACHMENT;
+    let (supported, unsupported): (Vec<_>, Vec<_>) = (0..u32::BITS)
+        .map(|bit| ImageUsageFlags::from_raw(1 << bit))
+        .filter(|&usage| requested.contains(usage))
+        .partition(|&usage| swapchain_support_details.supports_usage(usage));
+    let warnings = unsupported
+        .into_iter()
+        .map(|usage| {
+            format!(
+                "Swapchain images can't be used for {:?}, it's left out",
+                usage
+            )
+        })
+        .collect();
+
+    (
+        supported
+            .into_iter()
+            .fold(ImageUsageFlags::empty(), |usage, bit| usage | bit),
+        warnings,
+    )
+}
+
+/// Swaps the width and height for 90 and 270 degree rotations. Turning twice gives the extent
+/// back, so this goes both ways between the window and the swapchain images.
+pub fn pre_rotated_extent(extent: Extent2D, pre_transform: SurfaceTransformFlagsKHR) -> Extent2D {
+    match pre_transform {
+        SurfaceTransformFlagsKHR::ROTATE_90 | SurfaceTransformFlagsKHR::ROTATE_270 => Extent2D {
+            width: extent.height,
+            height: extent.width,
+        },
+        _ => extent,
     }
+}
 
-    PresentModeKHR::FIFO
+/// Column-major, turns clip space around Z by the rotation of `pre_transform`. Projections are
+/// multiplied with it from the left. Mirrored transforms are left to the compositor, they get the
+/// identity.
+pub fn pre_rotation_matrix(pre_transform: SurfaceTransformFlagsKHR) -> [[f32; 4]; 4] {
+    let (sin, cos) = match pre_transform {
+        SurfaceTransformFlagsKHR::ROTATE_90 => (1.0, 0.0),
+        SurfaceTransformFlagsKHR::ROTATE_180 => (0.0, -1.0),
+        SurfaceTransformFlagsKHR::ROTATE_270 => (-1.0, 0.0),
+        _ => (0.0, 1.0),
+    };
+
+    [
+        [cos, sin, 0.0, 0.0],
+        [-sin, cos, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
 }
 
-fn select_swapchain_extent(capabilities: &SurfaceCapabilitiesKHR, window: &Window) -> Extent2D {
-    if capabilities.current_extent.width != u32::MAX {
-        capabilities.current_extent
-    } else {
-        let window_size = window.inner_size();
-        info!(
-            "Inner window size: ({}, {})",
-            window_size.width, window_size.height
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn support_details(capabilities: SurfaceCapabilitiesKHR) -> SwapchainSupportDetails {
+        SwapchainSupportDetails {
+            capabilities,
+            formats: vec![],
+            present_modes: vec![],
+        }
+    }
+
+    #[test]
+    fn supports_usage_needs_every_bit() {
+        let details = support_details(SurfaceCapabilitiesKHR {
+            supported_usage_flags: ImageUsageFlags::COLOR_ATTACHMENT
+                | ImageUsageFlags::TRANSFER_SRC,
+            ..Default::default()
+        });
+        assert!(details.supports_usage(ImageUsageFlags::TRANSFER_SRC));
+        assert!(details
+            .supports_usage(ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC));
+        assert!(
+            !details.supports_usage(ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST)
         );
-        Extent2D {
-            width: clamp(
-                window_size.width,
-                capabilities.current_extent.width,
-                capabilities.current_extent.height,
-            ),
-            height: clamp(
-                window_size.height,
-                capabilities.current_extent.height,
-                capabilities.current_extent.height,
+        assert!(!details.supports_usage(ImageUsageFlags::STORAGE));
+    }
+
+    fn wayland_details() -> SwapchainSupportDetails {
+        support_details(SurfaceCapabilitiesKHR {
+            current_extent: Extent2D {
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            min_image_extent: Extent2D {
+                width: 64,
+                height: 32,
+            },
+            max_image_extent: Extent2D {
+                width: 4096,
+                height: 2048,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn select_extent_takes_the_window_size_when_the_surface_leaves_it() {
+        let extent = wayland_details().select_extent(PhysicalSize::new(800, 600));
+        assert_eq!((extent.width, extent.height), (800, 600));
+    }
+
+    #[test]
+    fn select_extent_clamps_to_the_min_extent() {
+        let extent = wayland_details().select_extent(PhysicalSize::new(10, 600));
+        assert_eq!((extent.width, extent.height), (64, 600));
+        let extent = wayland_details().select_extent(PhysicalSize::new(800, 5));
+        assert_eq!((extent.width, extent.height), (800, 32));
+    }
+
+    #[test]
+    fn select_extent_clamps_to_the_max_extent() {
+        let extent = wayland_details().select_extent(PhysicalSize::new(5000, 3000));
+        assert_eq!((extent.width, extent.height), (4096, 2048));
+    }
+
+    #[test]
+    fn select_extent_keeps_the_current_extent() {
+        let details = support_details(SurfaceCapabilitiesKHR {
+            current_extent: Extent2D {
+                width: 1280,
+                height: 720,
+            },
+            max_image_extent: Extent2D {
+                width: 4096,
+                height: 4096,
+            },
+            ..Default::default()
+        });
+        let extent = details.select_extent(PhysicalSize::new(800, 600));
+        assert_eq!((extent.width, extent.height), (1280, 720));
+    }
+
+    #[test]
+    fn pre_rotated_extent_swaps_quarter_turns() {
+        let extent = Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        let rotated = |transform| {
+            let extent = pre_rotated_extent(extent, transform);
+            (extent.width, extent.height)
+        };
+        assert_eq!(rotated(SurfaceTransformFlagsKHR::IDENTITY), (1920, 1080));
+        assert_eq!(rotated(SurfaceTransformFlagsKHR::ROTATE_90), (1080, 1920));
+        assert_eq!(rotated(SurfaceTransformFlagsKHR::ROTATE_180), (1920, 1080));
+        assert_eq!(rotated(SurfaceTransformFlagsKHR::ROTATE_270), (1080, 1920));
+        let back = pre_rotated_extent(
+            pre_rotated_extent(extent, SurfaceTransformFlagsKHR::ROTATE_90),
+            SurfaceTransformFlagsKHR::ROTATE_90,
+        );
+        assert_eq!((back.width, back.height), (1920, 1080));
+    }
+
+    #[test]
+    fn pre_rotation_matrix_turns_clip_space() {
+        let rotate = |transform, [x, y]: [f32; 2]| {
+            let matrix = pre_rotation_matrix(transform);
+            [
+                matrix[0][0] * x + matrix[1][0] * y,
+                matrix[0][1] * x + matrix[1][1] * y,
+            ]
+        };
+        assert_eq!(
+            rotate(SurfaceTransformFlagsKHR::IDENTITY, [1.0, 0.0]),
+            [1.0, 0.0]
+        );
+        assert_eq!(
+            rotate(SurfaceTransformFlagsKHR::ROTATE_90, [1.0, 0.0]),
+            [0.0, 1.0]
+        );
+        assert_eq!(
+            rotate(SurfaceTransformFlagsKHR::ROTATE_180, [1.0, 0.0]),
+            [-1.0, 0.0]
+        );
+        assert_eq!(
+            rotate(SurfaceTransformFlagsKHR::ROTATE_270, [1.0, 0.0]),
+            [0.0, -1.0]
+        );
+        assert_eq!(
+            pre_rotation_matrix(SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR),
+            pre_rotation_matrix(SurfaceTransformFlagsKHR::IDENTITY)
+        );
+    }
+
+    #[test]
+    fn select_pre_transform_keeps_rotations_and_drops_mirrors() {
+        let pre_transform = |current_transform| {
+            support_details(SurfaceCapabilitiesKHR {
+                current_transform,
+                supported_transforms: SurfaceTransformFlagsKHR::IDENTITY
+                    | SurfaceTransformFlagsKHR::ROTATE_90,
+                ..Default::default()
+            })
+            .select_pre_transform()
+        };
+        assert_eq!(
+            pre_transform(SurfaceTransformFlagsKHR::ROTATE_90),
+            SurfaceTransformFlagsKHR::ROTATE_90
+        );
+        assert_eq!(
+            pre_transform(SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR),
+            SurfaceTransformFlagsKHR::IDENTITY
+        );
+    }
+
+    #[test]
+    fn unconfigured_wayland_window_gets_a_nonzero_extent() {
+        let details = support_details(SurfaceCapabilitiesKHR {
+            current_extent: Extent2D {
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            max_image_extent: Extent2D {
+                width: 4096,
+                height: 4096,
+            },
+            ..Default::default()
+        });
+        let extent = nonzero_extent(details.select_extent(PhysicalSize::new(0, 0))).unwrap();
+        assert_eq!((extent.width, extent.height), (1, 1));
+    }
+
+    #[test]
+    fn zero_current_extent_is_rejected() {
+        let details = support_details(SurfaceCapabilitiesKHR {
+            current_extent: Extent2D {
+                width: 0,
+                height: 0,
+            },
+            ..Default::default()
+        });
+        assert!(nonzero_extent(details.select_extent(PhysicalSize::new(800, 600))).is_err());
+        assert!(nonzero_extent(Extent2D {
+            width: 800,
+            height: 0
+        })
+        .is_err());
+    }
+
+    fn format_details(formats: &[(Format, ColorSpaceKHR)]) -> SwapchainSupportDetails {
+        SwapchainSupportDetails {
+            formats: formats
+                .iter()
+                .map(|&(format, color_space)| SurfaceFormatKHR {
+                    format,
+                    color_space,
+                })
+                .collect(),
+            ..support_details(SurfaceCapabilitiesKHR::default())
+        }
+    }
+
+    const SRGB: ColorSpaceKHR = ColorSpaceKHR::SRGB_NONLINEAR;
+    const PREFERENCES: [Format; 3] = [
+        Format::B8G8R8A8_SRGB,
+        Format::R8G8B8A8_SRGB,
+        Format::B8G8R8A8_UNORM,
+    ];
+
+    #[test]
+    fn preferred_format_takes_the_best_ranked_available() {
+        let details = format_details(&[
+            (Format::B8G8R8A8_UNORM, SRGB),
+            (Format::R8G8B8A8_SRGB, SRGB),
+        ]);
+        let format = details.preferred_format(&PREFERENCES).unwrap();
+        assert_eq!(format.format, Format::R8G8B8A8_SRGB);
+    }
+
+    #[test]
+    fn preferred_format_needs_the_srgb_color_space() {
+        let details = format_details(&[
+            (
+                Format::B8G8R8A8_SRGB,
+                ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
             ),
+            (Format::B8G8R8A8_UNORM, SRGB),
+        ]);
+        let format = details.preferred_format(&PREFERENCES).unwrap();
+        assert_eq!(format.format, Format::B8G8R8A8_UNORM);
+    }
+
+    #[test]
+    fn preferred_format_falls_back_to_the_first_available() {
+        let details = format_details(&[
+            (Format::A2B10G10R10_UNORM_PACK32, SRGB),
+            (Format::R16G16B16A16_SFLOAT, SRGB),
+        ]);
+        let format = details.preferred_format(&PREFERENCES).unwrap();
+        assert_eq!(format.format, Format::A2B10G10R10_UNORM_PACK32);
+    }
+
+    #[test]
+    fn preferred_format_takes_anything_from_a_single_undefined_format() {
+        let details = format_details(&[(Format::UNDEFINED, SRGB)]);
+        let format = details.preferred_format(&PREFERENCES).unwrap();
+        assert_eq!(
+            format,
+            SurfaceFormatKHR {
+                format: Format::B8G8R8A8_SRGB,
+                color_space: SRGB,
+            }
+        );
+    }
+
+    #[test]
+    fn select_surface_format_ranks_the_intent_first() {
+        let details = format_details(&[
+            (Format::B8G8R8A8_SRGB, SRGB),
+            (Format::B8G8R8A8_UNORM, SRGB),
+        ]);
+        let format = details
+            .select_surface_format(&PREFERENCES, ColorSpaceIntent::Linear)
+            .unwrap();
+        assert_eq!(format.format, Format::B8G8R8A8_UNORM);
+    }
+
+    fn present_mode(preference: PresentPreference, available: &[PresentModeKHR]) -> PresentModeKHR {
+        SwapchainSupportDetails {
+            present_modes: available.to_vec(),
+            ..support_details(SurfaceCapabilitiesKHR::default())
         }
+        .select_present_mode(preference)
+        .unwrap()
+    }
+
+    #[test]
+    fn present_mode_follows_each_preference() {
+        use PresentModeKHR as Mode;
+        let all = [
+            Mode::FIFO,
+            Mode::FIFO_RELAXED,
+            Mode::MAILBOX,
+            Mode::IMMEDIATE,
+        ];
+        assert_eq!(present_mode(PresentPreference::VsyncOn, &all), Mode::FIFO);
+        assert_eq!(
+            present_mode(PresentPreference::VsyncOff, &all),
+            Mode::IMMEDIATE
+        );
+        assert_eq!(
+            present_mode(PresentPreference::LowLatency, &all),
+            Mode::MAILBOX
+        );
+        assert_eq!(
+            present_mode(PresentPreference::AdaptiveVsync, &all),
+            Mode::FIFO_RELAXED
+        );
+    }
+
+    #[test]
+    fn present_mode_falls_back_through_the_preference() {
+        use PresentModeKHR as Mode;
+        let mailbox = [Mode::FIFO, Mode::MAILBOX];
+        let immediate = [Mode::FIFO, Mode::IMMEDIATE];
+        assert_eq!(
+            present_mode(PresentPreference::VsyncOff, &mailbox),
+            Mode::MAILBOX
+        );
+        assert_eq!(
+            present_mode(PresentPreference::LowLatency, &immediate),
+            Mode::IMMEDIATE
+        );
+        for preference in [
+            PresentPreference::VsyncOn,
+            PresentPreference::VsyncOff,
+            PresentPreference::LowLatency,
+            PresentPreference::AdaptiveVsync,
+        ] {
+            assert_eq!(present_mode(preference, &[Mode::FIFO]), Mode::FIFO);
+        }
+    }
+
+    #[test]
+    fn composite_alpha_prefers_premultiplied() {
+        use CompositeAlphaFlagsKHR as Alpha;
+        let all = Alpha::OPAQUE | Alpha::PRE_MULTIPLIED | Alpha::POST_MULTIPLIED | Alpha::INHERIT;
+        assert_eq!(select_composite_alpha(all), Alpha::PRE_MULTIPLIED);
+        assert_eq!(
+            select_composite_alpha(Alpha::OPAQUE | Alpha::POST_MULTIPLIED | Alpha::INHERIT),
+            Alpha::POST_MULTIPLIED
+        );
+        assert_eq!(
+            select_composite_alpha(Alpha::OPAQUE | Alpha::INHERIT),
+            Alpha::INHERIT
+        );
+        assert_eq!(select_composite_alpha(Alpha::OPAQUE), Alpha::OPAQUE);
+    }
+
+    #[test]
+    fn opaque_window_ignores_the_supported_composite_alpha() {
+        let details = support_details(SurfaceCapabilitiesKHR {
+            supported_composite_alpha: CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            ..Default::default()
+        });
+        assert_eq!(
+            details.select_composite_alpha(false),
+            CompositeAlphaFlagsKHR::OPAQUE
+        );
+        assert_eq!(
+            details.select_composite_alpha(true),
+            CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+        );
+    }
+
+    fn usage_details(supported_usage_flags: ImageUsageFlags) -> SwapchainSupportDetails {
+        support_details(SurfaceCapabilitiesKHR {
+            supported_usage_flags,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn swapchain_usage_always_includes_color_attachment() {
+        let details = usage_details(ImageUsageFlags::COLOR_ATTACHMENT);
+        let (usage, warnings) = resolve_swapchain_usage(ImageUsageFlags::empty(), &details);
+        assert_eq!(usage, ImageUsageFlags::COLOR_ATTACHMENT);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn swapchain_usage_keeps_supported_bits() {
+        let details = usage_details(
+            ImageUsageFlags::COLOR_ATTACHMENT
+                | ImageUsageFlags::TRANSFER_SRC
+                | ImageUsageFlags::STORAGE,
+        );
+        let (usage, warnings) = resolve_swapchain_usage(ImageUsageFlags::TRANSFER_SRC, &details);
+        assert_eq!(
+            usage,
+            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn swapchain_usage_strips_unsupported_bits_with_a_warning_each() {
+        let details =
+            usage_details(ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC);
+        let (usage, warnings) = resolve_swapchain_usage(
+            ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::STORAGE | ImageUsageFlags::SAMPLED,
+            &details,
+        );
+        assert_eq!(
+            usage,
+            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC
+        );
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().any(|warning| warning.contains("STORAGE")));
+        assert!(warnings.iter().any(|warning| warning.contains("SAMPLED")));
+    }
+
+    #[test]
+    fn no_formats_is_an_error() {
+        let details = format_details(&[]);
+        assert_eq!(details.preferred_format(&PREFERENCES), None);
+        assert!(details
+            .select_surface_format(&PREFERENCES, ColorSpaceIntent::Srgb)
+            .is_err());
+        assert!(details
+            .select_surface_format(&[], ColorSpaceIntent::Srgb)
+            .is_err());
+    }
+
+    #[test]
+    fn no_present_modes_is_an_error() {
+        let details = support_details(SurfaceCapabilitiesKHR::default());
+        assert!(details
+            .select_present_mode(PresentPreference::VsyncOn)
+            .is_err());
+    }
+
+    fn extent_snapshot(width: u32, height: u32) -> SwapchainSupportDetails {
+        support_details(SurfaceCapabilitiesKHR {
+            current_extent: Extent2D { width, height },
+            max_image_extent: Extent2D {
+                width: 4096,
+                height: 4096,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn support_cache_queries_again_after_a_resize() {
+        let surface = SurfaceKHR::null();
+        let mut snapshots = vec![extent_snapshot(1024, 768), extent_snapshot(800, 600)];
+        let mut queries = 0;
+        let mut cache = SurfaceSupportCache::new();
+        let mut get = |cache: &mut SurfaceSupportCache, window_size| {
+            cache
+                .get_or_query_with(surface, window_size, || {
+                    queries += 1;
+                    Ok(snapshots.pop().unwrap())
+                })
+                .unwrap()
+                .select_extent(window_size)
+        };
+
+        let extent = get(&mut cache, PhysicalSize::new(800, 600));
+        assert_eq!((extent.width, extent.height), (800, 600));
+        let extent = get(&mut cache, PhysicalSize::new(800, 600));
+        assert_eq!((extent.width, extent.height), (800, 600));
+        // Resized without the cache being invalidated, the recorded 800x600 must not be used
+        let extent = get(&mut cache, PhysicalSize::new(1024, 768));
+        assert_eq!((extent.width, extent.height), (1024, 768));
+        assert_eq!(queries, 2);
+    }
+
+    #[test]
+    fn support_cache_queries_again_after_an_invalidation() {
+        let surface = SurfaceKHR::null();
+        let window_size = PhysicalSize::new(800, 600);
+        let mut queries = 0;
+        let mut cache = SurfaceSupportCache::new();
+        let mut query = || {
+            queries += 1;
+            Ok(extent_snapshot(800, 600))
+        };
+        cache
+            .get_or_query_with(surface, window_size, &mut query)
+            .unwrap();
+        cache
+            .get_or_query_with(surface, window_size, &mut query)
+            .unwrap();
+        cache.invalidate(surface);
+        cache
+            .get_or_query_with(surface, window_size, &mut query)
+            .unwrap();
+        cache.refresh_with(surface, &mut query).unwrap();
+        assert_eq!(queries, 3);
     }
 }
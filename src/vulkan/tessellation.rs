@@ -0,0 +1,103 @@
+use std::mem::size_of;
+use std::slice::from_raw_parts;
+
+use anyhow::Result;
+use ash::vk::{CommandBuffer, CullModeFlags, PipelineBindPoint, PolygonMode, ShaderStageFlags};
+use ash::Device;
+use log::info;
+
+use crate::constants::{TESSELLATION_LEVEL_DEFAULT, TESSELLATION_PATCH_CONTROL_POINTS};
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::features::DeviceFeature;
+use crate::vulkan::pipeline::{PipelineBuilder, PistonPipeline};
+use crate::vulkan::render::RenderTarget;
+
+/// Matches the push constant block of tess.tesc.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TessellationLevels {
+    inner: f32,
+    outer: f32,
+}
+
+/// A single quad patch subdivided by the tessellation stages. Drawn as lines when the device
+/// supports it so the subdivision is visible.
+pub struct TessellatedQuad {
+    pub pipeline: PistonPipeline,
+    levels: TessellationLevels,
+    max_level: f32,
+}
+
+impl TessellatedQuad {
+    pub fn new(context: &VulkanContext, render_target: &RenderTarget) -> Result<TessellatedQuad> {
+        let polygon_mode = if context
+            .capabilities
+            .is_enabled(DeviceFeature::FillModeNonSolid)
+        {
+            PolygonMode::LINE
+        } else {
+            PolygonMode::FILL
+        };
+        let pipeline = PipelineBuilder::new()
+            .shaders(
+                context.load_shader("tess-vert.spv")?,
+                context.load_shader("debug-frag.spv")?,
+            )
+            .tessellation_shaders(
+                context.load_shader("tess-tesc.spv")?,
+                context.load_shader("tess-tese.spv")?,
+                TESSELLATION_PATCH_CONTROL_POINTS,
+            )
+            .polygon_mode(polygon_mode)
+            .cull_mode(CullModeFlags::NONE)
+            .render_target(render_target)
+            .build(context)?;
+
+        Ok(TessellatedQuad {
+            pipeline,
+            levels: TessellationLevels {
+                inner: TESSELLATION_LEVEL_DEFAULT,
+                outer: TESSELLATION_LEVEL_DEFAULT,
+            },
+            max_level: context.device_info.limits.max_tessellation_generation_level as f32,
+        })
+    }
+
+    /// Changes the inner and outer levels together, clamped to what the device can generate.
+    pub fn adjust_levels(&mut self, step: f32) {
+        let level = (self.levels.inner + step).clamp(1.0, self.max_level);
+        self.levels = TessellationLevels {
+            inner: level,
+            outer: level,
+        };
+        info!("Tessellation level is now {}", level);
+    }
+
+    pub fn record(&self, device: &Device, command_buffer: CommandBuffer) {
+        let level_bytes = unsafe {
+            from_raw_parts(
+                &self.levels as *const TessellationLevels as *const u8,
+                size_of::<TessellationLevels>(),
+            )
+        };
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.pipeline_layout,
+                ShaderStageFlags::TESSELLATION_CONTROL,
+                0,
+                level_bytes,
+            );
+            device.cmd_draw(command_buffer, TESSELLATION_PATCH_CONTROL_POINTS, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+}
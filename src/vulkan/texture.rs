@@ -0,0 +1,964 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use ash::vk::{
+    BufferImageCopy, DescriptorImageInfo, DescriptorPoolSize, DescriptorSetLayoutBinding,
+    DescriptorType, DeviceSize, Extent2D, Extent3D, Format, FormatFeatureFlags, ImageAspectFlags,
+    ImageCreateFlags, ImageLayout, ImageSubresourceLayers, ImageUsageFlags, ImageViewType,
+    Offset3D, Sampler, SamplerAddressMode, ShaderStageFlags,
+};
+use ash::Device;
+use half::f16;
+use image::codecs::hdr::HdrDecoder;
+use image::imageops::{self, FilterType};
+use image::{Rgba32FImage, RgbaImage};
+use ktx2::SupercompressionScheme;
+use log::{info, warn};
+
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::command::execute_single_time_commands_in_background;
+use crate::vulkan::compute::record_compute_dispatch;
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::descriptor::{
+    allocate_descriptor_set, create_descriptor_pool, write_combined_image_sampler,
+    write_storage_image,
+};
+use crate::vulkan::format::{
+    compressed_image_size, compression_family, select_preferred_format, texel_size,
+    ColorSpaceIntent,
+};
+use crate::vulkan::image::{
+    create_image_view, record_image_layout_transition, ImageDesc, PistonImage,
+};
+use crate::vulkan::pipeline::{create_compute_pipeline, SpecializationConstants};
+use crate::vulkan::sampler::SamplerDesc;
+use crate::vulkan::upload::{upload_image_and_wait, upload_image_async};
+
+pub const CUBEMAP_FACE_NAMES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+const TEXTURE_FORMAT: Format = Format::R8G8B8A8_UNORM;
+
+const HDR_FORMATS: [Format; 2] = [Format::R16G16B16A16_SFLOAT, Format::R32G32B32A32_SFLOAT];
+
+const SAMPLED_FORMAT_FEATURES: FormatFeatureFlags = FormatFeatureFlags::from_raw(
+    FormatFeatureFlags::SAMPLED_IMAGE.as_raw() | FormatFeatureFlags::TRANSFER_DST.as_raw(),
+);
+
+const STORAGE_FORMAT_FEATURES: FormatFeatureFlags = FormatFeatureFlags::from_raw(
+    FormatFeatureFlags::SAMPLED_IMAGE.as_raw() | FormatFeatureFlags::STORAGE_IMAGE.as_raw(),
+);
+
+const CUBEMAP_STORAGE_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+const MISSING_TEXTURE_SIZE: u32 = 8;
+
+pub struct Texture {
+    pub image: PistonImage,
+    pub sampler: Sampler,
+}
+
+/// Procedurally generated textures that stand in for textures that failed to load or are still
+/// uploading. They are owned by the context and destroyed with it, never by texture users.
+pub struct DefaultTextures {
+    pub white: Texture,
+    pub flat_normal: Texture,
+    pub missing: Texture,
+}
+
+impl DefaultTextures {
+    pub fn new(context: &VulkanContext) -> Result<DefaultTextures> {
+        let single_texel = Extent2D {
+            width: 1,
+            height: 1,
+        };
+        let white = Texture::from_rgba_pixels(
+            context,
+            single_texel,
+            &[255, 255, 255, 255],
+            ColorSpaceIntent::Srgb,
+            &SamplerDesc::default(),
+        )?;
+        let flat_normal = Texture::from_rgba_pixels(
+            context,
+            single_texel,
+            &[128, 128, 255, 255],
+            ColorSpaceIntent::Linear,
+            &SamplerDesc::default(),
+        )
+        .inspect_err(|_| white.destroy(&context.device))?;
+
+        let checkerboard: Vec<u8> = (0..MISSING_TEXTURE_SIZE * MISSING_TEXTURE_SIZE)
+            .flat_map(|index| {
+                let (x, y) = (index % MISSING_TEXTURE_SIZE, index / MISSING_TEXTURE_SIZE);
+                if (x + y) % 2 == 0 {
+                    [255, 0, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            })
+            .collect();
+        let missing = Texture::from_rgba_pixels(
+            context,
+            Extent2D {
+                width: MISSING_TEXTURE_SIZE,
+                height: MISSING_TEXTURE_SIZE,
+            },
+            &checkerboard,
+            ColorSpaceIntent::Srgb,
+            &SamplerDesc::nearest(SamplerAddressMode::REPEAT),
+        )
+        .inspect_err(|_| {
+            white.destroy(&context.device);
+            flat_normal.destroy(&context.device);
+        })?;
+
+        Ok(DefaultTextures {
+            white,
+            flat_normal,
+            missing,
+        })
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.white.destroy(device);
+        self.flat_normal.destroy(device);
+        self.missing.destroy(device);
+    }
+}
+
+/// A texture whose upload may still be in flight. Until the context reports the upload as
+/// finished, descriptors should point at the placeholder instead.
+pub struct AsyncTexture {
+    pub texture: Texture,
+    placeholder: DescriptorImageInfo,
+    ready: Arc<AtomicBool>,
+    swapped: bool,
+}
+
+impl AsyncTexture {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Returns true exactly once, on the first call after the upload finished, so the owner
+    /// knows when to rewrite its descriptor sets.
+    pub fn became_ready(&mut self) -> bool {
+        if !self.swapped && self.is_ready() {
+            self.swapped = true;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn descriptor_image_info(&self) -> DescriptorImageInfo {
+        if self.is_ready() {
+            self.texture.descriptor_image_info()
+        } else {
+            self.placeholder
+        }
+    }
+
+    /// The upload must have completed, or the device must be idle.
+    pub fn destroy(&self, device: &Device) {
+        self.texture.destroy(device);
+    }
+}
+
+impl Texture {
+    pub fn from_file(
+        context: &VulkanContext,
+        path: &Path,
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let pixels = load_rgba_image(path)?;
+        Texture::from_rgba_pixels(
+            context,
+            Extent2D {
+                width: pixels.width(),
+                height: pixels.height(),
+            },
+            pixels.as_raw(),
+            intent,
+            sampler_desc,
+        )
+    }
+
+    pub fn from_rgba_pixels(
+        context: &VulkanContext,
+        extent: Extent2D,
+        pixels: &[u8],
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let desc = ImageDesc::texture_2d(extent, intent.apply_to(TEXTURE_FORMAT));
+        let image = upload_image_layers(context, &desc, pixels)?;
+        let sampler = context.get_or_create_sampler(sampler_desc)?;
+
+        Ok(Texture { image, sampler })
+    }
+
+    /// Decodes the image on the calling thread but does not wait for the GPU upload. On devices
+    /// with a dedicated transfer queue the copy runs there, otherwise on the graphics queue.
+    pub fn from_file_async(
+        context: &VulkanContext,
+        path: &Path,
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<AsyncTexture> {
+        let pixels = load_rgba_image(path)?;
+        let desc = ImageDesc::texture_2d(
+            Extent2D {
+                width: pixels.width(),
+                height: pixels.height(),
+            },
+            intent.apply_to(TEXTURE_FORMAT),
+        );
+
+        let copy_regions = create_layer_copy_regions(&desc, pixels.as_raw())?;
+        let image = PistonImage::new(context, &desc, &path.display().to_string())?;
+        let ready = match upload_image_async(context, &image, pixels.as_raw(), &copy_regions) {
+            Ok(ready) => ready,
+            Err(error) => {
+                image.destroy(&context.device);
+                return Err(error);
+            }
+        };
+        let sampler = context.get_or_create_sampler(sampler_desc)?;
+
+        Ok(AsyncTexture {
+            texture: Texture { image, sampler },
+            placeholder: context.default_textures().white.descriptor_image_info(),
+            ready,
+            swapped: false,
+        })
+    }
+
+    pub fn cubemap_from_files(
+        context: &VulkanContext,
+        paths: [&Path; 6],
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let mut faces: Vec<RgbaImage> = vec![];
+        for path in paths.iter() {
+            faces.push(load_rgba_image(path)?);
+        }
+
+        let face_size = faces[0].width();
+        for (index, face) in faces.iter().enumerate() {
+            if face.width() != face_size || face.height() != face_size {
+                return Err(anyhow!(
+                    "Cubemap face {} ({}) loaded from {:?} is {}x{}, expected {}x{} to match face {} ({}) loaded from {:?}",
+                    index,
+                    CUBEMAP_FACE_NAMES[index],
+                    paths[index],
+                    face.width(),
+                    face.height(),
+                    face_size,
+                    face_size,
+                    0,
+                    CUBEMAP_FACE_NAMES[0],
+                    paths[0]
+                ));
+            }
+        }
+
+        let pixels: Vec<u8> = faces
+            .iter()
+            .flat_map(|face| face.as_raw().iter().copied())
+            .collect();
+
+        create_cubemap(context, face_size, &pixels, intent, sampler_desc)
+    }
+
+    pub fn cubemap_from_strip(
+        context: &VulkanContext,
+        path: &Path,
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let strip = load_rgba_image(path)?;
+        let face_size = strip.width();
+        if strip.height() != face_size * 6 {
+            return Err(anyhow!(
+                "Cubemap strip {:?} is {}x{}, expected six vertically stacked square faces of {}x{}",
+                path,
+                strip.width(),
+                strip.height(),
+                face_size,
+                face_size * 6
+            ));
+        }
+
+        create_cubemap(context, face_size, strip.as_raw(), intent, sampler_desc)
+    }
+
+    pub fn from_ktx2_file(
+        context: &VulkanContext,
+        path: &Path,
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let file_bytes = fs::read(path)
+            .with_context(|| format!("Failed to read KTX2 texture from {:?}", path))?;
+        create_texture_from_ktx2(context, path, &file_bytes, intent, sampler_desc)
+    }
+
+    pub fn from_ktx2_variants(
+        context: &VulkanContext,
+        paths: &[&Path],
+        intent: ColorSpaceIntent,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let mut variants = vec![];
+        for &path in paths {
+            let file_bytes = fs::read(path)
+                .with_context(|| format!("Failed to read KTX2 texture from {:?}", path))?;
+            let format = parse_ktx2(path, &file_bytes)?.1;
+            variants.push((path, file_bytes, format));
+        }
+
+        let formats: Vec<Format> = variants.iter().map(|(_, _, format)| *format).collect();
+        match select_preferred_format(&formats, &context.compressed_format_support) {
+            Some(index) => {
+                let (path, file_bytes, format) = &variants[index];
+                info!(
+                    "Selected texture variant {:?} ({:?}) out of {} candidates",
+                    path,
+                    format,
+                    variants.len()
+                );
+                create_texture_from_ktx2(context, path, file_bytes, intent, sampler_desc)
+            }
+            None => Err(anyhow!(
+                "None of the texture variants use a format supported by this device (variant formats: {:?}, supported compressed families: {:?})",
+                formats,
+                context.compressed_format_support.supported_families()
+            )),
+        }
+    }
+
+    /// HDR textures always hold linear float data, so unlike the other loaders there is no
+    /// color space intent to pick.
+    pub fn from_hdr_file(
+        context: &VulkanContext,
+        path: &Path,
+        sampler_desc: &SamplerDesc,
+    ) -> Result<Texture> {
+        let format = select_hdr_format(context)?;
+        let max_dimension = context.device_info.max_image_dimension_2d();
+        let pixels = fit_to_max_dimension(load_hdr_image(path)?, max_dimension, path);
+
+        let desc = ImageDesc::texture_2d(
+            Extent2D {
+                width: pixels.width(),
+                height: pixels.height(),
+            },
+            format,
+        );
+        let image = upload_image_layers(context, &desc, &convert_hdr_pixels(&pixels, format))?;
+        let sampler = context.get_or_create_sampler(sampler_desc)?;
+
+        info!(
+            "Loaded HDR texture {:?}: {:?}, {}x{}",
+            path, format, desc.extent.width, desc.extent.height
+        );
+
+        Ok(Texture { image, sampler })
+    }
+
+    /// Projects an equirectangular HDR texture onto the six faces of a cubemap with a compute
+    /// dispatch. The result is always stored as `R16G16B16A16_SFLOAT`, and can be copied from
+    /// to read it back.
+    pub fn cubemap_from_equirectangular(
+        context: &VulkanContext,
+        equirectangular: &Texture,
+        face_size: u32,
+    ) -> Result<Texture> {
+        check_format_features(context, CUBEMAP_STORAGE_FORMAT, STORAGE_FORMAT_FEATURES)?;
+
+        let desc = ImageDesc {
+            usage: ImageUsageFlags::STORAGE
+                | ImageUsageFlags::SAMPLED
+                | ImageUsageFlags::TRANSFER_SRC,
+            ..ImageDesc::cubemap(face_size, CUBEMAP_STORAGE_FORMAT)
+        };
+        let image = PistonImage::new(context, &desc, "environment cubemap")?;
+        if let Err(error) = convert_equirectangular_to_cubemap(context, equirectangular, &image) {
+            image.destroy(&context.device);
+            return Err(error);
+        }
+        let sampler = context
+            .get_or_create_sampler(&SamplerDesc::linear(SamplerAddressMode::CLAMP_TO_EDGE))?;
+
+        info!(
+            "Converted {}x{} equirectangular texture into a cubemap with {}x{} faces",
+            equirectangular.image.extent.width,
+            equirectangular.image.extent.height,
+            face_size,
+            face_size
+        );
+
+        Ok(Texture { image, sampler })
+    }
+
+    pub fn descriptor_image_info(&self) -> DescriptorImageInfo {
+        DescriptorImageInfo::builder()
+            .sampler(self.sampler)
+            .image_view(self.image.view)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        self.image.destroy(device);
+    }
+}
+
+pub fn texture_layout_binding(binding: u32) -> DescriptorSetLayoutBinding {
+    DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(ShaderStageFlags::FRAGMENT)
+        .build()
+}
+
+fn create_cubemap(
+    context: &VulkanContext,
+    face_size: u32,
+    pixels: &[u8],
+    intent: ColorSpaceIntent,
+    sampler_desc: &SamplerDesc,
+) -> Result<Texture> {
+    let desc = ImageDesc::cubemap(face_size, intent.apply_to(TEXTURE_FORMAT));
+    let image = upload_image_layers(context, &desc, pixels)?;
+    let sampler = context.get_or_create_sampler(sampler_desc)?;
+
+    Ok(Texture { image, sampler })
+}
+
+fn load_rgba_image(path: &Path) -> Result<RgbaImage> {
+    Ok(image::open(path)
+        .with_context(|| format!("Failed to load texture from {:?}", path))?
+        .to_rgba8())
+}
+
+fn parse_ktx2<'data>(
+    path: &Path,
+    file_bytes: &'data [u8],
+) -> Result<(ktx2::Reader<&'data [u8]>, Format)> {
+    let reader = ktx2::Reader::new(file_bytes)
+        .map_err(|error| anyhow!("Failed to parse KTX2 texture {:?}: {}", path, error))?;
+
+    match reader.header().format {
+        Some(format) => Ok((reader, Format::from_raw(format.0.get() as i32))),
+        None => Err(anyhow!(
+            "KTX2 texture {:?} has an undefined VkFormat, Basis Universal payloads are not supported",
+            path
+        )),
+    }
+}
+
+fn create_texture_from_ktx2(
+    context: &VulkanContext,
+    path: &Path,
+    file_bytes: &[u8],
+    intent: ColorSpaceIntent,
+    sampler_desc: &SamplerDesc,
+) -> Result<Texture> {
+    let (reader, stored_format) = parse_ktx2(path, file_bytes)?;
+    let header = reader.header();
+
+    let format = intent.apply_to(stored_format);
+    if format != stored_format {
+        warn!(
+            "KTX2 texture {:?} is stored as {:?} but was loaded with {:?} intent, reinterpreting it as {:?}",
+            path, stored_format, intent, format
+        );
+    }
+
+    if header.pixel_depth > 1 {
+        return Err(anyhow!(
+            "KTX2 texture {:?} is a 3D texture, which is not supported",
+            path
+        ));
+    }
+    if let Some(family) = compression_family(format) {
+        if !context.compressed_format_support.supports(family) {
+            return Err(anyhow!(
+                "KTX2 texture {:?} uses {:?} from the {:?} family, but this device only supports compressed families {:?}",
+                path,
+                format,
+                family,
+                context.compressed_format_support.supported_families()
+            ));
+        }
+    }
+    check_sampled_format_support(context, format)
+        .with_context(|| format!("Cannot load KTX2 texture {:?}", path))?;
+
+    let levels = read_ktx2_levels(&reader)
+        .with_context(|| format!("Failed to read mip levels from KTX2 texture {:?}", path))?;
+
+    let is_cubemap = header.face_count == 6;
+    let layer_count = header.layer_count.max(1);
+    let desc = ImageDesc {
+        mip_levels: levels.len() as u32,
+        array_layers: layer_count * header.face_count,
+        flags: if is_cubemap {
+            ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            ImageCreateFlags::empty()
+        },
+        view_type: match (is_cubemap, header.layer_count > 0) {
+            (true, true) => ImageViewType::CUBE_ARRAY,
+            (true, false) => ImageViewType::CUBE,
+            (false, true) => ImageViewType::TYPE_2D_ARRAY,
+            (false, false) => ImageViewType::TYPE_2D,
+        },
+        ..ImageDesc::texture_2d(
+            Extent2D {
+                width: header.pixel_width,
+                height: header.pixel_height.max(1),
+            },
+            format,
+        )
+    };
+
+    let mut data = vec![];
+    let mut copy_regions = vec![];
+    for (mip_level, level) in levels.iter().enumerate() {
+        copy_regions.extend(
+            ktx2_level_copy_regions(
+                format,
+                desc.extent,
+                (mip_level as u32, desc.array_layers),
+                level.len(),
+                data.len(),
+            )
+            .with_context(|| format!("Invalid KTX2 texture {:?}", path))?,
+        );
+        data.extend_from_slice(level);
+    }
+
+    let image = upload_image(context, &desc, &data, &copy_regions)?;
+    let sampler = context.get_or_create_sampler(sampler_desc)?;
+
+    info!(
+        "Loaded KTX2 texture {:?}: {:?}, {}x{}, {} mip levels, {} layers",
+        path, format, desc.extent.width, desc.extent.height, desc.mip_levels, desc.array_layers
+    );
+
+    Ok(Texture { image, sampler })
+}
+
+/// One copy region per array layer of a KTX2 mip level that starts at `buffer_offset`. The
+/// size of block-compressed levels is checked against whole blocks for every layer.
+fn ktx2_level_copy_regions(
+    format: Format,
+    base_extent: Extent2D,
+    (mip_level, array_layers): (u32, u32),
+    level_size: usize,
+    buffer_offset: usize,
+) -> Result<Vec<BufferImageCopy>> {
+    let level_extent = mip_level_extent(base_extent, mip_level);
+    if let Some(image_size) = compressed_image_size(format, level_extent) {
+        let expected_size = image_size * array_layers as DeviceSize;
+        if level_size as DeviceSize != expected_size {
+            return Err(anyhow!(
+                "Mip level {} holds {} bytes, expected {} for {} layers of {}x{} {:?}",
+                mip_level,
+                level_size,
+                expected_size,
+                array_layers,
+                level_extent.width,
+                level_extent.height,
+                format
+            ));
+        }
+    }
+
+    // Block-compressed levels are stored as whole blocks, but the copy extent stays the texel
+    // extent of the level: Vulkan accepts a partial block when it ends at the edge.
+    let layer_size = level_size / array_layers as usize;
+    Ok((0..array_layers)
+        .map(|array_layer| {
+            create_buffer_image_copy(
+                (buffer_offset + array_layer as usize * layer_size) as DeviceSize,
+                mip_level,
+                array_layer,
+                level_extent,
+            )
+        })
+        .collect())
+}
+
+fn read_ktx2_levels(reader: &ktx2::Reader<&[u8]>) -> Result<Vec<Vec<u8>>> {
+    match reader.header().supercompression_scheme {
+        None => Ok(reader.levels().map(|level| level.to_vec()).collect()),
+        Some(SupercompressionScheme::Zstandard) => reader
+            .levels()
+            .enumerate()
+            .map(|(mip_level, level)| {
+                zstd::stream::decode_all(level)
+                    .with_context(|| format!("Failed to decompress zstd mip level {}", mip_level))
+            })
+            .collect(),
+        Some(scheme) => Err(anyhow!(
+            "Supercompression scheme {:?} is not supported, only zstd is",
+            scheme
+        )),
+    }
+}
+
+fn is_sampled_format_supported(context: &VulkanContext, format: Format) -> bool {
+    has_format_features(context, format, SAMPLED_FORMAT_FEATURES)
+}
+
+fn has_format_features(
+    context: &VulkanContext,
+    format: Format,
+    required_features: FormatFeatureFlags,
+) -> bool {
+    let format_properties = unsafe {
+        context
+            .instance
+            .get_physical_device_format_properties(context.physical_device, format)
+    };
+    format_properties
+        .optimal_tiling_features
+        .contains(required_features)
+}
+
+fn check_sampled_format_support(context: &VulkanContext, format: Format) -> Result<()> {
+    check_format_features(context, format, SAMPLED_FORMAT_FEATURES)
+}
+
+pub fn check_format_features(
+    context: &VulkanContext,
+    format: Format,
+    required_features: FormatFeatureFlags,
+) -> Result<()> {
+    if !has_format_features(context, format, required_features) {
+        return Err(anyhow!(
+            "Format {:?} does not support {:?} with optimal tiling on this device",
+            format,
+            required_features
+        ));
+    }
+
+    Ok(())
+}
+
+fn convert_equirectangular_to_cubemap(
+    context: &VulkanContext,
+    equirectangular: &Texture,
+    cubemap: &PistonImage,
+) -> Result<()> {
+    let device = &context.device;
+    let storage_view = create_image_view(
+        device,
+        cubemap.image,
+        cubemap.format,
+        ImageViewType::TYPE_2D_ARRAY,
+        cubemap.subresource_range,
+    )?;
+    let pipeline = create_compute_pipeline(
+        context,
+        context.load_shader("equirect-to-cubemap-comp.spv")?,
+        &SpecializationConstants::new(),
+    )?;
+    let descriptor_pool = create_descriptor_pool(
+        device,
+        &[
+            DescriptorPoolSize {
+                ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+            DescriptorPoolSize {
+                ty: DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+            },
+        ],
+        1,
+    )?;
+    let descriptor_set = allocate_descriptor_set(
+        device,
+        descriptor_pool,
+        pipeline.pipeline.descriptor_set_layouts[0],
+    )?;
+    write_combined_image_sampler(
+        device,
+        descriptor_set,
+        0,
+        equirectangular.descriptor_image_info(),
+    );
+    write_storage_image(
+        device,
+        descriptor_set,
+        1,
+        DescriptorImageInfo::builder()
+            .image_view(storage_view)
+            .image_layout(ImageLayout::GENERAL)
+            .build(),
+    );
+    let result = execute_single_time_commands_in_background(context, |device, command_buffer| {
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            cubemap.image,
+            cubemap.subresource_range,
+            ImageLayout::UNDEFINED,
+            ImageLayout::GENERAL,
+        )?;
+        record_compute_dispatch(
+            device,
+            command_buffer,
+            &pipeline,
+            &[descriptor_set],
+            [cubemap.extent.width, cubemap.extent.height, 6],
+        );
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            cubemap.image,
+            cubemap.subresource_range,
+            ImageLayout::GENERAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    });
+
+    pipeline.destroy(device);
+    unsafe {
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        device.destroy_image_view(storage_view, None);
+    }
+
+    result
+}
+
+fn select_hdr_format(context: &VulkanContext) -> Result<Format> {
+    HDR_FORMATS
+        .into_iter()
+        .find(|&format| is_sampled_format_supported(context, format))
+        .ok_or_else(|| {
+            anyhow!(
+                "None of the HDR texture formats {:?} support {:?} on this device",
+                HDR_FORMATS,
+                SAMPLED_FORMAT_FEATURES
+            )
+        })
+}
+
+fn load_hdr_image(path: &Path) -> Result<Rgba32FImage> {
+    let file = File::open(path).with_context(|| format!("Failed to open HDR image {:?}", path))?;
+    let decoder = HdrDecoder::new(BufReader::new(file))
+        .with_context(|| format!("Failed to read HDR header from {:?}", path))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()
+        .with_context(|| format!("Failed to decode HDR image {:?}", path))?;
+
+    let rgba_pixels = pixels
+        .iter()
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+        .collect();
+    Rgba32FImage::from_raw(metadata.width, metadata.height, rgba_pixels)
+        .ok_or_else(|| anyhow!("HDR image {:?} holds fewer pixels than its header", path))
+}
+
+fn fit_to_max_dimension(pixels: Rgba32FImage, max_dimension: u32, path: &Path) -> Rgba32FImage {
+    let (width, height) = pixels.dimensions();
+    if width <= max_dimension && height <= max_dimension {
+        return pixels;
+    }
+
+    let scale = max_dimension as f64 / width.max(height) as f64;
+    let scaled_width = ((width as f64 * scale) as u32).max(1);
+    let scaled_height = ((height as f64 * scale) as u32).max(1);
+    warn!(
+        "HDR image {:?} is {}x{}, which exceeds maxImageDimension2D {}, downsampling to {}x{}",
+        path, width, height, max_dimension, scaled_width, scaled_height
+    );
+
+    imageops::resize(&pixels, scaled_width, scaled_height, FilterType::Triangle)
+}
+
+fn convert_hdr_pixels(pixels: &Rgba32FImage, format: Format) -> Vec<u8> {
+    if format == Format::R16G16B16A16_SFLOAT {
+        pixels
+            .as_raw()
+            .iter()
+            .flat_map(|&value| f16::from_f32(value).to_ne_bytes())
+            .collect()
+    } else {
+        pixels
+            .as_raw()
+            .iter()
+            .flat_map(|&value| value.to_ne_bytes())
+            .collect()
+    }
+}
+
+fn mip_level_extent(extent: Extent2D, mip_level: u32) -> Extent2D {
+    Extent2D {
+        width: (extent.width >> mip_level).max(1),
+        height: (extent.height >> mip_level).max(1),
+    }
+}
+
+fn upload_image_layers(
+    context: &VulkanContext,
+    desc: &ImageDesc,
+    pixels: &[u8],
+) -> Result<PistonImage> {
+    let copy_regions = create_layer_copy_regions(desc, pixels)?;
+    upload_image(context, desc, pixels, &copy_regions)
+}
+
+fn create_layer_copy_regions(desc: &ImageDesc, pixels: &[u8]) -> Result<Vec<BufferImageCopy>> {
+    let layer_size = pixels.len() as DeviceSize / desc.array_layers as DeviceSize;
+    if let Some(texel_size) = texel_size(desc.format) {
+        let expected_layer_size =
+            desc.extent.width as DeviceSize * desc.extent.height as DeviceSize * texel_size;
+        if pixels.len() as DeviceSize != expected_layer_size * desc.array_layers as DeviceSize {
+            return Err(anyhow!(
+                "Expected {} layers of {} bytes for a {}x{} {:?} image, got {} bytes",
+                desc.array_layers,
+                expected_layer_size,
+                desc.extent.width,
+                desc.extent.height,
+                desc.format,
+                pixels.len()
+            ));
+        }
+    }
+
+    Ok((0..desc.array_layers)
+        .map(|layer| {
+            create_buffer_image_copy(layer as DeviceSize * layer_size, 0, layer, desc.extent)
+        })
+        .collect())
+}
+
+fn upload_image(
+    context: &VulkanContext,
+    desc: &ImageDesc,
+    data: &[u8],
+    copy_regions: &[BufferImageCopy],
+) -> Result<PistonImage> {
+    let staging_buffer = PistonBuffer::new_staging_with_data(context, data)?;
+    let image = PistonImage::new(context, desc, "texture")?;
+
+    let upload_result = upload_image_and_wait(context, &staging_buffer, &image, copy_regions);
+
+    staging_buffer.destroy(&context.device);
+    if let Err(error) = upload_result {
+        image.destroy(&context.device);
+        return Err(error);
+    }
+
+    Ok(image)
+}
+
+fn create_buffer_image_copy(
+    buffer_offset: DeviceSize,
+    mip_level: u32,
+    array_layer: u32,
+    extent: Extent2D,
+) -> BufferImageCopy {
+    BufferImageCopy::builder()
+        .buffer_offset(buffer_offset)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(
+            ImageSubresourceLayers::builder()
+                .aspect_mask(ImageAspectFlags::COLOR)
+                .mip_level(mip_level)
+                .base_array_layer(array_layer)
+                .layer_count(1)
+                .build(),
+        )
+        .image_offset(Offset3D::default())
+        .image_extent(Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_EXTENT: Extent2D = Extent2D {
+        width: 10,
+        height: 6,
+    };
+
+    fn copy_extent(region: &BufferImageCopy) -> (u32, u32) {
+        (region.image_extent.width, region.image_extent.height)
+    }
+
+    #[test]
+    fn partial_block_mip_keeps_its_texel_extent() {
+        // Mip 1 is 5x3 texels, stored as 2x1 BC1 blocks of 8 bytes
+        let regions =
+            ktx2_level_copy_regions(Format::BC1_RGBA_UNORM_BLOCK, BASE_EXTENT, (1, 1), 16, 0)
+                .unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(copy_extent(&regions[0]), (5, 3));
+        assert_eq!(regions[0].image_subresource.mip_level, 1);
+    }
+
+    #[test]
+    fn smallest_mips_take_a_whole_block() {
+        // Mip 3 is 1x1 texels, a single BC7 block of 16 bytes
+        let regions =
+            ktx2_level_copy_regions(Format::BC7_UNORM_BLOCK, BASE_EXTENT, (3, 1), 16, 0).unwrap();
+        assert_eq!(copy_extent(&regions[0]), (1, 1));
+    }
+
+    #[test]
+    fn layers_follow_each_other_in_the_level() {
+        // Mip 0 is 3x2 BC1 blocks per layer, 48 bytes
+        let regions =
+            ktx2_level_copy_regions(Format::BC1_RGBA_UNORM_BLOCK, BASE_EXTENT, (0, 6), 288, 100)
+                .unwrap();
+        let offsets = regions
+            .iter()
+            .map(|region| region.buffer_offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [100, 148, 196, 244, 292, 340]);
+        let layers = regions
+            .iter()
+            .map(|region| region.image_subresource.base_array_layer)
+            .collect::<Vec<_>>();
+        assert_eq!(layers, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn compressed_level_of_the_wrong_size_is_rejected() {
+        // A texel-exact 5x3 size instead of whole blocks
+        let error =
+            ktx2_level_copy_regions(Format::BC1_RGBA_UNORM_BLOCK, BASE_EXTENT, (1, 1), 8, 0)
+                .unwrap_err();
+        assert!(error.to_string().contains("holds 8 bytes, expected 16"));
+    }
+
+    #[test]
+    fn uncompressed_levels_are_not_size_checked() {
+        let regions =
+            ktx2_level_copy_regions(Format::R8G8B8A8_UNORM, BASE_EXTENT, (0, 2), 480, 0).unwrap();
+        assert_eq!(regions[1].buffer_offset, 240);
+        assert_eq!(copy_extent(&regions[1]), (10, 6));
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk::{
+    AccessFlags, BufferImageCopy, CommandBuffer, CommandBufferBeginInfo, CommandBufferUsageFlags,
+    CommandPool, Fence, FenceCreateInfo, ImageLayout, PipelineStageFlags, Semaphore,
+    SemaphoreCreateInfo,
+};
+use ash::Device;
+use log::{debug, warn};
+
+use crate::vulkan::barrier::{acquire_ownership, release_ownership, OwnedResource};
+use crate::vulkan::buffer::PistonBuffer;
+use crate::vulkan::command::{
+    allocate_command_buffers, execute_single_time_commands, execute_single_time_commands_on,
+};
+use crate::vulkan::context::VulkanContext;
+use crate::vulkan::image::{record_image_layout_transition, PistonImage};
+
+/// An image upload that has been submitted but not waited on. The context polls these once per
+/// frame and releases the staging resources when the fence signals.
+pub struct AsyncUpload {
+    fence: Fence,
+    semaphore: Option<Semaphore>,
+    command_buffers: Vec<(CommandPool, CommandBuffer)>,
+    staging_buffer: PistonBuffer,
+    ready: Arc<AtomicBool>,
+}
+
+impl AsyncUpload {
+    pub fn is_complete(&self, device: &Device) -> Result<bool> {
+        Ok(unsafe { device.get_fence_status(self.fence) }?)
+    }
+
+    pub fn complete(&self, device: &Device) {
+        self.ready.store(true, Ordering::Release);
+        self.destroy(device);
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_fence(self.fence, None);
+            if let Some(semaphore) = self.semaphore {
+                device.destroy_semaphore(semaphore, None);
+            }
+            for &(command_pool, command_buffer) in self.command_buffers.iter() {
+                device.free_command_buffers(command_pool, &[command_buffer]);
+            }
+        }
+        self.staging_buffer.destroy(device);
+    }
+}
+
+pub fn upload_image_async(
+    context: &VulkanContext,
+    image: &PistonImage,
+    data: &[u8],
+    copy_regions: &[BufferImageCopy],
+) -> Result<Arc<AtomicBool>> {
+    let device = &context.device;
+    let staging_buffer = PistonBuffer::new_staging_with_data(context, data)?;
+    let fence = match unsafe { device.create_fence(&FenceCreateInfo::default(), None) } {
+        Ok(fence) => fence,
+        Err(error) => {
+            staging_buffer.destroy(device);
+            return Err(error.into());
+        }
+    };
+    context.set_object_name(fence, "upload fence");
+
+    let ready = Arc::new(AtomicBool::new(false));
+    let mut upload = AsyncUpload {
+        fence,
+        semaphore: None,
+        command_buffers: vec![],
+        staging_buffer,
+        ready: ready.clone(),
+    };
+    if let Err(error) = submit_async_upload(context, &mut upload, image, copy_regions) {
+        // The transfer half may have been submitted before the acquire failed
+        if let Err(wait_error) = unsafe { device.queue_wait_idle(context.transfer_queue) } {
+            warn!("Failed to wait for the transfer queue: {}", wait_error);
+        }
+        upload.destroy(device);
+        return Err(error);
+    }
+
+    debug!(
+        "Submitted async upload of {} bytes into image {:?}",
+        data.len(),
+        image.image
+    );
+
+    match context.async_uploads.lock() {
+        Ok(mut async_uploads) => async_uploads.push(upload),
+        Err(_) => {
+            if let Err(wait_error) = unsafe { device.wait_for_fences(&[fence], true, u64::MAX) } {
+                warn!("Failed to wait for the upload fence: {}", wait_error);
+            }
+            upload.destroy(device);
+            return Err(anyhow!("Async upload list lock is poisoned"));
+        }
+    }
+
+    Ok(ready)
+}
+
+/// Records and submits the upload, keeping everything it creates in `upload` so that it's
+/// released on failure as well.
+fn submit_async_upload(
+    context: &VulkanContext,
+    upload: &mut AsyncUpload,
+    image: &PistonImage,
+    copy_regions: &[BufferImageCopy],
+) -> Result<()> {
+    let device = &context.device;
+    if !context.has_dedicated_transfer_queue() {
+        let command_buffer = allocate_command_buffers(device, context.command_pool, 1)?[0];
+        upload
+            .command_buffers
+            .push((context.command_pool, command_buffer));
+        record_one_time_commands(device, command_buffer, |device, command_buffer| {
+            record_copy_to_image(
+                device,
+                command_buffer,
+                &upload.staging_buffer,
+                image,
+                copy_regions,
+            )?;
+            record_image_layout_transition(
+                device,
+                command_buffer,
+                image.image,
+                image.subresource_range,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        })?;
+
+        return context
+            .submission(context.graphics_queue, "async texture upload")
+            .command_buffer(command_buffer)
+            .fence(upload.fence)
+            .submit();
+    }
+
+    let families = transfer_to_graphics_families(context)?;
+    let semaphore = unsafe { device.create_semaphore(&SemaphoreCreateInfo::default(), None) }?;
+    upload.semaphore = Some(semaphore);
+    context.set_object_name(semaphore, "upload ownership semaphore");
+    let transfer_command_buffer =
+        allocate_command_buffers(device, context.transfer_command_pool, 1)?[0];
+    upload
+        .command_buffers
+        .push((context.transfer_command_pool, transfer_command_buffer));
+    let graphics_command_buffer = allocate_command_buffers(device, context.command_pool, 1)?[0];
+    upload
+        .command_buffers
+        .push((context.command_pool, graphics_command_buffer));
+
+    record_one_time_commands(device, transfer_command_buffer, |device, command_buffer| {
+        record_copy_to_image(
+            device,
+            command_buffer,
+            &upload.staging_buffer,
+            image,
+            copy_regions,
+        )?;
+        release_ownership(
+            device,
+            command_buffer,
+            &uploaded_image(image),
+            families,
+            (AccessFlags::TRANSFER_WRITE, PipelineStageFlags::TRANSFER),
+        );
+        Ok(())
+    })?;
+    record_one_time_commands(device, graphics_command_buffer, |device, command_buffer| {
+        acquire_ownership(
+            device,
+            command_buffer,
+            &uploaded_image(image),
+            families,
+            (
+                AccessFlags::SHADER_READ,
+                PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+        );
+        Ok(())
+    })?;
+
+    context
+        .submission(context.transfer_queue, "async texture upload transfer")
+        .command_buffer(transfer_command_buffer)
+        .signal(semaphore)
+        .submit()?;
+    context
+        .submission(context.graphics_queue, "async texture upload acquire")
+        .command_buffer(graphics_command_buffer)
+        .wait(semaphore, PipelineStageFlags::ALL_COMMANDS)
+        .fence(upload.fence)
+        .submit()
+}
+
+/// The transfer and graphics family indices, the source and destination of an upload's
+/// ownership transfer.
+fn transfer_to_graphics_families(context: &VulkanContext) -> Result<(u32, u32)> {
+    let indices = &context.queue_family_indices;
+    let transfer_family_index = indices
+        .transfer_family_index
+        .ok_or_else(|| anyhow!("The device has no transfer queue family"))?;
+    let graphics_family_index = indices
+        .primary_family_index()
+        .ok_or_else(|| anyhow!("The device has no graphics or compute queue family"))?;
+
+    Ok((transfer_family_index, graphics_family_index))
+}
+
+/// Copies the staging buffer into the image and waits for it, leaving the image ready to be
+/// sampled. On devices with a dedicated transfer queue the copy runs there, and the image then
+/// moves to the graphics family.
+pub fn upload_image_and_wait(
+    context: &VulkanContext,
+    staging_buffer: &PistonBuffer,
+    image: &PistonImage,
+    copy_regions: &[BufferImageCopy],
+) -> Result<()> {
+    if !context.has_dedicated_transfer_queue() {
+        return execute_single_time_commands(context, |device, command_buffer| {
+            record_copy_to_image(device, command_buffer, staging_buffer, image, copy_regions)?;
+            record_image_layout_transition(
+                device,
+                command_buffer,
+                image.image,
+                image.subresource_range,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        });
+    }
+
+    let families = transfer_to_graphics_families(context)?;
+    // The transfer queue is idle before the acquire is submitted, so no semaphore is needed
+    execute_single_time_commands_on(
+        context,
+        context.transfer_command_pool,
+        context.transfer_queue,
+        "upload transfer",
+        |device, command_buffer| {
+            record_copy_to_image(device, command_buffer, staging_buffer, image, copy_regions)?;
+            release_ownership(
+                device,
+                command_buffer,
+                &uploaded_image(image),
+                families,
+                (AccessFlags::TRANSFER_WRITE, PipelineStageFlags::TRANSFER),
+            );
+            Ok(())
+        },
+    )?;
+    execute_single_time_commands(context, |device, command_buffer| {
+        acquire_ownership(
+            device,
+            command_buffer,
+            &uploaded_image(image),
+            families,
+            (
+                AccessFlags::SHADER_READ,
+                PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+        );
+        Ok(())
+    })
+}
+
+fn record_one_time_commands<F>(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    record: F,
+) -> Result<()>
+where
+    F: FnOnce(&Device, CommandBuffer) -> Result<()>,
+{
+    let begin_info = CommandBufferBeginInfo::builder()
+        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .build();
+    unsafe { device.begin_command_buffer(command_buffer, &begin_info) }?;
+    record(device, command_buffer)?;
+    unsafe { device.end_command_buffer(command_buffer) }?;
+
+    Ok(())
+}
+
+fn record_copy_to_image(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    staging_buffer: &PistonBuffer,
+    image: &PistonImage,
+    copy_regions: &[BufferImageCopy],
+) -> Result<()> {
+    record_image_layout_transition(
+        device,
+        command_buffer,
+        image.image,
+        image.subresource_range,
+        ImageLayout::UNDEFINED,
+        ImageLayout::TRANSFER_DST_OPTIMAL,
+    )?;
+    unsafe {
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging_buffer.buffer,
+            image.image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            copy_regions,
+        )
+    };
+
+    Ok(())
+}
+
+/// The image as it moves from the transfer family to the graphics family, after the copy.
+fn uploaded_image(image: &PistonImage) -> OwnedResource {
+    OwnedResource::Image {
+        image: image.image,
+        subresource_range: image.subresource_range,
+        old_layout: ImageLayout::TRANSFER_DST_OPTIMAL,
+        new_layout: ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }
+}
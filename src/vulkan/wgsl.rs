@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use ash::vk::{DescriptorType, PushConstantRange, ShaderStageFlags};
+use log::{error, info};
+use naga::back::spv::{write_vec, Options, PipelineOptions};
+use naga::front::wgsl::parse_str;
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use naga::{
+    AddressSpace, ArraySize, Binding, Handle, ImageClass, Module, ShaderStage, Type, TypeInner,
+};
+
+use crate::vulkan::reflect::{ReflectedBinding, ShaderReflection};
+
+/// The engine's WGSL sources, used when the source directory can't be found.
+const EMBEDDED_WGSL_SHADERS: [(&str, &str); 1] = [(
+    "triangle.wgsl",
+    include_str!("../../shaders/src/wgsl/triangle.wgsl"),
+)];
+
+/// One entry point of a WGSL source translated to SPIR-V, with naga's reflection of it.
+pub struct WgslShader {
+    pub name: String,
+    pub code: Vec<u32>,
+    pub reflection: ShaderReflection,
+}
+
+/// Translates the `stage` entry point of `file_name` to SPIR-V. WGSL sources live in the
+/// `src/wgsl` directory next to the SPIR-V directory. Parse and validation errors are logged
+/// with naga's source annotations.
+pub fn translate_wgsl_shader(
+    shader_dir: &Path,
+    file_name: &str,
+    stage: ShaderStageFlags,
+) -> Result<WgslShader> {
+    let source = load_wgsl_source(&wgsl_source_path(shader_dir, file_name))?;
+    let module = parse_str(&source).map_err(|error| {
+        error!("{}", error.emit_to_string_with_path(&source, file_name));
+        anyhow!("Failed to parse WGSL shader {}", file_name)
+    })?;
+    let info = Validator::new(ValidationFlags::all(), Capabilities::PUSH_CONSTANT)
+        .validate(&module)
+        .map_err(|error| {
+            error!("{}", error.emit_to_string_with_path(&source, file_name));
+            anyhow!("WGSL shader {} failed validation", file_name)
+        })?;
+
+    let shader_stage = match stage {
+        ShaderStageFlags::VERTEX => ShaderStage::Vertex,
+        ShaderStageFlags::FRAGMENT => ShaderStage::Fragment,
+        ShaderStageFlags::COMPUTE => ShaderStage::Compute,
+        _ => return Err(anyhow!("WGSL has no {:?} shader stage", stage)),
+    };
+    let entry_points = module
+        .entry_points
+        .iter()
+        .enumerate()
+        .filter(|(_, entry_point)| entry_point.stage == shader_stage)
+        .collect::<Vec<_>>();
+    let (index, entry_point) = match entry_points.as_slice() {
+        [entry_point] => *entry_point,
+        [] => {
+            return Err(anyhow!(
+                "WGSL shader {} has no {:?} entry point",
+                file_name,
+                stage
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "WGSL shader {} has {} {:?} entry points, expected one",
+                file_name,
+                entry_points.len(),
+                stage
+            ))
+        }
+    };
+
+    // The default options flip Y like wgpu does, so positions keep their WGSL meaning
+    let pipeline_options = PipelineOptions {
+        shader_stage,
+        entry_point: entry_point.name.clone(),
+    };
+    let code = write_vec(&module, &info, &Options::default(), Some(&pipeline_options)).map_err(
+        |error| {
+            anyhow!(
+                "Failed to translate entry point {} of {} to SPIR-V: {}",
+                entry_point.name,
+                file_name,
+                error
+            )
+        },
+    )?;
+
+    Ok(WgslShader {
+        name: format!("{} ({})", file_name, entry_point.name),
+        code,
+        reflection: reflect_entry_point(&module, &info, index, stage)?,
+    })
+}
+
+fn wgsl_source_path(shader_dir: &Path, file_name: &str) -> PathBuf {
+    shader_dir
+        .parent()
+        .unwrap_or(shader_dir)
+        .join("src")
+        .join("wgsl")
+        .join(file_name)
+}
+
+fn load_wgsl_source(source_path: &Path) -> Result<String> {
+    if source_path.exists() {
+        info!("Loading WGSL shader {:?} from disk", source_path);
+        return fs::read_to_string(source_path)
+            .with_context(|| format!("Failed to read WGSL shader {:?}", source_path));
+    }
+
+    let file_name = source_path.file_name().unwrap_or_default();
+    let (_, source) = EMBEDDED_WGSL_SHADERS
+        .iter()
+        .find(|(embedded_file_name, _)| file_name == *embedded_file_name)
+        .ok_or_else(|| {
+            anyhow!(
+                "WGSL shader {:?} does not exist and has no embedded copy",
+                source_path
+            )
+        })?;
+    info!(
+        "WGSL shader {:?} does not exist, using the embedded copy",
+        source_path
+    );
+
+    Ok(source.to_string())
+}
+
+/// The same interface `reflect_shader` reads from SPIR-V, taken from the resources the entry
+/// point uses.
+fn reflect_entry_point(
+    module: &Module,
+    info: &ModuleInfo,
+    index: usize,
+    stage: ShaderStageFlags,
+) -> Result<ShaderReflection> {
+    let entry_point = &module.entry_points[index];
+    let function_info = info.get_entry_point(index);
+
+    let mut bindings = vec![];
+    let mut push_constant_range = None;
+    for (handle, variable) in module.global_variables.iter() {
+        if function_info[handle].is_empty() {
+            continue;
+        }
+        match (variable.space, &variable.binding) {
+            (AddressSpace::PushConstant, _) => {
+                let size = module.types[variable.ty].inner.size(module.to_ctx());
+                push_constant_range = Some(
+                    PushConstantRange::builder()
+                        .stage_flags(stage)
+                        .offset(0)
+                        .size(size.next_multiple_of(4))
+                        .build(),
+                );
+            }
+            (space, Some(resource_binding)) => {
+                let (descriptor_type, descriptor_count) =
+                    descriptor_type(module, space, variable.ty)?;
+                bindings.push(ReflectedBinding {
+                    set: resource_binding.group,
+                    binding: resource_binding.binding,
+                    descriptor_type,
+                    descriptor_count,
+                    stage_flags: stage,
+                });
+            }
+            _ => {}
+        }
+    }
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+    let mut input_locations = vec![];
+    for argument in &entry_point.function.arguments {
+        input_locations.extend(locations(module, argument.binding.as_ref(), argument.ty));
+    }
+    input_locations.sort_unstable();
+
+    Ok(ShaderReflection {
+        stage,
+        entry_point: entry_point.name.clone(),
+        bindings,
+        push_constant_range,
+        input_locations,
+        local_size: match stage {
+            ShaderStageFlags::COMPUTE => entry_point.workgroup_size,
+            _ => [1, 1, 1],
+        },
+    })
+}
+
+fn descriptor_type(
+    module: &Module,
+    space: AddressSpace,
+    ty: Handle<Type>,
+) -> Result<(DescriptorType, u32)> {
+    match (space, &module.types[ty].inner) {
+        (_, TypeInner::BindingArray { base, size }) => {
+            let (descriptor_type, _) = descriptor_type(module, space, *base)?;
+            let descriptor_count = match size {
+                ArraySize::Constant(size) => size.get(),
+                _ => 1,
+            };
+            Ok((descriptor_type, descriptor_count))
+        }
+        (AddressSpace::Uniform, _) => Ok((DescriptorType::UNIFORM_BUFFER, 1)),
+        (AddressSpace::Storage { .. }, _) => Ok((DescriptorType::STORAGE_BUFFER, 1)),
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                class: ImageClass::Storage { .. },
+                ..
+            },
+        ) => Ok((DescriptorType::STORAGE_IMAGE, 1)),
+        (AddressSpace::Handle, TypeInner::Image { .. }) => Ok((DescriptorType::SAMPLED_IMAGE, 1)),
+        (AddressSpace::Handle, TypeInner::Sampler { .. }) => Ok((DescriptorType::SAMPLER, 1)),
+        (space, inner) => Err(anyhow!(
+            "Unsupported WGSL resource {:?} in {:?}",
+            inner,
+            space
+        )),
+    }
+}
+
+/// Input locations of an entry point argument, arguments of a struct type carry them on their
+/// members.
+fn locations(module: &Module, binding: Option<&Binding>, ty: Handle<Type>) -> Vec<u32> {
+    match (binding, &module.types[ty].inner) {
+        (Some(Binding::Location { location, .. }), _) => vec![*location],
+        (None, TypeInner::Struct { members, .. }) => members
+            .iter()
+            .flat_map(|member| locations(module, member.binding.as_ref(), member.ty))
+            .collect(),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,217 @@
+use std::f64::consts::PI;
+
+use ash::vk::{
+    BufferImageCopy, BufferUsageFlags, DeviceSize, Extent2D, Extent3D, ImageAspectFlags,
+    ImageLayout, ImageSubresourceLayers, MemoryPropertyFlags, SamplerAddressMode,
+};
+use half::f16;
+use piston::config::EngineConfig;
+use piston::util::debug::ValidationPolicy;
+use piston::vulkan::buffer::PistonBuffer;
+use piston::vulkan::command::execute_single_time_commands;
+use piston::vulkan::compute::run_gradient_check;
+use piston::vulkan::context::VulkanContext;
+use piston::vulkan::format::ColorSpaceIntent;
+use piston::vulkan::headless::HeadlessContext;
+use piston::vulkan::image::{record_image_layout_transition, PistonImage};
+use piston::vulkan::sampler::SamplerDesc;
+use piston::vulkan::texture::Texture;
+
+const CUBEMAP_FACE_SIZE: u32 = 16;
+
+/// Four azimuth quadrants over the upper and lower hemisphere. Every channel is 0 or 1, which
+/// converts to half floats exactly.
+const EQUIRECTANGULAR_TEXELS: [[u8; 4]; 8] = [
+    [255, 0, 0, 255],
+    [0, 255, 0, 255],
+    [0, 0, 255, 255],
+    [255, 255, 0, 255],
+    [0, 255, 255, 255],
+    [255, 0, 255, 255],
+    [255, 255, 255, 255],
+    [0, 0, 0, 255],
+];
+
+/// Skips, passing, on machines without a Vulkan driver or a usable device.
+fn headless_context() -> Option<HeadlessContext> {
+    let mut config = EngineConfig::default();
+    config.validation.policy = ValidationPolicy::CountAndReport;
+    match HeadlessContext::new(&config) {
+        Ok(headless) => Some(headless),
+        Err(error) => {
+            eprintln!("Skipping, no headless Vulkan context: {:?}", error);
+            None
+        }
+    }
+}
+
+#[test]
+fn compute_gradient_matches() {
+    let Some(headless) = headless_context() else {
+        return;
+    };
+
+    let result = run_gradient_check(&headless.context);
+    let message_filter = headless.message_filter.clone();
+    drop(headless);
+
+    result.expect("the compute gradient is wrong");
+    message_filter.check().expect("validation reported errors");
+}
+
+#[test]
+fn equirectangular_cubemap_checksum_matches() {
+    let Some(headless) = headless_context() else {
+        return;
+    };
+
+    let result = convert_and_read_back(&headless.context);
+    let message_filter = headless.message_filter.clone();
+    drop(headless);
+
+    let texels = result.expect("the cubemap conversion failed");
+    assert_eq!(checksum(&texels), checksum(&reference_cubemap()));
+    message_filter.check().expect("validation reported errors");
+}
+
+/// Checks the CPU reference without a device: the +Y and -Y faces only see their hemisphere.
+#[test]
+fn reference_cubemap_poles_use_their_hemisphere() {
+    let texels = reference_cubemap()
+        .chunks_exact(8)
+        .map(|texel| {
+            [0, 2, 4].map(|offset| {
+                let value = f16::from_ne_bytes([texel[offset], texel[offset + 1]]);
+                (value.to_f32() * 255.0) as u8
+            })
+        })
+        .collect::<Vec<_>>();
+    let face_texel_count = (CUBEMAP_FACE_SIZE * CUBEMAP_FACE_SIZE) as usize;
+    let hemisphere = |range: std::ops::Range<usize>| {
+        EQUIRECTANGULAR_TEXELS[range]
+            .iter()
+            .map(|&[r, g, b, _]| [r, g, b])
+            .collect::<Vec<_>>()
+    };
+    let (upper, lower) = (hemisphere(0..4), hemisphere(4..8));
+
+    assert_eq!(texels.len(), face_texel_count * 6);
+    let positive_y = &texels[2 * face_texel_count..3 * face_texel_count];
+    let negative_y = &texels[3 * face_texel_count..4 * face_texel_count];
+    assert!(positive_y.iter().all(|texel| upper.contains(texel)));
+    assert!(negative_y.iter().all(|texel| lower.contains(texel)));
+}
+
+fn convert_and_read_back(context: &VulkanContext) -> anyhow::Result<Vec<u8>> {
+    // Nearest sampling keeps each face texel on a single equirectangular texel
+    let equirectangular = Texture::from_rgba_pixels(
+        context,
+        Extent2D {
+            width: 4,
+            height: 2,
+        },
+        EQUIRECTANGULAR_TEXELS.as_flattened(),
+        ColorSpaceIntent::Linear,
+        &SamplerDesc::nearest(SamplerAddressMode::CLAMP_TO_EDGE),
+    )?;
+    let cubemap =
+        Texture::cubemap_from_equirectangular(context, &equirectangular, CUBEMAP_FACE_SIZE);
+    equirectangular.destroy(&context.device);
+    let cubemap = cubemap?;
+
+    let texels = read_back_cubemap(context, &cubemap.image);
+    cubemap.destroy(&context.device);
+    texels
+}
+
+fn read_back_cubemap(context: &VulkanContext, image: &PistonImage) -> anyhow::Result<Vec<u8>> {
+    let buffer = PistonBuffer::new(
+        context,
+        reference_cubemap().len() as DeviceSize,
+        BufferUsageFlags::TRANSFER_DST,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        "cubemap readback",
+    )?;
+    let result = execute_single_time_commands(context, |device, command_buffer| {
+        record_image_layout_transition(
+            device,
+            command_buffer,
+            image.image,
+            image.subresource_range,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )?;
+        let region = BufferImageCopy::builder()
+            .image_subresource(
+                ImageSubresourceLayers::builder()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(6)
+                    .build(),
+            )
+            .image_extent(Extent3D {
+                width: CUBEMAP_FACE_SIZE,
+                height: CUBEMAP_FACE_SIZE,
+                depth: 1,
+            })
+            .build();
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[region],
+            )
+        };
+        Ok(())
+    })
+    .and_then(|()| buffer.read(&context.device));
+    buffer.destroy(&context.device);
+
+    result
+}
+
+/// The direction through a face texel, as `equirect-to-cubemap.comp` computes it.
+fn face_direction(face: u32, x: u32, y: u32) -> [f64; 3] {
+    let [u, v] = [x, y].map(|texel| (texel as f64 + 0.5) / CUBEMAP_FACE_SIZE as f64 * 2.0 - 1.0);
+    match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    }
+}
+
+/// The R16G16B16A16 texels of the six faces, computed on the CPU.
+fn reference_cubemap() -> Vec<u8> {
+    let mut texels = vec![];
+    for face in 0..6 {
+        for y in 0..CUBEMAP_FACE_SIZE {
+            for x in 0..CUBEMAP_FACE_SIZE {
+                let [dx, dy, dz] = face_direction(face, x, y);
+                let length = (dx * dx + dy * dy + dz * dz).sqrt();
+                let u = dz.atan2(dx) / (2.0 * PI) + 0.5;
+                let v = (dy / length).clamp(-1.0, 1.0).acos() / PI;
+                let column = ((u * 4.0) as usize).min(3);
+                let row = ((v * 2.0) as usize).min(1);
+                let [r, g, b, _] = EQUIRECTANGULAR_TEXELS[row * 4 + column];
+                for channel in [r, g, b, 255] {
+                    let value = f16::from_f32(channel as f32 / 255.0);
+                    texels.extend_from_slice(&value.to_ne_bytes());
+                }
+            }
+        }
+    }
+    texels
+}
+
+/// FNV-1a over the bytes.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}